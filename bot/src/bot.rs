@@ -0,0 +1,533 @@
+use crate::command::{Command, Context, Reply};
+use crate::quota::Quota;
+use common::{
+    client::{ClientError, Connection, Event},
+    proto::Msg,
+    util::collapse,
+};
+use log::{debug, info};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+static TICK: Duration = Duration::from_millis(100);
+
+/// A bot: a `Connection` that answers commands said in its room, or sent to
+/// it privately, that start with its prefix.
+///
+/// Besides the commands it's given, every bot answers to `help`, and to
+/// `enable` and `disable`, with which the operator of a room can switch the
+/// bot's commands on and off there. Replies are queued and let out no faster
+/// than the server's byte quota allows.
+pub struct Bot {
+    conn: Connection,
+    prefix: String,
+    commands: Vec<Command>,
+    disabled_rooms: Vec<String>,
+    op: Option<String>,
+    quota: Quota,
+    outbox: VecDeque<Vec<u8>>,
+    timers: Vec<(Instant, Reply)>,
+}
+
+impl Bot {
+    pub fn new(conn: Connection, prefix: &str) -> Bot {
+        Bot {
+            conn,
+            prefix: prefix.to_string(),
+            commands: Vec::new(),
+            disabled_rooms: Vec::new(),
+            op: None,
+            quota: Quota::default(),
+            outbox: VecDeque::new(),
+            timers: Vec::new(),
+        }
+    }
+
+    pub fn connection(&mut self) -> &mut Connection {
+        &mut self.conn
+    }
+
+    /// Use a `Quota` matching the server's settings, if they aren't the
+    /// defaults.
+    pub fn set_quota(&mut self, quota: Quota) {
+        self.quota = quota;
+    }
+
+    /// Add a command, replacing any other with the same name.
+    pub fn add(&mut self, command: Command) {
+        self.commands.retain(|c| c.name != command.name);
+        self.commands.push(command);
+    }
+
+    /// Start with the bot's commands switched off in `room`.
+    pub fn disable_in(&mut self, room: &str) {
+        let room = collapse(room);
+        if !self.disabled_rooms.contains(&room) {
+            self.disabled_rooms.push(room);
+        }
+    }
+
+    pub fn enable_in(&mut self, room: &str) {
+        let room = collapse(room);
+        self.disabled_rooms.retain(|r| *r != room);
+    }
+
+    pub fn is_enabled_in(&self, room: &str) -> bool {
+        !self.disabled_rooms.contains(&collapse(room))
+    }
+
+    /// Answer commands until the bot is logged out or loses its connection.
+    pub fn run(&mut self) -> Result<(), ClientError> {
+        loop {
+            let loop_timer = Instant::now();
+
+            while let Some(event) = self.conn.poll()? {
+                debug!("bot: received {:?}", &event);
+                self.handle(event);
+            }
+            if self.conn.is_closed() {
+                return Ok(());
+            }
+
+            let now = Instant::now();
+            let (due, waiting): (Vec<_>, Vec<_>) =
+                self.timers.drain(..).partition(|(t, _)| *t <= now);
+            self.timers = waiting;
+            for (_, reply) in due {
+                self.queue(&reply);
+            }
+
+            if let Some(bytes) = self.outbox.front() {
+                if self.quota.try_spend(bytes.len()) {
+                    self.conn.send_bytes(bytes);
+                    self.outbox.pop_front();
+                }
+            }
+
+            let loop_time = loop_timer.elapsed();
+            if loop_time < TICK {
+                std::thread::sleep(TICK - loop_time);
+            }
+        }
+    }
+
+    fn queue(&mut self, reply: &Reply) {
+        let msg = match reply {
+            Reply::Say(text) => Msg::Text {
+                who: "".into(),
+                lines: text.lines().map(Into::into).collect(),
+            },
+            Reply::Priv { who, text } => Msg::Priv {
+                who: who.into(),
+                text: text.into(),
+            },
+        };
+        self.outbox.push_back(self.conn.encode(&msg));
+    }
+
+    fn handle(&mut self, event: Event) {
+        match event {
+            Event::Joined { user, room } if user == self.conn.name() => {
+                info!("Joined {}.", &room);
+                self.op = None;
+                self.conn.query("roster", "");
+            }
+            Event::NewOp { user, room } if collapse(&room) == collapse(self.conn.room()) => {
+                self.op = Some(user);
+            }
+            Event::Roster { entries } => {
+                self.op = entries.into_iter().find(|e| e.op).map(|e| e.name);
+            }
+            Event::Renamed { old, new } => {
+                if matches!(&self.op, Some(op) if collapse(op) == collapse(&old)) {
+                    self.op = Some(new);
+                }
+            }
+            Event::OverQuota { .. } => {
+                info!("Over the server's byte quota; holding off.");
+                self.quota.exhaust();
+            }
+            Event::Text { who, lines } if who != self.conn.name() => {
+                if let Some(line) = lines.first() {
+                    if let Some(rest) = line.trim_start().strip_prefix(self.prefix.as_str()) {
+                        self.dispatch(&who, rest, false);
+                    }
+                }
+            }
+            Event::Priv { who, text } => {
+                let text = text.trim_start();
+                let text = text.strip_prefix(self.prefix.as_str()).unwrap_or(text);
+                self.dispatch(&who, text, true);
+            }
+            _ => {}
+        }
+    }
+
+    /// Run the command in `line`, which `who` sent, less the prefix.
+    fn dispatch(&mut self, who: &str, line: &str, private: bool) {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let (name, args) = match words.split_first() {
+            Some((name, args)) => (name.to_lowercase(), args),
+            None => return,
+        };
+
+        let room = self.conn.room().to_string();
+        let mut ctx = Context::new(who, &room, private);
+
+        match name.as_str() {
+            "enable" | "disable" => {
+                let is_op = matches!(&self.op, Some(op) if collapse(op) == collapse(who));
+                if !is_op {
+                    ctx.reply(&format!(
+                        "Only the operator of {} can {} me there.",
+                        &room, &name
+                    ));
+                } else if name == "enable" {
+                    self.enable_in(&room);
+                    ctx.reply(&format!("Commands are on in {}.", &room));
+                } else {
+                    self.disable_in(&room);
+                    ctx.reply(&format!(
+                        "Commands are off in {} until its operator says {}enable.",
+                        &room, &self.prefix
+                    ));
+                }
+            }
+            _ if !private && !self.is_enabled_in(&room) => return,
+            "help" => ctx.reply(&self.help(args.first().copied())),
+            _ => match self.commands.iter_mut().find(|c| c.name == name) {
+                None => return,
+                Some(command) if !command.accepts(args.len()) => {
+                    let usage = format!(
+                        "Usage: {}{} {}",
+                        &self.prefix, &command.name, &command.usage
+                    );
+                    ctx.reply(usage.trim_end());
+                }
+                Some(command) => {
+                    if let Err(e) = command.call(&mut ctx, args) {
+                        ctx.reply(&e);
+                    }
+                }
+            },
+        }
+
+        let now = Instant::now();
+        for (delay, reply) in ctx.into_replies() {
+            if delay.is_zero() {
+                self.queue(&reply);
+            } else {
+                self.timers.push((now + delay, reply));
+            }
+        }
+    }
+
+    /// Help on one command, or a list of them all.
+    fn help(&self, name: Option<&str>) -> String {
+        let line = |c: &Command| {
+            let usage = format!("{}{} {}", &self.prefix, &c.name, &c.usage);
+            format!("{} - {}", usage.trim_end(), &c.help)
+        };
+
+        match name {
+            Some(name) => {
+                let name = name.trim_start_matches(self.prefix.as_str()).to_lowercase();
+                match self.commands.iter().find(|c| c.name == name) {
+                    Some(c) => line(c),
+                    None => format!("I don't know a \"{}\" command.", &name),
+                }
+            }
+            None => {
+                let mut lines: Vec<String> = self.commands.iter().map(line).collect();
+                lines.push(format!(
+                    "{}help [command] - Show this, or help on one command.",
+                    &self.prefix
+                ));
+                lines.join("\n")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::config::ServerConfig;
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// The longest to wait for anything to come through the server.
+    const LIMIT: Duration = Duration::from_secs(5);
+
+    /// Start a real server on a free port, ticking quickly, and return its
+    /// address and the `Quota` that matches it.
+    fn server(byte_limit: usize, byte_tick: usize) -> (String, Quota) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let cfg = ServerConfig {
+            min_tick: Duration::from_millis(20),
+            byte_limit,
+            byte_tick,
+            ..ServerConfig::default()
+        };
+        let quota = Quota::new(cfg.byte_limit, cfg.byte_tick, cfg.min_tick);
+        thread::spawn(move || fresh_server::serve(cfg, listener).unwrap());
+        (address, quota)
+    }
+
+    /// Wait for the first `Event` that `f` picks something out of, skipping
+    /// the rest.
+    fn expect<T>(conn: &mut Connection, mut f: impl FnMut(Event) -> Option<T>) -> T {
+        let deadline = Instant::now() + LIMIT;
+        loop {
+            let left = deadline.saturating_duration_since(Instant::now());
+            assert!(left > Duration::ZERO, "{} waited too long.", conn.name());
+            if let Some(x) = conn.wait(left).unwrap().and_then(&mut f) {
+                return x;
+            }
+        }
+    }
+
+    /// Send what `conn` has queued, for someone who isn't waiting on anything.
+    fn flush(conn: &mut Connection) {
+        while !conn.is_flushed() {
+            conn.poll().unwrap();
+        }
+    }
+
+    /// Connect as `name` and go to `room`.
+    fn person(address: &str, name: &str, room: &str) -> Connection {
+        let mut conn = Connection::connect(address, name).unwrap();
+        conn.join(room);
+        expect(&mut conn, |e| match e {
+            Event::Joined { user, room: r } if user == name && r == room => Some(()),
+            _ => None,
+        });
+        conn
+    }
+
+    /// Start a bot, prefix `!`, that knows `echo`, `remind` and `spam`, and
+    /// wait (as `someone` in its room) for it to arrive and settle in.
+    fn bot(address: &str, quota: Quota, someone: &mut Connection) {
+        let (address, room) = (address.to_string(), someone.room().to_string());
+        thread::spawn(move || {
+            let mut bot = Bot::new(Connection::connect(&address, "bot").unwrap(), "!");
+            bot.set_quota(quota);
+            bot.add(Command::new(
+                "echo",
+                "<text>...",
+                "Say it back.",
+                |ctx: &mut Context, args: &[&str]| {
+                    ctx.reply(&args.join(" "));
+                    Ok(())
+                },
+            ));
+            bot.add(Command::new(
+                "remind",
+                "<secs> <what>",
+                "Remind you later.",
+                |ctx: &mut Context, args: &[&str]| {
+                    let secs: u64 = args[0].parse().map_err(|_| "Not a number.")?;
+                    let reply = Reply::Priv {
+                        who: ctx.who.to_string(),
+                        text: args[1].to_string(),
+                    };
+                    ctx.later(Duration::from_secs(secs), reply);
+                    Ok(())
+                },
+            ));
+            bot.add(Command::new(
+                "spam",
+                "<n>",
+                "Count to n.",
+                |ctx: &mut Context, args: &[&str]| {
+                    let n: usize = args[0].parse().map_err(|_| "Not a number.")?;
+                    for i in 1..=n {
+                        ctx.say(&format!("{} {}", i, "and ".repeat(10)));
+                    }
+                    Ok(())
+                },
+            ));
+            bot.connection().join(&room);
+            bot.run().unwrap();
+        });
+        expect(someone, |e| match e {
+            Event::Joined { user, .. } if user == "bot" => Some(()),
+            _ => None,
+        });
+        // By the time it answers, it's heard who runs the room.
+        someone.say("!echo ready");
+        assert_eq!(bot_says(someone), "ready");
+    }
+
+    /// The next thing the bot says in the room.
+    fn bot_says(conn: &mut Connection) -> String {
+        expect(conn, |e| match e {
+            Event::Text { who, lines } if who == "bot" => Some(lines.join("\n")),
+            _ => None,
+        })
+    }
+
+    /// The next thing the bot tells us privately.
+    fn bot_tells(conn: &mut Connection) -> String {
+        expect(conn, |e| match e {
+            Event::Priv { who, text } if who == "bot" => Some(text),
+            _ => None,
+        })
+    }
+
+    /// A server with `op` (who made `dev`, so runs it), `alice` and a bot
+    /// in `dev`.
+    fn room() -> (Connection, Connection) {
+        let (address, quota) = server(512, 6);
+        let op = person(&address, "op", "dev");
+        let mut alice = person(&address, "alice", "dev");
+        bot(&address, quota, &mut alice);
+        (op, alice)
+    }
+
+    #[test]
+    fn answers_only_its_prefix() {
+        let (_op, mut alice) = room();
+        alice.say("echo no prefix");
+        alice.say("?echo wrong prefix");
+        alice.say("!unknown");
+        alice.say("!echo hi there");
+        assert_eq!(bot_says(&mut alice), "hi there");
+        alice.say("  !ECHO spaced");
+        assert_eq!(bot_says(&mut alice), "spaced");
+    }
+
+    #[test]
+    fn private_commands_are_answered_privately() {
+        let (_op, mut alice) = room();
+        alice.priv_msg("bot", "echo no prefix needed");
+        assert_eq!(bot_tells(&mut alice), "no prefix needed");
+        alice.priv_msg("bot", "!echo or with one");
+        assert_eq!(bot_tells(&mut alice), "or with one");
+    }
+
+    #[test]
+    fn wrong_arity_gets_usage() {
+        let (_op, mut alice) = room();
+        alice.say("!echo");
+        assert_eq!(bot_says(&mut alice), "Usage: !echo <text>...");
+        alice.say("!remind 5");
+        assert_eq!(bot_says(&mut alice), "Usage: !remind <secs> <what>");
+        alice.say("!remind 5 tea now");
+        assert_eq!(bot_says(&mut alice), "Usage: !remind <secs> <what>");
+        alice.say("!remind soon tea");
+        assert_eq!(bot_says(&mut alice), "Not a number.");
+    }
+
+    #[test]
+    fn help() {
+        let (_op, mut alice) = room();
+        alice.say("!help");
+        assert_eq!(
+            bot_says(&mut alice),
+            concat!(
+                "!echo <text>... - Say it back.\n",
+                "!remind <secs> <what> - Remind you later.\n",
+                "!spam <n> - Count to n.\n",
+                "!help [command] - Show this, or help on one command."
+            )
+        );
+        alice.say("!help !remind");
+        assert_eq!(
+            bot_says(&mut alice),
+            "!remind <secs> <what> - Remind you later."
+        );
+        alice.say("!help nothing");
+        assert_eq!(bot_says(&mut alice), "I don't know a \"nothing\" command.");
+    }
+
+    #[test]
+    fn only_the_op_can_disable_and_enable() {
+        let (mut op, mut alice) = room();
+        alice.say("!disable");
+        assert_eq!(
+            bot_says(&mut alice),
+            "Only the operator of dev can disable me there."
+        );
+        op.say("!disable");
+        flush(&mut op);
+        assert_eq!(
+            bot_says(&mut alice),
+            "Commands are off in dev until its operator says !enable."
+        );
+        alice.say("!echo ignored");
+        alice.say("!help");
+        alice.say("!enable");
+        assert_eq!(
+            bot_says(&mut alice),
+            "Only the operator of dev can enable me there."
+        );
+        alice.priv_msg("bot", "echo still here");
+        assert_eq!(bot_tells(&mut alice), "still here");
+        op.say("!enable");
+        flush(&mut op);
+        assert_eq!(bot_says(&mut alice), "Commands are on in dev.");
+        alice.say("!echo back");
+        assert_eq!(bot_says(&mut alice), "back");
+    }
+
+    #[test]
+    fn op_follows_the_server() {
+        let (mut op, mut alice) = room();
+        op.logout("bye");
+        flush(&mut op);
+        expect(&mut alice, |e| match e {
+            Event::NewOp { user, .. } if user == "alice" => Some(()),
+            _ => None,
+        });
+        alice.say("!disable");
+        assert_eq!(
+            bot_says(&mut alice),
+            "Commands are off in dev until its operator says !enable."
+        );
+    }
+
+    #[test]
+    fn op_keeps_its_powers_through_a_rename() {
+        let (mut op, mut alice) = room();
+        op.set_name("boss");
+        flush(&mut op);
+        expect(&mut alice, |e| match e {
+            Event::Renamed { new, .. } if new == "boss" => Some(()),
+            _ => None,
+        });
+        alice.set_name("op");
+        alice.say("!disable");
+        assert_eq!(
+            bot_says(&mut alice),
+            "Only the operator of dev can disable me there."
+        );
+        op.say("!disable");
+        flush(&mut op);
+        assert_eq!(
+            bot_says(&mut alice),
+            "Commands are off in dev until its operator says !enable."
+        );
+    }
+
+    #[test]
+    fn later_replies_wait_on_timers() {
+        let (_op, mut alice) = room();
+        let start = Instant::now();
+        alice.say("!remind 1 tea");
+        assert_eq!(bot_tells(&mut alice), "tea");
+        assert!(start.elapsed() >= Duration::from_secs(1));
+    }
+
+    #[test]
+    fn replies_keep_within_the_quota() {
+        let (address, quota) = server(200, 20);
+        let mut alice = person(&address, "alice", "dev");
+        bot(&address, quota, &mut alice);
+        alice.say("!spam 12");
+        for i in 1..=12 {
+            assert_eq!(bot_says(&mut alice), format!("{} {}", i, "and ".repeat(10)));
+        }
+    }
+}
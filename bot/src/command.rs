@@ -0,0 +1,215 @@
+use std::time::Duration;
+
+/// Something for the bot to send.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Reply {
+    /// Say something in the bot's current room.
+    Say(String),
+    /// Send a private message.
+    Priv { who: String, text: String },
+}
+
+/// What a command's handler gets to know about how it was called, and where
+/// it leaves its replies.
+pub struct Context<'a> {
+    /// Who called the command.
+    pub who: &'a str,
+    /// The room the bot is in.
+    pub room: &'a str,
+    /// Whether the command came in a private message.
+    pub private: bool,
+    replies: Vec<(Duration, Reply)>,
+}
+
+impl<'a> Context<'a> {
+    pub fn new(who: &'a str, room: &'a str, private: bool) -> Context<'a> {
+        Context {
+            who,
+            room,
+            private,
+            replies: Vec::new(),
+        }
+    }
+
+    /// Answer the caller the way they asked: privately if the command came in
+    /// a private message, or in the room if it didn't.
+    pub fn reply(&mut self, text: &str) {
+        let reply = if self.private {
+            Reply::Priv {
+                who: self.who.to_string(),
+                text: text.to_string(),
+            }
+        } else {
+            Reply::Say(text.to_string())
+        };
+        self.later(Duration::ZERO, reply);
+    }
+
+    pub fn say(&mut self, text: &str) {
+        self.later(Duration::ZERO, Reply::Say(text.to_string()));
+    }
+
+    pub fn priv_msg(&mut self, who: &str, text: &str) {
+        self.later(
+            Duration::ZERO,
+            Reply::Priv {
+                who: who.to_string(),
+                text: text.to_string(),
+            },
+        );
+    }
+
+    /// Send `reply` once `delay` has passed.
+    pub fn later(&mut self, delay: Duration, reply: Reply) {
+        self.replies.push((delay, reply));
+    }
+
+    pub fn into_replies(self) -> Vec<(Duration, Reply)> {
+        self.replies
+    }
+}
+
+/// Called with the `Context` and the command's arguments (split on
+/// whitespace). An `Err` is sent back to the caller.
+pub type Handler = Box<dyn FnMut(&mut Context, &[&str]) -> Result<(), String>>;
+
+/// A command the bot answers to.
+pub struct Command {
+    pub name: String,
+    /// The arguments the command takes, as shown in help: `<arg>` for one that
+    /// has to be there, `[arg]` for one that doesn't, and a trailing `...` on
+    /// the last one if it can be repeated.
+    pub usage: String,
+    pub help: String,
+    min_args: usize,
+    max_args: Option<usize>,
+    handler: Handler,
+}
+
+impl Command {
+    /// The number of arguments the command takes is worked out from `usage`.
+    pub fn new<F>(name: &str, usage: &str, help: &str, handler: F) -> Command
+    where
+        F: FnMut(&mut Context, &[&str]) -> Result<(), String> + 'static,
+    {
+        let words: Vec<&str> = usage.split_whitespace().collect();
+        let min_args = words.iter().filter(|w| w.starts_with('<')).count();
+        let max_args = match words.last() {
+            Some(w) if w.ends_with("...") => None,
+            _ => Some(words.len()),
+        };
+
+        Command {
+            name: name.to_lowercase(),
+            usage: usage.to_string(),
+            help: help.to_string(),
+            min_args,
+            max_args,
+            handler: Box::new(handler),
+        }
+    }
+
+    pub fn accepts(&self, n_args: usize) -> bool {
+        n_args >= self.min_args && self.max_args.unwrap_or(usize::MAX) >= n_args
+    }
+
+    pub fn call(&mut self, ctx: &mut Context, args: &[&str]) -> Result<(), String> {
+        (self.handler)(ctx, args)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command(usage: &str) -> Command {
+        Command::new("Test", usage, "", |_, _| Ok(()))
+    }
+
+    #[test]
+    fn arity_from_usage() {
+        let none = command("");
+        assert!(none.accepts(0));
+        assert!(!none.accepts(1));
+
+        let one = command("<who>");
+        assert!(!one.accepts(0));
+        assert!(one.accepts(1));
+        assert!(!one.accepts(2));
+
+        let optional = command("<who> [why]");
+        assert!(!optional.accepts(0));
+        assert!(optional.accepts(1));
+        assert!(optional.accepts(2));
+        assert!(!optional.accepts(3));
+
+        let many = command("<time> <what>...");
+        assert!(!many.accepts(1));
+        assert!(many.accepts(2));
+        assert!(many.accepts(20));
+
+        assert_eq!(many.name, "test");
+    }
+
+    #[test]
+    fn replies_go_back_the_way_they_came() {
+        let mut ctx = Context::new("alice", "dev", false);
+        ctx.reply("public");
+        let mut private = Context::new("alice", "dev", true);
+        private.reply("private");
+
+        assert_eq!(
+            ctx.into_replies(),
+            vec![(Duration::ZERO, Reply::Say("public".into()))]
+        );
+        assert_eq!(
+            private.into_replies(),
+            vec![(
+                Duration::ZERO,
+                Reply::Priv {
+                    who: "alice".into(),
+                    text: "private".into()
+                }
+            )]
+        );
+    }
+
+    #[test]
+    fn later_keeps_delays_in_order() {
+        let mut ctx = Context::new("alice", "dev", false);
+        ctx.later(Duration::from_secs(60), Reply::Say("tea".into()));
+        ctx.say("now");
+        ctx.priv_msg("bob", "psst");
+
+        assert_eq!(
+            ctx.into_replies(),
+            vec![
+                (Duration::from_secs(60), Reply::Say("tea".into())),
+                (Duration::ZERO, Reply::Say("now".into())),
+                (
+                    Duration::ZERO,
+                    Reply::Priv {
+                        who: "bob".into(),
+                        text: "psst".into()
+                    }
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn call_passes_arguments() {
+        let mut echo = Command::new("echo", "<text>...", "", |ctx, args| {
+            ctx.reply(&args.join(" "));
+            Ok(())
+        });
+        let mut fail = Command::new("fail", "", "", |_, _| Err("No.".into()));
+        let mut ctx = Context::new("alice", "dev", false);
+        assert_eq!(echo.call(&mut ctx, &["a", "b"]), Ok(()));
+        assert_eq!(fail.call(&mut ctx, &[]), Err("No.".into()));
+        assert_eq!(
+            ctx.into_replies(),
+            vec![(Duration::ZERO, Reply::Say("a b".into()))]
+        );
+    }
+}
@@ -0,0 +1,3 @@
+pub mod bot;
+pub mod command;
+pub use common::quota;
@@ -0,0 +1,205 @@
+use clap::Parser;
+use common::client::Connection;
+use common::codec;
+use common::compress;
+use common::config::{BYTE_LIMIT, BYTE_TICK, SERVER_TICK};
+use fresh_bot::bot::Bot;
+use fresh_bot::command::{Command, Reply};
+use fresh_bot::quota::Quota;
+use log::{error, info};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const MAX_DICE: u64 = 100;
+const MAX_SIDES: u64 = 1000;
+/// The longest a reminder can be put off for.
+const MAX_REMINDER: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// A sample bot that echoes, rolls dice and sends reminders.
+#[derive(Parser)]
+#[command(author, version, about, long_about = None)]
+struct ClapOpts {
+    #[arg(short = 'a', long = "address", default_value = "127.0.0.1:1234")]
+    address: String,
+
+    #[arg(short = 'n', long = "name", default_value = "bot")]
+    name: String,
+
+    /// The room to join.
+    #[arg(short = 'r', long = "room")]
+    room: Option<String>,
+
+    /// What commands start with.
+    #[arg(short = 'p', long = "prefix", default_value = "!")]
+    prefix: String,
+
+    /// The wire codec to ask the server for: "json" or "msgpack".
+    #[arg(short = 'c', long = "codec", default_value = "json")]
+    codec: String,
+
+    /// The compression to ask the server for: "none", "deflate" or "zstd".
+    #[arg(short = 'z', long = "compression", default_value = "none")]
+    compression: String,
+
+    /// The server's `byte_limit`.
+    #[arg(long = "byte-limit", default_value_t = BYTE_LIMIT)]
+    byte_limit: usize,
+
+    /// The server's `bytes_per_tick`.
+    #[arg(long = "bytes-per-tick", default_value_t = BYTE_TICK)]
+    bytes_per_tick: usize,
+
+    /// The server's `tick_ms`.
+    #[arg(long = "tick-ms", default_value_t = SERVER_TICK)]
+    tick_ms: u64,
+}
+
+/// A random number from 1 to `n`.
+fn random(n: u64) -> u64 {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or_default();
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u128(nanos);
+    hasher.finish() % n + 1
+}
+
+/// Parse dice like `2d6`, `d20` or `3`.
+fn parse_dice(s: &str) -> Option<(u64, u64)> {
+    let (n, sides) = match s.to_lowercase().split_once('d') {
+        Some(("", sides)) => (1, sides.parse().ok()?),
+        Some((n, sides)) => (n.parse().ok()?, sides.parse().ok()?),
+        None => (s.parse().ok()?, 6),
+    };
+    if (1..=MAX_DICE).contains(&n) && (2..=MAX_SIDES).contains(&sides) {
+        Some((n, sides))
+    } else {
+        None
+    }
+}
+
+/// Parse a delay like `90`, `90s`, `15m` or `2h`.
+fn parse_delay(s: &str) -> Option<Duration> {
+    let (n, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => s.split_at(i),
+        None => (s, "s"),
+    };
+    let n: u64 = n.parse().ok()?;
+    let secs = match unit {
+        "s" => n,
+        "m" => n.checked_mul(60)?,
+        "h" => n.checked_mul(60 * 60)?,
+        _ => return None,
+    };
+    Some(Duration::from_secs(secs))
+}
+
+fn main() {
+    let opts = ClapOpts::parse();
+    let _ =
+        simplelog::SimpleLogger::init(simplelog::LevelFilter::Info, simplelog::Config::default());
+
+    let codec = match codec::by_name(&opts.codec) {
+        Some(x) => x,
+        None => {
+            error!("Unknown codec \"{}\".", &opts.codec);
+            std::process::exit(1);
+        }
+    };
+    if !compress::is_known(&opts.compression) {
+        error!("Unknown compression \"{}\".", &opts.compression);
+        std::process::exit(1);
+    }
+    let conn = match Connection::connect_with(&opts.address, &opts.name, codec, &opts.compression) {
+        Ok(x) => x,
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    info!(
+        "Connected to {} using {}, compression {}.",
+        conn.server_address(),
+        conn.codec().name(),
+        conn.compression()
+    );
+
+    let mut bot = Bot::new(conn, &opts.prefix);
+    bot.set_quota(Quota::new(
+        opts.byte_limit,
+        opts.bytes_per_tick,
+        Duration::from_millis(opts.tick_ms),
+    ));
+    if let Some(room) = &opts.room {
+        bot.connection().join(room);
+    }
+
+    bot.add(Command::new(
+        "echo",
+        "<text>...",
+        "Say the text back.",
+        |ctx, args| {
+            ctx.reply(&args.join(" "));
+            Ok(())
+        },
+    ));
+
+    bot.add(Command::new(
+        "roll",
+        "[NdM]",
+        "Roll N M-sided dice (one six-sided die if not given).",
+        |ctx, args| {
+            let (n, sides) = match args.first() {
+                Some(s) => parse_dice(s).ok_or_else(|| {
+                    format!(
+                        "I can roll from 1 to {} dice with 2 to {} sides, like 2d6.",
+                        MAX_DICE, MAX_SIDES
+                    )
+                })?,
+                None => (1, 6),
+            };
+            let rolls: Vec<u64> = (0..n).map(|_| random(sides)).collect();
+            let total: u64 = rolls.iter().sum();
+            let text = if n == 1 {
+                format!("{} rolled {}.", ctx.who, total)
+            } else {
+                let rolls: Vec<String> = rolls.iter().map(u64::to_string).collect();
+                format!("{} rolled {} ({}).", ctx.who, total, rolls.join(" + "))
+            };
+            ctx.reply(&text);
+            Ok(())
+        },
+    ));
+
+    bot.add(Command::new(
+        "remind",
+        "<delay> <text>...",
+        "Send you the text privately after the delay (like 90s, 15m or 2h).",
+        |ctx, args| {
+            let delay = match parse_delay(args[0]) {
+                Some(d) if d <= MAX_REMINDER => d,
+                _ => {
+                    return Err(String::from(
+                        "Give the delay like 90s, 15m or 2h, up to 24h.",
+                    ))
+                }
+            };
+            ctx.later(
+                delay,
+                Reply::Priv {
+                    who: ctx.who.to_string(),
+                    text: format!("Reminder: {}", args[1..].join(" ")),
+                },
+            );
+            ctx.reply(&format!("OK, I'll remind you in {}.", args[0]));
+            Ok(())
+        },
+    ));
+
+    if let Err(e) = bot.run() {
+        error!("{}", e);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,187 @@
+use common::{
+    client::{Connection, Event},
+    codec::{self, Json},
+    config::ClientConfig,
+    proto::{cows, Msg},
+    quota::Quota,
+    util::collapse,
+};
+use log::debug;
+use std::io::BufRead;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::time::{Duration, Instant};
+
+/// How long to wait for the server to confirm we've joined the room, or to
+/// acknowledge our logging out.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Read lines from stdin on another thread, so the main loop can keep talking
+/// to the server while it waits for them. The channel closes at end of input.
+fn read_stdin() -> Receiver<String> {
+    let (tx, rx) = mpsc::channel();
+    std::thread::spawn(move || {
+        for line in std::io::stdin().lock().lines().map_while(Result::ok) {
+            if tx.send(line).is_err() {
+                break;
+            }
+        }
+    });
+    rx
+}
+
+/// Print an `Event` as plain text, or as a JSON object, one to a line. Errors
+/// go to stderr in plain text mode.
+fn print(event: &Event, json: bool) {
+    if json {
+        match serde_json::to_string(event) {
+            Ok(s) => println!("{}", s),
+            Err(e) => eprintln!("Error encoding {:?}: {}", event, e),
+        }
+        return;
+    }
+
+    match event {
+        Event::Text { .. } => println!("{}", event),
+        Event::Priv { who, text } => println!("$ {}: {}", who, text),
+        Event::PrivEcho { to, text } => println!("$ You @ {}: {}", to, text),
+        Event::Error { text } => eprintln!("# {}", text),
+        Event::Roster { .. } => {}
+        _ => println!("* {}", event),
+    }
+}
+
+/// The encoded `Text` of as many of the first `lines` as come to no more than
+/// `limit` bytes (but at least one), and how many that is.
+fn fitting_lines(conn: &Connection, lines: &[String], limit: usize) -> (usize, Vec<u8>) {
+    let encode = |n: usize| {
+        conn.encode(&Msg::Text {
+            who: "".into(),
+            lines: cows(&lines[..n]),
+        })
+    };
+    let all = encode(lines.len());
+    if all.len() <= limit || lines.len() == 1 {
+        return (lines.len(), all);
+    }
+    // Find the most that fit by bisection, as messages only grow with more
+    // lines.
+    let (mut fit, mut over) = (1, lines.len());
+    while over - fit > 1 {
+        let mid = (fit + over) / 2;
+        if encode(mid).len() <= limit {
+            fit = mid;
+        } else {
+            over = mid;
+        }
+    }
+    (fit, encode(fit))
+}
+
+/// Talk to the server without a terminal: join `room` (if given), send what
+/// comes in on stdin as `Text`, print what the server sends, and log out at
+/// the end of the input. Messages are held back to keep within the server's
+/// byte quota (at its default settings), rather than sent only to be ignored.
+/// Returns the process's exit code.
+pub fn run(cfg: &ClientConfig, room: Option<&str>, json: bool) -> i32 {
+    match run_loop(cfg, room, json) {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("{}", e);
+            1
+        }
+    }
+}
+
+fn run_loop(cfg: &ClientConfig, room: Option<&str>, json: bool) -> Result<(), String> {
+    let codec = codec::by_name(&cfg.codec).unwrap_or(&Json);
+    let mut conn = Connection::connect_with(&cfg.address, &cfg.name, codec, &cfg.compression)
+        .map_err(|e| e.to_string())?;
+    conn.set_read_buffer_size(cfg.read_size);
+
+    // The server counts our `Join` against the quota too.
+    let mut quota = Quota::default();
+    if let Some(room) = room {
+        quota.try_spend(conn.encode(&Msg::Join(room.into())).len());
+        conn.join(room);
+    }
+
+    let started = Instant::now();
+    let input = read_stdin();
+    let mut joined = room.is_none();
+    let mut input_done = false;
+    let mut pending: Vec<String> = Vec::new();
+    let mut logged_out: Option<Instant> = None;
+
+    loop {
+        let loop_timer = Instant::now();
+
+        while let Some(event) = conn.poll().map_err(|e| e.to_string())? {
+            debug!("batch: received {:?}", &event);
+            print(&event, json);
+            match event {
+                Event::LoggedOut { .. } => return Ok(()),
+                Event::OverQuota { .. } => quota.exhaust(),
+                Event::Error { text } if !joined => {
+                    return Err(format!(
+                        "Could not join {}: {}",
+                        room.unwrap_or_default(),
+                        text
+                    ));
+                }
+                Event::Joined {
+                    user,
+                    room: joined_room,
+                } if user == conn.name() => {
+                    joined |= matches!(room, Some(r) if collapse(r) == collapse(&joined_room));
+                }
+                _ => {}
+            }
+        }
+
+        if !joined && started.elapsed() > REPLY_TIMEOUT {
+            return Err(format!(
+                "Timed out waiting to join {}.",
+                room.unwrap_or_default()
+            ));
+        }
+
+        if joined && !input_done {
+            loop {
+                match input.try_recv() {
+                    Ok(line) => pending.push(line),
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => {
+                        input_done = true;
+                        break;
+                    }
+                }
+            }
+        }
+
+        // Whatever has piled up while waiting on the quota goes in as few
+        // messages as it can without any being over the quota on its own.
+        if pending.iter().all(|l| l.trim().is_empty()) {
+            pending.clear();
+        } else {
+            let (n, bytes) = fitting_lines(&conn, &pending, quota.limit());
+            if quota.try_spend(bytes.len()) {
+                conn.send_bytes(&bytes);
+                pending.drain(..n);
+            }
+        }
+
+        match logged_out {
+            None if input_done && pending.is_empty() && conn.is_flushed() => {
+                conn.logout("");
+                logged_out = Some(Instant::now());
+            }
+            Some(t) if t.elapsed() > REPLY_TIMEOUT => return Ok(()),
+            _ => {}
+        }
+
+        let loop_time = loop_timer.elapsed();
+        if loop_time < cfg.tick {
+            std::thread::sleep(cfg.tick - loop_time);
+        }
+    }
+}
@@ -0,0 +1,118 @@
+use crate::message::{COMMANDS, OP_COMMANDS};
+use common::util::collapse;
+
+/// An in-progress Tab completion: the candidates for the word being completed
+/// and which of them is currently in the input line.
+pub struct Completion {
+    start: usize,
+    inserted_len: usize,
+    candidates: Vec<String>,
+    index: usize,
+}
+
+impl Completion {
+    /// The text to put in the input line at `start()` in place of the
+    /// `inserted_len()` characters already there.
+    pub fn current(&self) -> &str {
+        &self.candidates[self.index]
+    }
+
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    pub fn inserted_len(&self) -> usize {
+        self.inserted_len
+    }
+
+    /// Move on to the next (or, if `forward` is false, previous) candidate.
+    pub fn cycle(&mut self, forward: bool) {
+        self.inserted_len = self.current().chars().count();
+        let n = self.candidates.len();
+        self.index = if forward {
+            (self.index + 1) % n
+        } else {
+            (self.index + n - 1) % n
+        };
+    }
+}
+
+/// Candidates from `names` whose collapsed form starts with the collapsed
+/// `prefix`, the same way the server matches names.
+fn matching<T: AsRef<str>>(prefix: &str, names: &[T]) -> Vec<String> {
+    let prefix = collapse(prefix);
+    let mut matches: Vec<String> = names
+        .iter()
+        .map(AsRef::as_ref)
+        .filter(|name| collapse(name).starts_with(&prefix))
+        .map(String::from)
+        .collect();
+    matches.sort_by_key(|name| collapse(name));
+    matches.dedup();
+    matches
+}
+
+/// Work out what the word before `cursor` in `input` could be completed to:
+/// a command name right after `cmd`, an operator subcommand after `op`, a room
+/// name (from the last `rooms` query) after `join` or `rooms`, and otherwise a
+/// name from the `roster`.
+pub fn complete(
+    input: &[char],
+    cursor: usize,
+    cmd: char,
+    roster: &[String],
+    rooms: &[String],
+) -> Option<Completion> {
+    if cursor == 0 {
+        return None;
+    }
+    let start = input[..cursor]
+        .iter()
+        .rposition(|c| c.is_whitespace())
+        .map_or(0, |n| n + 1);
+    let word: String = input[start..cursor].iter().collect();
+    let preceding: String = input[..start].iter().collect();
+    let preceding: Vec<&str> = preceding.split_whitespace().collect();
+    let is_command = input.first() == Some(&cmd);
+
+    let candidates: Vec<String> = if is_command && start == 0 {
+        matching(&word[cmd.len_utf8()..], COMMANDS)
+            .into_iter()
+            .map(|c| format!("{}{} ", cmd, c))
+            .collect()
+    } else if is_command {
+        // Command arguments are split on whitespace, so names go in collapsed;
+        // the server matches them that way anyway.
+        let command = preceding[0][cmd.len_utf8()..].to_lowercase();
+        let names = match (command.as_str(), preceding.len()) {
+            ("op", 1) => matching(&word, OP_COMMANDS),
+            ("join", 1) | ("rooms", 1) => matching(&word, rooms),
+            _ => matching(&word, roster),
+        };
+        names
+            .into_iter()
+            .map(|n| format!("{} ", collapse(&n)))
+            .collect()
+    } else if start == 0 {
+        matching(&word, roster)
+            .into_iter()
+            .map(|n| format!("{}: ", n))
+            .collect()
+    } else {
+        matching(&word, roster)
+            .into_iter()
+            .map(|n| format!("{} ", n))
+            .collect()
+    };
+
+    if candidates.is_empty() {
+        return None;
+    }
+
+    Some(Completion {
+        start,
+        inserted_len: cursor - start,
+        candidates,
+        index: 0,
+    })
+}
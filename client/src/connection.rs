@@ -1,6 +1,47 @@
-use crate::input::Mode;
-use common::{config::ClientConfig, proto::Sndr, socket::Socket};
-use std::net::TcpStream;
+use crate::{
+    completion::Completion, history::History, input::Mode, keys::Keymap, transcript::Transcript,
+    vi::Vi,
+};
+use common::{
+    client,
+    codec::{self, Json},
+    config::ClientConfig,
+    proto::Msg,
+    socket::Socket,
+    util::collapse,
+};
+use std::time::{Duration, Instant};
+
+const MIN_RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+
+/// Tracks when the next reconnection attempt is due, doubling the wait after
+/// every failed attempt.
+pub struct Backoff {
+    delay: Duration,
+    next_attempt: Instant,
+}
+
+impl Backoff {
+    pub fn new() -> Backoff {
+        Backoff {
+            delay: MIN_RECONNECT_DELAY,
+            next_attempt: Instant::now() + MIN_RECONNECT_DELAY,
+        }
+    }
+
+    pub fn is_due(&self) -> bool {
+        Instant::now() >= self.next_attempt
+    }
+
+    /// Schedule the next attempt after a failed one, returning how long it
+    /// will be until then.
+    pub fn fail(&mut self) -> Duration {
+        self.delay = (self.delay * 2).min(MAX_RECONNECT_DELAY);
+        self.next_attempt = Instant::now() + self.delay;
+        self.delay
+    }
+}
 
 pub struct State {
     pub username: String,
@@ -12,37 +53,58 @@ pub struct State {
     pub socket: Socket,
     pub cmd: char,
     pub running: bool,
+    pub resume_token: Option<String>,
+    pub reconnect: Option<Backoff>,
+    pub rejoin: Option<String>,
+    pub history: History,
+    pub roster: Vec<String>,
+    pub rooms: Vec<String>,
+    pub completion: Option<Completion>,
+    pub keymap: Keymap,
+    pub vi: Vi,
+    pub raw_text: bool,
+    pub find_query: String,
+    pub find_backward: bool,
+    pub transcript: Transcript,
 }
 
 impl State {
-    pub fn enqueue(&mut self, msg: &Sndr) {
-        let bytes = msg.bytes();
-        self.socket.enqueue(&bytes);
+    pub fn enqueue(&mut self, msg: &Msg) {
+        self.socket.enqueue_msg(msg);
     }
 
-    pub fn enqueue_bytes(&mut self, bytes: &[u8]) {
-        self.socket.enqueue(bytes);
+    /// Log out with the given message, or just stop if there's currently no
+    /// connection to log out of.
+    pub fn quit(&mut self, message: &str) {
+        if self.reconnect.is_some() {
+            self.running = false;
+        } else {
+            self.enqueue(&Msg::Logout(message.into()));
+        }
     }
-}
-
-/// Attempt to connect to the server.
-pub fn connect(cfg: &ClientConfig) -> Result<Socket, String> {
-    let tcp_stream = TcpStream::connect(&cfg.address)
-        .map_err(|e| format!("Error connecting to {}: {}", cfg.address, e))?;
 
-    let mut socket =
-        Socket::new(tcp_stream).map_err(|e| format!("Error setting up socket: {}", e))?;
-
-    let bytes = Sndr::Name(&cfg.name).bytes();
-    let res = socket.blocking_send(&bytes, cfg.tick);
-
-    if let Err(e) = res {
-        let shutdown_err = format!("Error in initial protocol: {}", e);
-        socket
-            .shutdown()
-            .map_err(|ee| format!("{}; error during shutdown: {}", shutdown_err, ee))?;
-        return Err(shutdown_err);
+    /// Called when the server tells us which room we've joined; if that isn't
+    /// the room we were in before reconnecting, ask to go back there.
+    pub fn rejoin_if_needed(&mut self, joined_room: &str) {
+        if let Some(room) = self.rejoin.take() {
+            if collapse(&room) != collapse(joined_room) {
+                self.enqueue(&Msg::Join(room.as_str().into()));
+            }
+        }
     }
+}
 
-    Ok(socket)
+/// Attempt to connect to the server, introducing ourselves as `name`. If a
+/// resume `token` from a previous connection is supplied, the server will try
+/// to restore that session.
+pub fn connect(cfg: &ClientConfig, name: &str, token: Option<&str>) -> Result<Socket, String> {
+    let hello = match token {
+        Some(token) => Msg::Resume {
+            name: name.into(),
+            token: token.into(),
+        },
+        None => Msg::Name(name.into()),
+    };
+    let codec = codec::by_name(&cfg.codec).unwrap_or(&Json);
+    client::handshake(&cfg.address, &hello, codec, &cfg.compression).map_err(|e| e.to_string())
 }
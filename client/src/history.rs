@@ -0,0 +1,187 @@
+use log::warn;
+use std::path::PathBuf;
+
+/// Previously-entered input lines, oldest first, with a cursor for recalling
+/// them one at a time and a query for incremental reverse search.
+pub struct History {
+    entries: Vec<Vec<char>>,
+    max_entries: usize,
+    path: Option<PathBuf>,
+    position: Option<usize>,
+    draft: Vec<char>,
+    query: String,
+}
+
+/// History files hold one entry per line, so backslashes and newlines inside
+/// entries get escaped.
+fn escape(entry: &[char]) -> String {
+    let mut s = String::with_capacity(entry.len());
+    for c in entry.iter() {
+        match c {
+            '\\' => s.push_str("\\\\"),
+            '\n' => s.push_str("\\n"),
+            c => s.push(*c),
+        }
+    }
+    s
+}
+
+fn unescape(line: &str) -> Vec<char> {
+    let mut v = Vec::with_capacity(line.len());
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some('n') => v.push('\n'),
+                Some(x) => v.push(x),
+                None => v.push('\\'),
+            }
+        } else {
+            v.push(c);
+        }
+    }
+    v
+}
+
+impl History {
+    /// Create a history holding at most `max_entries` lines. If a `path` is
+    /// given, existing entries are loaded from it and new ones saved to it.
+    pub fn new(max_entries: usize, path: Option<PathBuf>) -> History {
+        let mut entries: Vec<Vec<char>> = match &path {
+            None => Vec::new(),
+            Some(p) => match std::fs::read_to_string(p) {
+                Ok(s) => s.lines().map(unescape).collect(),
+                Err(_) => Vec::new(),
+            },
+        };
+        if entries.len() > max_entries {
+            entries.drain(0..(entries.len() - max_entries));
+        }
+
+        History {
+            entries,
+            max_entries,
+            path,
+            position: None,
+            draft: Vec::new(),
+            query: String::new(),
+        }
+    }
+
+    fn save(&self) {
+        if let Some(p) = &self.path {
+            let mut contents = String::new();
+            for entry in self.entries.iter() {
+                contents.push_str(&escape(entry));
+                contents.push('\n');
+            }
+            if let Err(e) = std::fs::write(p, contents) {
+                warn!("Error writing history file {}: {}", p.display(), e);
+            }
+        }
+    }
+
+    /// Record a line of input and stop browsing. Blank lines and immediate
+    /// repeats aren't recorded.
+    pub fn push(&mut self, entry: &[char]) {
+        self.reset();
+        if entry.iter().all(|c| c.is_whitespace())
+            || self.entries.last().map(Vec::as_slice) == Some(entry)
+        {
+            return;
+        }
+        self.entries.push(entry.to_vec());
+        if self.entries.len() > self.max_entries {
+            self.entries.remove(0);
+        }
+        self.save();
+    }
+
+    /// Stop browsing; the next recall starts again from the newest entry.
+    pub fn reset(&mut self) {
+        self.position = None;
+        self.draft.clear();
+        self.query.clear();
+    }
+
+    /// The line being typed before browsing started.
+    pub fn draft(&self) -> &[char] {
+        &self.draft
+    }
+
+    /// Step back to the next-older entry. `current` is what's in the input
+    /// line, which is kept as the draft when browsing starts.
+    pub fn older(&mut self, current: &[char]) -> Option<&[char]> {
+        let n = match self.position {
+            None if self.entries.is_empty() => return None,
+            None => {
+                self.draft = current.to_vec();
+                self.entries.len() - 1
+            }
+            Some(0) => return None,
+            Some(n) => n - 1,
+        };
+        self.position = Some(n);
+        Some(&self.entries[n])
+    }
+
+    /// Step forward to the next-newer entry, ending up back at the draft.
+    pub fn newer(&mut self) -> Option<&[char]> {
+        match self.position {
+            None => None,
+            Some(n) if n + 1 >= self.entries.len() => {
+                self.position = None;
+                Some(&self.draft)
+            }
+            Some(n) => {
+                self.position = Some(n + 1);
+                Some(&self.entries[n + 1])
+            }
+        }
+    }
+
+    /// The current reverse-search query.
+    pub fn query(&self) -> &str {
+        &self.query
+    }
+
+    /// Begin a reverse search, keeping `current` as the draft to restore if
+    /// the search is cancelled.
+    pub fn start_search(&mut self, current: &[char]) {
+        self.reset();
+        self.draft = current.to_vec();
+    }
+
+    /// Add to (or, with `None`, remove the last character of) the search
+    /// query, and find the newest entry at or before the current match that
+    /// still contains it.
+    pub fn edit_query(&mut self, ch: Option<char>) -> Option<&[char]> {
+        match ch {
+            Some(c) => self.query.push(c),
+            None => {
+                self.query.pop();
+                self.position = None;
+            }
+        }
+        let start = self.position.map_or(self.entries.len(), |n| n + 1);
+        self.search_before(start)
+    }
+
+    /// Find the next-older entry that contains the search query.
+    pub fn search_older(&mut self) -> Option<&[char]> {
+        let start = self.position.unwrap_or(self.entries.len());
+        self.search_before(start)
+    }
+
+    fn search_before(&mut self, end: usize) -> Option<&[char]> {
+        if self.query.is_empty() {
+            return None;
+        }
+        let query: Vec<char> = self.query.chars().collect();
+        let found = self.entries[..end]
+            .iter()
+            .rposition(|e| e.windows(query.len()).any(|w| w == query.as_slice()))?;
+        self.position = Some(found);
+        Some(&self.entries[found])
+    }
+}
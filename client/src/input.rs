@@ -1,123 +1,377 @@
 use crate::{
+    completion::complete,
     connection::State,
+    keys::Action,
     line::Line,
     message::respond_to_user_input,
     screen::Screen,
-    util::styles::{DIM, HIGHLIGHT},
+    util::{
+        styles::{DIM, HIGHLIGHT},
+        text::prev_boundary,
+    },
+    vi::{Buffer, InsertAt, Motion, Next, Operator, Vi},
+};
+use crossterm::{
+    event,
+    event::{Event, KeyCode, MouseButton, MouseEvent, MouseEventKind},
 };
-use common::proto::Sndr;
-use crossterm::{event, event::Event, event::KeyCode};
 use log::trace;
 use std::time::Duration;
 
+/// How many lines each notch of the mouse wheel scrolls.
+const WHEEL_LINES: i16 = 3;
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Mode {
     Insert,
     Command,
-    Delete,
+    Operator(Operator),
+    Visual,
+    Search,
+    Find,
 }
 
-fn command_key(event: event::KeyEvent, screen: &mut Screen, state: &mut State) {
-    match state.mode {
-        Mode::Command => match event.code {
-            KeyCode::Char(' ') | KeyCode::Enter => state.mode = Mode::Insert,
-
-            KeyCode::Up | KeyCode::Char('k') | KeyCode::Char('K') => {
-                if event.modifiers.contains(event::KeyModifiers::SHIFT) {
-                    screen.scroll_roster(-1);
-                } else {
-                    screen.scroll_lines(1);
-                }
-            }
-            KeyCode::Down | KeyCode::Char('j') | KeyCode::Char('J') => {
-                if event.modifiers.contains(event::KeyModifiers::SHIFT) {
-                    screen.scroll_roster(1);
-                } else {
-                    screen.scroll_lines(-1);
-                }
-            }
+/// The vi motion a cursor-movement action stands for outside insert mode.
+fn motion(action: Action) -> Option<Motion> {
+    match action {
+        Action::CursorLeft => Some(Motion::Left),
+        Action::CursorRight => Some(Motion::Right),
+        Action::WordForward => Some(Motion::WordForward),
+        Action::WordBack => Some(Motion::WordBack),
+        Action::LineStart => Some(Motion::LineStart),
+        Action::LineEnd => Some(Motion::LineEnd),
+        _ => None,
+    }
+}
 
-            KeyCode::Left | KeyCode::Char('h') => screen.input_skip_chars(-1),
-            KeyCode::Right | KeyCode::Char('l') => screen.input_skip_chars(1),
+/// Run the vi engine on a copy of the input line, then put the result back
+/// and switch to whichever mode the engine leaves us in.
+fn vi_edit<F>(screen: &mut Screen, state: &mut State, f: F)
+where
+    F: FnOnce(&mut Vi, &mut Buffer) -> Next,
+{
+    let mut buf = Buffer {
+        text: screen.get_input().to_vec(),
+        cursor: screen.get_input_cursor(),
+    };
+    let next = f(&mut state.vi, &mut buf);
+    screen.set_input_selection(state.vi.selection(buf.cursor));
+    screen.replace_input(buf.text, buf.cursor);
+    state.mode = match next {
+        Next::Normal => Mode::Command,
+        Next::Pending(op) => Mode::Operator(op),
+        Next::Insert => Mode::Insert,
+        Next::Visual => Mode::Visual,
+    };
+}
 
-            KeyCode::Char('w') => screen.input_skip_words(1),
-            KeyCode::Char('b') => screen.input_skip_words(-1),
+/// Go to command mode, finishing whatever change insert mode was part of.
+fn leave_insert(screen: &mut Screen, state: &mut State) {
+    if state.mode == Mode::Insert {
+        vi_edit(screen, state, |vi, buf| vi.end_insert(buf));
+    } else {
+        vi_edit(screen, state, |vi, _| vi.cancel());
+    }
+}
 
-            KeyCode::Char('0') => {
-                let delta = screen.get_input_length() as i16;
-                screen.input_skip_chars(-delta);
-            }
-            KeyCode::Char('$') => {
-                let delta = screen.get_input_length() as i16;
-                screen.input_skip_chars(delta);
-            }
+/// Carry out a bound `Action`. Those that only make sense in one mode are
+/// still allowed in the other, so users can bind keys however they like.
+fn perform(action: Action, screen: &mut Screen, state: &mut State) {
+    if state.mode != Mode::Insert {
+        if let Some(m) = motion(action) {
+            vi_edit(screen, state, |vi, buf| vi.motion(m, buf));
+            return;
+        }
+    }
 
-            KeyCode::Char('a') => {
+    match action {
+        Action::InsertMode | Action::Append if state.mode == Mode::Insert => {
+            if action == Action::Append {
                 screen.input_skip_chars(1);
-                state.mode = Mode::Insert;
             }
-            KeyCode::Char('i') => state.mode = Mode::Insert,
+        }
+        Action::InsertMode => vi_edit(screen, state, |vi, buf| vi.insert(InsertAt::Cursor, buf)),
+        Action::Append => vi_edit(screen, state, |vi, buf| {
+            vi.insert(InsertAt::AfterCursor, buf)
+        }),
+        Action::CommandMode => leave_insert(screen, state),
+        Action::DeleteMode => vi_edit(screen, state, |vi, buf| vi.operator(Operator::Delete, buf)),
+        Action::Send => {
+            state.vi.clear();
+            let input = screen.pop_input();
+            state.history.push(&input);
+            respond_to_user_input(input, screen, state);
+        }
+        Action::InsertNewline => screen.input_char('\n'),
+        Action::Quit => state.quit("[ client quit ]"),
 
-            KeyCode::PageUp => {
-                let jump = (screen.get_main_height() as i16) - 1;
-                screen.scroll_lines(jump);
-            }
-            KeyCode::PageDown => {
-                let jump = 1 - (screen.get_main_height() as i16);
-                screen.scroll_lines(jump);
-            }
-            KeyCode::Char('q') => state.enqueue(&Sndr::Logout("[ client quit ]")),
-            KeyCode::Char('d') => state.mode = Mode::Delete,
-            _ => {}
-        },
-        Mode::Delete => match event.code {
-            // KeyCode::Char('h') => {
-            //     screen.input_skip_chars(-1);
-            //     // screen.delete_char();
-            //     state.mode = Mode::Command;
-            // }
-            // KeyCode::Char('l') => {
-            //     screen.input_skip_chars(1);
-            //     // scrn.delete_char();
-            //     state.mode = Mode::Command;
-            // }
-            KeyCode::Char('d') => {
-                screen.pop_input();
-                state.mode = Mode::Command;
-            }
-            KeyCode::Char('w') => {
-                screen.input_delete_words(1);
-                state.mode = Mode::Command;
+        Action::CursorLeft => screen.input_skip_chars(-1),
+        Action::CursorRight => screen.input_skip_chars(1),
+        Action::WordForward => screen.input_skip_words(1),
+        Action::WordBack => screen.input_skip_words(-1),
+        Action::LineStart => screen.input_to_start(),
+        Action::LineEnd => screen.input_to_end(),
+
+        Action::Backspace => screen.input_backspace(),
+        Action::BackspaceToCommand => {
+            if screen.get_input_length() == 0 {
+                leave_insert(screen, state);
+            } else {
+                screen.input_backspace();
             }
-            KeyCode::Char('b') => {
-                screen.input_delete_words(-1);
-                state.mode = Mode::Command;
+        }
+        Action::DeleteChar => screen.input_delete_char(),
+        Action::DeleteWord => screen.input_delete_words(1),
+        Action::DeleteWordBack => screen.input_delete_words(-1),
+        Action::ClearLine => {
+            screen.pop_input();
+        }
+
+        Action::HistoryPrev => history_older(screen, state),
+        Action::HistoryNext => history_newer(screen, state),
+        Action::HistoryOrScrollUp => {
+            if screen.is_scrolled_back() {
+                screen.scroll_lines(1);
+            } else {
+                history_older(screen, state);
             }
-            _ => {
-                state.mode = Mode::Command;
+        }
+        Action::HistoryOrScrollDown => {
+            if screen.is_scrolled_back() {
+                screen.scroll_lines(-1);
+            } else {
+                history_newer(screen, state);
             }
-        },
-        _ => {}
+        }
+        Action::ReverseSearch => {
+            state.history.start_search(screen.get_input());
+            state.mode = Mode::Search;
+        }
+        Action::Complete => tab_complete(screen, state, true),
+        Action::CompleteBack => tab_complete(screen, state, false),
+
+        Action::ScrollUp => screen.scroll_lines(1),
+        Action::ScrollDown => screen.scroll_lines(-1),
+        Action::PageUp => {
+            let jump = (screen.get_main_height() as i16) - 1;
+            screen.scroll_lines(jump);
+        }
+        Action::PageDown => {
+            let jump = 1 - (screen.get_main_height() as i16);
+            screen.scroll_lines(jump);
+        }
+        Action::RosterUp => screen.scroll_roster(-1),
+        Action::RosterDown => screen.scroll_roster(1),
+
+        Action::SearchForward | Action::SearchBackward => {
+            state.find_query.clear();
+            state.find_backward = action == Action::SearchBackward;
+            state.mode = Mode::Find;
+        }
+        Action::SearchNext => find_next(screen, state, state.find_backward),
+        Action::SearchPrev => find_next(screen, state, !state.find_backward),
     }
 }
 
-fn input_key(event: event::KeyEvent, screen: &mut Screen, state: &mut State) {
+/// Handle keys in command, operator-pending and visual modes. Bound keys
+/// perform their actions (with cursor movements acting as vi motions); other
+/// characters go to the vi engine, as does the character after `f` or `t`.
+fn command_key(event: event::KeyEvent, screen: &mut Screen, state: &mut State) {
+    let ch = match event.code {
+        KeyCode::Char(c) if (event.modifiers - event::KeyModifiers::SHIFT).is_empty() => Some(c),
+        _ => None,
+    };
+
+    if event.code == KeyCode::Esc {
+        screen.clear_scrollback_match();
+        vi_edit(screen, state, |vi, _| vi.cancel());
+    } else if let Some(c) = ch.filter(|c| state.vi.wants_char(*c)) {
+        vi_edit(screen, state, |vi, buf| vi.key(c, buf));
+    } else if let Some(action) = state.keymap.command(&event) {
+        if motion(action).is_none() && action != Action::DeleteMode {
+            vi_edit(screen, state, |vi, _| vi.cancel());
+        }
+        perform(action, screen, state);
+    } else if let Some(c) = ch {
+        vi_edit(screen, state, |vi, buf| vi.key(c, buf));
+    }
+}
+
+fn history_older(screen: &mut Screen, state: &mut State) {
+    if let Some(entry) = state.history.older(screen.get_input()) {
+        screen.set_input(entry);
+    }
+}
+
+fn history_newer(screen: &mut Screen, state: &mut State) {
+    if let Some(entry) = state.history.newer() {
+        screen.set_input(entry);
+    }
+}
+
+/// Handle keys while incrementally searching backward through the input
+/// history; the input line shows the current match.
+fn search_key(event: event::KeyEvent, screen: &mut Screen, state: &mut State) {
+    let ctrl = event.modifiers.contains(event::KeyModifiers::CONTROL);
+    let found = match event.code {
+        KeyCode::Char('r') if ctrl => state.history.search_older(),
+        KeyCode::Char('g') if ctrl => {
+            screen.set_input(state.history.draft());
+            state.history.reset();
+            state.mode = Mode::Insert;
+            return;
+        }
+        KeyCode::Esc => {
+            screen.set_input(state.history.draft());
+            state.history.reset();
+            state.mode = Mode::Insert;
+            return;
+        }
+        KeyCode::Char(c) => state.history.edit_query(Some(c)),
+        KeyCode::Backspace => state.history.edit_query(None),
+        _ => {
+            state.history.reset();
+            state.mode = Mode::Insert;
+            return;
+        }
+    };
+    if let Some(entry) = found {
+        screen.set_input(entry);
+    }
+    write_mode_line(screen, state);
+}
+
+/// Jump to the next match for the scrollback search query, in the given
+/// direction, or say so in the mode line if there isn't one.
+fn find_next(screen: &mut Screen, state: &mut State, backward: bool) {
+    if state.find_query.is_empty() {
+        return;
+    }
+    if !screen.find_in_scrollback(&state.find_query, backward) {
+        let mut notice = Line::default();
+        notice.pushf("Com", &HIGHLIGHT);
+        notice.pushf(" │ ", &DIM);
+        notice.push("no match for ");
+        notice.pushf(&state.find_query, &HIGHLIGHT);
+        screen.set_stat_ll(notice);
+    }
+}
+
+/// Handle keys while typing a scrollback search query into the mode line.
+fn find_key(event: event::KeyEvent, screen: &mut Screen, state: &mut State) {
+    let mods = event.modifiers - event::KeyModifiers::SHIFT;
     match event.code {
-        KeyCode::Enter => respond_to_user_input(screen.pop_input(), screen, state),
+        KeyCode::Char(c) if mods.is_empty() => state.find_query.push(c),
+        KeyCode::Backspace if state.find_query.is_empty() => state.mode = Mode::Command,
         KeyCode::Backspace => {
-            if screen.get_input_length() == 0 {
-                state.mode = Mode::Command;
-            } else {
-                screen.input_backspace();
-            }
+            state.find_query.pop();
+        }
+        KeyCode::Enter => {
+            state.mode = Mode::Command;
+            write_mode_line(screen, state);
+            find_next(screen, state, state.find_backward);
+            return;
         }
-        KeyCode::Left => screen.input_skip_chars(-1),
-        KeyCode::Right => screen.input_skip_chars(1),
         KeyCode::Esc => {
+            state.find_query.clear();
             state.mode = Mode::Command;
         }
-        KeyCode::Char(c) => {
-            screen.input_char(c);
+        _ => {}
+    }
+    write_mode_line(screen, state);
+}
+
+/// Complete the word before the cursor, or, if the last key was also Tab,
+/// swap in the next (or with Shift-Tab, previous) candidate.
+fn tab_complete(screen: &mut Screen, state: &mut State, forward: bool) {
+    if let Some(completion) = state.completion.as_mut() {
+        completion.cycle(forward);
+    } else {
+        state.completion = complete(
+            screen.get_input(),
+            screen.get_input_cursor(),
+            state.cmd,
+            &state.roster,
+            &state.rooms,
+        );
+    }
+
+    if let Some(completion) = &state.completion {
+        screen.input_replace(
+            completion.start(),
+            completion.inserted_len(),
+            completion.current(),
+        );
+    }
+}
+
+fn input_key(event: event::KeyEvent, screen: &mut Screen, state: &mut State) {
+    let action = state.keymap.insert(&event);
+    if !matches!(action, Some(Action::Complete | Action::CompleteBack)) {
+        state.completion = None;
+    }
+
+    match action {
+        Some(action) => perform(action, screen, state),
+        None => {
+            if let KeyCode::Char(c) = event.code {
+                let mods = event.modifiers - event::KeyModifiers::SHIFT;
+                if mods.is_empty() {
+                    screen.input_char(c);
+                }
+            }
+        }
+    }
+}
+
+/// Put pasted text into the input at the cursor, in one piece, so a multi-line
+/// paste is sent as one multi-line message.
+fn paste(text: &str, screen: &mut Screen, state: &mut State) {
+    if matches!(state.mode, Mode::Search | Mode::Find) {
+        return;
+    }
+    let text = text
+        .replace("\r\n", "\n")
+        .replace('\r', "\n")
+        .replace('\t', "    ");
+    screen.input_str(&text);
+}
+
+/// Handle the mouse: the wheel scrolls whichever pane it's over, clicking a
+/// name in the roster starts a private message to them, and clicking in the
+/// input moves the cursor there.
+fn mouse(event: MouseEvent, screen: &mut Screen, state: &mut State) {
+    let (col, row) = (event.column, event.row);
+    match event.kind {
+        MouseEventKind::ScrollUp if screen.in_roster(col, row) => {
+            screen.scroll_roster(-WHEEL_LINES)
+        }
+        MouseEventKind::ScrollDown if screen.in_roster(col, row) => {
+            screen.scroll_roster(WHEEL_LINES)
+        }
+        MouseEventKind::ScrollUp => screen.scroll_lines(WHEEL_LINES),
+        MouseEventKind::ScrollDown => screen.scroll_lines(-WHEEL_LINES),
+        MouseEventKind::Down(MouseButton::Left) => {
+            if matches!(state.mode, Mode::Search | Mode::Find) {
+                return;
+            }
+            if let Some(name) = screen.roster_at(col, row).and_then(|i| state.roster.get(i)) {
+                let priv_line: Vec<char> = format!("{}priv {} ", state.cmd, name).chars().collect();
+                screen.set_input(&priv_line);
+                if state.mode != Mode::Insert {
+                    vi_edit(screen, state, |vi, buf| vi.insert(InsertAt::LineEnd, buf));
+                }
+            } else if let Some(mut index) = screen.input_index_at(col, row) {
+                if state.mode != Mode::Insert {
+                    vi_edit(screen, state, |vi, _| vi.cancel());
+                    let input = screen.get_input();
+                    if index >= input.len() {
+                        index = prev_boundary(input, input.len());
+                    }
+                }
+                let input = screen.get_input().to_vec();
+                screen.replace_input(input, index);
+            }
         }
         _ => {}
     }
@@ -130,15 +384,23 @@ pub fn process_user_typing(screen: &mut Screen, state: &mut State) -> crossterm:
     while event::poll(Duration::default())? {
         let prev_mode = state.mode;
 
-        if let Ok(Event::Key(event)) = event::read() {
-            trace!("event: {:?}", event);
+        match event::read()? {
+            Event::Key(event) => {
+                trace!("event: {:?}", event);
 
-            match state.mode {
-                Mode::Command | Mode::Delete => command_key(event, screen, state),
-                Mode::Insert => input_key(event, screen, state),
+                match state.mode {
+                    Mode::Command | Mode::Operator(_) | Mode::Visual => {
+                        command_key(event, screen, state)
+                    }
+                    Mode::Insert => input_key(event, screen, state),
+                    Mode::Search => search_key(event, screen, state),
+                    Mode::Find => find_key(event, screen, state),
+                }
             }
-        } else if let Ok(Event::Resize(w, h)) = event::read() {
-            screen.resize(w, h);
+            Event::Paste(text) => paste(&text, screen, state),
+            Event::Mouse(event) => mouse(event, screen, state),
+            Event::Resize(w, h) => screen.resize(w, h),
+            _ => {}
         }
 
         if prev_mode != state.mode {
@@ -156,10 +418,27 @@ pub fn write_mode_line(screen: &mut Screen, state: &State) {
     let mch: &str = match state.mode {
         Mode::Insert => "Ins",
         Mode::Command => "Com",
-        Mode::Delete => "Del",
+        Mode::Operator(Operator::Delete) => "Del",
+        Mode::Operator(Operator::Change) => "Chg",
+        Mode::Operator(Operator::Yank) => "Yank",
+        Mode::Visual => "Vis",
+        Mode::Search => "Srch",
+        Mode::Find => "Find",
     };
     mode_line.pushf(mch, &HIGHLIGHT);
     mode_line.pushf(" │ ", &DIM);
+    if state.mode == Mode::Search {
+        mode_line.push("reverse search: ");
+        mode_line.pushf(state.history.query(), &HIGHLIGHT);
+        screen.set_stat_ll(mode_line);
+        return;
+    }
+    if state.mode == Mode::Find {
+        mode_line.push(if state.find_backward { "?" } else { "/" });
+        mode_line.pushf(&state.find_query, &HIGHLIGHT);
+        screen.set_stat_ll(mode_line);
+        return;
+    }
     mode_line.pushf(&(state.username), &HIGHLIGHT);
     mode_line.push(" @ ");
     mode_line.pushf(&(state.local_address), &HIGHLIGHT);
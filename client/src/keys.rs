@@ -0,0 +1,322 @@
+use common::config::KeysConfig;
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use std::collections::{BTreeMap, HashMap};
+
+/// Something a key can be bound to do.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    InsertMode,
+    Append,
+    CommandMode,
+    DeleteMode,
+    Send,
+    InsertNewline,
+    Quit,
+    CursorLeft,
+    CursorRight,
+    WordForward,
+    WordBack,
+    LineStart,
+    LineEnd,
+    Backspace,
+    BackspaceToCommand,
+    DeleteChar,
+    DeleteWord,
+    DeleteWordBack,
+    ClearLine,
+    HistoryPrev,
+    HistoryNext,
+    HistoryOrScrollUp,
+    HistoryOrScrollDown,
+    ReverseSearch,
+    Complete,
+    CompleteBack,
+    ScrollUp,
+    ScrollDown,
+    PageUp,
+    PageDown,
+    RosterUp,
+    RosterDown,
+    SearchForward,
+    SearchBackward,
+    SearchNext,
+    SearchPrev,
+}
+
+/// The names actions go by in the config file.
+const ACTION_NAMES: &[(&str, Action)] = &[
+    ("insert_mode", Action::InsertMode),
+    ("append", Action::Append),
+    ("command_mode", Action::CommandMode),
+    ("delete_mode", Action::DeleteMode),
+    ("send", Action::Send),
+    ("newline", Action::InsertNewline),
+    ("quit", Action::Quit),
+    ("cursor_left", Action::CursorLeft),
+    ("cursor_right", Action::CursorRight),
+    ("word_forward", Action::WordForward),
+    ("word_back", Action::WordBack),
+    ("line_start", Action::LineStart),
+    ("line_end", Action::LineEnd),
+    ("backspace", Action::Backspace),
+    ("backspace_to_command", Action::BackspaceToCommand),
+    ("delete_char", Action::DeleteChar),
+    ("delete_word", Action::DeleteWord),
+    ("delete_word_back", Action::DeleteWordBack),
+    ("clear_line", Action::ClearLine),
+    ("history_prev", Action::HistoryPrev),
+    ("history_next", Action::HistoryNext),
+    ("history_or_scroll_up", Action::HistoryOrScrollUp),
+    ("history_or_scroll_down", Action::HistoryOrScrollDown),
+    ("reverse_search", Action::ReverseSearch),
+    ("complete", Action::Complete),
+    ("complete_back", Action::CompleteBack),
+    ("scroll_up", Action::ScrollUp),
+    ("scroll_down", Action::ScrollDown),
+    ("page_up", Action::PageUp),
+    ("page_down", Action::PageDown),
+    ("roster_up", Action::RosterUp),
+    ("roster_down", Action::RosterDown),
+    ("search_forward", Action::SearchForward),
+    ("search_backward", Action::SearchBackward),
+    ("search_next", Action::SearchNext),
+    ("search_prev", Action::SearchPrev),
+];
+
+/// Binding a chord to this instead of an action removes it from the preset.
+const UNBOUND: &str = "none";
+
+const VI_INSERT: &[(&str, &str)] = &[
+    ("enter", "send"),
+    ("shift-enter", "newline"),
+    ("alt-enter", "newline"),
+    ("backspace", "backspace_to_command"),
+    ("left", "cursor_left"),
+    ("right", "cursor_right"),
+    ("up", "history_prev"),
+    ("down", "history_next"),
+    ("esc", "command_mode"),
+    ("tab", "complete"),
+    ("backtab", "complete_back"),
+    ("ctrl-r", "reverse_search"),
+];
+
+const VI_COMMAND: &[(&str, &str)] = &[
+    ("space", "insert_mode"),
+    ("enter", "insert_mode"),
+    ("i", "insert_mode"),
+    ("a", "append"),
+    ("d", "delete_mode"),
+    ("q", "quit"),
+    ("up", "scroll_up"),
+    ("down", "scroll_down"),
+    ("k", "history_or_scroll_up"),
+    ("j", "history_or_scroll_down"),
+    ("K", "roster_up"),
+    ("J", "roster_down"),
+    ("shift-up", "roster_up"),
+    ("shift-down", "roster_down"),
+    ("left", "cursor_left"),
+    ("h", "cursor_left"),
+    ("right", "cursor_right"),
+    ("l", "cursor_right"),
+    ("w", "word_forward"),
+    ("b", "word_back"),
+    ("0", "line_start"),
+    ("$", "line_end"),
+    ("pageup", "page_up"),
+    ("pagedown", "page_down"),
+    ("/", "search_forward"),
+    ("?", "search_backward"),
+    ("n", "search_next"),
+    ("N", "search_prev"),
+];
+
+const EMACS_INSERT: &[(&str, &str)] = &[
+    ("enter", "send"),
+    ("shift-enter", "newline"),
+    ("alt-enter", "newline"),
+    ("backspace", "backspace"),
+    ("delete", "delete_char"),
+    ("ctrl-d", "delete_char"),
+    ("left", "cursor_left"),
+    ("ctrl-b", "cursor_left"),
+    ("right", "cursor_right"),
+    ("ctrl-f", "cursor_right"),
+    ("alt-b", "word_back"),
+    ("alt-f", "word_forward"),
+    ("home", "line_start"),
+    ("ctrl-a", "line_start"),
+    ("end", "line_end"),
+    ("ctrl-e", "line_end"),
+    ("ctrl-w", "delete_word_back"),
+    ("alt-backspace", "delete_word_back"),
+    ("alt-d", "delete_word"),
+    ("ctrl-u", "clear_line"),
+    ("up", "history_prev"),
+    ("ctrl-p", "history_prev"),
+    ("down", "history_next"),
+    ("ctrl-n", "history_next"),
+    ("ctrl-r", "reverse_search"),
+    ("tab", "complete"),
+    ("backtab", "complete_back"),
+    ("pageup", "page_up"),
+    ("alt-v", "page_up"),
+    ("pagedown", "page_down"),
+    ("ctrl-v", "page_down"),
+    ("esc", "command_mode"),
+];
+
+/// A key together with the modifiers held while pressing it. For character
+/// keys, Shift is folded into the character itself.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+struct Chord {
+    code: KeyCode,
+    modifiers: KeyModifiers,
+}
+
+impl Chord {
+    fn new(code: KeyCode, modifiers: KeyModifiers) -> Chord {
+        match code {
+            KeyCode::Char(_) | KeyCode::BackTab => Chord {
+                code,
+                modifiers: modifiers - KeyModifiers::SHIFT,
+            },
+            _ => Chord { code, modifiers },
+        }
+    }
+}
+
+fn strip_prefix_ignore_case<'a>(s: &'a str, prefix: &str) -> Option<&'a str> {
+    match s.get(..prefix.len()) {
+        Some(head) if s.len() > prefix.len() && head.eq_ignore_ascii_case(prefix) => {
+            Some(&s[prefix.len()..])
+        }
+        _ => None,
+    }
+}
+
+/// Parse a chord like `"k"`, `"ctrl-a"`, `"alt-backspace"` or `"shift-up"`.
+fn parse_chord(s: &str) -> Result<Chord, String> {
+    let mut modifiers = KeyModifiers::NONE;
+    let mut rest = s;
+    loop {
+        if let Some(r) = strip_prefix_ignore_case(rest, "ctrl-") {
+            modifiers |= KeyModifiers::CONTROL;
+            rest = r;
+        } else if let Some(r) = strip_prefix_ignore_case(rest, "alt-") {
+            modifiers |= KeyModifiers::ALT;
+            rest = r;
+        } else if let Some(r) = strip_prefix_ignore_case(rest, "shift-") {
+            modifiers |= KeyModifiers::SHIFT;
+            rest = r;
+        } else {
+            break;
+        }
+    }
+
+    let mut chars = rest.chars();
+    let code = match (chars.next(), chars.next()) {
+        (Some(c), None) if modifiers.contains(KeyModifiers::SHIFT) => {
+            KeyCode::Char(c.to_ascii_uppercase())
+        }
+        (Some(c), None) => KeyCode::Char(c),
+        _ => match rest.to_lowercase().as_str() {
+            "space" => KeyCode::Char(' '),
+            "enter" | "return" => KeyCode::Enter,
+            "esc" | "escape" => KeyCode::Esc,
+            "tab" => KeyCode::Tab,
+            "backtab" => KeyCode::BackTab,
+            "backspace" => KeyCode::Backspace,
+            "delete" | "del" => KeyCode::Delete,
+            "insert" | "ins" => KeyCode::Insert,
+            "up" => KeyCode::Up,
+            "down" => KeyCode::Down,
+            "left" => KeyCode::Left,
+            "right" => KeyCode::Right,
+            "home" => KeyCode::Home,
+            "end" => KeyCode::End,
+            "pageup" => KeyCode::PageUp,
+            "pagedown" => KeyCode::PageDown,
+            name => match name.strip_prefix('f').and_then(|n| n.parse::<u8>().ok()) {
+                Some(n) if (1..=12).contains(&n) => KeyCode::F(n),
+                _ => return Err(format!("unrecognized key \"{}\"", s)),
+            },
+        },
+    };
+
+    Ok(Chord::new(code, modifiers))
+}
+
+fn parse_action(name: &str) -> Result<Action, String> {
+    ACTION_NAMES
+        .iter()
+        .find(|(n, _)| n.eq_ignore_ascii_case(name))
+        .map(|(_, a)| *a)
+        .ok_or_else(|| format!("unrecognized action \"{}\"", name))
+}
+
+/// Add `bindings` to `map`, replacing any existing binding for the same chord.
+fn bind<'a, I>(map: &mut HashMap<Chord, Action>, bindings: I) -> Result<(), String>
+where
+    I: IntoIterator<Item = (&'a str, &'a str)>,
+{
+    for (chord, action) in bindings {
+        let chord = parse_chord(chord)?;
+        if action.eq_ignore_ascii_case(UNBOUND) {
+            map.remove(&chord);
+        } else {
+            map.insert(chord, parse_action(action)?);
+        }
+    }
+    Ok(())
+}
+
+fn pairs(table: &BTreeMap<String, String>) -> impl Iterator<Item = (&str, &str)> {
+    table.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+}
+
+/// Which `Action` each chord performs in insert and command modes.
+pub struct Keymap {
+    insert: HashMap<Chord, Action>,
+    command: HashMap<Chord, Action>,
+}
+
+impl Keymap {
+    /// Build the keymap from the `[keys]` config table: the named preset
+    /// (`vi`, the default, or `emacs`) with the configured chords on top.
+    pub fn from_config(cfg: &KeysConfig) -> Result<Keymap, String> {
+        let insert_preset = match cfg.preset.as_deref().unwrap_or("vi") {
+            "vi" => VI_INSERT,
+            "emacs" => EMACS_INSERT,
+            x => return Err(format!("[keys]: unrecognized preset \"{}\"", x)),
+        };
+
+        let mut keymap = Keymap {
+            insert: HashMap::new(),
+            command: HashMap::new(),
+        };
+        bind(&mut keymap.insert, insert_preset.iter().copied())?;
+        bind(&mut keymap.command, VI_COMMAND.iter().copied())?;
+        bind(&mut keymap.insert, pairs(&cfg.insert))
+            .map_err(|e| format!("[keys.insert]: {}", e))?;
+        bind(&mut keymap.command, pairs(&cfg.command))
+            .map_err(|e| format!("[keys.command]: {}", e))?;
+
+        Ok(keymap)
+    }
+
+    /// The action bound to `event` in insert mode.
+    pub fn insert(&self, event: &KeyEvent) -> Option<Action> {
+        self.insert
+            .get(&Chord::new(event.code, event.modifiers))
+            .copied()
+    }
+
+    /// The action bound to `event` in command mode.
+    pub fn command(&self, event: &KeyEvent) -> Option<Action> {
+        self.command
+            .get(&Chord::new(event.code, event.modifiers))
+            .copied()
+    }
+}
@@ -1,8 +1,27 @@
 use lazy_static::lazy_static;
 use log::trace;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 use crossterm::{style, ExecutableCommand};
 
+use crate::util::{text::clusters, url};
+
+/// Whether URLs are rendered as OSC 8 hyperlinks.
+static HYPERLINKS: AtomicBool = AtomicBool::new(false);
+
+/// Turn rendering URLs as OSC 8 hyperlinks on or off.
+pub fn set_hyperlinks(on: bool) {
+    HYPERLINKS.store(on, Ordering::Relaxed);
+}
+
+/// The escape sequence that starts a hyperlink to `target`.
+fn open_link(target: &str) -> String {
+    format!("\x1b]8;;{}\x1b\\", target)
+}
+
+/// The escape sequence that ends a hyperlink.
+const CLOSE_LINK: &str = "\x1b]8;;\x1b\\";
+
 lazy_static! {
     static ref RESET: Style = {
         use crossterm::{style, ExecutableCommand};
@@ -13,6 +32,7 @@ lazy_static! {
             .unwrap();
         Style(String::from_utf8(buff).unwrap())
     };
+    static ref MATCH: Style = Style::new(None, None, Some(&[style::Attribute::Reverse]));
 }
 
 /// A wrapper around a string that can be formatted with ANSI escape codes.
@@ -71,20 +91,46 @@ impl Fmtr {
 pub struct Line {
     chars: Vec<char>,
     max_line_width: Option<usize>,
-    num_characters: Option<usize>,
+    num_columns: Option<usize>,
     format_directions: Vec<Fmtr>,
+    highlight: Option<(usize, usize)>,
+    wraps: Vec<usize>,
     rendered_lines: Vec<String>,
     rendered_substring: String,
 }
 
 impl Line {
-    pub fn len(&self) -> usize {
-        self.chars.len()
+    /// The number of terminal columns the `Line` takes up unwrapped.
+    pub fn width(&self) -> usize {
+        crate::util::text::width(&self.chars)
+    }
+
+    /// The text of the `Line`, without formatting.
+    pub fn chars(&self) -> &[char] {
+        &self.chars
+    }
+
+    /// Highlight the characters in `start..end` (as a search match), or, with
+    /// `None`, nothing.
+    pub fn set_highlight(&mut self, highlight: Option<(usize, usize)>) {
+        if self.highlight != highlight {
+            self.highlight = highlight;
+            self.max_line_width = None;
+        }
+    }
+
+    /// Which of the rows the `Line` wraps into at `width` holds the character
+    /// at `index`.
+    pub fn row_of(&mut self, index: usize, width: usize) -> usize {
+        if self.max_line_width != Some(width) {
+            self.wrap(width);
+        }
+        self.wraps.iter().take_while(|&&w| w <= index).count()
     }
 
     pub fn push<T: AsRef<str>>(&mut self, string: T) {
         self.max_line_width = None;
-        self.num_characters = None;
+        self.num_columns = None;
         for c in string.as_ref().chars() {
             self.chars.push(c);
         }
@@ -93,7 +139,7 @@ impl Line {
     /// Add a chunk of _formatted_ text to the end of the `Line`.
     pub fn pushf<T: AsRef<str>>(&mut self, string: T, style: &Style) {
         self.max_line_width = None;
-        self.num_characters = None;
+        self.num_columns = None;
 
         let start_index = self.chars.len();
         self.format_directions.push(Fmtr::new(start_index, style));
@@ -104,88 +150,179 @@ impl Line {
         self.format_directions.push(Fmtr::new(end_index, &RESET));
     }
 
+    /// Break the `Line` into rows at most `width` columns wide, at whitespace
+    /// or the start of a URL where possible, so URLs are only split if they
+    /// don't fit on a row of their own. Whitespace at the start of a
+    /// continuation row is dropped.
     fn wrap(&mut self, width: usize) {
-        let mut wraps: Vec<usize> = Vec::with_capacity(1 + self.chars.len() / width);
-        let mut current_pos: usize = 0;
-        let mut last_whitespace_pos: usize = 0;
-        let mut include_leading_whitespace: bool = true;
+        let clusters = clusters(&self.chars);
+        let links = url::find(&self.chars);
+        let hyperlinks = HYPERLINKS.load(Ordering::Relaxed);
+        let is_space = |n: usize| self.chars[clusters[n].start].is_whitespace();
+        // An opening bracket or quote stays with the URL after it.
+        let starts_link = |n: usize| {
+            let i = clusters[n].start;
+            links.iter().any(|u| u.start == i)
+                && !matches!(
+                    i.checked_sub(1).map(|p| self.chars[p]),
+                    Some('(' | '[' | '{' | '<' | '"' | '\'')
+                )
+        };
 
         trace!("chars: {}", &(self.chars.iter().collect::<String>()));
 
-        for (i, c) in self.chars.iter().enumerate() {
-            if current_pos == width {
-                wraps.push(if i - width >= last_whitespace_pos {
-                    i
-                } else {
-                    last_whitespace_pos
-                });
-                current_pos = i - last_whitespace_pos;
-                include_leading_whitespace = false;
+        // Indices (into `clusters`) at which each row after the first starts.
+        let mut wraps: Vec<usize> = Vec::with_capacity(1 + self.chars.len() / width.max(1));
+        let mut row_start: usize = 0;
+        let mut row_width: usize = 0;
+        let mut last_break: Option<usize> = None;
+        let mut n: usize = 0;
+
+        while n < clusters.len() {
+            if !wraps.is_empty() && row_width == 0 && is_space(n) {
+                n += 1;
+                continue;
             }
 
-            if (current_pos > 0 || include_leading_whitespace) || !c.is_whitespace() {
-                current_pos += 1;
+            if row_width > 0 && row_width + clusters[n].width > width {
+                n = match last_break {
+                    Some(b) if b > row_start => b,
+                    _ => n,
+                };
+                wraps.push(n);
+                row_start = n;
+                row_width = 0;
+                last_break = None;
+                continue;
             }
 
-            if c.is_whitespace() {
-                last_whitespace_pos = i;
+            if is_space(n) || starts_link(n) {
+                last_break = Some(n);
             }
+            row_width += clusters[n].width;
+            n += 1;
         }
 
+        let wraps: Vec<usize> = wraps.iter().map(|&n| clusters[n].start).collect();
         trace!("wraps at: {:?}", &wraps);
 
+        // Rows are drawn separately, so each one ends with a reset and starts
+        // by restoring whatever formatting (and hyperlink) was in effect where
+        // it breaks.
+        let links: Vec<url::Url> = if hyperlinks { links } else { Vec::new() };
+        let mut link_iter = links.iter().peekable();
+        let mut active_link: Option<&url::Url> = None;
+        let in_highlight = |i: usize| matches!(self.highlight, Some((a, b)) if a <= i && i < b);
         self.rendered_lines = Vec::with_capacity(wraps.len() + 1);
         let mut fmt_iter = self.format_directions.iter().peekable();
+        let mut active: Option<&Style> = None;
         let mut current_line = String::with_capacity(width);
-        include_leading_whitespace = true;
+        let mut include_leading_whitespace = true;
         let mut line_wrap_index: usize = 0;
         let mut current_line_length: usize = 0;
 
         for (i, c) in self.chars.iter().enumerate() {
+            if matches!(active_link, Some(u) if u.end == i) {
+                current_line.push_str(CLOSE_LINK);
+                active_link = None;
+            }
+
             if line_wrap_index < wraps.len() && wraps[line_wrap_index] == i {
+                if active_link.is_some() {
+                    current_line.push_str(CLOSE_LINK);
+                }
+                current_line.push_str(&RESET);
                 self.rendered_lines.push(current_line.clone());
                 current_line.clear();
+                if let Some(code) = active {
+                    current_line.push_str(code);
+                }
+                if let Some(u) = active_link {
+                    current_line.push_str(&open_link(&u.target));
+                }
+                if in_highlight(i) {
+                    current_line.push_str(&MATCH);
+                }
                 include_leading_whitespace = false;
                 line_wrap_index += 1;
                 current_line_length = 0;
             }
 
+            if let Some(u) = link_iter.next_if(|u| u.start == i) {
+                current_line.push_str(&open_link(&u.target));
+                active_link = Some(u);
+            }
+
+            let mut restyled = false;
             while let Some(f) = fmt_iter.peek() {
                 if f.index == i {
                     current_line.push_str(&f.code);
+                    active = Some(&f.code);
+                    restyled = true;
                     fmt_iter.next();
                 } else {
                     break;
                 }
             }
 
+            match self.highlight {
+                Some((_, end)) if end == i => {
+                    current_line.push_str(&RESET);
+                    if let Some(code) = active {
+                        current_line.push_str(code);
+                    }
+                }
+                Some((start, _)) if in_highlight(i) && (start == i || restyled) => {
+                    current_line.push_str(&MATCH);
+                }
+                _ => {}
+            }
+
             if current_line_length > 0 || include_leading_whitespace || !c.is_whitespace() {
                 current_line.push(*c);
                 current_line_length += 1;
             }
         }
 
+        if active_link.is_some() {
+            current_line.push_str(CLOSE_LINK);
+        }
         for f in fmt_iter {
             current_line.push_str(&f.code);
         }
+        if self.highlight.is_some() {
+            current_line.push_str(&RESET);
+        }
 
         self.rendered_lines.push(current_line);
+        self.wraps = wraps;
         self.max_line_width = Some(width);
     }
 
     pub fn lines(&mut self, width: usize) -> &[String] {
-        if self.max_line_width.map_or(true, |n| n != width) {
+        if self.max_line_width != Some(width) {
             self.wrap(width);
         }
 
         &self.rendered_lines
     }
 
-    fn render_n_chars(&mut self, n: usize) {
+    /// Render as many whole grapheme clusters as fit in `n` columns.
+    fn render_n_columns(&mut self, n: usize) {
+        let mut end: usize = 0;
+        let mut used: usize = 0;
+        for cluster in clusters(&self.chars) {
+            if used + cluster.width > n {
+                break;
+            }
+            used += cluster.width;
+            end = cluster.end;
+        }
+
         let mut rendered_string = String::default();
         let mut format_iter = self.format_directions.iter().peekable();
 
-        for (i, &c) in self.chars[..n].iter().enumerate() {
+        for (i, &c) in self.chars[..end].iter().enumerate() {
             while let Some(format) = format_iter.peek() {
                 if format.index == i {
                     rendered_string.push_str(&format.code);
@@ -201,17 +338,83 @@ impl Line {
             rendered_string.push_str(&format.code);
         }
 
-        self.num_characters = Some(n);
+        self.num_columns = Some(n);
         self.rendered_substring = rendered_string;
     }
 
-    pub fn first_n_chars(&mut self, n: usize) -> &str {
-        let substring_length = n.min(self.chars.len());
-
-        if self.num_characters.map_or(true, |i| substring_length != i) {
-            self.render_n_chars(substring_length);
+    /// The start of the `Line`, cut off to fit in `n` columns.
+    pub fn first_n_columns(&mut self, n: usize) -> &str {
+        if self.num_columns != Some(n) {
+            self.render_n_columns(n);
         }
 
         &self.rendered_substring
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The rows `text` wraps into at `width`, without their escape codes.
+    fn rows(text: &str, width: usize) -> Vec<String> {
+        let mut line = Line::default();
+        line.push(text);
+        line.lines(width)
+            .iter()
+            .map(|row| {
+                let mut plain = String::new();
+                let mut chars = row.chars();
+                while let Some(c) = chars.next() {
+                    if c == '\u{1b}' {
+                        chars.by_ref().find(|c| c.is_ascii_alphabetic());
+                    } else {
+                        plain.push(c);
+                    }
+                }
+                plain
+            })
+            .collect()
+    }
+
+    #[test]
+    fn wraps_wide_characters_whole() {
+        assert_eq!(rows("日本語テキスト", 5), ["日本", "語テ", "キス", "ト"]);
+        assert_eq!(rows("日本語テキスト", 4), ["日本", "語テ", "キス", "ト"]);
+        assert_eq!(rows("a日", 2), ["a", "日"]);
+        assert_eq!(rows("日本", 1), ["日", "本"]);
+    }
+
+    #[test]
+    fn wraps_combining_characters_with_their_base() {
+        let text = "e\u{301}".repeat(4);
+        let two = "e\u{301}".repeat(2);
+        assert_eq!(rows(&text, 2), [two.clone(), two]);
+        assert_eq!(rows(&text, 3), ["e\u{301}".repeat(3), "e\u{301}".into()]);
+    }
+
+    #[test]
+    fn zero_width_characters_take_no_room() {
+        assert_eq!(rows("ab\u{200b}cd", 2), ["ab\u{200b}", "cd"]);
+        let family = "\u{1f468}\u{200d}\u{1f469}\u{200d}\u{1f467}";
+        assert_eq!(rows(&format!("{}{}", family, family), 3), [family, family]);
+    }
+
+    #[test]
+    fn wraps_at_whitespace() {
+        assert_eq!(rows("日本 語", 5), ["日本", "語"]);
+        assert_eq!(rows("one two three", 8), ["one two", "three"]);
+        assert_eq!(rows("one two three", 100), ["one two three"]);
+    }
+
+    #[test]
+    fn row_of_wide_characters() {
+        let mut line = Line::default();
+        line.push("日本語テキスト");
+        assert_eq!(line.row_of(0, 4), 0);
+        assert_eq!(line.row_of(1, 4), 0);
+        assert_eq!(line.row_of(2, 4), 1);
+        assert_eq!(line.row_of(6, 4), 3);
+        assert_eq!(line.lines(4).len(), 4);
+    }
+}
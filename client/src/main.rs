@@ -1,35 +1,52 @@
+mod batch;
+mod completion;
 mod connection;
+mod history;
 mod input;
+mod keys;
 mod line;
 mod message;
 mod screen;
+mod theme;
+mod transcript;
 mod util;
+mod vi;
 
-use crate::connection::connect;
+use crate::connection::{connect, Backoff};
+use crate::history::History;
 use crate::input::{process_user_typing, write_mode_line, Mode};
-use crate::line::Line;
+use crate::keys::Keymap;
+use crate::line::{set_hyperlinks, Line};
 use crate::message::process_msg;
 use crate::screen::Screen;
-use crate::util::styles::HIGHLIGHT;
+use crate::theme::Theme;
+use crate::transcript::{Format, Transcript};
+use crate::util::styles::{set_theme, BOLD, DIM, HIGHLIGHT};
+use crate::util::url::hyperlinks_supported;
+use crate::vi::Vi;
 
 use clap::Parser;
 use common::config::ClientConfig;
-use common::proto::Sndr;
+use common::proto::Msg;
 use common::socket::Socket;
 use connection::State;
-use lazy_static::lazy_static;
 use log::{debug, error};
+use std::borrow::Cow;
 use std::io::stdout;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
-lazy_static! {
-    static ref PING: Vec<u8> = Sndr::Ping.bytes();
-    static ref ROSTER_REQUEST: Vec<u8> = Sndr::Query {
-        what: "roster",
-        arg: "",
-    }
-    .bytes();
-}
+/// How often to ask for the roster again, to keep idle times up to date.
+const ROSTER_REFRESH: Duration = Duration::from_secs(60);
+
+const PING: Msg<'static> = Msg::Ping;
+const ROSTER_REQUEST: Msg<'static> = Msg::Query {
+    what: Cow::Borrowed("roster"),
+    arg: Cow::Borrowed(""),
+};
+const ADDR_REQUEST: Msg<'static> = Msg::Query {
+    what: Cow::Borrowed("addr"),
+    arg: Cow::Borrowed(""),
+};
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
@@ -42,12 +59,23 @@ struct ClapOpts {
 
     #[arg(short = 'a', long = "address")]
     address: Option<String>,
-}
 
-fn configure() -> ClientConfig {
-    let opts = ClapOpts::parse();
+    /// Run without a terminal UI: send lines from stdin as messages and print
+    /// what's received to stdout, logging out at the end of the input.
+    #[arg(short = 'b', long = "batch")]
+    batch: bool,
+
+    /// In batch mode, the room to join before sending anything.
+    #[arg(short = 'r', long = "room", requires = "batch")]
+    room: Option<String>,
+
+    /// In batch mode, print received messages as JSON objects, one per line.
+    #[arg(short = 'j', long = "json", requires = "batch")]
+    json: bool,
+}
 
-    let mut cfg = match ClientConfig::configure(opts.config) {
+fn configure(opts: &ClapOpts) -> ClientConfig {
+    let mut cfg = match ClientConfig::configure(opts.config.clone()) {
         Ok(x) => x,
         Err(e) => {
             println!("Configuration error: {}", e);
@@ -55,18 +83,38 @@ fn configure() -> ClientConfig {
         }
     };
 
-    if let Some(name) = opts.name {
-        cfg.name = name;
+    if let Some(name) = &opts.name {
+        cfg.name.clone_from(name);
     }
-    if let Some(address) = opts.address {
-        cfg.address = address;
+    if let Some(address) = &opts.address {
+        cfg.address.clone_from(address);
     }
 
     cfg
 }
 
 fn main() {
-    let cfg: ClientConfig = configure();
+    let opts = ClapOpts::parse();
+    let cfg: ClientConfig = configure(&opts);
+    if opts.batch {
+        std::process::exit(batch::run(&cfg, opts.room.as_deref(), opts.json));
+    }
+
+    let keymap = match Keymap::from_config(&cfg.keys) {
+        Ok(x) => x,
+        Err(e) => {
+            println!("Configuration error: {}", e);
+            std::process::exit(1);
+        }
+    };
+    match Theme::from_config(&cfg.theme) {
+        Ok(theme) => set_theme(theme),
+        Err(e) => {
+            println!("Configuration error: {}", e);
+            std::process::exit(1);
+        }
+    }
+    set_hyperlinks(hyperlinks_supported());
 
     #[cfg(debug_assertions)]
     simplelog::WriteLogger::init(
@@ -78,7 +126,7 @@ fn main() {
 
     debug!("{:?}", &cfg);
     println!("Attempting to connect to {}...", &cfg.address);
-    let mut socket: Socket = match connect(&cfg) {
+    let mut socket: Socket = match connect(&cfg, &cfg.name, None) {
         Err(e) => {
             println!("{}", e);
             std::process::exit(2);
@@ -88,14 +136,7 @@ fn main() {
     socket.set_read_buffer_size(cfg.read_size);
     println!("...success. Negotiating initial protocol...");
 
-    {
-        let bytes = Sndr::Query {
-            what: "addr",
-            arg: "",
-        }
-        .bytes();
-        socket.enqueue(&bytes);
-    }
+    socket.enqueue_msg(&ADDR_REQUEST);
     println!("...success. Initializing terminal.");
 
     let mut state = State {
@@ -108,17 +149,39 @@ fn main() {
         socket,
         cmd: cfg.cmd_char,
         running: true,
+        resume_token: None,
+        reconnect: None,
+        rejoin: None,
+        history: History::new(cfg.history_size, cfg.history_file.clone()),
+        roster: Vec::new(),
+        rooms: Vec::new(),
+        completion: None,
+        keymap,
+        vi: Vi::default(),
+        raw_text: false,
+        find_query: String::new(),
+        find_backward: false,
+        transcript: Transcript::new(
+            &cfg.transcript_dir,
+            &cfg.address,
+            match cfg.transcript_format.as_str() {
+                "json" => Format::Json,
+                _ => Format::Text,
+            },
+            cfg.transcripts,
+        ),
     };
 
     {
         let mut terminal_handle = stdout();
-        let mut screen: Screen = match Screen::new(&mut terminal_handle, cfg.roster_width) {
-            Ok(x) => x,
-            Err(e) => {
-                println!("Error setting up terminal: {}", e);
-                std::process::exit(1);
-            }
-        };
+        let mut screen: Screen =
+            match Screen::new(&mut terminal_handle, cfg.roster_width, cfg.mouse) {
+                Ok(x) => x,
+                Err(e) => {
+                    println!("Error setting up terminal: {}", e);
+                    std::process::exit(1);
+                }
+            };
 
         let mut server_address_line = Line::default();
         server_address_line.pushf(&state.server_address, &HIGHLIGHT);
@@ -129,6 +192,8 @@ fn main() {
         screen.set_stat_ur(current_room_line);
         write_mode_line(&mut screen, &state);
 
+        let mut roster_time = Instant::now();
+
         'main_loop: loop {
             let loop_timer = Instant::now();
 
@@ -156,55 +221,17 @@ fn main() {
                 }
             }
 
-            let outgoing_bytes = state.socket.send_buff_size();
-            match state.socket.send_data() {
-                Err(e) => {
-                    state.buffered_messages.push(format!("{}", e));
-                    break 'main_loop;
-                }
-                Ok(n) => {
-                    let sent = outgoing_bytes - n;
-                    if sent > 0 {
-                        debug!("Socket::send_data() wrote {} bytes.", sent);
-                    }
-                }
+            if state.reconnect.is_some() {
+                try_reconnect(&cfg, &mut screen, &mut state);
+            } else if let Err(e) = exchange_data(&mut screen, &mut state) {
+                lose_connection(&mut screen, &mut state, &e);
             }
-
-            // Try to read from the byte stream incoming from the server.
-            let read_result = state.socket.read_data();
-            match read_result {
-                Err(e) => {
-                    state.buffered_messages.push(format!("{}", e));
-                    break 'main_loop;
-                }
-                Ok(0) => {}
-                Ok(n) => {
-                    debug!("Socket::read_data() read {} bytes.", n);
-                    'msg_loop: loop {
-                        let message_result = state.socket.try_get();
-                        match message_result {
-                            Err(e) => {
-                                state.buffered_messages.push(format!("{}", e));
-                                break 'main_loop;
-                            }
-                            Ok(None) => {
-                                break 'msg_loop;
-                            }
-                            Ok(Some(message)) => {
-                                match process_msg(message, &mut screen, &mut state) {
-                                    Ok(()) => {
-                                        if !state.running {
-                                            break 'main_loop;
-                                        }
-                                    }
-                                    Err(e) => {
-                                        error!("process_msg(...) returned error: {}", e);
-                                    }
-                                };
-                            }
-                        }
-                    }
-                }
+            if !state.running {
+                break 'main_loop;
+            }
+            if state.reconnect.is_none() && roster_time.elapsed() >= ROSTER_REFRESH {
+                state.enqueue(&ROSTER_REQUEST);
+                roster_time = Instant::now();
             }
 
             if screen.get_scrollback_length() > cfg.max_scrollback {
@@ -229,3 +256,85 @@ fn main() {
         println!("{}", &message);
     }
 }
+
+/// Send whatever is queued for the server, then read and process whatever the
+/// server has sent. Errors returned from here mean the connection is gone.
+fn exchange_data(screen: &mut Screen, state: &mut State) -> Result<(), String> {
+    let outgoing_bytes = state.socket.send_buff_size();
+    let n = state.socket.send_data().map_err(|e| format!("{}", e))?;
+    let sent = outgoing_bytes - n;
+    if sent > 0 {
+        debug!("Socket::send_data() wrote {} bytes.", sent);
+    }
+
+    // Try to read from the byte stream incoming from the server.
+    let n = state.socket.read_data().map_err(|e| format!("{}", e))?;
+    if n == 0 {
+        return Ok(());
+    }
+    debug!("Socket::read_data() read {} bytes.", n);
+
+    while let Some(message) = state.socket.try_get().map_err(|e| format!("{}", e))? {
+        if let Err(e) = process_msg(message, screen, state) {
+            error!("process_msg(...) returned error: {}", e);
+        }
+        if !state.running {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+/// Note the lost connection and schedule the first attempt to get it back.
+fn lose_connection(screen: &mut Screen, state: &mut State, err: &str) {
+    debug!("Lost connection: {}", err);
+    let _ = state.socket.shutdown();
+
+    let mut sl = Line::default();
+    sl.pushf("# Connection lost: ", &DIM);
+    sl.pushf(err, &DIM);
+    screen.push_line(sl);
+    let mut sl = Line::default();
+    sl.pushf("# Reconnecting...", &DIM);
+    screen.push_line(sl);
+
+    state.reconnect = Some(Backoff::new());
+}
+
+/// If a reconnection attempt is due, make it. On success, ask the server to
+/// resume our previous session and remember which room to get back to.
+fn try_reconnect(cfg: &ClientConfig, screen: &mut Screen, state: &mut State) {
+    match &state.reconnect {
+        Some(backoff) if backoff.is_due() => {}
+        _ => return,
+    }
+
+    match connect(cfg, &state.username, state.resume_token.as_deref()) {
+        Ok(mut socket) => {
+            socket.set_read_buffer_size(cfg.read_size);
+            socket.enqueue_msg(&ADDR_REQUEST);
+            state.socket = socket;
+            state.reconnect = None;
+            state.rejoin = Some(state.room_name.clone());
+
+            let mut sl = Line::default();
+            sl.push("* ");
+            sl.pushf("Reconnected", &BOLD);
+            sl.push(" to ");
+            sl.pushf(&state.server_address, &HIGHLIGHT);
+            sl.push(".");
+            screen.push_line(sl);
+        }
+        Err(e) => {
+            let wait = state
+                .reconnect
+                .as_mut()
+                .map(Backoff::fail)
+                .unwrap_or_default();
+            let mut sl = Line::default();
+            sl.pushf(format!("# {}; retrying in {}s.", e, wait.as_secs()), &DIM);
+            screen.push_line(sl);
+        }
+    }
+}
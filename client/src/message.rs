@@ -3,233 +3,324 @@ use crate::{
     input::write_mode_line,
     line::Line,
     screen::Screen,
-    util::styles::{BOLD, DIM, DIM_BOLD, HIGHLIGHT},
+    transcript::Kind,
+    util::{
+        styles::{nick, BOLD, CODE, DIM, DIM_BOLD, HIGHLIGHT, ITALIC, STRIKE},
+        url,
+    },
     PING, ROSTER_REQUEST,
 };
-use common::proto::{Rcvr, SndOp, Sndr};
+use common::markup::{self, Markup, Span};
+use common::proto::{Msg, Op, RosterEntry};
 use log::debug;
-
-const OP_ERROR: &str = "# The recognized OP subcommands are OPEN, CLOSE, KICK, INVITE, and GIVE.";
+use std::borrow::Cow;
+
+/// Names of the commands understood by `respond_to_user_input`.
+pub const COMMANDS: &[&str] = &[
+    "away", "block", "help", "join", "log", "name", "op", "priv", "quit", "raw", "rooms",
+    "unblock", "url", "urls", "who",
+];
+/// Names of the operator subcommands understood after `op`.
+pub const OP_COMMANDS: &[&str] = &["ban", "close", "give", "invite", "kick", "open", "voice"];
+
+const OP_ERROR: &str =
+    "# The recognized OP subcommands are OPEN, CLOSE, KICK, INVITE, GIVE, and VOICE.";
+/// Users idle for less than this many seconds aren't shown as idle.
+const IDLE_SECS: u64 = 300;
 const RETURN: char = '\n';
+/// How many URLs `urls` lists if not told.
+const URLS_LISTED: usize = 10;
 const SPACE: char = ' ';
 
-pub fn process_msg(msg: Rcvr, screen: &mut Screen, state: &mut State) -> Result<(), String> {
+/// Add message text to `line`, styling any inline markup unless `raw` is set.
+fn push_markup(line: &mut Line, text: &str, raw: bool) {
+    if raw {
+        line.push(text);
+        return;
+    }
+    for span in markup::parse(text) {
+        match span {
+            Span::Plain(s) => line.push(&s),
+            Span::Marked(Markup::Bold, s) => line.pushf(&s, &BOLD),
+            Span::Marked(Markup::Italic, s) => line.pushf(&s, &ITALIC),
+            Span::Marked(Markup::Code, s) => line.pushf(&s, &CODE),
+            Span::Marked(Markup::Strike, s) => line.pushf(&s, &STRIKE),
+        }
+    }
+}
+
+/// Show a message from the server, and record it in the transcript for the
+/// room it arrived in. Messages and private messages are recorded as such;
+/// for anything else, whatever lines it put on the screen are.
+pub fn process_msg(
+    msg: Msg<'static>,
+    screen: &mut Screen,
+    state: &mut State,
+) -> Result<(), String> {
     debug!("process_msg(...): rec'd: {:?}", &msg);
+    let room = state.room_name.clone();
+    let first_new_line = screen.get_scrollback_length();
+
+    match &msg {
+        Msg::Text { who, lines } => {
+            for line in lines.iter() {
+                state.transcript.record(&room, Kind::Text, Some(who), line);
+            }
+        }
+        Msg::Priv { who, text } => state.transcript.record(&room, Kind::Priv, Some(who), text),
+        _ => {}
+    }
+    let logged = matches!(msg, Msg::Text { .. } | Msg::Priv { .. });
+
+    let result = show_msg(msg, screen, state);
+
+    if !logged {
+        for line in screen.scrollback_text(first_new_line) {
+            state.transcript.record(&room, Kind::Event, None, &line);
+        }
+    }
+    result
+}
+
+fn show_msg(msg: Msg<'static>, screen: &mut Screen, state: &mut State) -> Result<(), String> {
     match msg {
-        Rcvr::Ping => {
-            state.socket.enqueue(&PING);
+        Msg::Ping => {
+            state.enqueue(&PING);
         }
 
-        Rcvr::Text { who, lines } => {
+        Msg::Text { who, lines } => {
+            let mut in_block = false;
             for line in &lines {
+                if !state.raw_text && markup::is_fence(line) {
+                    in_block = !in_block;
+                    continue;
+                }
                 let mut sl = Line::default();
-                sl.pushf(&who, &HIGHLIGHT);
+                sl.pushf(&who, nick(&who));
                 sl.push(": ");
-                sl.push(line);
+                if in_block {
+                    sl.pushf(line, &CODE);
+                } else {
+                    push_markup(&mut sl, line, state.raw_text);
+                }
                 screen.push_line(sl);
             }
         }
 
-        Rcvr::Priv { who, text } => {
+        Msg::Priv { who, text } => {
             let mut sl = Line::default();
             sl.push("$ ");
-            sl.pushf(&who, &DIM);
+            sl.pushf(&who, nick(&who));
             sl.push(": ");
-            sl.push(&text);
+            push_markup(&mut sl, &text, state.raw_text);
             screen.push_line(sl);
         }
 
-        Rcvr::Logout(s) => {
-            state.buffered_messages.push(s);
+        Msg::Logout(s) => {
+            state.buffered_messages.push(s.into_owned());
             state.running = false;
         }
 
-        Rcvr::Info(s) => {
+        Msg::Info(s) => {
             let mut sl = Line::default();
             sl.push("* ");
             sl.push(&s);
             screen.push_line(sl);
         }
 
-        Rcvr::Err(s) => {
+        Msg::Err(s) | Msg::OverQuota(s) => {
             let mut sl = Line::default();
             sl.pushf("# ", &DIM);
             sl.pushf(&s, &DIM);
             screen.push_line(sl);
         }
 
-        Rcvr::Misc {
-            ref what,
-            ref alt,
-            ref data,
-        } => match what.as_str() {
-            "join" => {
-                let (name, room) = match &data[..] {
-                    [x, y] => (x, y),
-                    _ => {
-                        return Err(format!("Incomplete data: {:?}", &msg));
-                    }
-                };
-                let mut sl = Line::default();
-                sl.push("* ");
-                if name.as_str() == state.username.as_str() {
-                    sl.pushf("You", &BOLD);
-                    sl.push(" joined ");
-
-                    // Update the room name in the status bar.
-                    state.room_name = room.to_string();
-                    let mut room_line = Line::default();
-                    room_line.pushf(&state.room_name, &HIGHLIGHT);
-                    screen.set_stat_ur(room_line);
-                } else {
-                    sl.pushf(name, &HIGHLIGHT);
-                    sl.push(" joined ");
-                }
-                sl.pushf(room, &HIGHLIGHT);
-                sl.push(".");
-                state.enqueue_bytes(&ROSTER_REQUEST);
-                screen.push_line(sl);
+        Msg::Joined { user, room } => {
+            let mut sl = Line::default();
+            sl.push("* ");
+            if user == state.username {
+                sl.pushf("You", &BOLD);
+                sl.push(" joined ");
+
+                state.rejoin_if_needed(&room);
+
+                // Update the room name in the status bar.
+                state.room_name = room.to_string();
+                let mut room_line = Line::default();
+                room_line.pushf(&state.room_name, &HIGHLIGHT);
+                screen.set_stat_ur(room_line);
+            } else {
+                sl.pushf(&user, nick(&user));
+                sl.push(" joined ");
             }
+            sl.pushf(&room, &HIGHLIGHT);
+            sl.push(".");
+            state.enqueue(&ROSTER_REQUEST);
+            screen.push_line(sl);
+        }
 
-            "leave" => {
-                let (name, message) = match &data[..] {
-                    [x, y] => (x, y),
-                    _ => {
-                        return Err(format!("Incomplete data: {:?}", &msg));
-                    }
-                };
-                let mut sl = Line::default();
-                sl.push("* ");
-                sl.pushf(name, &HIGHLIGHT);
-                sl.push(" left: ");
-                sl.push(message);
-                state.enqueue_bytes(&ROSTER_REQUEST);
-                screen.push_line(sl);
+        Msg::Left { user, reason } => {
+            let mut sl = Line::default();
+            sl.push("* ");
+            sl.pushf(&user, nick(&user));
+            sl.push(" left: ");
+            sl.push(&reason);
+            state.enqueue(&ROSTER_REQUEST);
+            screen.push_line(sl);
+        }
+
+        Msg::PrivEcho { to, text } => {
+            let mut sl = Line::default();
+            sl.push("$ ");
+            sl.pushf("You", &DIM_BOLD);
+            sl.pushf(" @ ", &DIM);
+            sl.pushf(&to, nick(&to));
+            sl.push(": ");
+            push_markup(&mut sl, &text, state.raw_text);
+            screen.push_line(sl);
+        }
+
+        Msg::Renamed { old, new } => {
+            let mut sl = Line::default();
+            sl.push("* ");
+            if old == state.username {
+                sl.pushf("You", &BOLD);
+                sl.push(" are now known as ");
+                state.username = new.to_string();
+                write_mode_line(screen, state);
+            } else {
+                sl.pushf(&old, nick(&old));
+                sl.push(" is now known as ");
             }
+            sl.pushf(&new, nick(&new));
+            sl.push(".");
+            screen.push_line(sl);
+            state.enqueue(&ROSTER_REQUEST);
+        }
 
-            "priv_echo" => {
-                let (name, text) = match &data[..] {
-                    [x, y] => (x, y),
-                    _ => {
-                        return Err(format!("Incomplete data: {:?}", &msg));
-                    }
-                };
-                let mut sl = Line::default();
-                sl.push("$ ");
-                sl.pushf("You", &DIM_BOLD);
-                sl.pushf(" @ ", &DIM);
-                sl.pushf(name, &HIGHLIGHT);
-                sl.push(": ");
-                sl.push(text);
-                screen.push_line(sl);
+        Msg::NewOp { user, room } => {
+            let mut sl = Line::default();
+            sl.push("* ");
+            if user == state.username {
+                sl.pushf("You", &BOLD);
+                sl.push(" are now the operator of ");
+            } else {
+                sl.pushf(&user, nick(&user));
+                sl.push(" is now the operator of ");
             }
+            sl.pushf(&room, &BOLD);
+            sl.push(".");
+            screen.push_line(sl);
+            state.enqueue(&ROSTER_REQUEST);
+        }
 
-            "name" => {
-                let (old, new) = match &data[..] {
-                    [x, y] => (x, y),
-                    _ => {
-                        return Err(format!("Incomplete data: {:?}", &msg));
-                    }
-                };
+        Msg::Roster(entries) => show_roster(entries.into_owned(), screen, state),
 
-                let mut sl = Line::default();
-                sl.push("* ");
-                if old.as_str() == state.username.as_str() {
-                    sl.pushf("You", &BOLD);
-                    sl.push(" are now known as ");
-                    state.username.clone_from(new);
-                    write_mode_line(screen, state);
-                } else {
-                    sl.pushf(old, &HIGHLIGHT);
-                    sl.push(" is now known as ");
+        Msg::IsAway { ref user, .. } | Msg::IsBack { ref user } => {
+            let mut sl = Line::default();
+            sl.push("* ");
+            if *user == state.username {
+                sl.pushf("You", &BOLD);
+                sl.push(" are");
+            } else {
+                sl.pushf(user, nick(user));
+                sl.push(" is");
+            }
+            match &msg {
+                Msg::IsAway { message, .. } => {
+                    sl.push(" away: ");
+                    sl.push(message);
                 }
-                sl.pushf(new, &HIGHLIGHT);
-                sl.push(".");
-                screen.push_line(sl);
-                state.enqueue_bytes(&ROSTER_REQUEST);
+                _ => sl.push(" back."),
             }
+            screen.push_line(sl);
+            state.enqueue(&ROSTER_REQUEST);
+        }
 
-            "new_op" => {
-                let (name, room) = match &data[..] {
-                    [x, y] => (x, y),
-                    _ => {
-                        return Err(format!("Incomplete data: {:?}", &msg));
-                    }
-                };
-
-                let mut sl = Line::default();
-                sl.push("* ");
-                if name == &state.username {
-                    sl.pushf("You", &BOLD);
-                    sl.push(" are now the operator of ");
-                } else {
-                    sl.pushf(name, &HIGHLIGHT);
-                    sl.push(" is now the operator of ");
-                }
-                sl.pushf(room, &BOLD);
-                sl.push(".");
-                screen.push_line(sl);
-                state.enqueue_bytes(&ROSTER_REQUEST);
+        Msg::Voiced {
+            ref user,
+            ref by,
+            ref room,
+        }
+        | Msg::Devoiced {
+            ref user,
+            ref by,
+            ref room,
+        } => {
+            let mut sl = Line::default();
+            sl.push("* ");
+            sl.pushf(by, nick(by));
+            if matches!(msg, Msg::Voiced { .. }) {
+                sl.push(" has given ");
+                sl.pushf(user, nick(user));
+                sl.push(" a voice in ");
+            } else {
+                sl.push(" has taken away ");
+                sl.pushf(user, nick(user));
+                sl.push("'s voice in ");
             }
+            sl.pushf(room, &HIGHLIGHT);
+            sl.push(".");
+            screen.push_line(sl);
+            state.enqueue(&ROSTER_REQUEST);
+        }
 
-            "roster" => {
-                if data.is_empty() {
-                    return Err(format!("Incomplete data: {:?}", &msg));
-                }
+        Msg::Rooms(names) => {
+            let mut sl = Line::default();
+            sl.push("* Matching rooms: ");
+            sl.push(names.join(", "));
+            screen.push_line(sl);
+            state.rooms = names.into_iter().map(Cow::into_owned).collect();
+        }
 
-                screen.set_roster(data);
+        Msg::Who(names) => {
+            let mut sl = Line::default();
+            sl.push("* Matching names: ");
+            for (n, name) in names.iter().enumerate() {
+                if n > 0 {
+                    sl.push(", ");
+                }
+                sl.pushf(name, nick(name));
             }
+            screen.push_line(sl);
+        }
 
-            "kick_other" => {
-                let (name, room) = match &data[..] {
-                    [x, y] => (x, y),
-                    _ => {
-                        return Err(format!("Incomplete data: {:?}", &msg));
-                    }
-                };
-                let mut sl = Line::default();
-                sl.push("* ");
-                sl.pushf(name, &HIGHLIGHT);
-                sl.push(" has been kicked from ");
-                sl.pushf(room, &HIGHLIGHT);
-                sl.push(".");
-                screen.push_line(sl);
-                state.enqueue_bytes(&ROSTER_REQUEST);
-            }
+        Msg::Kicked { user, room } => {
+            let mut sl = Line::default();
+            sl.push("* ");
+            sl.pushf(&user, nick(&user));
+            sl.push(" has been kicked from ");
+            sl.pushf(&room, &HIGHLIGHT);
+            sl.push(".");
+            screen.push_line(sl);
+            state.enqueue(&ROSTER_REQUEST);
+        }
 
-            "kick_you" => {
-                let room = match &data[..] {
-                    [x] => x,
-                    _ => {
-                        return Err(format!("Incomplete data: {:?}", &msg));
-                    }
-                };
-                let mut sl = Line::default();
-                sl.push("* ");
-                sl.pushf("You", &BOLD);
-                sl.push(" have been kicked from ");
-                sl.pushf(room, &HIGHLIGHT);
-                sl.push(".");
-                screen.push_line(sl);
-            }
+        Msg::KickedOut { room } => {
+            let mut sl = Line::default();
+            sl.push("* ");
+            sl.pushf("You", &BOLD);
+            sl.push(" have been kicked from ");
+            sl.pushf(&room, &HIGHLIGHT);
+            sl.push(".");
+            screen.push_line(sl);
+        }
 
-            "addr" => match data.get(0) {
-                None => {
-                    return Err(format!("Incomplete data: {:?}", &msg));
-                }
-                Some(addr) => {
-                    state.local_address.clone_from(addr);
-                    write_mode_line(screen, state);
-                }
-            },
+        Msg::ResumeToken(token) => {
+            state.resume_token = Some(token.into_owned());
+        }
 
-            _ => {
-                let mut sl = Line::default();
-                sl.push("* ");
-                sl.push(alt);
-                screen.push_line(sl)
-            }
-        },
+        Msg::Addr(addr) => {
+            state.local_address = addr.into_owned();
+            write_mode_line(screen, state);
+        }
+
+        Msg::Misc { alt, .. } => {
+            let mut sl = Line::default();
+            sl.push("* ");
+            sl.push(&alt);
+            screen.push_line(sl)
+        }
 
         msg => {
             let msgs = format!("{:?}", msg);
@@ -241,7 +332,7 @@ pub fn process_msg(msg: Rcvr, screen: &mut Screen, state: &mut State) -> Result<
                 })
                 .collect();
             let mut sl = Line::default();
-            sl.push("# Unsupported Rcvr: ");
+            sl.push("# Unsupported Msg: ");
             sl.push(&s);
             screen.push_line(sl);
         }
@@ -249,6 +340,71 @@ pub fn process_msg(msg: Rcvr, screen: &mut Screen, state: &mut State) -> Result<
     Ok(())
 }
 
+/// A short form of a number of seconds, like `5m` or `2h`.
+fn short_duration(secs: u64) -> String {
+    match secs {
+        s if s < 60 * 60 => format!("{}m", s / 60),
+        s if s < 24 * 60 * 60 => format!("{}h", s / (60 * 60)),
+        s => format!("{}d", s / (24 * 60 * 60)),
+    }
+}
+
+/// Show the roster, operators first and then everyone else alphabetically,
+/// with a header counting them. Names are prefixed with `@` for the operator
+/// and `+` for those with a voice, and dimmed if they're away.
+fn show_roster(mut entries: Vec<RosterEntry>, screen: &mut Screen, state: &mut State) {
+    entries.sort_by(|a, b| {
+        b.op.cmp(&a.op)
+            .then_with(|| a.name.to_lowercase().cmp(&b.name.to_lowercase()))
+    });
+
+    let mut header = Line::default();
+    let away = entries.iter().filter(|e| e.away).count();
+    let noun = if entries.len() == 1 { "user" } else { "users" };
+    header.pushf(format!("{} {}", entries.len(), noun), &DIM_BOLD);
+    if away > 0 {
+        header.pushf(format!(", {} away", away), &DIM);
+    }
+
+    let lines: Vec<Line> = entries
+        .iter()
+        .map(|e| {
+            let mut line = Line::default();
+            match (e.op, e.voiced) {
+                (true, _) => line.pushf("@", &HIGHLIGHT),
+                (false, true) => line.pushf("+", &HIGHLIGHT),
+                (false, false) => line.push(" "),
+            }
+            if e.away {
+                line.pushf(&e.name, &DIM);
+            } else {
+                line.pushf(&e.name, nick(&e.name));
+            }
+            if e.idle_secs >= IDLE_SECS {
+                line.pushf(format!(" {}", short_duration(e.idle_secs)), &DIM);
+            }
+            line
+        })
+        .collect();
+
+    screen.set_roster(header, lines);
+    state.roster = entries.into_iter().map(|e| e.name).collect();
+}
+
+/// The distinct URLs in the scrollback, most recent first.
+fn recent_urls(screen: &Screen) -> Vec<String> {
+    let mut urls: Vec<String> = Vec::new();
+    for text in screen.scrollback_text(0).iter().rev() {
+        let chars: Vec<char> = text.chars().collect();
+        for u in url::find(&chars).into_iter().rev() {
+            if !urls.contains(&u.target) {
+                urls.push(u.target);
+            }
+        }
+    }
+    urls
+}
+
 /// In input mode, when the user hits return, this processes processes the
 /// content of the input line and decides what to do.
 pub fn respond_to_user_input(input: Vec<char>, screen: &mut Screen, state: &mut State) {
@@ -294,10 +450,118 @@ pub fn respond_to_user_input(input: Vec<char>, screen: &mut Screen, state: &mut
                     sl.pushf("  /join <room>", &DIM);
                     sl.push(" - join a room");
                     screen.push_line(sl);
+                    sl = Line::default();
+                    sl.pushf("# ", &DIM_BOLD);
+                    sl.pushf("  /away [message]", &DIM);
+                    sl.push(" - mark yourself away, or without a message, back");
+                    screen.push_line(sl);
+                    sl = Line::default();
+                    sl.pushf("# ", &DIM_BOLD);
+                    sl.pushf("  /log", &DIM);
+                    sl.push(" - toggle logging this room to a transcript file");
+                    screen.push_line(sl);
+                    sl = Line::default();
+                    sl.pushf("# ", &DIM_BOLD);
+                    sl.pushf("  /raw", &DIM);
+                    sl.push(" - toggle showing message markup as raw text");
+                    screen.push_line(sl);
+                    sl = Line::default();
+                    sl.pushf("# ", &DIM_BOLD);
+                    sl.pushf("  /urls [n]", &DIM);
+                    sl.push(" - list the last n URLs");
+                    screen.push_line(sl);
+                    sl = Line::default();
+                    sl.pushf("# ", &DIM_BOLD);
+                    sl.pushf("  /url [n]", &DIM);
+                    sl.push(" - open the nth most recent URL");
+                    screen.push_line(sl);
+                }
+                "urls" => {
+                    let n = match cmd_toks.get(1).map(|t| t.parse::<usize>()) {
+                        None => URLS_LISTED,
+                        Some(Ok(n)) => n,
+                        Some(Err(_)) => {
+                            let mut sl = Line::default();
+                            sl.pushf("# Usage: urls [number to list]", &DIM);
+                            screen.push_line(sl);
+                            return;
+                        }
+                    };
+                    let urls = recent_urls(screen);
+                    let mut sl = Line::default();
+                    if urls.is_empty() {
+                        sl.pushf("# No URLs yet.", &DIM);
+                        screen.push_line(sl);
+                        return;
+                    }
+                    sl.pushf("# Recent URLs:", &DIM);
+                    screen.push_line(sl);
+                    for (i, u) in urls.iter().take(n).enumerate() {
+                        let mut sl = Line::default();
+                        sl.pushf("# ", &DIM_BOLD);
+                        sl.pushf(format!("{:>3} ", i + 1), &HIGHLIGHT);
+                        sl.push(u);
+                        screen.push_line(sl);
+                    }
+                }
+                "url" => {
+                    let n = match cmd_toks.get(1).map(|t| t.parse::<usize>()) {
+                        None => 1,
+                        Some(Ok(n)) if n > 0 => n,
+                        Some(_) => {
+                            let mut sl = Line::default();
+                            sl.pushf("# Usage: url [number from urls]", &DIM);
+                            screen.push_line(sl);
+                            return;
+                        }
+                    };
+                    let mut sl = Line::default();
+                    match recent_urls(screen).get(n - 1) {
+                        None => sl.pushf("# No such URL.", &DIM),
+                        Some(u) => match url::open(u) {
+                            Ok(()) => {
+                                sl.pushf("# Opening ", &DIM);
+                                sl.push(u);
+                            }
+                            Err(e) => {
+                                sl.pushf("# Error opening ", &DIM);
+                                sl.push(u);
+                                sl.pushf(format!(": {}", e), &DIM);
+                            }
+                        },
+                    }
+                    screen.push_line(sl);
+                }
+                "log" => {
+                    let room = state.room_name.clone();
+                    let mut sl = Line::default();
+                    if state.transcript.toggle(&room) {
+                        sl.pushf("# Logging this room to ", &DIM);
+                        let dir = state.transcript.room_dir(&room);
+                        sl.pushf(dir.display().to_string(), &DIM_BOLD);
+                        sl.pushf(".", &DIM);
+                    } else {
+                        sl.pushf("# Stopped logging this room.", &DIM);
+                    }
+                    screen.push_line(sl);
+                }
+                "raw" => {
+                    state.raw_text = !state.raw_text;
+                    let mut sl = Line::default();
+                    if state.raw_text {
+                        sl.pushf("# Showing message markup as raw text.", &DIM);
+                    } else {
+                        sl.pushf("# Styling message markup.", &DIM);
+                    }
+                    screen.push_line(sl);
+                }
+                "away" => {
+                    let message = cmd_toks[1..].join(" ");
+                    state.enqueue(&Msg::Away(message.as_str().into()));
                 }
                 "quit" => match split_command_tokens(&cmd_toks, 1) {
                     Ok((_, arg)) => {
-                        state.enqueue(&Sndr::Logout(&arg));
+                        state.quit(&arg);
                     }
                     Err(_) => {
                         return;
@@ -306,9 +570,9 @@ pub fn respond_to_user_input(input: Vec<char>, screen: &mut Screen, state: &mut
 
                 "priv" => match split_command_tokens(&cmd_toks, 2) {
                     Ok((cmds, arg)) => {
-                        state.enqueue(&Sndr::Priv {
-                            who: cmds[1],
-                            text: &arg,
+                        state.enqueue(&Msg::Priv {
+                            who: cmds[1].into(),
+                            text: arg.as_str().into(),
                         });
                     }
                     Err(_) => {
@@ -323,7 +587,7 @@ pub fn respond_to_user_input(input: Vec<char>, screen: &mut Screen, state: &mut
 
                 "name" => match split_command_tokens(&cmd_toks, 1) {
                     Ok((_, arg)) => {
-                        state.enqueue(&Sndr::Name(&arg));
+                        state.enqueue(&Msg::Name(arg.as_str().into()));
                     }
                     Err(_) => {
                         return;
@@ -332,7 +596,7 @@ pub fn respond_to_user_input(input: Vec<char>, screen: &mut Screen, state: &mut
 
                 "join" => match split_command_tokens(&cmd_toks, 1) {
                     Ok((_, arg)) => {
-                        state.enqueue(&Sndr::Join(&arg));
+                        state.enqueue(&Msg::Join(arg.as_str().into()));
                     }
                     Err(_) => {
                         return;
@@ -341,9 +605,9 @@ pub fn respond_to_user_input(input: Vec<char>, screen: &mut Screen, state: &mut
 
                 "who" | "rooms" => match split_command_tokens(&cmd_toks, 1) {
                     Ok((_, arg)) => {
-                        state.enqueue(&Sndr::Query {
-                            what: &cmd,
-                            arg: &arg,
+                        state.enqueue(&Msg::Query {
+                            what: cmd.as_str().into(),
+                            arg: arg.as_str().into(),
                         });
                     }
                     Err(_) => {
@@ -353,7 +617,7 @@ pub fn respond_to_user_input(input: Vec<char>, screen: &mut Screen, state: &mut
 
                 "block" => match split_command_tokens(&cmd_toks, 1) {
                     Ok((_, arg)) => {
-                        state.enqueue(&Sndr::Block(&arg));
+                        state.enqueue(&Msg::Block(arg.as_str().into()));
                     }
                     Err(_) => {
                         return;
@@ -362,7 +626,7 @@ pub fn respond_to_user_input(input: Vec<char>, screen: &mut Screen, state: &mut
 
                 "unblock" => match split_command_tokens(&cmd_toks, 1) {
                     Ok((_, arg)) => {
-                        state.enqueue(&Sndr::Unblock(&arg));
+                        state.enqueue(&Msg::Unblock(arg.as_str().into()));
                     }
                     Err(_) => {
                         return;
@@ -376,12 +640,13 @@ pub fn respond_to_user_input(input: Vec<char>, screen: &mut Screen, state: &mut
                         screen.push_line(sl);
                     }
                     Ok((cmds, arg)) => {
-                        let msg: Option<Sndr> = match cmds[1].to_lowercase().as_str() {
-                            "open" => Some(Sndr::Op(SndOp::Open)),
-                            "close" => Some(Sndr::Op(SndOp::Close)),
-                            "ban" | "kick" => Some(Sndr::Op(SndOp::Kick(&arg))),
-                            "invite" => Some(Sndr::Op(SndOp::Invite(&arg))),
-                            "give" => Some(Sndr::Op(SndOp::Give(&arg))),
+                        let msg: Option<Msg> = match cmds[1].to_lowercase().as_str() {
+                            "open" => Some(Msg::Op(Op::Open)),
+                            "close" => Some(Msg::Op(Op::Close)),
+                            "ban" | "kick" => Some(Msg::Op(Op::Kick(arg.as_str().into()))),
+                            "invite" => Some(Msg::Op(Op::Invite(arg.as_str().into()))),
+                            "give" => Some(Msg::Op(Op::Give(arg.as_str().into()))),
+                            "voice" => Some(Msg::Op(Op::Voice(arg.as_str().into()))),
                             _ => {
                                 let mut sl = Line::default();
                                 sl.pushf(OP_ERROR, &DIM);
@@ -407,12 +672,10 @@ pub fn respond_to_user_input(input: Vec<char>, screen: &mut Screen, state: &mut
     }
 
     let input_str: String = input.into_iter().collect();
-    let lines: Vec<String> = input_str.lines().map(|line| line.to_string()).collect();
-    let lineref: Vec<&str> = lines.iter().map(|x| x.as_str()).collect();
 
-    state.enqueue(&Sndr::Text {
-        who: "",
-        lines: &lineref,
+    state.enqueue(&Msg::Text {
+        who: "".into(),
+        lines: input_str.lines().map(Into::into).collect(),
     });
 }
 
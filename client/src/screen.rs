@@ -1,12 +1,19 @@
 use crossterm::{
     cursor,
+    event::{DisableBracketedPaste, DisableMouseCapture, EnableBracketedPaste, EnableMouseCapture},
     style::{self, Stylize},
     terminal, QueueableCommand,
 };
 use log::trace;
 use std::io::{Stdout, Write};
 
-use crate::{line::*, util::styles::*};
+use crate::{
+    line::*,
+    util::{
+        styles::*,
+        text::{self, Cluster},
+    },
+};
 
 const SPACE: char = ' ';
 const VBAR: char = '│';
@@ -38,16 +45,16 @@ impl Bits {
             horizontal_line.pushf(&s, &DIM);
         }
 
-        let start_len = start.len();
-        let end_len = end.len();
+        let start_len = start.width();
+        let end_len = end.width();
 
         Bits {
-            status_begin: start.first_n_chars(start_len).to_string(),
-            status_end: end.first_n_chars(end_len).to_string(),
+            status_begin: start.first_n_columns(start_len).to_string(),
+            status_end: end.first_n_columns(end_len).to_string(),
             status_begin_length: start_len,
             status_end_length: end_len,
             full_horizontal_line: horizontal_line
-                .first_n_chars((width + 1) as usize)
+                .first_n_columns((width + 1) as usize)
                 .to_string(),
         }
     }
@@ -56,8 +63,12 @@ impl Bits {
 pub struct Screen {
     scrollback: Vec<Line>,
     input: Vec<char>,
-    input_cursor: u16,
+    input_cursor: usize,
+    input_rows: u16,
+    input_selection: Option<(usize, usize)>,
+    scrollback_match: Option<(usize, usize)>,
     roster: Vec<Line>,
+    roster_header: Line,
     roster_width: u16,
     status_upper_left: Line,
     status_upper_right: Line,
@@ -67,6 +78,7 @@ pub struct Screen {
     roster_dirty: bool,
     stat_dirty: bool,
     bits: Bits,
+    mouse: bool,
 
     lines_scroll: u16,
     roster_scroll: u16,
@@ -75,10 +87,17 @@ pub struct Screen {
 }
 
 impl Screen {
-    pub fn new(term: &mut Stdout, roster_chars: u16) -> crossterm::Result<Screen> {
+    /// Take over the terminal. If `mouse`, capture mouse events too (which
+    /// stops the terminal's own text selection from working).
+    pub fn new(term: &mut Stdout, roster_chars: u16, mouse: bool) -> crossterm::Result<Screen> {
         terminal::enable_raw_mode()?;
         let (x, y): (u16, u16) = terminal::size()?;
-        term.queue(cursor::Hide)?.queue(terminal::DisableLineWrap)?;
+        term.queue(cursor::Hide)?
+            .queue(terminal::DisableLineWrap)?
+            .queue(EnableBracketedPaste)?;
+        if mouse {
+            term.queue(EnableMouseCapture)?;
+        }
         term.queue(terminal::SetTitle("Fresh Client"))?;
         term.flush()?;
 
@@ -86,8 +105,12 @@ impl Screen {
             scrollback: Vec::new(),
             input: Vec::new(),
             roster: Vec::new(),
+            roster_header: Line::default(),
             roster_width: roster_chars,
             input_cursor: 0,
+            input_rows: 1,
+            input_selection: None,
+            scrollback_match: None,
             status_upper_left: Line::default(),
             status_upper_right: Line::default(),
             status_lower_left: Line::default(),
@@ -100,12 +123,13 @@ impl Screen {
             terminal_width: x,
             terminal_height: y,
             bits: Bits::new(x),
+            mouse,
         })
     }
 
     /// Return the height of the main scrollback window.
     pub fn get_main_height(&self) -> u16 {
-        self.terminal_height - 2
+        self.terminal_height - 1 - self.input_rows
     }
 
     /// Return the number of `Line`s in the scrollback buffer.
@@ -113,6 +137,16 @@ impl Screen {
         self.scrollback.len()
     }
 
+    /// The text of the scrollback lines from `first` on, without formatting.
+    pub fn scrollback_text(&self, first: usize) -> Vec<String> {
+        self.scrollback
+            .get(first..)
+            .unwrap_or_default()
+            .iter()
+            .map(|l| l.chars().iter().collect())
+            .collect()
+    }
+
     /// Trim the scrollback buffer to the latest `n` lines.
     pub fn prune_scrollback(&mut self, n: usize) {
         if n >= self.scrollback.len() {
@@ -121,6 +155,10 @@ impl Screen {
         let new_zero = self.scrollback.len() - n;
 
         self.scrollback.drain(0..new_zero);
+        self.scrollback_match = match self.scrollback_match {
+            Some((line, start)) if line >= new_zero => Some((line - new_zero, start)),
+            _ => None,
+        };
         self.lines_dirty = true;
     }
 
@@ -130,17 +168,97 @@ impl Screen {
         self.lines_dirty = true;
     }
 
-    /// Populate the roster with the given slice of strings.
-    pub fn set_roster<T: AsRef<str>>(&mut self, items: &[T]) {
-        self.roster = items
-            .iter()
-            .map(|s| {
-                let mut l = Line::default();
-                l.push(s.as_ref());
-                l
-            })
-            .collect();
+    /// Find the next match for `query` in the scrollback after (or, if
+    /// `backward`, before) the current one, wrapping around at either end.
+    /// The match is highlighted and scrolled into view. The search ignores
+    /// case unless `query` has capitals in it. Returns whether anything
+    /// matched.
+    pub fn find_in_scrollback(&mut self, query: &str, backward: bool) -> bool {
+        let query: Vec<char> = query.chars().collect();
+        if query.is_empty() {
+            return false;
+        }
+        let fold_case = !query.iter().any(|c| c.is_uppercase());
+        let fold = |c: char| {
+            if fold_case {
+                c.to_lowercase().next().unwrap_or(c)
+            } else {
+                c
+            }
+        };
+        let query: Vec<char> = query.into_iter().map(fold).collect();
+
+        let mut matches: Vec<(usize, usize)> = Vec::new();
+        for (n, line) in self.scrollback.iter().enumerate() {
+            let chars: Vec<char> = line.chars().iter().copied().map(fold).collect();
+            for (start, w) in chars.windows(query.len()).enumerate() {
+                if w == query.as_slice() {
+                    matches.push((n, start));
+                }
+            }
+        }
+
+        let found = match (self.scrollback_match, backward) {
+            (None, _) => matches.last(),
+            (Some(current), false) => matches
+                .iter()
+                .find(|&&m| m > current)
+                .or_else(|| matches.first()),
+            (Some(current), true) => matches
+                .iter()
+                .rev()
+                .find(|&&m| m < current)
+                .or_else(|| matches.last()),
+        };
+
+        match found.copied() {
+            Some(m) => {
+                self.show_scrollback_match(m, query.len());
+                true
+            }
+            None => {
+                self.clear_scrollback_match();
+                false
+            }
+        }
+    }
+
+    /// Highlight `len` characters of scrollback line `line` from `start`, and
+    /// scroll so they sit in the middle of the chat window.
+    fn show_scrollback_match(&mut self, (line, start): (usize, usize), len: usize) {
+        self.clear_scrollback_match();
+        self.scrollback[line].set_highlight(Some((start, start + len)));
+        self.scrollback_match = Some((line, start));
+
+        let width = (self.terminal_width - self.roster_width - 1) as usize;
+        let rows_below: usize = self.scrollback[line + 1..]
+            .iter_mut()
+            .map(|l| l.lines(width).len())
+            .sum();
+        let rows = self.scrollback[line].lines(width).len();
+        let row = self.scrollback[line].row_of(start, width);
+        let from_bottom = rows_below + (rows - 1 - row);
+        let half = (self.get_main_height() / 2) as usize;
+
+        self.lines_scroll = from_bottom.saturating_sub(half) as u16;
+        self.lines_dirty = true;
+    }
+
+    /// Remove the highlight from the last scrollback search match.
+    pub fn clear_scrollback_match(&mut self) {
+        if let Some((line, _)) = self.scrollback_match.take() {
+            if let Some(l) = self.scrollback.get_mut(line) {
+                l.set_highlight(None);
+            }
+            self.lines_dirty = true;
+        }
+    }
 
+    /// Populate the roster: a `header` line that stays at the top, and a line
+    /// for each user below it.
+    pub fn set_roster(&mut self, header: Line, entries: Vec<Line>) {
+        self.roster_header = header;
+        self.roster = entries;
         self.roster_dirty = true;
     }
 
@@ -149,9 +267,67 @@ impl Screen {
         self.input.len()
     }
 
+    /// Get the contents of the input line.
+    pub fn get_input(&self) -> &[char] {
+        &self.input
+    }
+
+    /// Replace the contents of the input line, leaving the cursor at the end.
+    pub fn set_input(&mut self, new_input: &[char]) {
+        self.input = new_input.to_vec();
+        self.input_cursor = self.input.len();
+        self.input_dirty = true;
+    }
+
+    /// Get the position of the cursor in the input line.
+    pub fn get_input_cursor(&self) -> usize {
+        self.input_cursor
+    }
+
+    /// Replace `len` characters of the input line starting at `start` with
+    /// `text`, leaving the cursor after it.
+    pub fn input_replace(&mut self, start: usize, len: usize, text: &str) {
+        let end = (start + len).min(self.input.len());
+        let start = start.min(end);
+        let new_chars: Vec<char> = text.chars().collect();
+        self.input_cursor = start + new_chars.len();
+        self.input.splice(start..end, new_chars);
+        self.input_dirty = true;
+    }
+
+    /// Replace the contents of the input line and put the cursor at `cursor`.
+    pub fn replace_input(&mut self, new_input: Vec<char>, cursor: usize) {
+        self.input_cursor = cursor.min(new_input.len());
+        self.input = new_input;
+        self.input_dirty = true;
+    }
+
+    /// Highlight the characters in `start..end` of the input line, or, with
+    /// `None`, nothing.
+    pub fn set_input_selection(&mut self, selection: Option<(usize, usize)>) {
+        if self.input_selection != selection {
+            self.input_selection = selection;
+            self.input_dirty = true;
+        }
+    }
+
+    /// Whether the chat text is scrolled back from the most recent line.
+    pub fn is_scrolled_back(&self) -> bool {
+        self.lines_scroll > 0
+    }
+
+    /// Insert a string (like pasted text) into the input line at the cursor.
+    pub fn input_str(&mut self, s: &str) {
+        let input_cursor = self.input_cursor.min(self.input.len());
+        let new_chars: Vec<char> = s.chars().collect();
+        self.input_cursor = input_cursor + new_chars.len();
+        self.input.splice(input_cursor..input_cursor, new_chars);
+        self.input_dirty = true;
+    }
+
     /// Add a `char` to the input line.
     pub fn input_char(&mut self, ch: char) {
-        let input_cursor = self.input_cursor as usize;
+        let input_cursor = self.input_cursor;
 
         if input_cursor >= self.input.len() {
             self.input.push(ch);
@@ -159,25 +335,36 @@ impl Screen {
             self.input.insert(input_cursor, ch);
         }
 
-        self.input_cursor = (input_cursor + 1) as u16;
+        self.input_cursor = input_cursor + 1;
         self.input_dirty = true;
     }
 
+    /// Delete the grapheme cluster before the cursor.
     pub fn input_backspace(&mut self) {
-        let ilen = self.input.len();
-        let input_cursor = self.input_cursor as usize;
+        let input_cursor = self.input_cursor;
 
-        if ilen == 0 || input_cursor == 0 {
+        if self.input.is_empty() || input_cursor == 0 {
             return;
         }
 
-        self.input_cursor = (input_cursor - 1) as u16;
-        self.input.remove(input_cursor - 1);
+        let start = text::prev_boundary(&self.input, input_cursor);
+        self.input.drain(start..input_cursor);
+        self.input_cursor = start;
         self.input_dirty = true;
     }
 
+    /// Delete the grapheme cluster under the cursor.
+    pub fn input_delete_char(&mut self) {
+        let input_cursor = self.input_cursor;
+        if input_cursor < self.input.len() {
+            let end = text::next_boundary(&self.input, input_cursor);
+            self.input.drain(input_cursor..end);
+            self.input_dirty = true;
+        }
+    }
+
     pub fn input_delete_words(&mut self, words_to_delete: i32) {
-        let input_cursor = self.input_cursor as usize;
+        let input_cursor = self.input_cursor;
         let ilen = self.input.len();
 
         if (input_cursor == ilen && words_to_delete > 0)
@@ -222,33 +409,40 @@ impl Screen {
             }
 
             self.input.drain(i..input_cursor);
-            self.input_cursor = i as u16;
+            self.input_cursor = i;
         }
     }
 
-    /// Move the input cursor by `n_chars` characters. Negative values move the
-    /// cursor to the left.
+    /// Move the input cursor by `n_chars` grapheme clusters. Negative values
+    /// move the cursor to the left.
     pub fn input_skip_chars(&mut self, n_chars: i16) {
-        let cur = self.input_cursor as i16;
-        let new = cur + n_chars;
-        let ilen = self.input.len() as u16;
-
-        self.input_cursor = if new < 0 {
-            0
-        } else {
-            let new = new as u16;
-            if new > ilen {
-                ilen
+        let mut cur = self.input_cursor.min(self.input.len());
+        for _ in 0..n_chars.unsigned_abs() {
+            cur = if n_chars < 0 {
+                text::prev_boundary(&self.input, cur)
             } else {
-                new
-            }
-        };
+                text::next_boundary(&self.input, cur)
+            };
+        }
+
+        self.input_cursor = cur;
+        self.input_dirty = true;
+    }
 
+    /// Move the input cursor to the start of the input line.
+    pub fn input_to_start(&mut self) {
+        self.input_cursor = 0;
+        self.input_dirty = true;
+    }
+
+    /// Move the input cursor to the end of the input line.
+    pub fn input_to_end(&mut self) {
+        self.input_cursor = self.input.len();
         self.input_dirty = true;
     }
 
     pub fn input_skip_words(&mut self, words_to_skip: i32) {
-        let uip = self.input_cursor as usize;
+        let uip = self.input_cursor;
 
         if uip == self.input.len() && words_to_skip > 0 {
             return;
@@ -272,13 +466,13 @@ impl Screen {
                 } else if c.is_whitespace() {
                     words_skipped += 1;
                     if words_skipped >= words_to_skip {
-                        self.input_cursor = (uip + i) as u16;
+                        self.input_cursor = uip + i;
                         return;
                     }
                     in_ws = true;
                 }
             }
-            self.input_cursor = self.input.len() as u16;
+            self.input_cursor = self.input.len();
         } else {
             let words_to_skip_abs = words_to_skip.abs();
             let mut in_ws = false;
@@ -294,7 +488,7 @@ impl Screen {
                 } else if c.is_whitespace() {
                     words_skipped += 1;
                     if words_skipped >= words_to_skip_abs {
-                        self.input_cursor = (uip - i) as u16;
+                        self.input_cursor = uip - i;
                         return;
                     }
                     in_ws = true;
@@ -313,8 +507,49 @@ impl Screen {
         self.lines_dirty = true;
     }
 
+    /// Whether the terminal cell at (`col`, `row`) is in the roster pane.
+    pub fn in_roster(&self, col: u16, row: u16) -> bool {
+        col + self.roster_width + 1 >= self.terminal_width && row < self.get_main_height()
+    }
+
+    /// The index of the roster entry shown at (`col`, `row`), if any.
+    pub fn roster_at(&self, col: u16, row: u16) -> Option<usize> {
+        if !self.in_roster(col, row) || row < 2 {
+            return None;
+        }
+        let i = self.roster_scroll as usize + (row - 2) as usize;
+        (i < self.roster.len()).then_some(i)
+    }
+
+    /// The position in the input of the character shown at (`col`, `row`), or
+    /// `None` if that cell isn't in the input area. Clicking past the end of a
+    /// row lands at its end.
+    pub fn input_index_at(&self, col: u16, row: u16) -> Option<usize> {
+        let top = self.terminal_height - self.input_rows;
+        if row < top {
+            return None;
+        }
+        let (rows, cursor_row) = self.layout_input(self.terminal_width as usize);
+        let first_row = (cursor_row + 1).saturating_sub(self.input_rows as usize);
+        let r = first_row + (row - top) as usize;
+        let clusters = rows.get(r)?;
+
+        let mut x: usize = 0;
+        for cluster in clusters {
+            let newline = self.input[cluster.start] == '\n';
+            if newline || (col as usize) < x + cluster.width {
+                return Some(cluster.start);
+            }
+            x += cluster.width;
+        }
+        match clusters.last() {
+            Some(cluster) if r + 1 < rows.len() => Some(cluster.start),
+            _ => Some(self.input.len()),
+        }
+    }
+
     pub fn scroll_roster(&mut self, n_chars: i16) {
-        let rost_vsize = self.terminal_height - 3;
+        let rost_vsize = self.get_main_height().saturating_sub(2);
         if rost_vsize as usize >= self.roster.len() {
             if self.roster_scroll != 0 {
                 self.roster_dirty = true;
@@ -362,11 +597,10 @@ impl Screen {
     pub fn resize(&mut self, cols: u16, rows: u16) {
         if cols != self.terminal_width || rows != self.terminal_height {
             if cols != self.terminal_width {
-                let horizontal_line =
-                    String::from_iter(std::iter::repeat(HBAR).take(cols as usize));
+                let horizontal_line = HBAR.to_string().repeat(cols as usize);
                 let mut line = Line::default();
                 line.pushf(&horizontal_line, &DIM);
-                self.bits.full_horizontal_line = line.first_n_chars(cols as usize).to_string();
+                self.bits.full_horizontal_line = line.first_n_columns(cols as usize).to_string();
             }
 
             self.lines_dirty = true;
@@ -451,10 +685,17 @@ impl Screen {
             let mut l = Line::default();
             l.pushf(String::from(VBAR), &DIM);
             l.push(&s);
-            l.first_n_chars(roster_width_with_vbar).to_string()
+            l.first_n_columns(roster_width_with_vbar).to_string()
         };
 
-        let mut y: u16 = 1;
+        term.queue(cursor::MoveTo(x_start, 1))?
+            .queue(style::Print(&blank_line))?
+            .queue(cursor::MoveTo(x_start + 1, 1))?
+            .queue(style::Print(
+                self.roster_header.first_n_columns(roster_width),
+            ))?;
+
+        let mut y: u16 = 2;
         let target_y = height;
         let roster_scroll = self.roster_scroll as usize;
 
@@ -466,7 +707,7 @@ impl Screen {
                 term.queue(cursor::MoveTo(x_start, y))?
                     .queue(style::Print(&blank_line))?
                     .queue(cursor::MoveTo(x_start + 1, y))?
-                    .queue(style::Print(line.first_n_chars(roster_width)))?;
+                    .queue(style::Print(line.first_n_columns(roster_width)))?;
                 y += 1;
             }
         }
@@ -481,43 +722,90 @@ impl Screen {
         Ok(())
     }
 
-    /// Refresh the screen, if necessary.
-    fn refresh_input(&mut self, term: &mut Stdout) -> crossterm::Result<()> {
-        term.queue(cursor::MoveTo(0, self.terminal_height - 1))?
-            .queue(terminal::Clear(terminal::ClearType::CurrentLine))?
-            .queue(cursor::MoveToColumn(0))?;
-
-        let third = self.terminal_width / 3;
-        let maxpos = self.terminal_width - third;
-        let start_cursor_position =
-            if self.input.len() < self.terminal_width as usize || self.input_cursor < third {
-                0
-            } else if self.input_cursor > maxpos {
-                self.input_cursor - maxpos
-            } else {
-                self.input_cursor - third
-            };
+    /// Lay the input out in rows `width` columns wide, breaking after newlines
+    /// and wherever a row fills up. Returns the grapheme clusters in each row,
+    /// and which row the cursor is in.
+    fn layout_input(&self, width: usize) -> (Vec<Vec<Cluster>>, usize) {
+        let input_cursor = self.input_cursor;
+        let mut rows: Vec<Vec<Cluster>> = vec![Vec::new()];
+        let mut col: usize = 0;
+        let mut cursor_row: Option<usize> = None;
+
+        for cluster in text::clusters(&self.input) {
+            if col > 0 && col + cluster.width > width {
+                rows.push(Vec::new());
+                col = 0;
+            }
+            if cluster.start <= input_cursor && input_cursor < cluster.end {
+                cursor_row = Some(rows.len() - 1);
+            }
+            let newline = self.input[cluster.start] == '\n';
+            col += cluster.width;
+            if let Some(row) = rows.last_mut() {
+                row.push(cluster);
+            }
+            if newline {
+                rows.push(Vec::new());
+                col = 0;
+            }
+        }
 
-        let input_cursor_us = self.input_cursor as usize;
-        let end_cursor_position =
-            ((start_cursor_position + self.terminal_width) as usize).min(self.input.len());
+        let cursor_row = cursor_row.unwrap_or_else(|| {
+            // The cursor is past the end, where it needs a column of its own.
+            if col + 1 > width {
+                rows.push(Vec::new());
+            }
+            rows.len() - 1
+        });
+        (rows, cursor_row)
+    }
 
-        for (i, c) in self.input[start_cursor_position as usize..end_cursor_position]
-            .iter()
-            .enumerate()
-        {
-            let i = i + start_cursor_position as usize;
-            let c = if i == input_cursor_us {
-                style::style(*c).attribute(style::Attribute::Reverse)
-            } else {
-                style::style(*c)
-            };
-            term.queue(style::PrintStyledContent(c))?;
-        }
+    /// The number of rows the input area needs: enough for all of the input,
+    /// up to a third of the terminal.
+    fn wanted_input_rows(&self) -> u16 {
+        let (rows, _) = self.layout_input(self.terminal_width as usize);
+        let max = (self.terminal_height / 3).max(1);
+        rows.len().clamp(1, max as usize) as u16
+    }
 
-        if input_cursor_us == self.input.len() {
-            let cch = style::style(' ').attribute(style::Attribute::Reverse);
-            term.queue(style::PrintStyledContent(cch))?;
+    fn refresh_input(&mut self, term: &mut Stdout) -> crossterm::Result<()> {
+        let (rows, cursor_row) = self.layout_input(self.terminal_width as usize);
+        let height = self.input_rows as usize;
+        let first_row = (cursor_row + 1).saturating_sub(height);
+        let top = self.terminal_height - self.input_rows;
+        let input_cursor = self.input_cursor;
+
+        for r in 0..height {
+            term.queue(cursor::MoveTo(0, top + r as u16))?
+                .queue(terminal::Clear(terminal::ClearType::CurrentLine))?;
+
+            for cluster in rows.get(first_row + r).map_or(&[][..], Vec::as_slice) {
+                let under_cursor = cluster.start <= input_cursor && input_cursor < cluster.end;
+                let selected = matches!(
+                    self.input_selection,
+                    Some((a, b)) if a <= cluster.start && cluster.start < b
+                );
+                let g: String = if self.input[cluster.start] == '\n' {
+                    if under_cursor {
+                        String::from(SPACE)
+                    } else {
+                        continue;
+                    }
+                } else {
+                    self.input[cluster.start..cluster.end].iter().collect()
+                };
+                let g = if under_cursor || selected {
+                    style::style(g).attribute(style::Attribute::Reverse)
+                } else {
+                    style::style(g)
+                };
+                term.queue(style::PrintStyledContent(g))?;
+            }
+
+            if first_row + r == cursor_row && input_cursor >= self.input.len() {
+                let cch = style::style(SPACE).attribute(style::Attribute::Reverse);
+                term.queue(style::PrintStyledContent(cch))?;
+            }
         }
 
         self.input_dirty = false;
@@ -529,14 +817,14 @@ impl Screen {
 
         let stat_padding = 2 + self.bits.status_begin_length + self.bits.status_end_length;
         let stat_width = (self.terminal_width as usize) - stat_padding;
-        let lower_line_y = self.terminal_height - 2;
+        let lower_line_y = self.get_main_height();
 
         term.queue(cursor::MoveTo(0, lower_line_y))?
             .queue(style::Print(&self.bits.full_horizontal_line))?
             .queue(cursor::MoveTo(1, lower_line_y))?
             .queue(style::Print(&self.bits.status_begin))?
             .queue(style::Print(
-                self.status_lower_left.first_n_chars(stat_width),
+                self.status_lower_left.first_n_columns(stat_width),
             ))?
             .queue(style::Print(&self.bits.status_end))?;
 
@@ -549,19 +837,19 @@ impl Screen {
             .queue(style::Print(&self.bits.full_horizontal_line))?
             .queue(cursor::MoveTo(1, 0))?
             .queue(style::Print(&self.bits.status_begin))?;
-        if self.status_upper_left.len() > space_per_section {
+        if self.status_upper_left.width() > space_per_section {
             term.queue(style::Print(
-                self.status_upper_left.first_n_chars(abbreviation_space),
+                self.status_upper_left.first_n_columns(abbreviation_space),
             ))?
             .queue(style::Print("..."))?;
         } else {
             term.queue(style::Print(
-                self.status_upper_left.first_n_chars(space_per_section),
+                self.status_upper_left.first_n_columns(space_per_section),
             ))?;
         }
         term.queue(style::Print(&self.bits.status_end))?;
 
-        let upper_right_offset: u16 = if self.status_upper_right.len() > space_per_section {
+        let upper_right_offset: u16 = if self.status_upper_right.width() > space_per_section {
             self.terminal_width
                 - (2 + self.bits.status_begin_length
                     + self.bits.status_end_length
@@ -570,19 +858,19 @@ impl Screen {
             self.terminal_width
                 - (2 + self.bits.status_begin_length
                     + self.bits.status_end_length
-                    + self.status_upper_right.len()) as u16
+                    + self.status_upper_right.width()) as u16
         };
 
         term.queue(cursor::MoveTo(upper_right_offset, 0))?
             .queue(style::Print(&self.bits.status_begin))?;
-        if self.status_upper_right.len() > space_per_section {
+        if self.status_upper_right.width() > space_per_section {
             term.queue(style::Print(
-                self.status_upper_right.first_n_chars(abbreviation_space),
+                self.status_upper_right.first_n_columns(abbreviation_space),
             ))?
             .queue(style::Print("..."))?;
         } else {
             term.queue(style::Print(
-                self.status_upper_right.first_n_chars(space_per_section),
+                self.status_upper_right.first_n_columns(space_per_section),
             ))?;
         }
         term.queue(style::Print(&self.bits.status_end))?;
@@ -607,9 +895,19 @@ impl Screen {
             return Ok(());
         }
 
+        if self.input_dirty {
+            let input_rows = self.wanted_input_rows();
+            if input_rows != self.input_rows {
+                self.input_rows = input_rows;
+                self.lines_dirty = true;
+                self.roster_dirty = true;
+                self.stat_dirty = true;
+            }
+        }
+
         let roster_width = self.roster_width + 1;
         let main_width = self.terminal_width - roster_width;
-        let main_height = self.terminal_height - 2;
+        let main_height = self.get_main_height();
 
         if main_width < 20 || main_height < 5 {
             return self
@@ -642,6 +940,8 @@ impl Drop for Screen {
     fn drop(&mut self) {
         let mut term = std::io::stdout();
         term.queue(cursor::Show)
+            .unwrap()
+            .queue(DisableBracketedPaste)
             .unwrap()
             .queue(terminal::EnableLineWrap)
             .unwrap()
@@ -649,6 +949,9 @@ impl Drop for Screen {
             .unwrap()
             .queue(cursor::MoveTo(0, 0))
             .unwrap();
+        if self.mouse {
+            term.queue(DisableMouseCapture).unwrap();
+        }
         term.flush().unwrap();
         terminal::disable_raw_mode().unwrap();
     }
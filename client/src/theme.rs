@@ -0,0 +1,192 @@
+use common::config::ThemeConfig;
+use crossterm::style::Color;
+
+/// The colours the client's styles are built from. `None` leaves the
+/// terminal's own colour alone.
+#[derive(Clone, Debug)]
+pub struct Theme {
+    pub dim: Option<Color>,
+    pub highlight: Option<Color>,
+    pub code: Option<Color>,
+    pub code_background: Option<Color>,
+    /// Colours to draw nicks in, picked between by a hash of the name.
+    pub nicks: Vec<Color>,
+}
+
+/// A bundled theme: its palette entries and nick colours.
+type Bundled = (
+    &'static [(&'static str, &'static str)],
+    &'static [&'static str],
+);
+
+const DARK: Bundled = (
+    &[
+        ("dim", "239"),
+        ("highlight", "white"),
+        ("code", "223"),
+        ("code_background", "236"),
+    ],
+    &[
+        "167", "173", "179", "143", "107", "72", "73", "74", "110", "140", "175", "217",
+    ],
+);
+
+const LIGHT: Bundled = (
+    &[
+        ("dim", "246"),
+        ("highlight", "black"),
+        ("code", "88"),
+        ("code_background", "254"),
+    ],
+    &[
+        "124", "130", "94", "58", "22", "29", "30", "25", "61", "90", "125", "160",
+    ],
+);
+
+const SOLARIZED: Bundled = (
+    &[
+        ("dim", "#586e75"),
+        ("highlight", "#eee8d5"),
+        ("code", "#b58900"),
+        ("code_background", "#073642"),
+    ],
+    &[
+        "#b58900", "#cb4b16", "#dc322f", "#d33682", "#6c71c4", "#268bd2", "#2aa198", "#859900",
+    ],
+);
+
+const MONO: Bundled = (
+    &[
+        ("dim", "none"),
+        ("highlight", "none"),
+        ("code", "none"),
+        ("code_background", "none"),
+    ],
+    &[],
+);
+
+/// Parse a colour: a name like `"white"` or `"dark_red"`, an index into the
+/// 256-colour palette like `"239"`, a truecolor `"#rrggbb"`, or `"none"`.
+fn parse_color(s: &str) -> Result<Option<Color>, String> {
+    if let Some(hex) = s.strip_prefix('#') {
+        let channel = |n: usize| {
+            hex.get(n..n + 2)
+                .and_then(|x| u8::from_str_radix(x, 16).ok())
+        };
+        return match (hex.len(), channel(0), channel(2), channel(4)) {
+            (6, Some(r), Some(g), Some(b)) => Ok(Some(Color::Rgb { r, g, b })),
+            _ => Err(format!("unrecognized colour \"{}\"", s)),
+        };
+    }
+    if let Ok(n) = s.parse::<u8>() {
+        return Ok(Some(Color::AnsiValue(n)));
+    }
+
+    let name: String = s
+        .chars()
+        .filter(|c| !matches!(c, '_' | '-' | ' '))
+        .collect::<String>()
+        .to_lowercase();
+    let color = match name.as_str() {
+        "none" | "default" => return Ok(None),
+        "black" => Color::Black,
+        "darkgrey" | "darkgray" => Color::DarkGrey,
+        "red" => Color::Red,
+        "darkred" => Color::DarkRed,
+        "green" => Color::Green,
+        "darkgreen" => Color::DarkGreen,
+        "yellow" => Color::Yellow,
+        "darkyellow" => Color::DarkYellow,
+        "blue" => Color::Blue,
+        "darkblue" => Color::DarkBlue,
+        "magenta" => Color::Magenta,
+        "darkmagenta" => Color::DarkMagenta,
+        "cyan" => Color::Cyan,
+        "darkcyan" => Color::DarkCyan,
+        "white" => Color::White,
+        "grey" | "gray" => Color::Grey,
+        _ => return Err(format!("unrecognized colour \"{}\"", s)),
+    };
+    Ok(Some(color))
+}
+
+impl Theme {
+    fn empty() -> Theme {
+        Theme {
+            dim: None,
+            highlight: None,
+            code: None,
+            code_background: None,
+            nicks: Vec::new(),
+        }
+    }
+
+    /// Set the palette entry `name` to the colour `value`.
+    fn set(&mut self, name: &str, value: &str) -> Result<(), String> {
+        let color = parse_color(value)?;
+        match name {
+            "dim" => self.dim = color,
+            "highlight" => self.highlight = color,
+            "code" => self.code = color,
+            "code_background" => self.code_background = color,
+            x => return Err(format!("unrecognized palette entry \"{}\"", x)),
+        }
+        Ok(())
+    }
+
+    fn set_nicks<'a, I>(&mut self, colors: I) -> Result<(), String>
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        self.nicks.clear();
+        for value in colors {
+            if let Some(color) = parse_color(value)? {
+                self.nicks.push(color);
+            }
+        }
+        Ok(())
+    }
+
+    /// Build the theme from the `[theme]` config table: the named bundled
+    /// theme (`dark`, the default, `light`, `solarized` or `mono`) with the
+    /// configured palette entries and nick colours on top. If the `NO_COLOR`
+    /// environment variable is set, all colours are dropped.
+    pub fn from_config(cfg: &ThemeConfig) -> Result<Theme, String> {
+        let (palette, nicks) = match cfg.name.as_deref().unwrap_or("dark") {
+            "dark" => DARK,
+            "light" => LIGHT,
+            "solarized" => SOLARIZED,
+            "mono" => MONO,
+            x => return Err(format!("[theme]: unrecognized theme \"{}\"", x)),
+        };
+
+        let mut theme = Theme::empty();
+        for (name, value) in palette.iter() {
+            theme.set(name, value)?;
+        }
+        theme.set_nicks(nicks.iter().copied())?;
+
+        for (name, value) in cfg.palette.iter() {
+            theme
+                .set(name, value)
+                .map_err(|e| format!("[theme.palette]: {}", e))?;
+        }
+        if !cfg.nicks.is_empty() {
+            theme
+                .set_nicks(cfg.nicks.iter().map(String::as_str))
+                .map_err(|e| format!("[theme]: nicks: {}", e))?;
+        }
+
+        if matches!(std::env::var_os("NO_COLOR"), Some(v) if !v.is_empty()) {
+            theme = Theme::empty();
+        }
+
+        Ok(theme)
+    }
+}
+
+impl Default for Theme {
+    fn default() -> Theme {
+        Theme::from_config(&ThemeConfig::default()).unwrap_or_else(|_| Theme::empty())
+    }
+}
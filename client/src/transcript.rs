@@ -0,0 +1,159 @@
+use common::util::collapse;
+use log::warn;
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use time::{format_description::well_known::Rfc3339, macros::format_description, OffsetDateTime};
+
+/// How each entry is written: as a line of text, or as a JSON object.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Text,
+    Json,
+}
+
+/// What an entry records.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    Text,
+    Priv,
+    Event,
+}
+
+impl Kind {
+    fn name(self) -> &'static str {
+        match self {
+            Kind::Text => "text",
+            Kind::Priv => "priv",
+            Kind::Event => "event",
+        }
+    }
+}
+
+/// Keep path components to characters that are safe in file names.
+fn sanitize(s: &str) -> String {
+    let s: String = s
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '-' || c == '.' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if s.is_empty() || s.chars().all(|c| c == '.') {
+        String::from("_")
+    } else {
+        s
+    }
+}
+
+/// Writes what's said in each room to a transcript file per server, room and
+/// day, for the rooms logging is turned on for.
+pub struct Transcript {
+    dir: PathBuf,
+    format: Format,
+    enabled: bool,
+    overrides: HashMap<String, bool>,
+    file: Option<(PathBuf, File)>,
+}
+
+impl Transcript {
+    /// Transcripts from the server at `server` go under `dir`. If `enabled`,
+    /// rooms are logged unless turned off.
+    pub fn new(dir: &Path, server: &str, format: Format, enabled: bool) -> Transcript {
+        Transcript {
+            dir: dir.join(sanitize(server)),
+            format,
+            enabled,
+            overrides: HashMap::new(),
+            file: None,
+        }
+    }
+
+    pub fn is_enabled(&self, room: &str) -> bool {
+        *self.overrides.get(&collapse(room)).unwrap_or(&self.enabled)
+    }
+
+    /// Turn logging for `room` on or off, returning whether it's now on.
+    pub fn toggle(&mut self, room: &str) -> bool {
+        let enabled = !self.is_enabled(room);
+        self.overrides.insert(collapse(room), enabled);
+        enabled
+    }
+
+    /// The directory `room`'s transcripts go in.
+    pub fn room_dir(&self, room: &str) -> PathBuf {
+        self.dir.join(sanitize(&collapse(room)))
+    }
+
+    /// The file `now`'s entries for `room` go in; there's one for each day.
+    fn path(&self, room: &str, now: OffsetDateTime) -> PathBuf {
+        let date = now
+            .format(format_description!("[year]-[month]-[day]"))
+            .unwrap_or_default();
+        let extension = match self.format {
+            Format::Text => "log",
+            Format::Json => "jsonl",
+        };
+        self.room_dir(room).join(format!("{}.{}", date, extension))
+    }
+
+    fn now() -> OffsetDateTime {
+        OffsetDateTime::now_local().unwrap_or_else(|_| OffsetDateTime::now_utc())
+    }
+
+    /// Write `entry` to the file at `path`, opening it (and closing the
+    /// previous one) if it isn't already.
+    fn write(&mut self, path: PathBuf, entry: &str) -> std::io::Result<()> {
+        if self.file.as_ref().map(|(p, _)| p) != Some(&path) {
+            self.file = None;
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            let f = OpenOptions::new().create(true).append(true).open(&path)?;
+            self.file = Some((path, f));
+        }
+        match self.file.as_mut() {
+            Some((_, f)) => writeln!(f, "{}", entry),
+            None => Ok(()),
+        }
+    }
+
+    /// Add an entry to `room`'s transcript, if it's being logged.
+    pub fn record(&mut self, room: &str, kind: Kind, who: Option<&str>, text: &str) {
+        if !self.is_enabled(room) {
+            return;
+        }
+
+        let now = Self::now();
+        let entry = match self.format {
+            Format::Text => {
+                let time = now
+                    .format(format_description!("[hour]:[minute]:[second]"))
+                    .unwrap_or_default();
+                match (kind, who) {
+                    (Kind::Priv, Some(who)) => format!("[{}] $ {}: {}", time, who, text),
+                    (_, Some(who)) => format!("[{}] {}: {}", time, who, text),
+                    (_, None) => format!("[{}] {}", time, text),
+                }
+            }
+            Format::Json => serde_json::json!({
+                "time": now.format(&Rfc3339).unwrap_or_default(),
+                "room": room,
+                "kind": kind.name(),
+                "who": who,
+                "text": text,
+            })
+            .to_string(),
+        };
+
+        let path = self.path(room, now);
+        if let Err(e) = self.write(path.clone(), &entry) {
+            warn!("Error writing transcript {}: {}", path.display(), e);
+            self.file = None;
+        }
+    }
+}
@@ -1 +1,3 @@
 pub mod styles;
+pub mod text;
+pub mod url;
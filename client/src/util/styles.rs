@@ -1,26 +1,66 @@
 use crossterm::style;
 use lazy_static::lazy_static;
+use std::sync::RwLock;
 
-use crate::line::Style;
+use crate::{line::Style, theme::Theme};
+use common::util::collapse;
 
 lazy_static! {
-    pub static ref DIM: Style = Style::new(Some(style::Color::AnsiValue(239)), None, None);
-    pub static ref DIM_BOLD: Style = Style::new(
-        Some(style::Color::AnsiValue(239)),
-        None,
-        Some(&[style::Attribute::Bold])
-    );
+    static ref THEME: RwLock<Theme> = RwLock::new(Theme::default());
+}
+
+/// Use `theme` for all styles. This has to happen before any of them is first
+/// used, as each is built only once.
+pub fn set_theme(theme: Theme) {
+    if let Ok(mut t) = THEME.write() {
+        *t = theme;
+    }
+}
+
+fn theme() -> Theme {
+    match THEME.read() {
+        Ok(t) => t.clone(),
+        Err(_) => Theme::default(),
+    }
+}
+
+lazy_static! {
+    pub static ref DIM: Style = Style::new(theme().dim, None, None);
+    pub static ref DIM_BOLD: Style = Style::new(theme().dim, None, Some(&[style::Attribute::Bold]));
     pub static ref BOLD: Style = Style::new(None, None, Some(&[style::Attribute::Bold]));
-    pub static ref HIGHLIGHT: Style = Style::new(Some(style::Color::White), None, None);
-    pub static ref HIGHLIGHT_BOLD: Style = Style::new(
-        Some(style::Color::White),
-        None,
-        Some(&[style::Attribute::Bold])
-    );
+    pub static ref HIGHLIGHT: Style = Style::new(theme().highlight, None, None);
+    pub static ref HIGHLIGHT_BOLD: Style =
+        Style::new(theme().highlight, None, Some(&[style::Attribute::Bold]));
+    pub static ref ITALIC: Style = Style::new(None, None, Some(&[style::Attribute::Italic]));
+    pub static ref STRIKE: Style = Style::new(None, None, Some(&[style::Attribute::CrossedOut]));
+    pub static ref CODE: Style = {
+        let t = theme();
+        Style::new(t.code, t.code_background, None)
+    };
     pub static ref REVERSE: Style = Style::new(None, None, Some(&[style::Attribute::Reverse]));
     pub static ref RESET_ALL: Style = Style::new(
         Some(style::Color::Reset),
         Some(style::Color::Reset),
         Some(&[style::Attribute::Reset])
     );
+    static ref NICKS: Vec<Style> = theme()
+        .nicks
+        .iter()
+        .map(|c| Style::new(Some(*c), None, None))
+        .collect();
+}
+
+/// The style to draw `name` in: one of the theme's nick colours, picked by a
+/// hash of the collapsed name so it's the same everywhere and every time.
+pub fn nick(name: &str) -> &'static Style {
+    if NICKS.is_empty() {
+        return &HIGHLIGHT;
+    }
+    // FNV-1a, which unlike the standard library's hasher is stable across runs.
+    let hash = collapse(name)
+        .bytes()
+        .fold(0xcbf2_9ce4_8422_2325_u64, |h, b| {
+            (h ^ b as u64).wrapping_mul(0x0000_0100_0000_01b3)
+        });
+    &NICKS[(hash % NICKS.len() as u64) as usize]
 }
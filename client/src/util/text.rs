@@ -0,0 +1,139 @@
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+/// A grapheme cluster within a slice of `char`s: the range of indices it
+/// covers and how many terminal columns it takes up.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cluster {
+    pub start: usize,
+    pub end: usize,
+    pub width: usize,
+}
+
+/// The number of terminal columns a grapheme cluster takes up. Terminals draw
+/// a zero-width-joined sequence (like a family emoji) as a single glyph, as
+/// wide as its first part.
+pub fn grapheme_width(g: &str) -> usize {
+    g.split('\u{200d}').next().map_or(0, UnicodeWidthStr::width)
+}
+
+/// Split `chars` into grapheme clusters.
+pub fn clusters(chars: &[char]) -> Vec<Cluster> {
+    let s: String = chars.iter().collect();
+    let mut v = Vec::new();
+    let mut start = 0;
+    for g in s.graphemes(true) {
+        let end = start + g.chars().count();
+        v.push(Cluster {
+            start,
+            end,
+            width: grapheme_width(g),
+        });
+        start = end;
+    }
+    v
+}
+
+/// The number of terminal columns `chars` take up.
+pub fn width(chars: &[char]) -> usize {
+    clusters(chars).iter().map(|c| c.width).sum()
+}
+
+/// The index of the start of the grapheme cluster after the one at `i`.
+pub fn next_boundary(chars: &[char], i: usize) -> usize {
+    clusters(chars)
+        .iter()
+        .find(|c| c.end > i)
+        .map_or(chars.len(), |c| c.end)
+}
+
+/// The index of the start of the grapheme cluster before the one at `i`.
+pub fn prev_boundary(chars: &[char], i: usize) -> usize {
+    clusters(chars)
+        .iter()
+        .rev()
+        .find(|c| c.start < i)
+        .map_or(0, |c| c.start)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chars(s: &str) -> Vec<char> {
+        s.chars().collect()
+    }
+
+    const FAMILY: &str = "\u{1f468}\u{200d}\u{1f469}\u{200d}\u{1f467}";
+
+    #[test]
+    fn clusters_of_combining_characters() {
+        let text = chars("e\u{301}x");
+        assert_eq!(
+            clusters(&text),
+            vec![
+                Cluster {
+                    start: 0,
+                    end: 2,
+                    width: 1
+                },
+                Cluster {
+                    start: 2,
+                    end: 3,
+                    width: 1
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn clusters_of_zero_width_joined_emoji() {
+        let text = chars(FAMILY);
+        assert_eq!(text.len(), 5);
+        assert_eq!(
+            clusters(&text),
+            vec![Cluster {
+                start: 0,
+                end: 5,
+                width: 2
+            }]
+        );
+    }
+
+    #[test]
+    fn widths() {
+        assert_eq!(width(&chars("")), 0);
+        assert_eq!(width(&chars("abc")), 3);
+        assert_eq!(width(&chars("日本語")), 6);
+        assert_eq!(width(&chars("e\u{301}e\u{301}")), 2);
+        assert_eq!(width(&chars("a\u{200b}b")), 2);
+        assert_eq!(width(&chars(FAMILY)), 2);
+        assert_eq!(width(&chars("a日\u{301}b")), 4);
+    }
+
+    #[test]
+    fn boundaries_skip_whole_clusters() {
+        let text = chars("e\u{301}日x");
+        assert_eq!(next_boundary(&text, 0), 2);
+        assert_eq!(next_boundary(&text, 1), 2);
+        assert_eq!(next_boundary(&text, 2), 3);
+        assert_eq!(next_boundary(&text, 3), 4);
+        assert_eq!(next_boundary(&text, 4), 4);
+
+        assert_eq!(prev_boundary(&text, 4), 3);
+        assert_eq!(prev_boundary(&text, 3), 2);
+        assert_eq!(prev_boundary(&text, 2), 0);
+        assert_eq!(prev_boundary(&text, 1), 0);
+        assert_eq!(prev_boundary(&text, 0), 0);
+    }
+
+    #[test]
+    fn boundaries_around_zero_width_characters() {
+        let text = chars(&format!("a\u{200b}{}b", FAMILY));
+        assert_eq!(next_boundary(&text, 0), 1);
+        assert_eq!(next_boundary(&text, 1), 2);
+        assert_eq!(next_boundary(&text, 2), 7);
+        assert_eq!(prev_boundary(&text, 7), 2);
+        assert_eq!(prev_boundary(&text, 8), 7);
+    }
+}
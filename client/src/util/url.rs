@@ -0,0 +1,120 @@
+use std::process::{Command, Stdio};
+
+const SCHEMES: &[&str] = &["https://", "http://", "ftp://"];
+/// Punctuation that's more likely to end the sentence around a URL than the URL.
+const TRAILING: &[char] = &['.', ',', ';', ':', '!', '?', '\'', '"', '*', '_', '~', '`'];
+
+/// A URL found in a slice of `char`s: the range of indices it covers and the
+/// URL itself.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Url {
+    pub start: usize,
+    pub end: usize,
+    pub target: String,
+}
+
+/// The length of the scheme `chars` starts with, if it starts with one.
+fn scheme_len(chars: &[char]) -> Option<usize> {
+    SCHEMES
+        .iter()
+        .find(|s| {
+            chars.len() > s.len()
+                && s.chars()
+                    .zip(chars)
+                    .all(|(a, b)| a == b.to_ascii_lowercase())
+        })
+        .map(|s| s.len())
+}
+
+fn is_url_char(c: char) -> bool {
+    !c.is_whitespace() && !c.is_control() && !matches!(c, '<' | '>' | '"')
+}
+
+/// Whether the URL in `chars` ends in a closing bracket with no opening one.
+fn unbalanced(chars: &[char], open: char, close: char) -> bool {
+    chars.last() == Some(&close)
+        && chars.iter().filter(|&&c| c == open).count()
+            < chars.iter().filter(|&&c| c == close).count()
+}
+
+/// Find the URLs in `chars`. A URL starts with a scheme at the start of a
+/// word and runs to the next whitespace, less any trailing punctuation or
+/// closing bracket that isn't part of it.
+pub fn find(chars: &[char]) -> Vec<Url> {
+    let mut urls = Vec::new();
+    let mut i: usize = 0;
+
+    while i < chars.len() {
+        let at_word_start = i == 0 || !chars[i - 1].is_alphanumeric();
+        let scheme_len = match scheme_len(&chars[i..]) {
+            Some(len) if at_word_start => len,
+            _ => {
+                i += 1;
+                continue;
+            }
+        };
+
+        let mut end = i + scheme_len;
+        while end < chars.len() && is_url_char(chars[end]) {
+            end += 1;
+        }
+        while end > i + scheme_len {
+            let url = &chars[i..end];
+            if TRAILING.contains(&url[url.len() - 1])
+                || unbalanced(url, '(', ')')
+                || unbalanced(url, '[', ']')
+            {
+                end -= 1;
+            } else {
+                break;
+            }
+        }
+
+        if end > i + scheme_len {
+            urls.push(Url {
+                start: i,
+                end,
+                target: chars[i..end].iter().collect(),
+            });
+        }
+        i = end.max(i + 1);
+    }
+
+    urls
+}
+
+/// Whether the terminal is likely to understand OSC 8 hyperlinks. Most do,
+/// and most of those that don't just ignore them, but the Linux console and
+/// GNU screen print them as garbage.
+pub fn hyperlinks_supported() -> bool {
+    match std::env::var("TERM") {
+        Ok(term) => !(term == "dumb" || term == "linux" || term.starts_with("screen")),
+        Err(_) => std::env::var_os("WT_SESSION").is_some(),
+    }
+}
+
+/// Open `target` with the system's default handler, without waiting for it.
+///
+/// On Windows this doesn't go through `cmd /C start`, as `cmd` would treat
+/// characters like `&` and `|` in the URL (which came from someone else's
+/// message) as the start of another command.
+pub fn open(target: &str) -> std::io::Result<()> {
+    let mut command = if cfg!(target_os = "windows") {
+        let mut c = Command::new("rundll32");
+        c.arg("url.dll,FileProtocolHandler");
+        c
+    } else if cfg!(target_os = "macos") {
+        Command::new("open")
+    } else {
+        Command::new("xdg-open")
+    };
+
+    let mut child = command
+        .arg(target)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+    std::thread::spawn(move || child.wait());
+    Ok(())
+}
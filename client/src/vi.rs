@@ -0,0 +1,831 @@
+use crate::util::text::{next_boundary, prev_boundary};
+
+/// The most snapshots of the input line kept for undo.
+const UNDO_LEVELS: usize = 100;
+
+/// The largest count a command takes; bigger ones are cut down to this, and a
+/// put never pastes more than this many characters unless the register alone
+/// holds more.
+const MAX_COUNT: usize = 10_000;
+
+/// The input line as the vi engine sees it: its characters and the position
+/// of the cursor within them.
+pub struct Buffer {
+    pub text: Vec<char>,
+    pub cursor: usize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operator {
+    Delete,
+    Change,
+    Yank,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Motion {
+    Left,
+    Right,
+    WordForward,
+    WordBack,
+    WordEnd,
+    LineStart,
+    LineEnd,
+    FindForward(char),
+    TillForward(char),
+    FindBack(char),
+    TillBack(char),
+    /// The whole input line, as in `dd`, `cc` and `yy`.
+    WholeLine,
+}
+
+impl Motion {
+    /// Whether an operator applied with this motion includes the character
+    /// the motion lands on.
+    fn inclusive(self) -> bool {
+        matches!(
+            self,
+            Motion::WordEnd | Motion::LineEnd | Motion::FindForward(_) | Motion::TillForward(_)
+        )
+    }
+}
+
+/// Where `i`, `a`, `I` and `A` start inserting.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InsertAt {
+    Cursor,
+    AfterCursor,
+    LineStart,
+    LineEnd,
+}
+
+/// Which mode the client should be in after the engine has handled a key.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Next {
+    Normal,
+    Pending(Operator),
+    Insert,
+    Visual,
+}
+
+/// The `f`, `t`, `F` or `T` waiting for the character to look for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Find {
+    Forward,
+    Till,
+    Back,
+    TillBack,
+}
+
+/// A repeatable change, as replayed by `.`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Edit {
+    Operate(Operator, Motion, usize),
+    Visual(Operator, usize),
+    Put { before: bool, count: usize },
+    Insert(InsertAt),
+}
+
+#[derive(Clone, Debug)]
+struct Change {
+    edit: Edit,
+    /// Text typed in insert mode as part of the change.
+    text: Vec<char>,
+}
+
+/// The state of a small vi-style editor for the input line: pending counts,
+/// operators and `f`/`t` searches, the yank register, undo history, the last
+/// change (for `.`) and the visual-mode anchor.
+#[derive(Default)]
+pub struct Vi {
+    count: Option<usize>,
+    operator: Option<(Operator, usize)>,
+    find: Option<Find>,
+    register: Vec<char>,
+    undo: Vec<(Vec<char>, usize)>,
+    last_change: Option<Change>,
+    recording: Option<Change>,
+    insert_start: usize,
+    visual_anchor: Option<usize>,
+}
+
+/// Characters of the same class form a vi "word".
+fn class(c: char) -> u8 {
+    if c.is_whitespace() {
+        0
+    } else if c.is_alphanumeric() || c == '_' {
+        1
+    } else {
+        2
+    }
+}
+
+fn word_forward(text: &[char], from: usize) -> usize {
+    let len = text.len();
+    let mut i = from;
+    if i >= len {
+        return len;
+    }
+    let c = class(text[i]);
+    if c != 0 {
+        while i < len && class(text[i]) == c {
+            i += 1;
+        }
+    }
+    while i < len && class(text[i]) == 0 {
+        i += 1;
+    }
+    i
+}
+
+fn word_back(text: &[char], from: usize) -> usize {
+    if from == 0 {
+        return 0;
+    }
+    let mut i = from - 1;
+    while i > 0 && class(text[i]) == 0 {
+        i -= 1;
+    }
+    let c = class(text[i]);
+    while i > 0 && class(text[i - 1]) == c {
+        i -= 1;
+    }
+    i
+}
+
+fn word_end(text: &[char], from: usize) -> usize {
+    let len = text.len();
+    if len == 0 {
+        return 0;
+    }
+    let mut i = from + 1;
+    while i < len && class(text[i]) == 0 {
+        i += 1;
+    }
+    if i >= len {
+        return len - 1;
+    }
+    let c = class(text[i]);
+    while i + 1 < len && class(text[i + 1]) == c {
+        i += 1;
+    }
+    i
+}
+
+/// The last position the cursor may rest on outside insert mode: the start of
+/// the last grapheme cluster.
+fn last_position(text: &[char]) -> usize {
+    prev_boundary(text, text.len())
+}
+
+impl Vi {
+    /// Whether `c` has to go straight to the engine rather than through key
+    /// bindings: it's the target of an `f` or `t`, or a `0` continuing a count.
+    pub fn wants_char(&self, c: char) -> bool {
+        self.find.is_some() || (c == '0' && self.count.is_some())
+    }
+
+    /// Drop any pending count, operator or search, and leave visual mode.
+    pub fn cancel(&mut self) -> Next {
+        self.count = None;
+        self.operator = None;
+        self.find = None;
+        self.visual_anchor = None;
+        Next::Normal
+    }
+
+    /// Forget the undo history and any change being recorded, as when the
+    /// input line is sent.
+    pub fn clear(&mut self) {
+        self.cancel();
+        self.undo.clear();
+        self.recording = None;
+    }
+
+    /// The selected range of the input line, if in visual mode.
+    pub fn selection(&self, cursor: usize) -> Option<(usize, usize)> {
+        self.visual_anchor
+            .map(|anchor| (anchor.min(cursor), anchor.max(cursor) + 1))
+    }
+
+    fn take_count(&mut self) -> usize {
+        self.count.take().unwrap_or(1)
+    }
+
+    fn snapshot(&mut self, buf: &Buffer) {
+        self.undo.push((buf.text.clone(), buf.cursor));
+        if self.undo.len() > UNDO_LEVELS {
+            self.undo.remove(0);
+        }
+    }
+
+    /// Where `motion`, repeated `count` times, takes the cursor, or `None` if
+    /// it can't (like an `f` search for a character that isn't there).
+    fn target(&self, motion: Motion, buf: &Buffer, count: usize) -> Option<usize> {
+        let text = &buf.text;
+        let len = text.len();
+        let cur = buf.cursor.min(len);
+
+        let find = |from: usize, c: char, n: usize| -> Option<usize> {
+            text.iter()
+                .enumerate()
+                .skip(from)
+                .filter(|(_, x)| **x == c)
+                .nth(n - 1)
+                .map(|(i, _)| i)
+        };
+        let find_back = |before: usize, c: char, n: usize| -> Option<usize> {
+            text[..before]
+                .iter()
+                .enumerate()
+                .rev()
+                .filter(|(_, x)| **x == c)
+                .nth(n - 1)
+                .map(|(i, _)| i)
+        };
+
+        let target = match motion {
+            Motion::Left => (0..count).fold(cur, |i, _| prev_boundary(text, i)),
+            Motion::Right => (0..count).fold(cur, |i, _| next_boundary(text, i)),
+            Motion::WordForward => (0..count).fold(cur, |i, _| word_forward(text, i)),
+            Motion::WordBack => (0..count).fold(cur, |i, _| word_back(text, i)),
+            Motion::WordEnd => (0..count).fold(cur, |i, _| word_end(text, i)),
+            Motion::LineStart => 0,
+            Motion::LineEnd => last_position(text),
+            Motion::FindForward(c) => find(cur + 1, c, count)?,
+            Motion::TillForward(c) => prev_boundary(text, find(cur + 1, c, count)?).max(cur),
+            Motion::FindBack(c) => find_back(cur, c, count)?,
+            Motion::TillBack(c) => next_boundary(text, find_back(cur, c, count)?).min(cur),
+            Motion::WholeLine => 0,
+        };
+        Some(target)
+    }
+
+    /// The end of the range `cw` changes: like `ce`, except that on the last
+    /// character of a word it changes just that character.
+    fn change_word_end(buf: &Buffer, count: usize) -> usize {
+        let text = &buf.text;
+        let mut end = buf.cursor;
+        let c = class(text[end]);
+        while end + 1 < text.len() && class(text[end + 1]) == c {
+            end += 1;
+        }
+        (1..count).fold(end, |i, _| word_end(text, i))
+    }
+
+    /// Move the cursor according to `motion`, or, if an operator is pending,
+    /// apply the operator over the text the motion covers.
+    pub fn motion(&mut self, motion: Motion, buf: &mut Buffer) -> Next {
+        let count = self.take_count();
+
+        if let Some((op, op_count)) = self.operator.take() {
+            let count = count.saturating_mul(op_count).min(MAX_COUNT);
+            return self.operate(op, motion, count, buf, true);
+        }
+
+        if let Some(target) = self.target(motion, buf, count) {
+            buf.cursor = target.min(last_position(&buf.text));
+        }
+        self.still_pending()
+    }
+
+    /// Start an operator, or, if the same one is already pending (as in `dd`),
+    /// apply it to the whole line.
+    pub fn operator(&mut self, op: Operator, buf: &mut Buffer) -> Next {
+        if let Some(anchor) = self.visual_anchor.take() {
+            let start = anchor.min(buf.cursor);
+            let end = next_boundary(&buf.text, anchor.max(buf.cursor));
+            buf.cursor = start;
+            let next = self.apply(op, start, end, buf);
+            if op != Operator::Yank {
+                self.begin_change(Edit::Visual(op, end - start), next);
+            }
+            return next;
+        }
+
+        match self.operator.take() {
+            Some((pending, op_count)) if pending == op => {
+                let count = self.take_count().saturating_mul(op_count).min(MAX_COUNT);
+                self.operate(op, Motion::WholeLine, count, buf, true)
+            }
+            _ => {
+                let count = self.take_count();
+                self.operator = Some((op, count));
+                Next::Pending(op)
+            }
+        }
+    }
+
+    /// Apply `op` over the range `motion` covers, remembering it for `.` if
+    /// `record` is set.
+    fn operate(
+        &mut self,
+        op: Operator,
+        motion: Motion,
+        count: usize,
+        buf: &mut Buffer,
+        record: bool,
+    ) -> Next {
+        let len = buf.text.len();
+        let cur = buf.cursor.min(len);
+
+        let (start, end) = if motion == Motion::WholeLine {
+            (0, len)
+        } else if op == Operator::Change
+            && motion == Motion::WordForward
+            && cur < len
+            && !buf.text[cur].is_whitespace()
+        {
+            (
+                cur,
+                next_boundary(&buf.text, Self::change_word_end(buf, count)),
+            )
+        } else {
+            let target = match self.target(motion, buf, count) {
+                Some(t) => t,
+                None => return Next::Normal,
+            };
+            let (start, end) = if target < cur {
+                (target, cur)
+            } else {
+                (cur, target)
+            };
+            if motion.inclusive() {
+                (start, next_boundary(&buf.text, end))
+            } else {
+                (start, end)
+            }
+        };
+
+        buf.cursor = start;
+        let next = self.apply(op, start, end, buf);
+        if record && op != Operator::Yank {
+            self.begin_change(Edit::Operate(op, motion, count), next);
+        }
+        next
+    }
+
+    /// Delete, change or yank the characters in `start..end`.
+    fn apply(&mut self, op: Operator, start: usize, end: usize, buf: &mut Buffer) -> Next {
+        let end = end.min(buf.text.len());
+        let start = start.min(end);
+        match op {
+            Operator::Yank => {
+                self.register = buf.text[start..end].to_vec();
+                buf.cursor = start.min(last_position(&buf.text));
+                Next::Normal
+            }
+            Operator::Delete if start == end => Next::Normal,
+            Operator::Delete => {
+                self.snapshot(buf);
+                self.register = buf.text.drain(start..end).collect();
+                buf.cursor = start.min(last_position(&buf.text));
+                Next::Normal
+            }
+            Operator::Change => {
+                self.snapshot(buf);
+                self.register = buf.text.drain(start..end).collect();
+                buf.cursor = start;
+                self.insert_start = start;
+                Next::Insert
+            }
+        }
+    }
+
+    /// Remember `edit` as the last change, or, if it leads into insert mode,
+    /// start recording it until insert mode ends.
+    fn begin_change(&mut self, edit: Edit, next: Next) {
+        let change = Change {
+            edit,
+            text: Vec::new(),
+        };
+        if next == Next::Insert {
+            self.recording = Some(change);
+        } else {
+            self.last_change = Some(change);
+        }
+    }
+
+    /// Enter insert mode at the given place.
+    pub fn insert(&mut self, at: InsertAt, buf: &mut Buffer) -> Next {
+        self.cancel();
+        self.snapshot(buf);
+        self.place_insert_cursor(at, buf);
+        self.insert_start = buf.cursor;
+        self.recording = Some(Change {
+            edit: Edit::Insert(at),
+            text: Vec::new(),
+        });
+        Next::Insert
+    }
+
+    fn place_insert_cursor(&self, at: InsertAt, buf: &mut Buffer) {
+        let len = buf.text.len();
+        buf.cursor = match at {
+            InsertAt::Cursor => buf.cursor.min(len),
+            InsertAt::AfterCursor => next_boundary(&buf.text, buf.cursor),
+            InsertAt::LineStart => 0,
+            InsertAt::LineEnd => len,
+        };
+    }
+
+    /// Leave insert mode, finishing the change being recorded with whatever
+    /// was typed since it began.
+    pub fn end_insert(&mut self, buf: &mut Buffer) -> Next {
+        if let Some(mut change) = self.recording.take() {
+            let start = self.insert_start.min(buf.text.len());
+            let end = buf.cursor.clamp(start, buf.text.len());
+            change.text = buf.text[start..end].to_vec();
+            self.last_change = Some(change);
+        }
+        buf.cursor = prev_boundary(&buf.text, buf.cursor).min(last_position(&buf.text));
+        Next::Normal
+    }
+
+    /// Put the yank register after (or `before`) the cursor.
+    fn put(&mut self, before: bool, count: usize, buf: &mut Buffer) -> Next {
+        if self.register.is_empty() {
+            return Next::Normal;
+        }
+        self.snapshot(buf);
+        let at = if before || buf.text.is_empty() {
+            buf.cursor.min(buf.text.len())
+        } else {
+            next_boundary(&buf.text, buf.cursor)
+        };
+        let count = count.min(MAX_COUNT / self.register.len()).max(1);
+        let pasted: Vec<char> = (0..count).flat_map(|_| self.register.clone()).collect();
+        let n = pasted.len();
+        buf.text.splice(at..at, pasted);
+        buf.cursor = prev_boundary(&buf.text, at + n);
+        self.last_change = Some(Change {
+            edit: Edit::Put { before, count },
+            text: Vec::new(),
+        });
+        Next::Normal
+    }
+
+    fn undo(&mut self, count: usize, buf: &mut Buffer) -> Next {
+        for _ in 0..count {
+            match self.undo.pop() {
+                Some((text, cursor)) => {
+                    buf.text = text;
+                    buf.cursor = cursor.min(last_position(&buf.text));
+                }
+                None => break,
+            }
+        }
+        Next::Normal
+    }
+
+    /// Replay the last change, with a new count if one was given.
+    fn repeat(&mut self, count: Option<usize>, buf: &mut Buffer) -> Next {
+        let change = match self.last_change.clone() {
+            Some(c) => c,
+            None => return Next::Normal,
+        };
+
+        let next = match change.edit {
+            Edit::Operate(op, motion, n) => {
+                self.operate(op, motion, count.unwrap_or(n), buf, false)
+            }
+            Edit::Visual(op, width) => {
+                let start = buf.cursor.min(buf.text.len());
+                self.apply(op, start, start + width, buf)
+            }
+            Edit::Put { before, count: n } => self.put(before, count.unwrap_or(n), buf),
+            Edit::Insert(at) => {
+                self.snapshot(buf);
+                self.place_insert_cursor(at, buf);
+                Next::Insert
+            }
+        };
+
+        if next == Next::Insert {
+            let n = change.text.len();
+            let at = buf.cursor;
+            buf.text.splice(at..at, change.text.iter().copied());
+            buf.cursor = prev_boundary(&buf.text, at + n).min(last_position(&buf.text));
+        }
+        self.last_change = Some(change);
+        Next::Normal
+    }
+
+    /// Handle a character typed in command, operator-pending or visual mode.
+    pub fn key(&mut self, c: char, buf: &mut Buffer) -> Next {
+        if let Some(find) = self.find.take() {
+            let motion = match find {
+                Find::Forward => Motion::FindForward(c),
+                Find::Till => Motion::TillForward(c),
+                Find::Back => Motion::FindBack(c),
+                Find::TillBack => Motion::TillBack(c),
+            };
+            return self.motion(motion, buf);
+        }
+
+        match c {
+            '1'..='9' => {
+                let digit = c.to_digit(10).unwrap_or_default() as usize;
+                let count = self.count.unwrap_or(0).saturating_mul(10) + digit;
+                self.count = Some(count.min(MAX_COUNT));
+                return self.still_pending();
+            }
+            '0' if self.count.is_some() => {
+                self.count = self.count.map(|n| n.saturating_mul(10).min(MAX_COUNT));
+                return self.still_pending();
+            }
+            _ => {}
+        }
+
+        let visual = self.visual_anchor.is_some();
+        match c {
+            'h' => self.motion(Motion::Left, buf),
+            'l' | ' ' => self.motion(Motion::Right, buf),
+            'w' => self.motion(Motion::WordForward, buf),
+            'b' => self.motion(Motion::WordBack, buf),
+            'e' => self.motion(Motion::WordEnd, buf),
+            '0' | '^' => self.motion(Motion::LineStart, buf),
+            '$' => self.motion(Motion::LineEnd, buf),
+            'f' | 't' | 'F' | 'T' => {
+                self.find = Some(match c {
+                    'f' => Find::Forward,
+                    't' => Find::Till,
+                    'F' => Find::Back,
+                    _ => Find::TillBack,
+                });
+                self.still_pending()
+            }
+
+            'd' | 'x' if visual => self.operator(Operator::Delete, buf),
+            'c' | 's' if visual => self.operator(Operator::Change, buf),
+            'y' if visual => self.operator(Operator::Yank, buf),
+            'v' if visual => self.cancel(),
+
+            'd' => self.operator(Operator::Delete, buf),
+            'c' => self.operator(Operator::Change, buf),
+            'y' => self.operator(Operator::Yank, buf),
+            'D' => self.shorthand(Operator::Delete, Motion::LineEnd, buf),
+            'C' => self.shorthand(Operator::Change, Motion::LineEnd, buf),
+            'x' => self.shorthand(Operator::Delete, Motion::Right, buf),
+            'X' => self.shorthand(Operator::Delete, Motion::Left, buf),
+            's' => self.shorthand(Operator::Change, Motion::Right, buf),
+
+            'i' => self.insert(InsertAt::Cursor, buf),
+            'a' => self.insert(InsertAt::AfterCursor, buf),
+            'I' => self.insert(InsertAt::LineStart, buf),
+            'A' => self.insert(InsertAt::LineEnd, buf),
+
+            'p' | 'P' => {
+                let count = self.take_count();
+                self.operator = None;
+                self.put(c == 'P', count, buf)
+            }
+            'u' => {
+                let count = self.take_count();
+                self.operator = None;
+                self.undo(count, buf)
+            }
+            '.' => {
+                let count = self.count.take();
+                self.operator = None;
+                self.repeat(count, buf)
+            }
+            'v' => {
+                self.cancel();
+                self.visual_anchor = Some(buf.cursor.min(last_position(&buf.text)));
+                Next::Visual
+            }
+
+            _ => self.cancel(),
+        }
+    }
+
+    /// Commands like `x` and `D` that stand for an operator and a motion.
+    fn shorthand(&mut self, op: Operator, motion: Motion, buf: &mut Buffer) -> Next {
+        self.operator = None;
+        let count = self.take_count();
+        if buf.text.is_empty() && op != Operator::Change {
+            return Next::Normal;
+        }
+        self.operate(op, motion, count, buf, true)
+    }
+
+    fn still_pending(&self) -> Next {
+        match (self.visual_anchor, self.operator) {
+            (Some(_), _) => Next::Visual,
+            (None, Some((op, _))) => Next::Pending(op),
+            (None, None) => Next::Normal,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEXT: &str = "one two three";
+
+    fn buffer(text: &str, cursor: usize) -> Buffer {
+        Buffer {
+            text: text.chars().collect(),
+            cursor,
+        }
+    }
+
+    /// Feed `keys` to the engine one at a time, returning the last `Next`.
+    fn keys(vi: &mut Vi, buf: &mut Buffer, keys: &str) -> Next {
+        keys.chars().fold(Next::Normal, |_, c| vi.key(c, buf))
+    }
+
+    /// Type `text` in insert mode, then leave it.
+    fn type_text(vi: &mut Vi, buf: &mut Buffer, text: &str) {
+        for c in text.chars() {
+            buf.text.insert(buf.cursor, c);
+            buf.cursor += 1;
+        }
+        vi.end_insert(buf);
+    }
+
+    fn text(buf: &Buffer) -> String {
+        buf.text.iter().collect()
+    }
+
+    /// Where the cursor ends up after `keys`, starting from `cursor`.
+    fn lands(cursor: usize, k: &str) -> usize {
+        let mut buf = buffer(TEXT, cursor);
+        keys(&mut Vi::default(), &mut buf, k);
+        buf.cursor
+    }
+
+    /// What's left of the text after `keys`, starting from `cursor`.
+    fn leaves(cursor: usize, k: &str) -> String {
+        let mut buf = buffer(TEXT, cursor);
+        keys(&mut Vi::default(), &mut buf, k);
+        text(&buf)
+    }
+
+    #[test]
+    fn word_motions() {
+        assert_eq!(lands(0, "w"), 4);
+        assert_eq!(lands(0, "2w"), 8);
+        assert_eq!(lands(0, "e"), 2);
+        assert_eq!(lands(0, "2e"), 6);
+        assert_eq!(lands(12, "b"), 8);
+        assert_eq!(lands(12, "2b"), 4);
+        assert_eq!(lands(0, "9w"), 12);
+    }
+
+    #[test]
+    fn line_motions() {
+        assert_eq!(lands(5, "0"), 0);
+        assert_eq!(lands(5, "^"), 0);
+        assert_eq!(lands(5, "$"), 12);
+        assert_eq!(lands(0, "3l"), 3);
+        assert_eq!(lands(5, "2h"), 3);
+        assert_eq!(lands(5, "10h"), 0);
+    }
+
+    #[test]
+    fn find_motions() {
+        assert_eq!(lands(0, "fe"), 2);
+        assert_eq!(lands(0, "2fe"), 11);
+        assert_eq!(lands(0, "te"), 1);
+        assert_eq!(lands(12, "Fo"), 6);
+        assert_eq!(lands(12, "To"), 7);
+        assert_eq!(lands(0, "fz"), 0);
+    }
+
+    #[test]
+    fn counts_of_ten_and_more() {
+        let mut vi = Vi::default();
+        let mut buf = buffer(&"x".repeat(30), 0);
+        assert!(!vi.wants_char('0'));
+        keys(&mut vi, &mut buf, "1");
+        assert!(vi.wants_char('0'));
+        keys(&mut vi, &mut buf, "0l");
+        assert_eq!(buf.cursor, 10);
+    }
+
+    #[test]
+    fn delete() {
+        assert_eq!(leaves(0, "dw"), "two three");
+        assert_eq!(leaves(0, "d2w"), "three");
+        assert_eq!(leaves(0, "2dw"), "three");
+        assert_eq!(leaves(0, "dd"), "");
+        assert_eq!(leaves(4, "d$"), "one ");
+        assert_eq!(leaves(4, "D"), "one ");
+        assert_eq!(leaves(0, "dfo"), " three");
+        assert_eq!(leaves(0, "dto"), "o three");
+        assert_eq!(leaves(8, "d0"), "three");
+
+        let mut vi = Vi::default();
+        let mut buf = buffer(TEXT, 0);
+        assert_eq!(
+            keys(&mut vi, &mut buf, "d"),
+            Next::Pending(Operator::Delete)
+        );
+        assert_eq!(keys(&mut vi, &mut buf, "w"), Next::Normal);
+    }
+
+    #[test]
+    fn change() {
+        let mut vi = Vi::default();
+        let mut buf = buffer(TEXT, 0);
+        assert_eq!(keys(&mut vi, &mut buf, "cw"), Next::Insert);
+        assert_eq!(text(&buf), " two three");
+        type_text(&mut vi, &mut buf, "ONE");
+        assert_eq!(text(&buf), "ONE two three");
+        assert_eq!(buf.cursor, 2);
+
+        let mut buf = buffer(TEXT, 4);
+        assert_eq!(keys(&mut vi, &mut buf, "C"), Next::Insert);
+        type_text(&mut vi, &mut buf, "2");
+        assert_eq!(text(&buf), "one 2");
+    }
+
+    #[test]
+    fn yank_and_put() {
+        let mut vi = Vi::default();
+        let mut buf = buffer(TEXT, 0);
+        keys(&mut vi, &mut buf, "ywP");
+        assert_eq!(text(&buf), "one one two three");
+
+        let mut buf = buffer(TEXT, 4);
+        keys(&mut vi, &mut buf, "ye$p");
+        assert_eq!(text(&buf), "one two threetwo");
+        assert_eq!(buf.cursor, 15);
+
+        let mut buf = buffer("ab", 0);
+        keys(&mut vi, &mut buf, "yl3p");
+        assert_eq!(text(&buf), "aaaab");
+    }
+
+    #[test]
+    fn x_and_put() {
+        assert_eq!(leaves(0, "x"), "ne two three");
+        assert_eq!(leaves(0, "3x"), " two three");
+        assert_eq!(leaves(12, "X"), "one two thre");
+        assert_eq!(leaves(0, "xp"), "noe two three");
+        assert_eq!(leaves(0, "x$p"), "ne two threeo");
+        assert_eq!(leaves(0, "p"), TEXT);
+    }
+
+    #[test]
+    fn undo() {
+        assert_eq!(leaves(0, "dwu"), TEXT);
+        assert_eq!(leaves(0, "dwdwu"), "two three");
+        assert_eq!(leaves(0, "dwdw2u"), TEXT);
+        assert_eq!(leaves(0, "xpu"), "ne two three");
+        assert_eq!(leaves(0, "u"), TEXT);
+    }
+
+    #[test]
+    fn repeat() {
+        assert_eq!(leaves(0, "x.."), " two three");
+        assert_eq!(leaves(0, "dw."), "three");
+        assert_eq!(leaves(0, "dw2."), "");
+        assert_eq!(leaves(0, "."), TEXT);
+
+        let mut vi = Vi::default();
+        let mut buf = buffer(TEXT, 0);
+        keys(&mut vi, &mut buf, "cw");
+        type_text(&mut vi, &mut buf, "ONE");
+        keys(&mut vi, &mut buf, "w.");
+        assert_eq!(text(&buf), "ONE ONE three");
+    }
+
+    #[test]
+    fn visual() {
+        let mut vi = Vi::default();
+        let mut buf = buffer(TEXT, 0);
+        assert_eq!(keys(&mut vi, &mut buf, "vll"), Next::Visual);
+        assert_eq!(vi.selection(buf.cursor), Some((0, 3)));
+        assert_eq!(keys(&mut vi, &mut buf, "d"), Next::Normal);
+        assert_eq!(vi.selection(buf.cursor), None);
+        assert_eq!(text(&buf), " two three");
+
+        assert_eq!(leaves(4, "vey$p"), "one two threetwo");
+        assert_eq!(leaves(8, "vbd"), "one hree");
+        assert_eq!(leaves(0, "vlv"), TEXT);
+        assert_eq!(leaves(0, "vlx."), "two three");
+
+        let mut buf = buffer(TEXT, 0);
+        assert_eq!(keys(&mut vi, &mut buf, "vec"), Next::Insert);
+        type_text(&mut vi, &mut buf, "1");
+        assert_eq!(text(&buf), "1 two three");
+    }
+
+    #[test]
+    fn huge_counts_are_bounded() {
+        assert_eq!(lands(12, "99999999999h"), 0);
+        assert_eq!(leaves(0, "99999999999d99999999999w"), "");
+        assert_eq!(leaves(0, "99999999999x"), "");
+
+        let mut vi = Vi::default();
+        let mut buf = buffer(TEXT, 0);
+        keys(&mut vi, &mut buf, "yl9999999999p");
+        assert_eq!(buf.text.len(), TEXT.len() + MAX_COUNT);
+        keys(&mut vi, &mut buf, "9999999999.");
+        assert_eq!(buf.text.len(), TEXT.len() + 2 * MAX_COUNT);
+    }
+}
@@ -0,0 +1,85 @@
+//! Compares the wire codecs: how big each makes some typical messages, and
+//! how fast it encodes and decodes them. Run with `cargo bench -p common`.
+
+use common::codec::CODECS;
+use common::proto::{Msg, RosterEntry};
+use std::hint::black_box;
+use std::time::Instant;
+
+const ROUNDS: usize = 20_000;
+
+fn samples() -> Vec<(&'static str, Msg<'static>)> {
+    let roster: Vec<RosterEntry> = (0..20)
+        .map(|n| RosterEntry {
+            name: format!("user{}", n),
+            op: n == 0,
+            voiced: n % 3 == 0,
+            away: n % 7 == 0,
+            idle_secs: n * 37,
+        })
+        .collect();
+
+    vec![
+        ("ping", Msg::Ping),
+        (
+            "text",
+            Msg::Text {
+                who: "alice".into(),
+                lines: vec![
+                    "Has anyone tried the new build yet?".into(),
+                    "It's **much** faster on my machine.".into(),
+                ],
+            },
+        ),
+        (
+            "priv",
+            Msg::Priv {
+                who: "bob".into(),
+                text: "See you at lunch.".into(),
+            },
+        ),
+        (
+            "joined",
+            Msg::Joined {
+                user: "carol".into(),
+                room: "Lobby".into(),
+            },
+        ),
+        ("roster", Msg::Roster(roster.into())),
+    ]
+}
+
+fn main() {
+    let samples = samples();
+
+    println!(
+        "{:<8} {:<8} {:>8} {:>14} {:>14}",
+        "message", "codec", "bytes", "encode msg/s", "decode msg/s"
+    );
+    for (label, msg) in samples.iter() {
+        for codec in CODECS.iter() {
+            let bytes = codec.encode(msg);
+
+            let start = Instant::now();
+            for _ in 0..ROUNDS {
+                black_box(codec.encode(black_box(msg)));
+            }
+            let encode_rate = ROUNDS as f64 / start.elapsed().as_secs_f64();
+
+            let start = Instant::now();
+            for _ in 0..ROUNDS {
+                black_box(codec.decode(black_box(&bytes)).unwrap());
+            }
+            let decode_rate = ROUNDS as f64 / start.elapsed().as_secs_f64();
+
+            println!(
+                "{:<8} {:<8} {:>8} {:>14.0} {:>14.0}",
+                label,
+                codec.name(),
+                bytes.len(),
+                encode_rate,
+                decode_rate
+            );
+        }
+    }
+}
@@ -0,0 +1,870 @@
+use crate::codec::{Codec, Json};
+use crate::compress;
+use crate::proto::{cows, Msg, Op, RosterEntry};
+use crate::socket::{Socket, SocketError};
+use serde::Serialize;
+use std::borrow::Cow;
+use std::fmt::Display;
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
+
+static TICK: Duration = Duration::from_millis(100);
+/// The server pings quiet clients every few seconds, so hearing nothing for
+/// this long means the connection is gone.
+const SILENCE_TIMEOUT: Duration = Duration::from_secs(60);
+/// How long to wait for the server to agree on a codec.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Debug)]
+pub struct ClientError {
+    msg: String,
+}
+
+impl ClientError {
+    fn new(message: &str) -> ClientError {
+        ClientError {
+            msg: String::from(message),
+        }
+    }
+
+    fn from_socket(err: &SocketError) -> ClientError {
+        ClientError {
+            msg: format!("Underlying socket error: {}", err),
+        }
+    }
+}
+
+impl Display for ClientError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", &(self.msg))
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+/// Connect to the server at `address` and go through the opening handshake:
+/// ask for `codec` and the kind of `compression` named (settling for whatever
+/// the server agrees to), then introduce ourselves with `hello`, a `Name` or a
+/// `Resume`. `Connection`s start this way; it's here on its own for clients
+/// that drive a `Socket` themselves.
+pub fn handshake(
+    address: &str,
+    hello: &Msg,
+    codec: &'static dyn Codec,
+    compression: &str,
+) -> Result<Socket, ClientError> {
+    let stream = TcpStream::connect(address)
+        .map_err(|e| ClientError::new(&format!("Error connecting to {}: {}", address, e)))?;
+    let mut socket = Socket::new(stream).map_err(|e| ClientError::from_socket(&e))?;
+    let handshake = socket
+        .request_codec(codec, HANDSHAKE_TIMEOUT, TICK)
+        .and_then(|_| socket.request_compression(compression, HANDSHAKE_TIMEOUT, TICK))
+        .and_then(|_| socket.blocking_send(&socket.codec().encode(hello), TICK));
+    if let Err(e) = handshake {
+        let _ = socket.shutdown();
+        return Err(ClientError::new(&format!(
+            "Error in initial protocol: {}",
+            e
+        )));
+    }
+    Ok(socket)
+}
+
+fn owned(cows: Vec<Cow<str>>) -> Vec<String> {
+    cows.into_iter().map(Cow::into_owned).collect()
+}
+
+/// Something the server has told us, decoded from the `Msg` it arrived as.
+/// `Misc` messages, which only a server newer than this knows what to make
+/// of, come through as `Other`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    Text {
+        who: String,
+        lines: Vec<String>,
+    },
+    Priv {
+        who: String,
+        text: String,
+    },
+    /// The server's copy of a private message we sent.
+    PrivEcho {
+        to: String,
+        text: String,
+    },
+    Info {
+        text: String,
+    },
+    Error {
+        text: String,
+    },
+    /// We've gone over the server's byte quota, and what we send will be
+    /// ignored for a while.
+    OverQuota {
+        text: String,
+    },
+    Joined {
+        user: String,
+        room: String,
+    },
+    Left {
+        user: String,
+        reason: String,
+    },
+    Renamed {
+        old: String,
+        new: String,
+    },
+    NewOp {
+        user: String,
+        room: String,
+    },
+    Away {
+        user: String,
+        message: String,
+    },
+    Back {
+        user: String,
+    },
+    Voiced {
+        user: String,
+        by: String,
+        room: String,
+    },
+    Devoiced {
+        user: String,
+        by: String,
+        room: String,
+    },
+    Kicked {
+        user: String,
+        room: String,
+    },
+    /// We've been kicked from `room`.
+    KickedOut {
+        room: String,
+    },
+    Roster {
+        entries: Vec<RosterEntry>,
+    },
+    Rooms {
+        names: Vec<String>,
+    },
+    Who {
+        names: Vec<String>,
+    },
+    LoggedOut {
+        message: String,
+    },
+    Other {
+        what: String,
+        data: Vec<String>,
+        alt: String,
+    },
+}
+
+impl Display for Event {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Event::Text { who, lines } => {
+                let mut first = true;
+                for line in lines {
+                    if !first {
+                        writeln!(f)?;
+                    }
+                    write!(f, "{}: {}", who, line)?;
+                    first = false;
+                }
+                Ok(())
+            }
+            Event::Priv { who, text } => write!(f, "{} (privately): {}", who, text),
+            Event::PrivEcho { to, text } => write!(f, "(privately to {}): {}", to, text),
+            Event::Info { text } | Event::Error { text } | Event::OverQuota { text } => {
+                write!(f, "{}", text)
+            }
+            Event::Joined { user, room } => write!(f, "{} joined {}.", user, room),
+            Event::Left { user, reason } => write!(f, "{} left: {}", user, reason),
+            Event::Renamed { old, new } => write!(f, "{} is now known as {}.", old, new),
+            Event::NewOp { user, room } => {
+                write!(f, "{} is now the operator of {}.", user, room)
+            }
+            Event::Away { user, message } => write!(f, "{} is away: {}", user, message),
+            Event::Back { user } => write!(f, "{} is back.", user),
+            Event::Voiced { user, by, room } => {
+                write!(f, "{} has given {} a voice in {}.", by, user, room)
+            }
+            Event::Devoiced { user, by, room } => {
+                write!(f, "{} has taken away {}'s voice in {}.", by, user, room)
+            }
+            Event::Kicked { user, room } => write!(f, "{} has been kicked from {}.", user, room),
+            Event::KickedOut { room } => write!(f, "You have been kicked from {}.", room),
+            Event::Roster { entries } => {
+                let names: Vec<&str> = entries.iter().map(|e| e.name.as_str()).collect();
+                write!(f, "Roster: {}", names.join(", "))
+            }
+            Event::Rooms { names } => write!(f, "Matching Rooms: {}", names.join(", ")),
+            Event::Who { names } => write!(f, "Matching names: {}", names.join(", ")),
+            Event::LoggedOut { message } => write!(f, "{}", message),
+            Event::Other { alt, .. } => write!(f, "{}", alt),
+        }
+    }
+}
+
+/// A connection to the server, for bots and other programs that want to talk
+/// to it without reimplementing the protocol. Outgoing messages are queued by
+/// the methods that make them and sent the next time the `Connection` is
+/// polled; pings are answered, and the session's resume token and our own
+/// name and room are kept track of, along the way.
+pub struct Connection {
+    socket: Socket,
+    name: String,
+    room: String,
+    server_address: String,
+    local_address: Option<String>,
+    resume_token: Option<String>,
+    last_heard: Instant,
+    logged_out: bool,
+}
+
+impl Connection {
+    /// Connect to the server at `address`, introducing ourselves as `name`.
+    pub fn connect(address: &str, name: &str) -> Result<Connection, ClientError> {
+        Connection::connect_with(address, name, &Json, compress::NONE)
+    }
+
+    /// Connect as with `connect`, asking the server to talk to us in `codec`
+    /// and to compress the connection with the kind of `compression` named.
+    /// If it doesn't know (or allow) them, the connection stays on `Json`, or
+    /// uncompressed.
+    pub fn connect_with(
+        address: &str,
+        name: &str,
+        codec: &'static dyn Codec,
+        compression: &str,
+    ) -> Result<Connection, ClientError> {
+        Connection::open(address, name, &Msg::Name(name.into()), codec, compression)
+    }
+
+    /// Connect to the server at `address` and ask it to restore the session
+    /// that issued `token` to a previous connection.
+    pub fn resume(address: &str, name: &str, token: &str) -> Result<Connection, ClientError> {
+        Connection::resume_with(address, name, token, &Json, compress::NONE)
+    }
+
+    pub fn resume_with(
+        address: &str,
+        name: &str,
+        token: &str,
+        codec: &'static dyn Codec,
+        compression: &str,
+    ) -> Result<Connection, ClientError> {
+        Connection::open(
+            address,
+            name,
+            &Msg::Resume {
+                name: name.into(),
+                token: token.into(),
+            },
+            codec,
+            compression,
+        )
+    }
+
+    fn open(
+        address: &str,
+        name: &str,
+        hello: &Msg,
+        codec: &'static dyn Codec,
+        compression: &str,
+    ) -> Result<Connection, ClientError> {
+        let mut socket = handshake(address, hello, codec, compression)?;
+        socket.enqueue_msg(&Msg::Query {
+            what: "addr".into(),
+            arg: "".into(),
+        });
+
+        Ok(Connection {
+            server_address: socket.get_addr().unwrap_or_else(|_| address.to_string()),
+            socket,
+            name: name.to_string(),
+            // Until the server says which room we've landed in.
+            room: String::new(),
+            local_address: None,
+            resume_token: None,
+            last_heard: Instant::now(),
+            logged_out: false,
+        })
+    }
+
+    pub fn set_read_buffer_size(&mut self, new_size: usize) {
+        self.socket.set_read_buffer_size(new_size);
+    }
+
+    /// Our name, as far as we've heard from the server.
+    pub fn name(&self) -> &str {
+        &(self.name)
+    }
+    /// The room we're in, as far as we've heard from the server (empty until
+    /// it's told us).
+    pub fn room(&self) -> &str {
+        &(self.room)
+    }
+    pub fn server_address(&self) -> &str {
+        &(self.server_address)
+    }
+    /// Our own address, as the server sees it, once it's told us.
+    pub fn local_address(&self) -> Option<&str> {
+        self.local_address.as_deref()
+    }
+    /// The token to pass to `Connection::resume` to get this session back
+    /// after losing the connection, once the server has issued one.
+    pub fn resume_token(&self) -> Option<&str> {
+        self.resume_token.as_deref()
+    }
+    /// Whether the server has acknowledged our logging out, or the connection
+    /// has otherwise ended.
+    pub fn is_closed(&self) -> bool {
+        self.logged_out
+    }
+
+    /// The codec agreed on with the server.
+    pub fn codec(&self) -> &'static dyn Codec {
+        self.socket.codec()
+    }
+
+    /// The kind of compression agreed on with the server, or `none`.
+    pub fn compression(&self) -> &'static str {
+        self.socket.compression()
+    }
+
+    /// Encode `msg` the way it would be sent, to see how big it is, say.
+    pub fn encode(&self, msg: &Msg) -> Vec<u8> {
+        self.socket.codec().encode(msg)
+    }
+
+    /// Queue any message for the server.
+    pub fn send(&mut self, msg: &Msg) {
+        self.socket.enqueue_msg(msg);
+    }
+
+    /// Queue a message that's already been encoded with `.encode()`.
+    pub fn send_bytes(&mut self, bytes: &[u8]) {
+        self.socket.enqueue(bytes);
+    }
+
+    pub fn join(&mut self, room: &str) {
+        self.send(&Msg::Join(room.into()));
+    }
+
+    /// Say `text` in the current room; a multi-line `text` is sent as one
+    /// multi-line message.
+    pub fn say(&mut self, text: &str) {
+        let lines: Vec<&str> = text.lines().collect();
+        self.say_lines(&lines);
+    }
+
+    pub fn say_lines(&mut self, lines: &[&str]) {
+        self.send(&Msg::Text {
+            who: "".into(),
+            lines: cows(lines),
+        });
+    }
+
+    pub fn priv_msg(&mut self, who: &str, text: &str) {
+        self.send(&Msg::Priv {
+            who: who.into(),
+            text: text.into(),
+        });
+    }
+
+    /// Ask the server for information: `"roster"`, `"who"` or `"rooms"`
+    /// (the last two take a pattern to match as `arg`).
+    pub fn query(&mut self, what: &str, arg: &str) {
+        self.send(&Msg::Query {
+            what: what.into(),
+            arg: arg.into(),
+        });
+    }
+
+    pub fn set_name(&mut self, name: &str) {
+        self.send(&Msg::Name(name.into()));
+    }
+
+    pub fn block(&mut self, who: &str) {
+        self.send(&Msg::Block(who.into()));
+    }
+
+    pub fn unblock(&mut self, who: &str) {
+        self.send(&Msg::Unblock(who.into()));
+    }
+
+    /// Mark ourselves away with `message`, or back if it's empty.
+    pub fn away(&mut self, message: &str) {
+        self.send(&Msg::Away(message.into()));
+    }
+
+    pub fn op(&mut self, op: Op) {
+        self.send(&Msg::Op(op));
+    }
+
+    /// Ask the server to log us out. It acknowledges with `Event::LoggedOut`.
+    pub fn logout(&mut self, message: &str) {
+        self.send(&Msg::Logout(message.into()));
+    }
+
+    /// Whether everything queued has been sent.
+    pub fn is_flushed(&self) -> bool {
+        self.socket.send_buff_size() == 0
+    }
+
+    /// Send whatever is queued, read whatever has arrived, and return the
+    /// next `Event`, if there is one, without waiting.
+    pub fn poll(&mut self) -> Result<Option<Event>, ClientError> {
+        if self.logged_out {
+            return Ok(None);
+        }
+
+        self.socket
+            .send_data()
+            .map_err(|e| ClientError::from_socket(&e))?;
+        match self.socket.read_data() {
+            Err(e) => return Err(ClientError::from_socket(&e)),
+            Ok(0)
+                if self.socket.recv_buff_size() == 0
+                    && self.last_heard.elapsed() > SILENCE_TIMEOUT =>
+            {
+                self.logged_out = true;
+                return Err(ClientError::new("Lost connection to the server."));
+            }
+            Ok(0) => {}
+            Ok(_) => self.last_heard = Instant::now(),
+        }
+
+        while let Some(msg) = self
+            .socket
+            .try_get()
+            .map_err(|e| ClientError::from_socket(&e))?
+        {
+            if let Some(event) = self.decode(msg) {
+                return Ok(Some(event));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Wait up to `limit` for the next `Event`.
+    pub fn wait(&mut self, limit: Duration) -> Result<Option<Event>, ClientError> {
+        let start_t = Instant::now();
+        loop {
+            if let Some(event) = self.poll()? {
+                return Ok(Some(event));
+            }
+            if self.logged_out || start_t.elapsed() > limit {
+                return Ok(None);
+            }
+            std::thread::sleep(TICK);
+        }
+    }
+
+    /// Call `f` with each `Event` as it arrives, until we're logged out. `f`
+    /// gets the `Connection` too, so it can answer.
+    pub fn run<F>(&mut self, mut f: F) -> Result<(), ClientError>
+    where
+        F: FnMut(&mut Connection, Event),
+    {
+        while !self.logged_out {
+            match self.poll()? {
+                Some(event) => f(self, event),
+                None => std::thread::sleep(TICK),
+            }
+        }
+        Ok(())
+    }
+
+    /// Iterate over `Event`s as they arrive, waiting for each, until we're
+    /// logged out or the connection fails.
+    pub fn events(&mut self) -> Events<'_> {
+        Events { conn: self }
+    }
+
+    /// Handle the messages that are the `Connection`'s business and turn the
+    /// rest into `Event`s.
+    fn decode(&mut self, msg: Msg<'static>) -> Option<Event> {
+        let event = match msg {
+            Msg::Ping => {
+                self.send(&Msg::Ping);
+                return None;
+            }
+            Msg::Text { who, lines } => Event::Text {
+                who: who.into_owned(),
+                lines: owned(lines),
+            },
+            Msg::Priv { who, text } => Event::Priv {
+                who: who.into_owned(),
+                text: text.into_owned(),
+            },
+            Msg::Info(text) => Event::Info {
+                text: text.into_owned(),
+            },
+            Msg::Err(text) => Event::Error {
+                text: text.into_owned(),
+            },
+            Msg::OverQuota(text) => Event::OverQuota {
+                text: text.into_owned(),
+            },
+            Msg::Logout(message) => {
+                self.logged_out = true;
+                let _ = self.socket.shutdown();
+                Event::LoggedOut {
+                    message: message.into_owned(),
+                }
+            }
+            Msg::Joined { user, room } => Event::Joined {
+                user: user.into_owned(),
+                room: room.into_owned(),
+            },
+            Msg::Left { user, reason } => Event::Left {
+                user: user.into_owned(),
+                reason: reason.into_owned(),
+            },
+            Msg::Renamed { old, new } => Event::Renamed {
+                old: old.into_owned(),
+                new: new.into_owned(),
+            },
+            Msg::PrivEcho { to, text } => Event::PrivEcho {
+                to: to.into_owned(),
+                text: text.into_owned(),
+            },
+            Msg::NewOp { user, room } => Event::NewOp {
+                user: user.into_owned(),
+                room: room.into_owned(),
+            },
+            Msg::IsAway { user, message } => Event::Away {
+                user: user.into_owned(),
+                message: message.into_owned(),
+            },
+            Msg::IsBack { user } => Event::Back {
+                user: user.into_owned(),
+            },
+            Msg::Voiced { user, by, room } => Event::Voiced {
+                user: user.into_owned(),
+                by: by.into_owned(),
+                room: room.into_owned(),
+            },
+            Msg::Devoiced { user, by, room } => Event::Devoiced {
+                user: user.into_owned(),
+                by: by.into_owned(),
+                room: room.into_owned(),
+            },
+            Msg::Kicked { user, room } => Event::Kicked {
+                user: user.into_owned(),
+                room: room.into_owned(),
+            },
+            Msg::KickedOut { room } => Event::KickedOut {
+                room: room.into_owned(),
+            },
+            Msg::Roster(entries) => Event::Roster {
+                entries: entries.into_owned(),
+            },
+            Msg::Rooms(names) => Event::Rooms {
+                names: owned(names),
+            },
+            Msg::Who(names) => Event::Who {
+                names: owned(names),
+            },
+            Msg::Addr(addr) => {
+                self.local_address = Some(addr.into_owned());
+                return None;
+            }
+            Msg::ResumeToken(token) => {
+                self.resume_token = Some(token.into_owned());
+                return None;
+            }
+            Msg::Misc { what, data, alt } => Event::Other {
+                what: what.into_owned(),
+                data: owned(data),
+                alt: alt.into_owned(),
+            },
+            _ => return None,
+        };
+
+        match &event {
+            Event::Joined { user, room } if *user == self.name => self.room.clone_from(room),
+            Event::Renamed { old, new } if *old == self.name => self.name.clone_from(new),
+            _ => {}
+        }
+        Some(event)
+    }
+}
+
+/// The iterator returned by `Connection::events`.
+pub struct Events<'a> {
+    conn: &'a mut Connection,
+}
+
+impl Iterator for Events<'_> {
+    type Item = Result<Event, ClientError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.conn.logged_out {
+                return None;
+            }
+            match self.conn.poll() {
+                Ok(Some(event)) => return Some(Ok(event)),
+                Ok(None) => std::thread::sleep(TICK),
+                Err(e) => {
+                    self.conn.logged_out = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec;
+    use std::net::TcpListener;
+    use std::thread::{self, JoinHandle};
+
+    const LIMIT: Duration = Duration::from_secs(5);
+
+    /// The server's end of a connection, played by hand.
+    struct Peer(Socket);
+
+    impl Peer {
+        fn get(&mut self) -> Msg<'static> {
+            let deadline = Instant::now() + LIMIT;
+            loop {
+                self.0.read_data().unwrap();
+                if let Some(msg) = self.0.try_get().unwrap() {
+                    return msg;
+                }
+                assert!(Instant::now() < deadline, "The peer waited too long.");
+                thread::sleep(Duration::from_millis(10));
+            }
+        }
+
+        fn send(&mut self, msg: &Msg) {
+            self.0.enqueue_msg(msg);
+            while self.0.send_data().unwrap() > 0 {}
+        }
+    }
+
+    /// Start opening a `Connection` with `open`, which is given the address
+    /// of the `Peer` returned.
+    fn start<F>(open: F) -> (JoinHandle<Result<Connection, ClientError>>, Peer)
+    where
+        F: FnOnce(&str) -> Result<Connection, ClientError> + Send + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let handle = thread::spawn(move || open(&address));
+        let (stream, _) = listener.accept().unwrap();
+        (handle, Peer(Socket::new(stream).unwrap()))
+    }
+
+    /// A `Connection` as `alice`, with a `Peer` that's read her `Name`.
+    fn connected() -> (Connection, Peer) {
+        let (handle, mut peer) = start(|address| Connection::connect(address, "alice"));
+        assert_eq!(peer.get(), Msg::Name("alice".into()));
+        (handle.join().unwrap().unwrap(), peer)
+    }
+
+    fn next(conn: &mut Connection) -> Event {
+        conn.wait(LIMIT).unwrap().expect("No event arrived.")
+    }
+
+    #[test]
+    fn introduces_itself_and_asks_for_its_address() {
+        let (mut conn, mut peer) = connected();
+        assert_eq!(conn.name(), "alice");
+        assert_eq!(conn.codec().name(), "json");
+        assert_eq!(conn.compression(), compress::NONE);
+        conn.poll().unwrap();
+        assert_eq!(
+            peer.get(),
+            Msg::Query {
+                what: "addr".into(),
+                arg: "".into(),
+            }
+        );
+    }
+
+    #[test]
+    fn resumes_with_its_token() {
+        let (handle, mut peer) = start(|address| Connection::resume(address, "alice", "abc"));
+        assert_eq!(
+            peer.get(),
+            Msg::Resume {
+                name: "alice".into(),
+                token: "abc".into(),
+            }
+        );
+        handle.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn negotiates_a_codec_and_compression() {
+        let msgpack = codec::by_name("msgpack").unwrap();
+        let (handle, mut peer) =
+            start(move |address| Connection::connect_with(address, "alice", msgpack, "zstd"));
+        assert_eq!(peer.get(), Msg::Codec("msgpack".into()));
+        peer.send(&Msg::Codec("msgpack".into()));
+        peer.0.set_codec(msgpack);
+        assert_eq!(peer.get(), Msg::Compress("zstd".into()));
+        peer.send(&Msg::Compress("zstd".into()));
+        peer.0.set_compression(compress::by_name("zstd"));
+        assert_eq!(peer.get(), Msg::Name("alice".into()));
+
+        let mut conn = handle.join().unwrap().unwrap();
+        assert_eq!(conn.codec().name(), "msgpack");
+        assert_eq!(conn.compression(), "zstd");
+        peer.send(&Msg::Info("hello".into()));
+        assert_eq!(
+            next(&mut conn),
+            Event::Info {
+                text: "hello".into()
+            }
+        );
+    }
+
+    #[test]
+    fn settles_for_what_the_server_agrees_to() {
+        let msgpack = codec::by_name("msgpack").unwrap();
+        let (handle, mut peer) =
+            start(move |address| Connection::connect_with(address, "alice", msgpack, "zstd"));
+        assert_eq!(peer.get(), Msg::Codec("msgpack".into()));
+        peer.send(&Msg::Codec("json".into()));
+        assert_eq!(peer.get(), Msg::Compress("zstd".into()));
+        peer.send(&Msg::Compress("none".into()));
+        assert_eq!(peer.get(), Msg::Name("alice".into()));
+
+        let conn = handle.join().unwrap().unwrap();
+        assert_eq!(conn.codec().name(), "json");
+        assert_eq!(conn.compression(), compress::NONE);
+    }
+
+    #[test]
+    fn being_turned_away_during_the_handshake_is_an_error() {
+        let (handle, mut peer) =
+            start(|address| Connection::connect_with(address, "alice", &Json, "zstd"));
+        assert_eq!(peer.get(), Msg::Compress("zstd".into()));
+        peer.send(&Msg::Logout("Go away.".into()));
+        let err = match handle.join().unwrap() {
+            Ok(_) => panic!("The handshake went through."),
+            Err(e) => e,
+        };
+        assert!(err.to_string().contains("Go away."), "{}", err);
+    }
+
+    #[test]
+    fn follows_its_room_and_name() {
+        let (mut conn, mut peer) = connected();
+        assert_eq!(conn.room(), "");
+        let joined = |user: &str, room: &str| Msg::Joined {
+            user: user.to_string().into(),
+            room: room.to_string().into(),
+        };
+
+        peer.send(&joined("alice", "Hall"));
+        next(&mut conn);
+        assert_eq!(conn.room(), "Hall");
+        peer.send(&joined("bob", "dev"));
+        next(&mut conn);
+        assert_eq!(conn.room(), "Hall");
+
+        peer.send(&Msg::Renamed {
+            old: "alice".into(),
+            new: "carol".into(),
+        });
+        assert_eq!(
+            next(&mut conn),
+            Event::Renamed {
+                old: "alice".into(),
+                new: "carol".into(),
+            }
+        );
+        assert_eq!(conn.name(), "carol");
+        peer.send(&joined("carol", "dev"));
+        next(&mut conn);
+        assert_eq!(conn.room(), "dev");
+    }
+
+    #[test]
+    fn keeps_its_own_business_to_itself() {
+        let (mut conn, mut peer) = connected();
+        peer.send(&Msg::Ping);
+        peer.send(&Msg::Addr("10.0.0.1:5000".into()));
+        peer.send(&Msg::ResumeToken("abc".into()));
+        peer.send(&Msg::Info("hi".into()));
+        assert_eq!(next(&mut conn), Event::Info { text: "hi".into() });
+        assert_eq!(conn.local_address(), Some("10.0.0.1:5000"));
+        assert_eq!(conn.resume_token(), Some("abc"));
+
+        conn.poll().unwrap();
+        assert!(matches!(peer.get(), Msg::Query { .. }));
+        assert_eq!(peer.get(), Msg::Ping);
+    }
+
+    #[test]
+    fn sends_what_its_asked_to() {
+        let (mut conn, mut peer) = connected();
+        conn.say("one\ntwo");
+        conn.priv_msg("bob", "psst");
+        conn.op(Op::Give("bob".into()));
+        conn.logout("bye");
+        while !conn.is_flushed() {
+            conn.poll().unwrap();
+        }
+        assert!(matches!(peer.get(), Msg::Query { .. }));
+        assert_eq!(
+            peer.get(),
+            Msg::Text {
+                who: "".into(),
+                lines: vec!["one".into(), "two".into()],
+            }
+        );
+        assert_eq!(
+            peer.get(),
+            Msg::Priv {
+                who: "bob".into(),
+                text: "psst".into(),
+            }
+        );
+        assert_eq!(peer.get(), Msg::Op(Op::Give("bob".into())));
+        assert_eq!(peer.get(), Msg::Logout("bye".into()));
+    }
+
+    #[test]
+    fn logging_out_closes_the_connection() {
+        let (mut conn, mut peer) = connected();
+        peer.send(&Msg::Logout("Bye.".into()));
+        assert_eq!(
+            next(&mut conn),
+            Event::LoggedOut {
+                message: "Bye.".into()
+            }
+        );
+        assert!(conn.is_closed());
+        assert_eq!(conn.poll().unwrap(), None);
+        assert!(conn.events().next().is_none());
+    }
+
+    #[test]
+    fn events_read_as_text() {
+        let event = Event::Text {
+            who: "alice".into(),
+            lines: vec!["one".into(), "two".into()],
+        };
+        assert_eq!(event.to_string(), "alice: one\nalice: two");
+        let event = Event::NewOp {
+            user: "bob".into(),
+            room: "dev".into(),
+        };
+        assert_eq!(event.to_string(), "bob is now the operator of dev.");
+    }
+}
@@ -0,0 +1,111 @@
+use super::proto::Msg;
+
+/// The longest MessagePack frame either end will decode. Anything claiming to
+/// be longer is taken as garbage rather than buffered up for.
+const MAX_FRAME: usize = 1024 * 1024;
+
+/// Bytes in the length that starts each MessagePack frame.
+const LENGTH_SIZE: usize = 4;
+
+/// A way of turning `Msg`s into bytes for the wire, and back.
+///
+/// Every connection starts out on `Json`. A client that wants something else
+/// asks for it by name with a `Codec` message before its `Name` or `Resume`;
+/// the server answers (still in JSON) with a `Codec` message naming the codec
+/// it'll use, which is `Json` again if it doesn't know the one asked for, and
+/// both ends switch to it from there on.
+pub trait Codec: Sync {
+    /// The name a client asks for this codec by.
+    fn name(&self) -> &'static str;
+
+    fn encode(&self, msg: &Msg) -> Vec<u8>;
+
+    /// Decode the first `Msg` in `data`, returning it with the number of bytes
+    /// it took up, or `None` if `data` doesn't hold a whole one yet.
+    fn decode(&self, data: &[u8]) -> Result<Option<(Msg<'static>, usize)>, String>;
+}
+
+/// Compact JSON, one object after another with nothing in between.
+pub struct Json;
+
+impl Codec for Json {
+    fn name(&self) -> &'static str {
+        "json"
+    }
+
+    fn encode(&self, msg: &Msg) -> Vec<u8> {
+        serde_json::to_vec(msg).unwrap()
+    }
+
+    fn decode(&self, data: &[u8]) -> Result<Option<(Msg<'static>, usize)>, String> {
+        let mut stream = serde_json::Deserializer::from_slice(data).into_iter::<Msg<'static>>();
+        match stream.next() {
+            None => Ok(None),
+            Some(Ok(msg)) => Ok(Some((msg, stream.byte_offset()))),
+            Some(Err(e)) if e.is_eof() => Ok(None),
+            Some(Err(e)) => Err(e.to_string()),
+        }
+    }
+}
+
+/// MessagePack, with field and variant names kept, so it decodes to the same
+/// shape as the JSON does. Each `Msg` goes in a frame that starts with its
+/// length as a big-endian `u32`.
+pub struct MessagePack;
+
+impl Codec for MessagePack {
+    fn name(&self) -> &'static str {
+        "msgpack"
+    }
+
+    fn encode(&self, msg: &Msg) -> Vec<u8> {
+        let mut frame = vec![0u8; LENGTH_SIZE];
+        rmp_serde::encode::write_named(&mut frame, msg).unwrap();
+        let length = (frame.len() - LENGTH_SIZE) as u32;
+        frame[..LENGTH_SIZE].copy_from_slice(&length.to_be_bytes());
+        frame
+    }
+
+    fn decode(&self, data: &[u8]) -> Result<Option<(Msg<'static>, usize)>, String> {
+        if data.len() < LENGTH_SIZE {
+            return Ok(None);
+        }
+        let mut length = [0u8; LENGTH_SIZE];
+        length.copy_from_slice(&data[..LENGTH_SIZE]);
+        let length = u32::from_be_bytes(length) as usize;
+        if length > MAX_FRAME {
+            return Err(format!(
+                "Frame of {} bytes is longer than the limit of {}.",
+                length, MAX_FRAME
+            ));
+        }
+
+        let end = LENGTH_SIZE + length;
+        match data.get(LENGTH_SIZE..end) {
+            None => Ok(None),
+            Some(frame) => match rmp_serde::from_slice::<Msg<'static>>(frame) {
+                Ok(msg) => Ok(Some((msg, end))),
+                Err(e) => Err(e.to_string()),
+            },
+        }
+    }
+}
+
+/// Every codec there is, the default (`Json`) first.
+pub static CODECS: [&dyn Codec; 2] = [&Json, &MessagePack];
+
+/// The codec called `name`, if there is one.
+pub fn by_name(name: &str) -> Option<&'static dyn Codec> {
+    CODECS
+        .iter()
+        .copied()
+        .find(|c| c.name().eq_ignore_ascii_case(name))
+}
+
+/// Where `codec` is in `CODECS`.
+pub fn index_of(codec: &dyn Codec) -> usize {
+    CODECS
+        .iter()
+        .position(|c| c.name() == codec.name())
+        .unwrap_or(0)
+}
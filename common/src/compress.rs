@@ -0,0 +1,332 @@
+use flate2::{Compress, Decompress, FlushCompress, FlushDecompress};
+use std::io;
+use zstd::stream::raw::{Decoder, Encoder, Operation, OutBuffer};
+
+/// Bytes of output worked on at a time.
+const CHUNK: usize = 4096;
+
+/// The most that `decompress` will let its output grow to, counting whatever
+/// was already there, so that a few bytes of input can't be made to fill up
+/// memory, whether all at once or a little at a time.
+const MAX_INFLATED: usize = 1024 * 1024;
+
+/// The name a client asks for each kind of compression by.
+pub const NAMES: [&str; 2] = ["deflate", "zstd"];
+
+/// What a client asks for to leave its connection uncompressed.
+pub const NONE: &str = "none";
+
+/// A pair of compression streams, one for each direction of a connection.
+///
+/// A client asks for compression by name with a `Compress` message before its
+/// `Name` or `Resume` (after any `Codec`); the server answers, uncompressed,
+/// with a `Compress` message naming what it'll use (`none` if it doesn't know
+/// the one asked for, or doesn't allow compression), and everything either
+/// end sends from then on goes through it.
+///
+/// Each direction is one long stream, flushed after every message, so what
+/// comes before helps compress what comes after, and the other end can always
+/// decompress a whole message as soon as it's all arrived.
+pub trait Compression: Send {
+    fn name(&self) -> &'static str;
+
+    /// Compress `data`, appending it to `out`, flushed so the other end can
+    /// decompress all of it as soon as it's received.
+    fn compress(&mut self, data: &[u8], out: &mut Vec<u8>) -> io::Result<()>;
+
+    /// Decompress as much of `data` as can be, appending it to `out`. The
+    /// stream can be split anywhere between calls; whatever is left of a
+    /// partial block is kept for the next one. It's an error for `out` to
+    /// grow past `MAX_INFLATED` bytes.
+    fn decompress(&mut self, data: &[u8], out: &mut Vec<u8>) -> io::Result<()>;
+}
+
+/// The kind of compression called `name`, ready to start a connection with,
+/// or `None` if it's `none` or there's no such kind.
+pub fn by_name(name: &str) -> Option<Box<dyn Compression>> {
+    match name.to_ascii_lowercase().as_str() {
+        "deflate" => Some(Box::new(Deflate::new())),
+        "zstd" => Some(Box::new(Zstd::new())),
+        _ => None,
+    }
+}
+
+/// Whether `name` is `none` or one of the `NAMES`.
+pub fn is_known(name: &str) -> bool {
+    name.eq_ignore_ascii_case(NONE) || NAMES.iter().any(|n| n.eq_ignore_ascii_case(name))
+}
+
+fn too_big() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!(
+            "Decompressed data is over the limit of {} bytes.",
+            MAX_INFLATED
+        ),
+    )
+}
+
+/// Raw deflate streams (no zlib header), sync-flushed after every message.
+pub struct Deflate {
+    compress: Compress,
+    decompress: Decompress,
+}
+
+impl Deflate {
+    pub fn new() -> Deflate {
+        Deflate {
+            compress: Compress::new(flate2::Compression::default(), false),
+            decompress: Decompress::new(false),
+        }
+    }
+}
+
+impl Default for Deflate {
+    fn default() -> Self {
+        Deflate::new()
+    }
+}
+
+impl Compression for Deflate {
+    fn name(&self) -> &'static str {
+        "deflate"
+    }
+
+    fn compress(&mut self, data: &[u8], out: &mut Vec<u8>) -> io::Result<()> {
+        let mut input = data;
+        loop {
+            out.reserve(CHUNK);
+            let read_before = self.compress.total_in();
+            self.compress
+                .compress_vec(input, out, FlushCompress::Sync)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+            input = &input[(self.compress.total_in() - read_before) as usize..];
+            // The flush is done once it's stopped short of filling `out`.
+            if input.is_empty() && out.len() < out.capacity() {
+                return Ok(());
+            }
+        }
+    }
+
+    fn decompress(&mut self, data: &[u8], out: &mut Vec<u8>) -> io::Result<()> {
+        let mut buf = [0u8; CHUNK];
+        let mut input = data;
+        loop {
+            let (read_before, written_before) =
+                (self.decompress.total_in(), self.decompress.total_out());
+            self.decompress
+                .decompress(input, &mut buf, FlushDecompress::None)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            let read = (self.decompress.total_in() - read_before) as usize;
+            let written = (self.decompress.total_out() - written_before) as usize;
+            input = &input[read..];
+            out.extend_from_slice(&buf[..written]);
+
+            if out.len() > MAX_INFLATED {
+                return Err(too_big());
+            }
+            let stuck = read == 0 && written == 0;
+            if stuck || (input.is_empty() && written < buf.len()) {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// A zstd stream, flushed after every message.
+pub struct Zstd {
+    encoder: Encoder<'static>,
+    decoder: Decoder<'static>,
+}
+
+impl Zstd {
+    pub fn new() -> Zstd {
+        Zstd {
+            encoder: Encoder::new(zstd::DEFAULT_COMPRESSION_LEVEL).unwrap(),
+            decoder: Decoder::new().unwrap(),
+        }
+    }
+}
+
+impl Default for Zstd {
+    fn default() -> Self {
+        Zstd::new()
+    }
+}
+
+impl Compression for Zstd {
+    fn name(&self) -> &'static str {
+        "zstd"
+    }
+
+    fn compress(&mut self, data: &[u8], out: &mut Vec<u8>) -> io::Result<()> {
+        let mut buf = [0u8; CHUNK];
+        let mut read = 0;
+        while read < data.len() {
+            let status = self.encoder.run_on_buffers(&data[read..], &mut buf)?;
+            read += status.bytes_read;
+            out.extend_from_slice(&buf[..status.bytes_written]);
+        }
+        loop {
+            let mut output = OutBuffer::around(&mut buf[..]);
+            let remaining = self.encoder.flush(&mut output)?;
+            let written = output.pos();
+            out.extend_from_slice(&buf[..written]);
+            if remaining == 0 {
+                return Ok(());
+            }
+        }
+    }
+
+    fn decompress(&mut self, data: &[u8], out: &mut Vec<u8>) -> io::Result<()> {
+        let mut buf = [0u8; CHUNK];
+        let mut read = 0;
+        loop {
+            let status = self.decoder.run_on_buffers(&data[read..], &mut buf)?;
+            read += status.bytes_read;
+            out.extend_from_slice(&buf[..status.bytes_written]);
+
+            if out.len() > MAX_INFLATED {
+                return Err(too_big());
+            }
+            let stuck = status.bytes_read == 0 && status.bytes_written == 0;
+            if stuck || (read == data.len() && status.bytes_written < buf.len()) {
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn messages() -> Vec<Vec<u8>> {
+        vec![
+            br#"{"Name":"alice"}"#.to_vec(),
+            br#"{"Text":{"who":"alice","lines":["hello","hello","hello"]}}"#.to_vec(),
+            Vec::new(),
+            b"abcdefghij".repeat(2000),
+            (0..=255u8).cycle().take(3000).collect(),
+            br#""Ping""#.to_vec(),
+        ]
+    }
+
+    /// `messages()` compressed one after another into one stream.
+    fn compressed(name: &str) -> Vec<u8> {
+        let mut c = by_name(name).unwrap();
+        let mut out = Vec::new();
+        for m in messages() {
+            c.compress(&m, &mut out).unwrap();
+        }
+        out
+    }
+
+    #[test]
+    fn each_message_decompresses_as_soon_as_it_arrives() {
+        for name in NAMES {
+            let (mut tx, mut rx) = (by_name(name).unwrap(), by_name(name).unwrap());
+            for m in messages() {
+                let mut data = Vec::new();
+                tx.compress(&m, &mut data).unwrap();
+                let mut out = Vec::new();
+                rx.decompress(&data, &mut out).unwrap();
+                assert_eq!(out, m, "{}", name);
+            }
+        }
+    }
+
+    #[test]
+    fn stream_split_at_every_offset() {
+        let whole: Vec<u8> = messages().concat();
+        for name in NAMES {
+            let data = compressed(name);
+            for at in 0..=data.len() {
+                let mut rx = by_name(name).unwrap();
+                let mut out = Vec::new();
+                rx.decompress(&data[..at], &mut out).unwrap();
+                assert!(whole.starts_with(&out), "{} split at {}", name, at);
+                rx.decompress(&data[at..], &mut out).unwrap();
+                assert!(out == whole, "{} split at {}", name, at);
+            }
+        }
+    }
+
+    #[test]
+    fn stream_fed_a_byte_at_a_time() {
+        let whole: Vec<u8> = messages().concat();
+        for name in NAMES {
+            let mut rx = by_name(name).unwrap();
+            let mut out = Vec::new();
+            for byte in compressed(name).chunks(1) {
+                rx.decompress(byte, &mut out).unwrap();
+                assert!(whole.starts_with(&out), "{}", name);
+            }
+            assert!(out == whole, "{}", name);
+        }
+    }
+
+    #[test]
+    fn inflating_past_the_limit_fails() {
+        for name in NAMES {
+            let (mut tx, mut rx) = (by_name(name).unwrap(), by_name(name).unwrap());
+            let mut data = Vec::new();
+            tx.compress(&vec![0u8; MAX_INFLATED], &mut data).unwrap();
+            let mut out = Vec::new();
+            rx.decompress(&data, &mut out).unwrap();
+            assert_eq!(out.len(), MAX_INFLATED, "{}", name);
+
+            let (mut tx, mut rx) = (by_name(name).unwrap(), by_name(name).unwrap());
+            let mut data = Vec::new();
+            tx.compress(&vec![0u8; 2 * MAX_INFLATED], &mut data)
+                .unwrap();
+            assert!(data.len() < MAX_INFLATED / 100, "{}", name);
+            let err = rx.decompress(&data, &mut Vec::new()).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{}", name);
+        }
+    }
+
+    #[test]
+    fn inflating_past_the_limit_a_little_at_a_time_fails() {
+        for name in NAMES {
+            let (mut tx, mut rx) = (by_name(name).unwrap(), by_name(name).unwrap());
+            let chunk = vec![0u8; MAX_INFLATED / 8];
+            let mut out = Vec::new();
+            let mut result = Ok(());
+            for _ in 0..9 {
+                let mut data = Vec::new();
+                tx.compress(&chunk, &mut data).unwrap();
+                result = rx.decompress(&data, &mut out);
+                if result.is_err() {
+                    break;
+                }
+            }
+            assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidData);
+            assert!(out.len() <= MAX_INFLATED + CHUNK, "{}", name);
+        }
+    }
+
+    #[test]
+    fn garbage_is_an_error() {
+        for name in NAMES {
+            let mut rx = by_name(name).unwrap();
+            let garbage = [0xffu8; 64];
+            assert!(
+                rx.decompress(&garbage, &mut Vec::new()).is_err(),
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn names() {
+        for name in NAMES {
+            assert_eq!(by_name(name).unwrap().name(), name);
+            assert!(is_known(&name.to_uppercase()));
+        }
+        assert!(by_name(NONE).is_none());
+        assert!(is_known(NONE));
+        assert!(!is_known("brotli"));
+    }
+}
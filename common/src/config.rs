@@ -1,4 +1,7 @@
+use crate::codec::{self, CODECS};
+use crate::compress;
 use simplelog::LevelFilter;
+use std::collections::BTreeMap;
 use std::fmt::Write;
 use std::path::PathBuf;
 use std::time::Duration;
@@ -12,18 +15,29 @@ const SERVER_LOG: &str = "freshd.log"; // server log file
 const NAME: &str = "fresh user"; // client user name
 const LOBBY_NAME: &str = "Lobby"; // server landing room name
 const WELCOME_MESSAGE: &str = "Welcome to the server."; // server welcome message
-const SERVER_TICK: u64 = 500; // server, min time through main loop
-const BYTE_LIMIT: usize = 512; // server user rate limiting byte quota
-const BYTE_TICK: usize = 6; // server byte quota dissipation per tick
+pub const SERVER_TICK: u64 = 500; // server, min time through main loop
+pub const BYTE_LIMIT: usize = 512; // server user rate limiting byte quota
+pub const BYTE_TICK: usize = 6; // server byte quota dissipation per tick
 const LOG_LEVEL: LevelFilter = LevelFilter::Warn; // server log level
 const TIME_TO_PING: u64 = 10000; // msec since data received from a client that server will send a ping
 const TIME_TO_KICK: u64 = 20000; // to confirm connection or log the client off for unreachability
+const RESUME_TIMEOUT: u64 = 60000; // msec the server holds a dropped user's session for them to reclaim
+const ALLOW_MARKUP: bool = true; // server relays inline markup in messages; if false, strips it
+const ALLOW_COMPRESSION: bool = true; // server agrees to compress connections whose clients ask for it
 const CLIENT_TICK: u64 = 100; // client time through main loop
 const READ_SIZE: usize = 1024; // client number of bytes per read attempt
 const ROSTER_WIDTH: u16 = 24; // Also server max user name and max room name lengths
 const CMD_CHAR: char = '/';
 const MIN_SCROLLBACK: usize = 1000; // client `Line`s of scrollback kept
 const MAX_SCROLLBACK: usize = 2000; // client will trim scrollback to MIN_SCROLLBACK when this many `Line`s reached
+const HISTORY_SIZE: usize = 500; // client lines of input history kept
+const MOUSE: bool = true; // client captures the mouse for scrolling and clicking
+const KEY_PRESET: &str = "vi"; // client key binding layout
+const THEME_NAME: &str = "dark"; // client colour theme
+const TRANSCRIPTS: bool = false; // client logs room transcripts from the start
+const TRANSCRIPT_FORMAT: &str = "text"; // client transcript files, "text" or "json" lines
+const CODEC: &str = "json"; // client wire codec asked of the server, "json" or "msgpack"
+const COMPRESSION: &str = "none"; // client compression asked of the server, "none", "deflate" or "zstd"
 
 /// Generates a platform-specific path
 fn default_config_dir() -> PathBuf {
@@ -33,6 +47,14 @@ fn default_config_dir() -> PathBuf {
     }
 }
 
+/// Generates a platform-specific path for the client's transcripts
+fn default_transcript_dir() -> PathBuf {
+    match directories::BaseDirs::new() {
+        None => PathBuf::from("transcripts"),
+        Some(dir) => dir.data_local_dir().join("fresh").join("transcripts"),
+    }
+}
+
 /// Attempts to read the first file in the list of paths, returning the contents as a String
 fn read_first_to_string(paths: &[PathBuf]) -> Result<String, String> {
     let mut misses = String::from("Couldn't read from");
@@ -49,12 +71,41 @@ fn read_first_to_string(paths: &[PathBuf]) -> Result<String, String> {
     Err(misses)
 }
 
+/// A token that lets an outside system (CI, alerting and the like) say things
+/// in `room`, as `name`, by POSTing to the admin API's `/hooks/<token>`.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct IncomingWebhook {
+    pub room: String,
+    pub name: String,
+    pub token: String,
+}
+
+/// An `http://` URL that messages said in `room` get POSTed to (as JSON) if
+/// their first line starts with `prefix` or they contain `contains`, or all of
+/// them if neither is set.
+///
+/// The server doesn't speak TLS, so `https://` URLs aren't accepted (the hook
+/// is left out, with an error in the log); to reach one, point `url` at a
+/// local relay or proxy that forwards to it.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct OutgoingWebhook {
+    pub room: String,
+    pub url: String,
+    pub prefix: Option<String>,
+    pub contains: Option<String>,
+}
+
 #[derive(serde::Serialize, serde::Deserialize, Default)]
 struct ServerConfigFile {
     address: Option<String>,
+    websocket_address: Option<String>,
+    irc_address: Option<String>,
+    admin_address: Option<String>,
+    admin_token: Option<String>,
     tick_ms: Option<u64>,
     time_to_ping_ms: Option<u64>,
     time_to_kick_ms: Option<u64>,
+    resume_timeout_ms: Option<u64>,
     max_user_name_length: Option<usize>,
     max_room_name_length: Option<usize>,
     lobby_name: Option<String>,
@@ -63,14 +114,27 @@ struct ServerConfigFile {
     log_level: Option<u8>,
     byte_limit: Option<usize>,
     bytes_per_tick: Option<usize>,
+    allow_markup: Option<bool>,
+    allow_compression: Option<bool>,
+    incoming_webhooks: Option<Vec<IncomingWebhook>>,
+    outgoing_webhooks: Option<Vec<OutgoingWebhook>>,
 }
 
 #[derive(Debug)]
 pub struct ServerConfig {
     pub address: String,
+    /// Where to also listen for WebSocket clients, if anywhere.
+    pub websocket_address: Option<String>,
+    /// Where to also listen for IRC clients, if anywhere.
+    pub irc_address: Option<String>,
+    /// Where to serve the HTTP admin API, if anywhere.
+    pub admin_address: Option<String>,
+    /// The bearer token every admin API request has to carry.
+    pub admin_token: Option<String>,
     pub min_tick: Duration,
     pub time_to_ping: Duration,
     pub time_to_kick: Duration,
+    pub resume_timeout: Duration,
     pub max_user_name_length: usize,
     pub max_room_name_length: usize,
     pub lobby_name: String,
@@ -79,6 +143,38 @@ pub struct ServerConfig {
     pub log_level: LevelFilter,
     pub byte_limit: usize,
     pub byte_tick: usize,
+    pub allow_markup: bool,
+    pub allow_compression: bool,
+    pub incoming_webhooks: Vec<IncomingWebhook>,
+    pub outgoing_webhooks: Vec<OutgoingWebhook>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            address: ADDRESS.to_string(),
+            websocket_address: None,
+            irc_address: None,
+            admin_address: None,
+            admin_token: None,
+            min_tick: Duration::from_millis(SERVER_TICK),
+            time_to_ping: Duration::from_millis(TIME_TO_PING),
+            time_to_kick: Duration::from_millis(TIME_TO_KICK),
+            resume_timeout: Duration::from_millis(RESUME_TIMEOUT),
+            max_user_name_length: ROSTER_WIDTH as usize,
+            max_room_name_length: ROSTER_WIDTH as usize,
+            lobby_name: LOBBY_NAME.to_string(),
+            welcome_message: WELCOME_MESSAGE.to_string(),
+            log_file: SERVER_LOG.to_string(),
+            log_level: LOG_LEVEL,
+            byte_limit: BYTE_LIMIT,
+            byte_tick: BYTE_TICK,
+            allow_markup: ALLOW_MARKUP,
+            allow_compression: ALLOW_COMPRESSION,
+            incoming_webhooks: Vec::new(),
+            outgoing_webhooks: Vec::new(),
+        }
+    }
 }
 
 impl ServerConfig {
@@ -130,36 +226,57 @@ impl ServerConfig {
             }
         };
 
+        let defaults = ServerConfig::default();
         ServerConfig {
-            address: cfg_file.address.unwrap_or_else(|| ADDRESS.to_string()),
-            min_tick: Duration::from_millis(cfg_file.tick_ms.unwrap_or(SERVER_TICK)),
-            time_to_ping: Duration::from_millis(cfg_file.time_to_ping_ms.unwrap_or(TIME_TO_PING)),
-            time_to_kick: Duration::from_millis(cfg_file.time_to_kick_ms.unwrap_or(TIME_TO_KICK)),
+            address: cfg_file.address.unwrap_or(defaults.address),
+            websocket_address: cfg_file.websocket_address,
+            irc_address: cfg_file.irc_address,
+            admin_address: cfg_file.admin_address,
+            admin_token: cfg_file.admin_token.filter(|t| !t.is_empty()),
+            min_tick: cfg_file
+                .tick_ms
+                .map_or(defaults.min_tick, Duration::from_millis),
+            time_to_ping: cfg_file
+                .time_to_ping_ms
+                .map_or(defaults.time_to_ping, Duration::from_millis),
+            time_to_kick: cfg_file
+                .time_to_kick_ms
+                .map_or(defaults.time_to_kick, Duration::from_millis),
+            resume_timeout: cfg_file
+                .resume_timeout_ms
+                .map_or(defaults.resume_timeout, Duration::from_millis),
             max_user_name_length: cfg_file
                 .max_user_name_length
-                .unwrap_or(ROSTER_WIDTH as usize),
+                .unwrap_or(defaults.max_user_name_length),
             max_room_name_length: cfg_file
                 .max_room_name_length
-                .unwrap_or(ROSTER_WIDTH as usize),
-            lobby_name: cfg_file
-                .lobby_name
-                .unwrap_or_else(|| LOBBY_NAME.to_string()),
-            welcome_message: cfg_file
-                .welcome_message
-                .unwrap_or_else(|| WELCOME_MESSAGE.to_string()),
-            log_file: cfg_file.log_file.unwrap_or_else(|| SERVER_LOG.to_string()),
+                .unwrap_or(defaults.max_room_name_length),
+            lobby_name: cfg_file.lobby_name.unwrap_or(defaults.lobby_name),
+            welcome_message: cfg_file.welcome_message.unwrap_or(defaults.welcome_message),
+            log_file: cfg_file.log_file.unwrap_or(defaults.log_file),
             log_level,
-            byte_limit: cfg_file.byte_limit.unwrap_or(BYTE_LIMIT),
-            byte_tick: cfg_file.bytes_per_tick.unwrap_or(BYTE_TICK),
+            byte_limit: cfg_file.byte_limit.unwrap_or(defaults.byte_limit),
+            byte_tick: cfg_file.bytes_per_tick.unwrap_or(defaults.byte_tick),
+            allow_markup: cfg_file.allow_markup.unwrap_or(defaults.allow_markup),
+            allow_compression: cfg_file
+                .allow_compression
+                .unwrap_or(defaults.allow_compression),
+            incoming_webhooks: cfg_file.incoming_webhooks.unwrap_or_default(),
+            outgoing_webhooks: cfg_file.outgoing_webhooks.unwrap_or_default(),
         }
     }
 
     fn generate() -> Result<String, String> {
         let cfg = ServerConfigFile {
             address: Some(ADDRESS.to_string()),
+            websocket_address: None,
+            irc_address: None,
+            admin_address: None,
+            admin_token: None,
             tick_ms: Some(SERVER_TICK),
             time_to_ping_ms: Some(TIME_TO_PING),
             time_to_kick_ms: Some(TIME_TO_KICK),
+            resume_timeout_ms: Some(RESUME_TIMEOUT),
             max_user_name_length: Some(ROSTER_WIDTH as usize),
             max_room_name_length: Some(ROSTER_WIDTH as usize),
             lobby_name: Some(LOBBY_NAME.to_string()),
@@ -168,6 +285,10 @@ impl ServerConfig {
             log_level: Some(LOG_LEVEL as u8),
             byte_limit: Some(BYTE_LIMIT),
             bytes_per_tick: Some(BYTE_TICK),
+            allow_markup: Some(ALLOW_MARKUP),
+            allow_compression: Some(ALLOW_COMPRESSION),
+            incoming_webhooks: None,
+            outgoing_webhooks: None,
         };
 
         let mut cfg_path = default_config_dir();
@@ -188,6 +309,30 @@ impl ServerConfig {
     }
 }
 
+/// The client's `[keys]` table: a named `preset` layout, plus chords (like
+/// `"ctrl-a"`) mapped to action names for insert and command modes, which
+/// override or add to the preset's bindings.
+#[derive(serde::Serialize, serde::Deserialize, Default, Debug, Clone)]
+pub struct KeysConfig {
+    pub preset: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub insert: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub command: BTreeMap<String, String>,
+}
+
+/// The client's `[theme]` table: a bundled theme `name`, palette entries (like
+/// `dim = "#808080"`) overriding its colours, and the colours nicks are drawn
+/// in, if not the theme's own.
+#[derive(serde::Serialize, serde::Deserialize, Default, Debug, Clone)]
+pub struct ThemeConfig {
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub palette: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub nicks: Vec<String>,
+}
+
 #[derive(serde::Serialize, serde::Deserialize, Default)]
 struct ClientConfigFile {
     address: Option<String>,
@@ -198,6 +343,16 @@ struct ClientConfigFile {
     cmd_char: Option<char>,
     max_scrollback: Option<usize>,
     min_scrollback: Option<usize>,
+    history_size: Option<usize>,
+    history_file: Option<String>,
+    mouse: Option<bool>,
+    keys: Option<KeysConfig>,
+    theme: Option<ThemeConfig>,
+    transcripts: Option<bool>,
+    transcript_dir: Option<String>,
+    transcript_format: Option<String>,
+    codec: Option<String>,
+    compression: Option<String>,
 }
 
 #[derive(Debug)]
@@ -210,6 +365,16 @@ pub struct ClientConfig {
     pub cmd_char: char,
     pub max_scrollback: usize,
     pub min_scrollback: usize,
+    pub history_size: usize,
+    pub history_file: Option<PathBuf>,
+    pub mouse: bool,
+    pub keys: KeysConfig,
+    pub theme: ThemeConfig,
+    pub transcripts: bool,
+    pub transcript_dir: PathBuf,
+    pub transcript_format: String,
+    pub codec: String,
+    pub compression: String,
 }
 
 impl ClientConfig {
@@ -259,6 +424,25 @@ impl ClientConfig {
         if (cmd_char as u32) > 128 {
             return Err("cmd_char must be an ASCII character".to_string());
         };
+        let transcript_format = f
+            .transcript_format
+            .unwrap_or_else(|| String::from(TRANSCRIPT_FORMAT));
+        if transcript_format != "text" && transcript_format != "json" {
+            return Err("transcript_format must be \"text\" or \"json\"".to_string());
+        };
+        let codec = f.codec.unwrap_or_else(|| String::from(CODEC));
+        if codec::by_name(&codec).is_none() {
+            let names: Vec<&str> = CODECS.iter().map(|c| c.name()).collect();
+            return Err(format!("codec must be one of: {}", names.join(", ")));
+        };
+        let compression = f.compression.unwrap_or_else(|| String::from(COMPRESSION));
+        if !compress::is_known(&compression) {
+            return Err(format!(
+                "compression must be one of: {}, {}",
+                compress::NONE,
+                compress::NAMES.join(", ")
+            ));
+        };
 
         let cc = ClientConfig {
             address: f.address.unwrap_or_else(|| String::from(ADDRESS)),
@@ -269,6 +453,18 @@ impl ClientConfig {
             cmd_char,
             max_scrollback: max_scroll,
             min_scrollback: min_scroll,
+            history_size: f.history_size.unwrap_or(HISTORY_SIZE),
+            history_file: f.history_file.map(PathBuf::from),
+            mouse: f.mouse.unwrap_or(MOUSE),
+            keys: f.keys.unwrap_or_default(),
+            theme: f.theme.unwrap_or_default(),
+            transcripts: f.transcripts.unwrap_or(TRANSCRIPTS),
+            transcript_dir: f
+                .transcript_dir
+                .map_or_else(default_transcript_dir, PathBuf::from),
+            transcript_format,
+            codec,
+            compression,
         };
 
         Ok(cc)
@@ -284,6 +480,22 @@ impl ClientConfig {
             cmd_char: Some(CMD_CHAR),
             max_scrollback: Some(MAX_SCROLLBACK),
             min_scrollback: Some(MIN_SCROLLBACK),
+            history_size: Some(HISTORY_SIZE),
+            history_file: None,
+            mouse: Some(MOUSE),
+            keys: Some(KeysConfig {
+                preset: Some(String::from(KEY_PRESET)),
+                ..KeysConfig::default()
+            }),
+            theme: Some(ThemeConfig {
+                name: Some(String::from(THEME_NAME)),
+                ..ThemeConfig::default()
+            }),
+            transcripts: Some(TRANSCRIPTS),
+            transcript_dir: None,
+            transcript_format: Some(String::from(TRANSCRIPT_FORMAT)),
+            codec: Some(String::from(CODEC)),
+            compression: Some(String::from(COMPRESSION)),
         };
 
         let mut cfg_path = default_config_dir();
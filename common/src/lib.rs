@@ -1,6 +1,12 @@
+pub mod client;
+pub mod codec;
+pub mod compress;
 pub mod config;
+pub mod markup;
 pub mod proto;
+pub mod quota;
 pub mod room;
 pub mod socket;
 pub mod user;
 pub mod util;
+pub mod websocket;
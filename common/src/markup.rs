@@ -0,0 +1,104 @@
+/// The inline styles chat text can ask for: `*bold*`, `_italic_`, `` `code` ``
+/// and `~strike~`. A delimiter only opens a span at the start of a word and
+/// closes one at the end of a word, so `snake_case_names` and `2*3*4` are left
+/// alone. Lines starting with three backticks fence off blocks of code.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Markup {
+    Bold,
+    Italic,
+    Code,
+    Strike,
+}
+
+impl Markup {
+    fn from_delimiter(c: char) -> Option<Markup> {
+        match c {
+            '*' => Some(Markup::Bold),
+            '_' => Some(Markup::Italic),
+            '`' => Some(Markup::Code),
+            '~' => Some(Markup::Strike),
+            _ => None,
+        }
+    }
+}
+
+/// A run of text within a line, either plain or marked up.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Span {
+    Plain(String),
+    Marked(Markup, String),
+}
+
+const FENCE: &str = "```";
+
+/// Whether `line` opens or closes a fenced code block.
+pub fn is_fence(line: &str) -> bool {
+    line.trim_start().starts_with(FENCE)
+}
+
+fn opens(chars: &[char], i: usize) -> bool {
+    (i == 0 || !chars[i - 1].is_alphanumeric())
+        && matches!(chars.get(i + 1), Some(c) if !c.is_whitespace())
+}
+
+fn closes(chars: &[char], i: usize) -> bool {
+    i > 0
+        && !chars[i - 1].is_whitespace()
+        && !matches!(chars.get(i + 1), Some(c) if c.is_alphanumeric())
+}
+
+/// Split a single line into plain and marked-up spans. Delimiters that don't
+/// pair up are left in as plain text.
+pub fn parse(line: &str) -> Vec<Span> {
+    let chars: Vec<char> = line.chars().collect();
+    let mut spans: Vec<Span> = Vec::new();
+    let mut plain = String::new();
+    let mut i: usize = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        if let Some(markup) = Markup::from_delimiter(c).filter(|_| opens(&chars, i)) {
+            let close = (i + 2..chars.len()).find(|&j| chars[j] == c && closes(&chars, j));
+            if let Some(j) = close {
+                if !plain.is_empty() {
+                    spans.push(Span::Plain(std::mem::take(&mut plain)));
+                }
+                spans.push(Span::Marked(markup, chars[i + 1..j].iter().collect()));
+                i = j + 1;
+                continue;
+            }
+        }
+        plain.push(c);
+        i += 1;
+    }
+
+    if !plain.is_empty() {
+        spans.push(Span::Plain(plain));
+    }
+    spans
+}
+
+/// Remove all markup from the lines of a message: fence lines are dropped and
+/// delimiters outside code blocks are taken out.
+pub fn strip<T: AsRef<str>>(lines: &[T]) -> Vec<String> {
+    let mut stripped: Vec<String> = Vec::with_capacity(lines.len());
+    let mut in_block = false;
+
+    for line in lines.iter().map(AsRef::as_ref) {
+        if is_fence(line) {
+            in_block = !in_block;
+        } else if in_block {
+            stripped.push(line.to_string());
+        } else {
+            let text: String = parse(line)
+                .into_iter()
+                .map(|span| match span {
+                    Span::Plain(s) | Span::Marked(_, s) => s,
+                })
+                .collect();
+            stripped.push(text);
+        }
+    }
+
+    stripped
+}
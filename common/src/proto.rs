@@ -1,140 +1,220 @@
+use super::codec::{index_of, Codec, CODECS};
 use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
 
-/// `SndOp` enum represents one of the `Room` operator subcommands.
-#[derive(Clone, Copy, Debug, Serialize)]
-pub enum SndOp<'a> {
+/// One of the `Room` operator subcommands.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Op<'a> {
     /// Open the current `Room`, allowing the general public to join.
     Open,
     /// Close the current `Room`, restricting access to only those with invitations.
     Close,
     /// Ban the specified `User` from the `Room` (even if it's `Open`), and remove them if they're currently in it.
-    Kick(&'a str),
+    Kick(Cow<'a, str>),
     /// Permit the specified `User` to enter the current room (even if it's `Close`d), and send an invitation message to them.
-    Invite(&'a str),
+    Invite(Cow<'a, str>),
     /// Transfer operator privileges to another `User` (the `User` must be in the `Room` to receive the privileges).
-    Give(&'a str),
+    Give(Cow<'a, str>),
+    /// Give the specified `User` a voice in the `Room`, or take it away if they already have one.
+    Voice(Cow<'a, str>),
 }
 
-/// The `Sndr` enum is the structure that gets serialized to JSON and sent over TCP connections between the server and clients.
+/// The `Msg` enum is the structure that gets encoded (by one of the `codec`s) and sent over TCP connections between
+/// the server and clients, and decoded at the other end. Its text borrows when a message is being put together to send, and is
+/// owned (as a `Msg<'static>`) when one has been received.
 ///
-/// The first four variants (`Text {...}`, `Ping`, `Priv {...}`, and `Logout(...)`) are bi-directional.
+/// The first six variants (`Text {...}`, `Ping`, `Priv {...}`, `Logout(...)`, `Codec(...)` and `Compress(...)`) are
+/// bi-directional.
 ///
-/// The next six (`Name`, `Join`, `Query`, `Block`, `Unblock`, and `Op`) are for sending commands or requests from the client to the server.
+/// The next eight (`Name`, `Resume`, `Join`, `Query`, `Block`, `Unblock`, `Op`, and `Away`) are for sending commands or requests from the client to the server.
 ///
-/// The final three (`Info`, `Err`, and `Misc`) are used only to send information from the server back to the client.
-#[derive(Clone, Copy, Debug, Serialize)]
-pub enum Sndr<'a> {
+/// The rest (`Info`, `Err`, `Joined` and so on, down to `Misc`) are used only to send information from the server back to the client.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Msg<'a> {
     // Bi-directional messages
     /// Standard text message exchanged in a chat.
-    Text { who: &'a str, lines: &'a [&'a str] },
+    Text {
+        #[serde(default)]
+        who: Cow<'a, str>,
+        lines: Vec<Cow<'a, str>>,
+    },
 
     /// Ping message to confirm the connection between client and server.
     Ping,
 
     /// Private message sent to a single recipient.
-    Priv { who: &'a str, text: &'a str },
+    Priv {
+        who: Cow<'a, str>,
+        text: Cow<'a, str>,
+    },
 
     /// Client message to request a clean disconnect from the server, displaying the supplied message to other users in the `Room`. The server responds with a similar message as an acknowledgment before closing the connection.
-    Logout(&'a str),
+    Logout(Cow<'a, str>),
+
+    /// Client request, before its `Name` or `Resume`, to switch to the named `Codec`; the server answers with the name
+    /// of the one it'll use (`json` if it doesn't know the one asked for). Both are sent in JSON.
+    Codec(Cow<'a, str>),
+
+    /// Client request, before its `Name` or `Resume` (and after any `Codec`), to compress the connection with the named
+    /// `Compression`; the server answers, uncompressed, with the name of the one it'll use (`none` if it won't).
+    Compress(Cow<'a, str>),
 
     // Client-to-server messages
     /// Request to change the user's name.
-    Name(&'a str),
+    Name(Cow<'a, str>),
+
+    /// Initial message (in place of `Name`) from a reconnecting client, presenting the token the server issued to its previous connection in order to reclaim its name and `Room`.
+    Resume {
+        name: Cow<'a, str>,
+        token: Cow<'a, str>,
+    },
 
     /// Request to join (or create if necessary) a room.
-    Join(&'a str),
+    Join(Cow<'a, str>),
 
     /// A request from the client to the server for specific information.
-    Query { what: &'a str, arg: &'a str },
+    Query {
+        what: Cow<'a, str>,
+        arg: Cow<'a, str>,
+    },
 
     /// Request from the client to block messages (including private messages) from the specified `User`.
-    Block(&'a str),
+    Block(Cow<'a, str>),
 
     /// Request from the client to unblock the specified `User`.
-    Unblock(&'a str),
+    Unblock(Cow<'a, str>),
+
+    /// One of the operator subcommands (refer to the `Op` enum).
+    Op(Op<'a>),
 
-    /// One of the operator subcommands (refer to the `SndOp` enum).
-    Op(SndOp<'a>),
+    /// Mark the user as away, with the supplied message, or (if it's empty) as back.
+    Away(Cow<'a, str>),
 
     // Server-to-client messages
     /// A non-error informative message sent from the server to the client.
-    Info(&'a str),
+    Info(Cow<'a, str>),
 
     /// A message sent from the server to the client indicating an error or an invalid action.
-    Err(&'a str),
+    Err(Cow<'a, str>),
 
-    /// The `Misc` variant represents information that the client may want to display in a structured manner.
-    /// The client can either implement its own way of displaying the information or use the provided `.alt` field.
-    Misc {
-        what: &'a str,
-        data: &'a [&'a str],
-        alt: &'a str,
+    /// A `User` (perhaps the recipient) has joined a `Room`.
+    Joined {
+        user: Cow<'a, str>,
+        room: Cow<'a, str>,
     },
-}
 
-impl Sndr<'_> {
-    /// Return the JSON-encoded bytes of the reciever.
-    pub fn bytes(&self) -> Vec<u8> {
-        serde_json::to_vec_pretty(&self).unwrap()
-    }
-}
+    /// A `User` has left the recipient's `Room`, for the given reason.
+    Left {
+        user: Cow<'a, str>,
+        reason: Cow<'a, str>,
+    },
 
-#[derive(Clone, Debug, PartialEq, Deserialize)]
-pub enum RcvOp {
-    Open,
-    Close,
-    Kick(String),
-    Invite(String),
-    Give(String),
-}
+    /// A `User` (perhaps the recipient) has changed their name.
+    Renamed {
+        old: Cow<'a, str>,
+        new: Cow<'a, str>,
+    },
 
-/// The reciever side of the client-server protocol.
-#[derive(Clone, Debug, PartialEq, Deserialize)]
-pub enum Rcvr {
-    Text {
-        #[serde(default)]
-        who: String,
-        lines: Vec<String>,
+    /// The server's copy, sent back to the sender, of a private message.
+    PrivEcho {
+        to: Cow<'a, str>,
+        text: Cow<'a, str>,
     },
 
-    Ping,
-    Priv {
-        who: String,
-        text: String,
+    /// A `User` has become the operator of a `Room`.
+    NewOp {
+        user: Cow<'a, str>,
+        room: Cow<'a, str>,
     },
-    Logout(String),
 
-    Name(String),
-    Join(String),
-    Query {
-        what: String,
-        arg: String,
+    /// A `User` has gone away, leaving the given message.
+    IsAway {
+        user: Cow<'a, str>,
+        message: Cow<'a, str>,
+    },
+
+    /// A `User` is no longer away.
+    IsBack { user: Cow<'a, str> },
+
+    /// The operator of a `Room` has given a `User` a voice there.
+    Voiced {
+        user: Cow<'a, str>,
+        by: Cow<'a, str>,
+        room: Cow<'a, str>,
     },
-    Block(String),
-    Unblock(String),
-    Op(RcvOp),
 
-    Info(String),
-    Err(String),
+    /// The operator of a `Room` has taken a `User`'s voice there away.
+    Devoiced {
+        user: Cow<'a, str>,
+        by: Cow<'a, str>,
+        room: Cow<'a, str>,
+    },
+
+    /// A `User` has been kicked from the recipient's `Room`.
+    Kicked {
+        user: Cow<'a, str>,
+        room: Cow<'a, str>,
+    },
+
+    /// The recipient has been kicked from a `Room`.
+    KickedOut { room: Cow<'a, str> },
+
+    /// The `User`s in the recipient's `Room`, in answer to a `roster` `Query`.
+    Roster(Cow<'a, [RosterEntry]>),
+
+    /// The `Room`s matching a `rooms` `Query`.
+    Rooms(Vec<Cow<'a, str>>),
+
+    /// The `User`s matching a `who` `Query`.
+    Who(Vec<Cow<'a, str>>),
+
+    /// The recipient's address, as the server sees it, in answer to an `addr` `Query`.
+    Addr(Cow<'a, str>),
+
+    /// The token the recipient can present in a `Resume` to reclaim its session after losing the connection.
+    ResumeToken(Cow<'a, str>),
+
+    /// The recipient has gone over its byte quota, and its messages will be ignored until it's drained; the text says
+    /// as much, for clients that just show it.
+    OverQuota(Cow<'a, str>),
+
+    /// Information of a kind the client may not know about. The client can either implement its own way of displaying
+    /// it or use the provided `.alt` field. Everything the server currently sends has its own variant, so this is
+    /// only here so that clients can cope with servers newer than they are.
     Misc {
-        what: String,
-        data: Vec<String>,
-        alt: String,
+        what: Cow<'a, str>,
+        data: Vec<Cow<'a, str>>,
+        alt: Cow<'a, str>,
     },
 }
 
-impl Rcvr {
+impl Msg<'_> {
+    /// Whether the server counts this message against the sender's byte quota.
     pub fn counts(&self) -> bool {
         matches!(
             self,
-            Rcvr::Text { who: _, lines: _ }
-                | Rcvr::Priv { who: _, text: _ }
-                | Rcvr::Name(_)
-                | Rcvr::Join(_)
+            Msg::Text { .. } | Msg::Priv { .. } | Msg::Name(_) | Msg::Join(_) | Msg::Away(_)
         )
     }
 }
 
+/// Borrow each of `strs` for a `Msg`'s list of lines or names.
+pub fn cows<S: AsRef<str>>(strs: &[S]) -> Vec<Cow<'_, str>> {
+    strs.iter().map(|s| Cow::Borrowed(s.as_ref())).collect()
+}
+
+/// One `User`'s entry in a `Roster`: whether they're the `Room`'s operator,
+/// have a voice there or are away, and how many seconds it's been since they
+/// last said anything.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RosterEntry {
+    pub name: String,
+    pub op: bool,
+    pub voiced: bool,
+    pub away: bool,
+    pub idle_secs: u64,
+}
+
 /// Message endpoint.
 #[derive(Clone, Copy, Debug)]
 pub enum End {
@@ -144,28 +224,411 @@ pub enum End {
     All,
 }
 
-/// The `Env` struct represents a message sent between two `End`s.
+/// The `Env` struct represents a message sent between two `End`s. It's
+/// encoded once with each of the `CODECS`, so it can go to every recipient in
+/// whichever one their connection uses.
 #[derive(Clone, Debug)]
 pub struct Env {
     pub source: End,
     pub dest: End,
-    data: Vec<u8>,
+    data: Vec<Vec<u8>>,
 }
 
 impl<'a> Env {
-    pub fn new(from: End, to: End, msg: &'a Sndr) -> Env {
+    pub fn new(from: End, to: End, msg: &'a Msg) -> Env {
         Env {
             source: from,
             dest: to,
-            data: msg.bytes(),
+            data: CODECS.iter().map(|c| c.encode(msg)).collect(),
         }
     }
 
-    pub fn bytes(&self) -> &[u8] {
-        &self.data
+    pub fn bytes(&self, codec: &dyn Codec) -> &[u8] {
+        &self.data[index_of(codec)]
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::collection::vec;
+    use proptest::prelude::*;
 
-    pub fn into_bytes(self) -> Vec<u8> {
-        self.data
+    /// The name of `msg`'s variant. There's no catch-all arm, so a new variant
+    /// won't compile until it's been added here, and `every_variant_is_covered`
+    /// won't pass until it's been added to `every_msg` below.
+    fn variant(msg: &Msg) -> &'static str {
+        match msg {
+            Msg::Text { .. } => "Text",
+            Msg::Ping => "Ping",
+            Msg::Priv { .. } => "Priv",
+            Msg::Logout(_) => "Logout",
+            Msg::Codec(_) => "Codec",
+            Msg::Compress(_) => "Compress",
+            Msg::Name(_) => "Name",
+            Msg::Resume { .. } => "Resume",
+            Msg::Join(_) => "Join",
+            Msg::Query { .. } => "Query",
+            Msg::Block(_) => "Block",
+            Msg::Unblock(_) => "Unblock",
+            Msg::Op(Op::Open) => "Op::Open",
+            Msg::Op(Op::Close) => "Op::Close",
+            Msg::Op(Op::Kick(_)) => "Op::Kick",
+            Msg::Op(Op::Invite(_)) => "Op::Invite",
+            Msg::Op(Op::Give(_)) => "Op::Give",
+            Msg::Op(Op::Voice(_)) => "Op::Voice",
+            Msg::Away(_) => "Away",
+            Msg::Info(_) => "Info",
+            Msg::Err(_) => "Err",
+            Msg::Joined { .. } => "Joined",
+            Msg::Left { .. } => "Left",
+            Msg::Renamed { .. } => "Renamed",
+            Msg::PrivEcho { .. } => "PrivEcho",
+            Msg::NewOp { .. } => "NewOp",
+            Msg::IsAway { .. } => "IsAway",
+            Msg::IsBack { .. } => "IsBack",
+            Msg::Voiced { .. } => "Voiced",
+            Msg::Devoiced { .. } => "Devoiced",
+            Msg::Kicked { .. } => "Kicked",
+            Msg::KickedOut { .. } => "KickedOut",
+            Msg::Roster(_) => "Roster",
+            Msg::Rooms(_) => "Rooms",
+            Msg::Who(_) => "Who",
+            Msg::Addr(_) => "Addr",
+            Msg::ResumeToken(_) => "ResumeToken",
+            Msg::OverQuota(_) => "OverQuota",
+            Msg::Misc { .. } => "Misc",
+        }
+    }
+
+    fn every_msg() -> Vec<Msg<'static>> {
+        let roster = vec![
+            RosterEntry {
+                name: "alice".into(),
+                op: true,
+                voiced: false,
+                away: false,
+                idle_secs: 0,
+            },
+            RosterEntry {
+                name: "bob \u{1f600}".into(),
+                op: false,
+                voiced: true,
+                away: true,
+                idle_secs: u64::MAX,
+            },
+        ];
+        vec![
+            Msg::Text {
+                who: "alice".into(),
+                lines: vec!["one".into(), "".into(), "\"quoted\" \\ ünïcødé".into()],
+            },
+            Msg::Text {
+                who: "".into(),
+                lines: vec![],
+            },
+            Msg::Ping,
+            Msg::Priv {
+                who: "bob".into(),
+                text: "psst".into(),
+            },
+            Msg::Logout("bye".into()),
+            Msg::Codec("msgpack".into()),
+            Msg::Compress("zstd".into()),
+            Msg::Name("alice".into()),
+            Msg::Resume {
+                name: "alice".into(),
+                token: "0123456789abcdef".into(),
+            },
+            Msg::Join("Lobby".into()),
+            Msg::Query {
+                what: "roster".into(),
+                arg: "".into(),
+            },
+            Msg::Block("bob".into()),
+            Msg::Unblock("bob".into()),
+            Msg::Op(Op::Open),
+            Msg::Op(Op::Close),
+            Msg::Op(Op::Kick("bob".into())),
+            Msg::Op(Op::Invite("bob".into())),
+            Msg::Op(Op::Give("bob".into())),
+            Msg::Op(Op::Voice("bob".into())),
+            Msg::Away("lunch".into()),
+            Msg::Info("Welcome!".into()),
+            Msg::Err("No such user.".into()),
+            Msg::Joined {
+                user: "alice".into(),
+                room: "Lobby".into(),
+            },
+            Msg::Left {
+                user: "alice".into(),
+                reason: "bye".into(),
+            },
+            Msg::Renamed {
+                old: "alice".into(),
+                new: "alicia".into(),
+            },
+            Msg::PrivEcho {
+                to: "bob".into(),
+                text: "psst".into(),
+            },
+            Msg::NewOp {
+                user: "bob".into(),
+                room: "dev".into(),
+            },
+            Msg::IsAway {
+                user: "bob".into(),
+                message: "lunch".into(),
+            },
+            Msg::IsBack { user: "bob".into() },
+            Msg::Voiced {
+                user: "bob".into(),
+                by: "alice".into(),
+                room: "dev".into(),
+            },
+            Msg::Devoiced {
+                user: "bob".into(),
+                by: "alice".into(),
+                room: "dev".into(),
+            },
+            Msg::Kicked {
+                user: "bob".into(),
+                room: "dev".into(),
+            },
+            Msg::KickedOut { room: "dev".into() },
+            Msg::Roster(roster.into()),
+            Msg::Roster(Vec::new().into()),
+            Msg::Rooms(vec!["Lobby".into(), "dev".into()]),
+            Msg::Who(vec!["alice".into()]),
+            Msg::Addr("127.0.0.1:51516".into()),
+            Msg::ResumeToken("0123456789abcdef".into()),
+            Msg::OverQuota("Slow down.".into()),
+            Msg::Misc {
+                what: "future".into(),
+                data: vec!["a".into(), "b".into()],
+                alt: "Something new.".into(),
+            },
+        ]
+    }
+
+    /// The names of `T`'s variants, in the order they're declared, as its
+    /// derived `Deserialize` hands them to a deserializer.
+    fn variants_of<T: for<'de> Deserialize<'de>>() -> &'static [&'static str] {
+        use serde::de::{self, value::Error, Visitor};
+
+        struct Peek<'a>(&'a mut &'static [&'static str]);
+
+        impl<'de> de::Deserializer<'de> for Peek<'_> {
+            type Error = Error;
+
+            fn deserialize_any<V: Visitor<'de>>(self, _: V) -> Result<V::Value, Error> {
+                Err(de::Error::custom("not an enum"))
+            }
+
+            fn deserialize_enum<V: Visitor<'de>>(
+                self,
+                _: &'static str,
+                variants: &'static [&'static str],
+                _: V,
+            ) -> Result<V::Value, Error> {
+                *self.0 = variants;
+                Err(de::Error::custom("only peeking"))
+            }
+
+            serde::forward_to_deserialize_any! {
+                bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+                bytes byte_buf option unit unit_struct newtype_struct seq tuple
+                tuple_struct map struct identifier ignored_any
+            }
+        }
+
+        let mut variants: &'static [&'static str] = &[];
+        let _ = T::deserialize(Peek(&mut variants));
+        variants
+    }
+
+    #[test]
+    fn every_variant_is_covered() {
+        let mut expected = Vec::new();
+        for name in variants_of::<Msg>() {
+            if *name == "Op" {
+                expected.extend(variants_of::<Op>().iter().map(|op| format!("Op::{}", op)));
+            } else {
+                expected.push(name.to_string());
+            }
+        }
+        let names: Vec<&str> = every_msg().iter().map(variant).collect();
+        let missing: Vec<&String> = expected
+            .iter()
+            .filter(|e| !names.contains(&e.as_str()))
+            .collect();
+        assert!(missing.is_empty(), "every_msg has no {:?}", missing);
+    }
+
+    fn text() -> impl Strategy<Value = Cow<'static, str>> {
+        any::<String>().prop_map(Cow::Owned)
+    }
+
+    fn texts() -> impl Strategy<Value = Vec<Cow<'static, str>>> {
+        vec(text(), 0..4)
+    }
+
+    fn any_op() -> impl Strategy<Value = Op<'static>> {
+        prop_oneof![
+            Just(Op::Open),
+            Just(Op::Close),
+            text().prop_map(Op::Kick),
+            text().prop_map(Op::Invite),
+            text().prop_map(Op::Give),
+            text().prop_map(Op::Voice),
+        ]
+    }
+
+    fn any_roster_entry() -> impl Strategy<Value = RosterEntry> {
+        (
+            any::<String>(),
+            any::<bool>(),
+            any::<bool>(),
+            any::<bool>(),
+            any::<u64>(),
+        )
+            .prop_map(|(name, op, voiced, away, idle_secs)| RosterEntry {
+                name,
+                op,
+                voiced,
+                away,
+                idle_secs,
+            })
+    }
+
+    /// Any `Msg` at all, with arbitrary text in it.
+    fn any_msg() -> impl Strategy<Value = Msg<'static>> {
+        prop_oneof![
+            (text(), texts()).prop_map(|(who, lines)| Msg::Text { who, lines }),
+            Just(Msg::Ping),
+            (text(), text()).prop_map(|(who, text)| Msg::Priv { who, text }),
+            text().prop_map(Msg::Logout),
+            text().prop_map(Msg::Codec),
+            text().prop_map(Msg::Compress),
+            text().prop_map(Msg::Name),
+            (text(), text()).prop_map(|(name, token)| Msg::Resume { name, token }),
+            text().prop_map(Msg::Join),
+            (text(), text()).prop_map(|(what, arg)| Msg::Query { what, arg }),
+            text().prop_map(Msg::Block),
+            text().prop_map(Msg::Unblock),
+            any_op().prop_map(Msg::Op),
+            text().prop_map(Msg::Away),
+            text().prop_map(Msg::Info),
+            text().prop_map(Msg::Err),
+            (text(), text()).prop_map(|(user, room)| Msg::Joined { user, room }),
+            (text(), text()).prop_map(|(user, reason)| Msg::Left { user, reason }),
+            (text(), text()).prop_map(|(old, new)| Msg::Renamed { old, new }),
+            (text(), text()).prop_map(|(to, text)| Msg::PrivEcho { to, text }),
+            (text(), text()).prop_map(|(user, room)| Msg::NewOp { user, room }),
+            (text(), text()).prop_map(|(user, message)| Msg::IsAway { user, message }),
+            text().prop_map(|user| Msg::IsBack { user }),
+            (text(), text(), text()).prop_map(|(user, by, room)| Msg::Voiced { user, by, room }),
+            (text(), text(), text()).prop_map(|(user, by, room)| Msg::Devoiced { user, by, room }),
+            (text(), text()).prop_map(|(user, room)| Msg::Kicked { user, room }),
+            text().prop_map(|room| Msg::KickedOut { room }),
+            vec(any_roster_entry(), 0..4).prop_map(|entries| Msg::Roster(entries.into())),
+            texts().prop_map(Msg::Rooms),
+            texts().prop_map(Msg::Who),
+            text().prop_map(Msg::Addr),
+            text().prop_map(Msg::ResumeToken),
+            text().prop_map(Msg::OverQuota),
+            (text(), texts(), text()).prop_map(|(what, data, alt)| Msg::Misc { what, data, alt }),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn any_msg_round_trips_through_every_codec(msg in any_msg()) {
+            for codec in CODECS.iter() {
+                let data = codec.encode(&msg);
+                prop_assert_eq!(codec.decode(&data), Ok(Some((msg.clone(), data.len()))), "{}", codec.name());
+            }
+        }
+
+        #[test]
+        fn any_msgs_back_to_back_decode_in_order(msgs in vec(any_msg(), 0..8)) {
+            for codec in CODECS.iter() {
+                let mut data: Vec<u8> = msgs.iter().flat_map(|m| codec.encode(m)).collect();
+                for msg in &msgs {
+                    let (decoded, used) = codec.decode(&data).unwrap().unwrap();
+                    prop_assert_eq!(&decoded, msg, "{}", codec.name());
+                    data.drain(..used);
+                }
+                prop_assert!(data.is_empty());
+            }
+        }
+
+        #[test]
+        fn any_msg_cut_short_waits_for_the_rest(msg in any_msg(), cut in any::<prop::sample::Index>()) {
+            for codec in CODECS.iter() {
+                let data = codec.encode(&msg);
+                let end = cut.index(data.len());
+                prop_assert_eq!(codec.decode(&data[..end]), Ok(None), "cut at {} through {}", end, codec.name());
+            }
+        }
+    }
+
+    #[test]
+    fn every_msg_round_trips_through_every_codec() {
+        for codec in CODECS.iter() {
+            for msg in every_msg() {
+                let data = codec.encode(&msg);
+                let decoded = codec.decode(&data);
+                assert_eq!(
+                    decoded,
+                    Ok(Some((msg.clone(), data.len()))),
+                    "{} through {}",
+                    variant(&msg),
+                    codec.name()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn back_to_back_msgs_decode_in_order() {
+        for codec in CODECS.iter() {
+            let msgs = every_msg();
+            let mut data: Vec<u8> = msgs.iter().flat_map(|m| codec.encode(m)).collect();
+            for msg in msgs {
+                let (decoded, used) = codec.decode(&data).unwrap().unwrap();
+                assert_eq!(decoded, msg, "{}", codec.name());
+                data.drain(..used);
+            }
+            assert!(data.is_empty());
+        }
+    }
+
+    #[test]
+    fn partial_msgs_wait_for_the_rest() {
+        for codec in CODECS.iter() {
+            for msg in every_msg() {
+                let data = codec.encode(&msg);
+                for end in 0..data.len() {
+                    assert_eq!(
+                        codec.decode(&data[..end]),
+                        Ok(None),
+                        "{} cut at {} through {}",
+                        variant(&msg),
+                        end,
+                        codec.name()
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn env_holds_every_encoding() {
+        let msg = Msg::Info("hi".into());
+        let env = Env::new(End::Server, End::All, &msg);
+        for codec in CODECS.iter() {
+            assert_eq!(env.bytes(*codec), &codec.encode(&msg)[..]);
+        }
     }
 }
@@ -0,0 +1,148 @@
+use super::config::{BYTE_LIMIT, BYTE_TICK, SERVER_TICK};
+use std::time::{Duration, Instant};
+
+/// Keeps track of the server's byte quota from our side, so a bot (or the
+/// client in batch mode) can hold its messages back instead of having them
+/// ignored. The server counts the encoded size of every `Text`, `Priv`,
+/// `Name`, `Join` and `Away` against the quota, forgives a few bytes every
+/// tick, and handles at most one message from each user per tick.
+#[derive(Debug)]
+pub struct Quota {
+    limit: usize,
+    per_tick: usize,
+    tick: Duration,
+    used: usize,
+    last_drain: Instant,
+    last_send: Option<Instant>,
+}
+
+impl Quota {
+    /// A `Quota` matching a server configured with the given `byte_limit`,
+    /// `bytes_per_tick` and `tick_ms`.
+    pub fn new(limit: usize, per_tick: usize, tick: Duration) -> Quota {
+        Quota {
+            limit,
+            per_tick,
+            tick: tick.max(Duration::from_millis(1)),
+            used: 0,
+            last_drain: Instant::now(),
+            last_send: None,
+        }
+    }
+
+    fn drain(&mut self) {
+        let ticks = self.last_drain.elapsed().as_millis() / self.tick.as_millis();
+        if ticks > 0 {
+            let ticks = ticks.min(u32::MAX as u128) as u32;
+            self.used = self
+                .used
+                .saturating_sub(self.per_tick.saturating_mul(ticks as usize));
+            self.last_drain += self.tick * ticks;
+        }
+    }
+
+    /// The most that can be counted against the quota.
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// If a message of `cost` bytes can go out now without going over the
+    /// quota, count it and return `true`. A message bigger than the whole
+    /// quota can still go once nothing else is counted against it.
+    pub fn try_spend(&mut self, cost: usize) -> bool {
+        self.drain();
+        if matches!(self.last_send, Some(t) if t.elapsed() < self.tick) {
+            return false;
+        }
+        if self.used > 0 && self.used + cost > self.limit {
+            return false;
+        }
+        self.used += cost;
+        self.last_send = Some(Instant::now());
+        true
+    }
+
+    /// Called when the server tells us we're over the quota after all (if
+    /// it ticks slower than we think, say), to hold off until it's drained.
+    pub fn exhaust(&mut self) {
+        self.used = self.used.max(self.limit + self.per_tick);
+    }
+}
+
+impl Default for Quota {
+    fn default() -> Self {
+        Quota::new(BYTE_LIMIT, BYTE_TICK, Duration::from_millis(SERVER_TICK))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TICK: Duration = Duration::from_millis(100);
+
+    /// Pretend `ticks` ticks have passed since the quota was last drained,
+    /// and since anything was last sent.
+    fn age(q: &mut Quota, ticks: u32) {
+        q.last_drain -= TICK * ticks;
+        q.last_send = q.last_send.map(|t| t - TICK * ticks);
+    }
+
+    #[test]
+    fn drain_forgives_per_tick() {
+        let mut q = Quota::new(100, 10, TICK);
+        q.used = 50;
+        q.drain();
+        assert_eq!(q.used, 50);
+        age(&mut q, 2);
+        q.drain();
+        assert_eq!(q.used, 30);
+        assert!(q.last_drain.elapsed() < TICK);
+        age(&mut q, 10);
+        q.drain();
+        assert_eq!(q.used, 0);
+    }
+
+    #[test]
+    fn one_message_per_tick() {
+        let mut q = Quota::new(100, 10, TICK);
+        assert!(q.try_spend(10));
+        assert!(!q.try_spend(10));
+        age(&mut q, 1);
+        assert!(q.try_spend(10));
+        assert_eq!(q.used, 10);
+    }
+
+    #[test]
+    fn waits_until_a_message_fits() {
+        let mut q = Quota::new(100, 10, TICK);
+        assert!(q.try_spend(90));
+        age(&mut q, 1);
+        assert!(!q.try_spend(30));
+        assert_eq!(q.used, 80);
+        age(&mut q, 1);
+        assert!(q.try_spend(30));
+        assert_eq!(q.used, 100);
+    }
+
+    #[test]
+    fn oversized_message_goes_once_drained() {
+        let mut q = Quota::new(100, 50, TICK);
+        assert!(q.try_spend(250));
+        age(&mut q, 4);
+        assert!(!q.try_spend(250));
+        age(&mut q, 1);
+        assert!(q.try_spend(250));
+    }
+
+    #[test]
+    fn exhaust_holds_off_until_drained() {
+        let mut q = Quota::new(100, 10, TICK);
+        q.exhaust();
+        assert!(!q.try_spend(1));
+        age(&mut q, 1);
+        assert!(!q.try_spend(1));
+        age(&mut q, 1);
+        assert!(q.try_spend(10));
+    }
+}
@@ -15,6 +15,7 @@ pub struct Room {
     pub closed: bool,
     bans: Vec<u64>,
     invites: Vec<u64>,
+    voiced: Vec<u64>,
     inbox: Vec<Env>,
 }
 
@@ -29,6 +30,7 @@ impl Room {
             closed: false,
             bans: Vec::new(),
             invites: Vec::new(),
+            voiced: Vec::new(),
             inbox: Vec::new(),
         }
     }
@@ -64,9 +66,9 @@ impl Room {
         self.inbox.push(env);
     }
 
-    /// Deliver all the `Env`s in the queue.
+    /// Deliver all the `Env`s in the queue, in the order they were queued.
     pub fn deliver_inbox(&mut self, user_id_hash: &mut HashMap<u64, User>) {
-        while let Some(env) = self.inbox.pop() {
+        for env in self.inbox.drain(..) {
             match env.dest {
                 End::User(user_id) => {
                     if let Some(user) = user_id_hash.get_mut(&user_id) {
@@ -90,6 +92,7 @@ impl Room {
 
     pub fn leave(&mut self, user_id: u64) {
         self.users.retain(|n| *n != user_id);
+        self.voiced.retain(|n| *n != user_id);
     }
 
     pub fn ban(&mut self, user_id: u64) {
@@ -121,4 +124,56 @@ impl Room {
     pub fn is_invited(&self, user_id: &u64) -> bool {
         self.invites.contains(user_id)
     }
+
+    /// Give the user a voice, or take it away if they have one. Returns
+    /// whether they now have one.
+    pub fn toggle_voice(&mut self, user_id: u64) -> bool {
+        if self.is_voiced(&user_id) {
+            self.voiced.retain(|n| *n != user_id);
+            false
+        } else {
+            self.voiced.push(user_id);
+            true
+        }
+    }
+
+    pub fn is_voiced(&self, user_id: &u64) -> bool {
+        self.voiced.contains(user_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::Json;
+    use crate::proto::Msg;
+    use crate::socket::Socket;
+    use std::io::Read;
+    use std::net::{TcpListener, TcpStream};
+
+    #[test]
+    fn inbox_is_delivered_in_the_order_it_was_queued() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        let mut users = HashMap::new();
+        users.insert(1, User::new(Socket::new(stream).unwrap(), 1));
+        let mut room = Room::new(0, String::from("Lobby"), 1);
+        room.join(1);
+
+        let envs: Vec<Env> = ["one", "two", "three"]
+            .iter()
+            .map(|t| Env::new(End::Server, End::Room(0), &Msg::Info((*t).into())))
+            .collect();
+        let expected: Vec<u8> = envs.iter().flat_map(|e| e.bytes(&Json).to_vec()).collect();
+        for env in envs {
+            room.enqueue(env);
+        }
+        room.deliver_inbox(&mut users);
+        users.get_mut(&1).unwrap().send();
+
+        let mut got = vec![0u8; expected.len()];
+        client.read_exact(&mut got).unwrap();
+        assert_eq!(got, expected);
+    }
 }
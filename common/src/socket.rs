@@ -1,12 +1,13 @@
-use super::proto::Rcvr;
-use serde_json::error::Category;
-use std::io::{Read, Write};
+use super::codec::{self, Codec, Json};
+use super::compress::{self, Compression};
+use super::proto::Msg;
+use super::websocket::WebSocket;
+use std::io::{self, Read, Write};
 use std::net::{Shutdown, TcpStream};
+use std::time::{Duration, Instant};
 
 const DEFAULT_BUFFER_SIZE: usize = 1024;
 
-const NEWLINE: u8 = b'\n';
-
 #[derive(Debug)]
 pub enum SocketErrorKind {
     SetNoDelayFailed,
@@ -17,6 +18,9 @@ pub enum SocketErrorKind {
     WriteFailed,
     FlushFailed,
     GetRemoteAddressFailed,
+    NegotiationFailed,
+    DecompressFailed,
+    CompressFailed,
 }
 
 #[derive(Debug)]
@@ -41,23 +45,26 @@ impl std::fmt::Display for SocketError {
     }
 }
 
-fn get_offset(data: &[u8], e: &serde_json::Error) -> Result<usize, &'static str> {
-    let line_number = e.line() - 1;
-    let column_number = e.column() - 1;
-    let mut parsed_line_count: usize = 0;
+fn unexpected(what: &str, msg: &Msg) -> SocketError {
+    SocketError::new(
+        SocketErrorKind::NegotiationFailed,
+        format!("Expected a \"{}\" message, got {:?}", what, msg),
+    )
+}
 
-    let character_offset = data.iter().enumerate().find_map(|(n, b)| {
-        if parsed_line_count < line_number {
-            if *b == NEWLINE {
-                parsed_line_count += 1;
-            }
-            None
-        } else {
-            Some(n + column_number)
-        }
-    });
+/// A layer beneath a `Socket`'s buffers for clients that speak some other
+/// protocol (like IRC): what they send is translated into `Msg`s, in JSON,
+/// before the socket decodes it, and what's sent to them is translated from
+/// JSON `Msg`s into whatever they expect.
+pub trait Translation: Send {
+    /// Take the bytes in `data`, appending the JSON `Msg`s they make to `out`,
+    /// and anything that's answered without the server's help to `replies`.
+    fn read(&mut self, data: &[u8], out: &mut Vec<u8>, replies: &mut Vec<u8>) -> io::Result<()>;
 
-    character_offset.ok_or("Failed to find offset")
+    /// Take the JSON `Msg`s in `data`, appending what they make to `out`. Any
+    /// `Msg` that needs sending back on the client's behalf is appended (in
+    /// JSON) to `fed`.
+    fn write(&mut self, data: &[u8], out: &mut Vec<u8>, fed: &mut Vec<u8>);
 }
 
 pub struct Socket {
@@ -65,6 +72,13 @@ pub struct Socket {
     read_buff: Vec<u8>,
     current: Vec<u8>,
     send_buff: Vec<u8>,
+    codec: &'static dyn Codec,
+    compression: Option<Box<dyn Compression>>,
+    websocket: Option<WebSocket>,
+    translation: Option<Box<dyn Translation>>,
+    /// Why queueing something failed, if it has; the connection can't go on
+    /// once part of its stream is missing.
+    failed: Option<String>,
 }
 
 impl Socket {
@@ -83,10 +97,44 @@ impl Socket {
             read_buff,
             current: Vec::<u8>::new(),
             send_buff: Vec::<u8>::new(),
+            codec: &Json,
+            compression: None,
+            websocket: None,
+            translation: None,
+            failed: None,
         })
     }
 
+    /// Like `new`, but for a `stream` whose WebSocket upgrade request has
+    /// already been answered by `websocket::accept()`; `already_read` is what
+    /// that returned. Everything sent and received goes in WebSocket messages.
+    pub fn new_websocket(stream: TcpStream, already_read: Vec<u8>) -> Result<Socket, SocketError> {
+        let mut socket = Socket::new(stream)?;
+        socket.websocket = Some(WebSocket::new());
+        if !already_read.is_empty() {
+            socket.receive(&already_read)?;
+        }
+        Ok(socket)
+    }
+
+    /// Like `new`, but for a client that speaks another protocol: what it
+    /// sends is read as the `Msg`s `translation` makes of it, and everything
+    /// sent to it goes through `translation` first. The codec has to stay
+    /// `Json`.
+    pub fn new_translated(
+        stream: TcpStream,
+        translation: Box<dyn Translation>,
+    ) -> Result<Socket, SocketError> {
+        let mut socket = Socket::new(stream)?;
+        socket.translation = Some(translation);
+        Ok(socket)
+    }
+
     pub fn shutdown(&mut self) -> Result<(), SocketError> {
+        if let Some(ws) = &mut self.websocket {
+            ws.close(&mut self.send_buff);
+            let _ = self.send_data();
+        }
         match self.stream.shutdown(Shutdown::Both) {
             Err(e) => Err(SocketError::from_err(SocketErrorKind::ShutdownFailed, &e)),
             Ok(()) => Ok(()),
@@ -106,8 +154,8 @@ impl Socket {
         match self.stream.read(&mut self.read_buff) {
             Ok(read_bytes_count) => {
                 if read_bytes_count > 0 {
-                    self.current
-                        .extend_from_slice(&self.read_buff[..read_bytes_count]);
+                    let data = self.read_buff[..read_bytes_count].to_vec();
+                    self.receive(&data)?;
                 }
                 Ok(read_bytes_count)
             }
@@ -118,31 +166,194 @@ impl Socket {
         }
     }
 
-    pub fn try_get(&mut self) -> Result<Option<Rcvr>, SocketError> {
-        match serde_json::from_slice::<Rcvr>(&self.current) {
-            Ok(msg) => {
-                self.current.clear();
+    /// Unwrap `data` from its WebSocket frames and decompress it, if need be,
+    /// into the receive buffer. (Or, from a translated client, translate it.)
+    fn receive(&mut self, data: &[u8]) -> Result<(), SocketError> {
+        if let Some(t) = &mut self.translation {
+            return t
+                .read(data, &mut self.current, &mut self.send_buff)
+                .map_err(|e| SocketError::from_err(SocketErrorKind::ReadFailed, &e));
+        }
+        let mut unframed = Vec::new();
+        let data = match &mut self.websocket {
+            Some(ws) => {
+                ws.unframe(data, &mut unframed, &mut self.send_buff)
+                    .map_err(|e| SocketError::from_err(SocketErrorKind::ReadFailed, &e))?;
+                &unframed[..]
+            }
+            None => data,
+        };
+        match &mut self.compression {
+            Some(c) => c
+                .decompress(data, &mut self.current)
+                .map_err(|e| SocketError::from_err(SocketErrorKind::DecompressFailed, &e)),
+            None => {
+                self.current.extend_from_slice(data);
+                Ok(())
+            }
+        }
+    }
+
+    /// The codec messages are encoded and decoded with; `Json` until the
+    /// handshake says otherwise.
+    pub fn codec(&self) -> &'static dyn Codec {
+        self.codec
+    }
+
+    pub fn set_codec(&mut self, codec: &'static dyn Codec) {
+        self.codec = codec;
+    }
+
+    /// Ask the server to switch to `codec`. This is the client's side of the
+    /// handshake, so it has to come before the `Name` or `Resume`. Blocks for
+    /// up to `limit` waiting for an answer. If the server doesn't know `codec`,
+    /// this leaves the socket on `Json`; check `.codec()` to see which it got.
+    pub fn request_codec(
+        &mut self,
+        codec: &'static dyn Codec,
+        limit: Duration,
+        tick: Duration,
+    ) -> Result<(), SocketError> {
+        if codec.name() == self.codec.name() {
+            return Ok(());
+        }
+        let request = self.codec.encode(&Msg::Codec(codec.name().into()));
+        self.blocking_send(&request, tick)?;
+        match self.await_answer("Codec", limit, tick)? {
+            Msg::Codec(name) => {
+                self.codec = codec::by_name(&name).unwrap_or(&Json);
+                Ok(())
+            }
+            m => Err(unexpected("Codec", &m)),
+        }
+    }
+
+    /// The kind of compression on the connection, or `compress::NONE`.
+    pub fn compression(&self) -> &'static str {
+        match &self.compression {
+            Some(c) => c.name(),
+            None => compress::NONE,
+        }
+    }
+
+    /// Compress (and decompress) everything from here on with `compression`.
+    pub fn set_compression(&mut self, compression: Option<Box<dyn Compression>>) {
+        self.compression = compression;
+    }
+
+    /// Ask the server to compress the connection with the kind of compression
+    /// called `name`. Like `.request_codec()`, this has to come before the
+    /// `Name` or `Resume`, and blocks for up to `limit` waiting for an answer.
+    /// If the server won't, this leaves the connection uncompressed.
+    pub fn request_compression(
+        &mut self,
+        name: &str,
+        limit: Duration,
+        tick: Duration,
+    ) -> Result<(), SocketError> {
+        if name.eq_ignore_ascii_case(compress::NONE) {
+            return Ok(());
+        }
+        let request = self.codec.encode(&Msg::Compress(name.into()));
+        self.blocking_send(&request, tick)?;
+        match self.await_answer("Compress", limit, tick)? {
+            Msg::Compress(name) => {
+                self.compression = compress::by_name(&name);
+                Ok(())
+            }
+            m => Err(unexpected("Compress", &m)),
+        }
+    }
+
+    /// Wait up to `limit` for the server's answer to a handshake request.
+    fn await_answer(
+        &mut self,
+        what: &str,
+        limit: Duration,
+        tick: Duration,
+    ) -> Result<Msg<'static>, SocketError> {
+        let start_t = Instant::now();
+        loop {
+            self.read_data()?;
+            match self.try_get()? {
+                Some(Msg::Logout(message)) => {
+                    return Err(SocketError::new(
+                        SocketErrorKind::NegotiationFailed,
+                        message.into_owned(),
+                    ))
+                }
+                Some(m) => return Ok(m),
+                None => {}
+            }
+            if start_t.elapsed() > limit {
+                return Err(SocketError::new(
+                    SocketErrorKind::NegotiationFailed,
+                    format!("Timed out waiting for the server's \"{}\" answer.", what),
+                ));
+            }
+            std::thread::sleep(tick);
+        }
+    }
+
+    /// Attempts to decode a `Msg` from the data that's been read so far.
+    /// Anything the codec can't make sense of is thrown away, as there's no
+    /// telling where the next message starts.
+    pub fn try_get(&mut self) -> Result<Option<Msg<'static>>, SocketError> {
+        match self.codec.decode(&self.current) {
+            Ok(Some((msg, length))) => {
+                self.current.drain(..length);
                 Ok(Some(msg))
             }
-            Err(e) => match e.classify() {
-                Category::Eof => Ok(None),
-                Category::Syntax => match get_offset(&self.current, &e) {
-                    Ok(offset) => {
-                        let msg_result =
-                            serde_json::from_slice::<Rcvr>(&self.current[..offset]).map(Some);
-                        self.current.drain(0..offset);
-                        msg_result.map_err(|err| {
-                            SocketError::from_err(SocketErrorKind::SyntaxError, &err)
-                        })
-                    }
-                    Err(_) => Err(SocketError::from_err(SocketErrorKind::SyntaxError, &e)),
-                },
-                _ => Err(SocketError::from_err(SocketErrorKind::SyntaxError, &e)),
-            },
+            Ok(None) => Ok(None),
+            Err(e) => {
+                self.current.clear();
+                Err(SocketError::new(SocketErrorKind::SyntaxError, e))
+            }
         }
     }
+
+    /// Queue bytes that have already been encoded with this socket's codec,
+    /// compressing them if the connection is compressed. On a WebSocket they
+    /// go as one message: text if they're uncompressed JSON, binary if not. To
+    /// a translated client, they go however its `Translation` says.
+    ///
+    /// If compressing fails, nothing more is queued, and `send_data()` fails
+    /// from then on.
     pub fn enqueue(&mut self, data: &[u8]) {
-        self.send_buff.extend_from_slice(data);
+        if self.failed.is_some() {
+            return;
+        }
+        if let Some(t) = &mut self.translation {
+            t.write(data, &mut self.send_buff, &mut self.current);
+            return;
+        }
+        let text = self.compression.is_none() && self.codec.name() == Json.name();
+        let compressed = match &mut self.compression {
+            Some(c) => {
+                let mut compressed = Vec::new();
+                if let Err(e) = c.compress(data, &mut compressed) {
+                    self.failed = Some(e.to_string());
+                    return;
+                }
+                Some(compressed)
+            }
+            None => None,
+        };
+        let data = compressed.as_deref().unwrap_or(data);
+        match &mut self.websocket {
+            // Framing only fails once the WebSocket's closed, when there's
+            // nobody left to send to.
+            Some(ws) => {
+                let _ = ws.frame(data, text, &mut self.send_buff);
+            }
+            None => self.send_buff.extend_from_slice(data),
+        }
+    }
+
+    /// Encode `msg` with this socket's codec and queue it.
+    pub fn enqueue_msg(&mut self, msg: &Msg) {
+        let bytes = self.codec.encode(msg);
+        self.enqueue(&bytes);
     }
 
     /// Attempts to send the contents of the send buffer to the remote endpoint.
@@ -151,6 +362,9 @@ impl Socket {
     /// `.shutdown()`.
     /// A return value of `Ok(0)` means the send buffer is empty.
     pub fn send_data(&mut self) -> Result<usize, SocketError> {
+        if let Some(e) = &self.failed {
+            return Err(SocketError::new(SocketErrorKind::CompressFailed, e.clone()));
+        }
         match self.stream.write(&self.send_buff) {
             Ok(written_bytes_count) => {
                 if written_bytes_count == self.send_buff.len() {
@@ -208,3 +422,72 @@ impl Socket {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    fn pair() -> (Socket, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        (Socket::new(stream).unwrap(), client)
+    }
+
+    /// Compression that always fails.
+    struct Broken;
+
+    impl Compression for Broken {
+        fn name(&self) -> &'static str {
+            "broken"
+        }
+
+        fn compress(&mut self, _: &[u8], _: &mut Vec<u8>) -> io::Result<()> {
+            Err(io::Error::new(io::ErrorKind::InvalidData, "broken"))
+        }
+
+        fn decompress(&mut self, _: &[u8], _: &mut Vec<u8>) -> io::Result<()> {
+            Err(io::Error::new(io::ErrorKind::InvalidData, "broken"))
+        }
+    }
+
+    #[test]
+    fn failing_to_compress_fails_the_connection() {
+        let (mut socket, _client) = pair();
+        socket.enqueue_msg(&Msg::Ping);
+        assert_eq!(socket.send_data().unwrap(), 0);
+
+        socket.set_compression(Some(Box::new(Broken)));
+        socket.enqueue_msg(&Msg::Ping);
+        socket.set_compression(None);
+        socket.enqueue_msg(&Msg::Ping);
+        assert_eq!(socket.send_buff_size(), 0);
+        assert!(socket.send_data().is_err());
+        assert!(socket.send_data().is_err());
+    }
+
+    #[test]
+    fn the_receive_buffer_is_bounded() {
+        let (mut socket, mut client) = pair();
+        socket.set_compression(compress::by_name("deflate"));
+        let mut tx = compress::by_name("deflate").unwrap();
+        let mut data = Vec::new();
+        // Never a whole message, so nothing is ever taken off the buffer.
+        tx.compress(b"{\"Info\":\"", &mut data).unwrap();
+        for _ in 0..64 {
+            tx.compress(&[b'x'; 64 * 1024], &mut data).unwrap();
+        }
+        client.write_all(&data).unwrap();
+        let start = Instant::now();
+        let err = loop {
+            match socket.read_data() {
+                Err(e) => break e,
+                Ok(_) => assert!(start.elapsed() < Duration::from_secs(5)),
+            }
+            assert!(socket.try_get().unwrap().is_none());
+        };
+        assert!(matches!(err.kind, SocketErrorKind::DecompressFailed));
+        assert!(socket.recv_buff_size() <= 2 * 1024 * 1024);
+    }
+}
@@ -1,6 +1,8 @@
+use crate::codec::Codec;
+use crate::compress::Compression;
 use crate::util::collapse;
 
-use super::proto::{End, Env, Rcvr, Sndr};
+use super::proto::{End, Env, Msg};
 use super::socket::{Socket, SocketError};
 use std::fmt::Display;
 use std::time::{Duration, Instant};
@@ -53,6 +55,9 @@ pub struct User {
     last_data_time: Instant,
     errs: Vec<SocketError>,
     blocked_users: Vec<u64>,
+    resume_token: String,
+    away: Option<String>,
+    last_active: Instant,
 }
 
 impl User {
@@ -68,6 +73,9 @@ impl User {
             last_data_time: Instant::now(),
             errs: Vec::<SocketError>::new(),
             blocked_users: Vec::<u64>::new(),
+            resume_token: String::default(),
+            away: None,
+            last_active: Instant::now(),
         }
     }
 
@@ -90,11 +98,50 @@ impl User {
         }
     }
 
+    /// Switch this `User`'s connection to `codec`, as agreed in the handshake.
+    pub fn set_codec(&mut self, codec: &'static dyn Codec) {
+        self.socket.set_codec(codec);
+    }
+
+    /// Compress this `User`'s connection from here on, as agreed in the
+    /// handshake.
+    pub fn set_compression(&mut self, compression: Option<Box<dyn Compression>>) {
+        self.socket.set_compression(compression);
+    }
+
     pub fn set_name(&mut self, new_name: &str) {
         self.name = String::from(new_name);
         self.idstr = collapse(new_name);
     }
 
+    /// The token this `User`'s client can present to reclaim its session
+    /// after losing the connection.
+    pub fn get_resume_token(&self) -> &str {
+        &(self.resume_token)
+    }
+
+    pub fn set_resume_token(&mut self, token: String) {
+        self.resume_token = token;
+    }
+
+    /// The message this `User` left when they went away, if they're away.
+    pub fn get_away(&self) -> Option<&str> {
+        self.away.as_deref()
+    }
+
+    pub fn set_away(&mut self, message: Option<String>) {
+        self.away = message;
+    }
+
+    /// How long since this `User` last said anything.
+    pub fn get_idle(&self) -> Duration {
+        self.last_active.elapsed()
+    }
+
+    pub fn mark_active(&mut self) {
+        self.last_active = Instant::now();
+    }
+
     pub fn get_byte_quota(&self) -> usize {
         self.quota_bytes
     }
@@ -120,7 +167,7 @@ impl User {
     }
 
     pub fn logout(&mut self, logout_message: &str) {
-        let msg = Sndr::Logout(logout_message);
+        let msg = Msg::Logout(logout_message.into());
         self.deliver_msg(&msg);
         let _ = self.socket.send_data();
         let _ = self.socket.shutdown();
@@ -151,18 +198,18 @@ impl User {
             End::User(id) => match &(self.blocked_users).binary_search(&id) {
                 Ok(_) => {} // do nothing
                 Err(_) => {
-                    self.socket.enqueue(env.bytes());
+                    self.socket.enqueue(env.bytes(self.socket.codec()));
                 }
             },
             _ => {
-                self.socket.enqueue(env.bytes());
+                self.socket.enqueue(env.bytes(self.socket.codec()));
             }
         }
     }
 
-    /// Add the contents of an `Sndr` to the outgoing buffer.
-    pub fn deliver_msg(&mut self, msg: &Sndr) {
-        self.socket.enqueue(&(msg.bytes()));
+    /// Add the contents of a `Msg` to the outgoing buffer.
+    pub fn deliver_msg(&mut self, msg: &Msg) {
+        self.socket.enqueue_msg(msg);
     }
 
     /// Send any data that's been queued up.
@@ -174,7 +221,7 @@ impl User {
         }
     }
 
-    pub fn blocking_send(&mut self, msg: &Sndr, limit: Duration) -> Result<(), UserError> {
+    pub fn blocking_send(&mut self, msg: &Msg, limit: Duration) -> Result<(), UserError> {
         self.deliver_msg(msg);
         let start_t = Instant::now();
         loop {
@@ -196,7 +243,7 @@ impl User {
     }
 
     /// Attempt to read data and decode a `Msg` from the underlying socket.
-    pub fn try_get(&mut self) -> Option<Rcvr> {
+    pub fn try_get(&mut self) -> Option<Msg<'static>> {
         let n = match self.socket.read_data() {
             Err(e) => {
                 self.errs.push(e);
@@ -229,7 +276,7 @@ impl User {
         }
     }
 
-    pub fn blocking_get(&mut self, limit: Duration) -> Result<Rcvr, UserError> {
+    pub fn blocking_get(&mut self, limit: Duration) -> Result<Msg<'static>, UserError> {
         let start_t = Instant::now();
 
         loop {
@@ -254,7 +301,7 @@ impl User {
     }
 }
 
-fn handle_error(errs: &mut Vec<SocketError>, e: SocketError) -> Result<Rcvr, UserError> {
+fn handle_error(errs: &mut Vec<SocketError>, e: SocketError) -> Result<Msg<'static>, UserError> {
     let err = UserError::from_socket(&e);
     errs.push(e);
     Err(err)
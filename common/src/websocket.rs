@@ -0,0 +1,356 @@
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
+use tungstenite::handshake::derive_accept_key;
+use tungstenite::protocol::{Role, WebSocketConfig, WebSocketContext};
+use tungstenite::{Error, Message};
+
+/// The most a client can send in one WebSocket message.
+const MAX_MESSAGE: usize = 1024 * 1024;
+
+/// The longest an upgrade request can be.
+const MAX_REQUEST: usize = 8192;
+
+const END_OF_HEADERS: &[u8] = b"\r\n\r\n";
+
+/// Answer a WebSocket upgrade request on `stream`, waiting up to `limit` for
+/// it. Returns whatever the client sent after the request, which is the start
+/// of its first frames.
+pub fn accept(stream: &mut TcpStream, limit: Duration) -> Result<Vec<u8>, String> {
+    let start_t = Instant::now();
+    let mut data: Vec<u8> = Vec::new();
+    let mut buff = [0u8; 1024];
+
+    let header_end = loop {
+        if let Some(n) = data
+            .windows(END_OF_HEADERS.len())
+            .position(|w| w == END_OF_HEADERS)
+        {
+            break n + END_OF_HEADERS.len();
+        }
+        if data.len() > MAX_REQUEST {
+            return Err(reject(stream, "Upgrade request is too long."));
+        }
+        if start_t.elapsed() > limit {
+            return Err(String::from("Timed out reading the upgrade request."));
+        }
+        stream
+            .set_read_timeout(Some(limit))
+            .map_err(|e| e.to_string())?;
+        match stream.read(&mut buff) {
+            Ok(0) => return Err(String::from("Closed before finishing the upgrade request.")),
+            Ok(n) => data.extend_from_slice(&buff[..n]),
+            Err(e) => return Err(format!("Error reading the upgrade request: {}", e)),
+        }
+    };
+
+    let mut headers = [httparse::EMPTY_HEADER; 32];
+    let mut request = httparse::Request::new(&mut headers);
+    if let Err(e) = request.parse(&data[..header_end]) {
+        return Err(reject(stream, &format!("Bad upgrade request: {}", e)));
+    }
+    let header = |name: &str| {
+        request
+            .headers
+            .iter()
+            .find(|h| h.name.eq_ignore_ascii_case(name))
+            .map(|h| h.value)
+    };
+    let is_upgrade = matches!(header("Upgrade"), Some(v) if v.eq_ignore_ascii_case(b"websocket"));
+    let key = match header("Sec-WebSocket-Key") {
+        Some(key) if request.method == Some("GET") && is_upgrade => key,
+        _ => return Err(reject(stream, "Not a WebSocket upgrade request.")),
+    };
+
+    let response = format!(
+        "HTTP/1.1 101 Switching Protocols\r\n\
+         Connection: Upgrade\r\n\
+         Upgrade: websocket\r\n\
+         Sec-WebSocket-Accept: {}\r\n\r\n",
+        derive_accept_key(key)
+    );
+    stream
+        .write_all(response.as_bytes())
+        .map_err(|e| format!("Error answering the upgrade request: {}", e))?;
+
+    Ok(data.split_off(header_end))
+}
+
+/// Tell the client its request was no good, returning `reason`.
+fn reject(stream: &mut TcpStream, reason: &str) -> String {
+    let _ = stream.write_all(b"HTTP/1.1 400 Bad Request\r\nConnection: close\r\n\r\n");
+    String::from(reason)
+}
+
+/// What the `WebSocketContext` reads from and writes to: the bytes read from
+/// the connection, and the `Socket`'s send buffer.
+struct Pipe<'a> {
+    input: &'a [u8],
+    output: &'a mut Vec<u8>,
+}
+
+impl Read for Pipe<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.input.is_empty() {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        self.input.read(buf)
+    }
+}
+
+impl Write for Pipe<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.output.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The server's end of a WebSocket connection, as a layer beneath a `Socket`'s
+/// buffers: each chunk of data the `Socket` sends goes out as one message, and
+/// the messages that come in are run together into its receive buffer. Pings
+/// and closes are answered as they arrive.
+pub struct WebSocket {
+    context: WebSocketContext,
+}
+
+impl WebSocket {
+    pub fn new() -> WebSocket {
+        let config = WebSocketConfig {
+            max_message_size: Some(MAX_MESSAGE),
+            max_frame_size: Some(MAX_MESSAGE),
+            ..WebSocketConfig::default()
+        };
+        WebSocket {
+            context: WebSocketContext::new(Role::Server, Some(config)),
+        }
+    }
+
+    /// Frame `data` as one message, appending it to `out`. It goes as a text
+    /// message if `text` is set and it's valid UTF-8, and a binary one if not.
+    pub fn frame(&mut self, data: &[u8], text: bool, out: &mut Vec<u8>) -> io::Result<()> {
+        let message = match std::str::from_utf8(data) {
+            Ok(s) if text => Message::Text(s.to_string()),
+            _ => Message::Binary(data.to_vec()),
+        };
+        let mut pipe = Pipe {
+            input: &[],
+            output: out,
+        };
+        self.context.write(&mut pipe, message).map_err(to_io)?;
+        self.context.flush(&mut pipe).map_err(to_io)
+    }
+
+    /// Take the frames in `data`, appending what the messages they make up
+    /// say to `out`, and any answers to pings or closes to `replies`. Partial
+    /// frames are kept until the rest arrives.
+    pub fn unframe(
+        &mut self,
+        data: &[u8],
+        out: &mut Vec<u8>,
+        replies: &mut Vec<u8>,
+    ) -> io::Result<()> {
+        let mut pipe = Pipe {
+            input: data,
+            output: replies,
+        };
+        loop {
+            match self.context.read(&mut pipe) {
+                Ok(Message::Text(text)) => out.extend_from_slice(text.as_bytes()),
+                Ok(Message::Binary(bytes)) => out.extend_from_slice(&bytes),
+                Ok(Message::Close(_)) => {
+                    // Get the answering close frame out before reporting it.
+                    let _ = self.context.flush(&mut pipe);
+                    return Err(io::Error::new(
+                        io::ErrorKind::ConnectionAborted,
+                        "WebSocket closed by the client.",
+                    ));
+                }
+                Ok(_) => {}
+                Err(Error::Io(e)) if e.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                Err(e) => return Err(to_io(e)),
+            }
+        }
+    }
+
+    /// Append a close frame to `out`.
+    pub fn close(&mut self, out: &mut Vec<u8>) {
+        let mut pipe = Pipe {
+            input: &[],
+            output: out,
+        };
+        let _ = self.context.close(&mut pipe, None);
+    }
+}
+
+impl Default for WebSocket {
+    fn default() -> Self {
+        WebSocket::new()
+    }
+}
+
+fn to_io(e: Error) -> io::Error {
+    match e {
+        Error::Io(e) => e,
+        Error::ConnectionClosed | Error::AlreadyClosed => io::ErrorKind::ConnectionAborted.into(),
+        e => io::Error::new(io::ErrorKind::InvalidData, e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::{Codec, Json};
+    use crate::proto::Msg;
+    use std::net::TcpListener;
+    use std::thread;
+
+    const LIMIT: Duration = Duration::from_secs(5);
+
+    type Client = tungstenite::WebSocket<TcpStream>;
+
+    /// A tungstenite client connected to the server's end of a connection that
+    /// `accept` has upgraded.
+    fn connect() -> (Client, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let client = thread::spawn(move || {
+            let stream = TcpStream::connect(address).unwrap();
+            stream.set_read_timeout(Some(LIMIT)).unwrap();
+            tungstenite::client(format!("ws://{}/", address), stream)
+                .unwrap()
+                .0
+        });
+        let (mut server, _) = listener.accept().unwrap();
+        let leftover = accept(&mut server, LIMIT).unwrap();
+        assert!(leftover.is_empty());
+        server.set_read_timeout(Some(LIMIT)).unwrap();
+        (client.join().unwrap(), server)
+    }
+
+    /// Read from `stream` into `ws` until it's produced some output or
+    /// replies, or failed.
+    fn receive(ws: &mut WebSocket, stream: &mut TcpStream) -> io::Result<(Vec<u8>, Vec<u8>)> {
+        let (mut out, mut replies) = (Vec::new(), Vec::new());
+        let mut buff = [0u8; 1024];
+        while out.is_empty() && replies.is_empty() {
+            let n = stream.read(&mut buff)?;
+            assert!(n > 0, "Connection closed.");
+            ws.unframe(&buff[..n], &mut out, &mut replies)?;
+        }
+        Ok((out, replies))
+    }
+
+    fn msg() -> Msg<'static> {
+        Msg::Text {
+            who: "alice".into(),
+            lines: vec!["hello".into(), "ünïcode ☃".into()],
+        }
+    }
+
+    #[test]
+    fn msgs_round_trip() {
+        let (mut client, mut server) = connect();
+        let mut ws = WebSocket::new();
+
+        // Server to client, as text.
+        let encoded = Json.encode(&msg());
+        let mut framed = Vec::new();
+        ws.frame(&encoded, true, &mut framed).unwrap();
+        server.write_all(&framed).unwrap();
+        let text = match client.read().unwrap() {
+            Message::Text(text) => text,
+            other => panic!("Expected text, got {:?}", other),
+        };
+        assert_eq!(Json.decode(text.as_bytes()).unwrap().unwrap().0, msg());
+
+        // Not UTF-8, so it has to go as binary.
+        let mut framed = Vec::new();
+        ws.frame(&[0xff, 0x00], true, &mut framed).unwrap();
+        server.write_all(&framed).unwrap();
+        assert_eq!(client.read().unwrap(), Message::Binary(vec![0xff, 0x00]));
+
+        // Client to server, as text and as binary.
+        client
+            .send(Message::Text(String::from_utf8(encoded.clone()).unwrap()))
+            .unwrap();
+        let (out, replies) = receive(&mut ws, &mut server).unwrap();
+        assert!(replies.is_empty());
+        assert_eq!(Json.decode(&out).unwrap().unwrap().0, msg());
+        client.send(Message::Binary(encoded)).unwrap();
+        let (out, _) = receive(&mut ws, &mut server).unwrap();
+        assert_eq!(Json.decode(&out).unwrap().unwrap().0, msg());
+    }
+
+    #[test]
+    fn pings_are_answered() {
+        let (mut client, mut server) = connect();
+        let mut ws = WebSocket::new();
+        client
+            .send(Message::Ping(b"still there?".to_vec()))
+            .unwrap();
+        let (out, replies) = receive(&mut ws, &mut server).unwrap();
+        assert!(out.is_empty());
+        server.write_all(&replies).unwrap();
+        assert_eq!(
+            client.read().unwrap(),
+            Message::Pong(b"still there?".to_vec())
+        );
+    }
+
+    #[test]
+    fn client_closes() {
+        let (mut client, mut server) = connect();
+        let mut ws = WebSocket::new();
+        client.close(None).unwrap();
+
+        let mut buff = [0u8; 1024];
+        let n = server.read(&mut buff).unwrap();
+        let mut replies = Vec::new();
+        let err = ws
+            .unframe(&buff[..n], &mut Vec::new(), &mut replies)
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionAborted);
+        assert!(!replies.is_empty(), "The close wasn't answered.");
+
+        server.write_all(&replies).unwrap();
+        assert!(matches!(client.read().unwrap(), Message::Close(_)));
+        // The server hangs up once it's answered.
+        drop(server);
+        match client.read() {
+            Err(Error::ConnectionClosed) => {}
+            other => panic!("Expected the close to finish, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn server_closes() {
+        let (mut client, mut server) = connect();
+        let mut ws = WebSocket::new();
+        let mut framed = Vec::new();
+        ws.close(&mut framed);
+        server.write_all(&framed).unwrap();
+        assert!(matches!(client.read().unwrap(), Message::Close(_)));
+        client.flush().unwrap();
+        let err = receive(&mut ws, &mut server).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionAborted);
+    }
+
+    #[test]
+    fn non_upgrade_requests_are_rejected() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        client
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let (mut server, _) = listener.accept().unwrap();
+        assert!(accept(&mut server, LIMIT).is_err());
+        drop(server);
+        let mut answer = String::new();
+        client.read_to_string(&mut answer).unwrap();
+        assert!(answer.starts_with("HTTP/1.1 400 "));
+    }
+}
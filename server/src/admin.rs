@@ -0,0 +1,831 @@
+use common::{
+    config::{IncomingWebhook, ServerConfig},
+    markup,
+    proto::{End, Env, Msg},
+    room::Room,
+    user::User,
+    util::collapse,
+};
+use log::{debug, warn};
+use serde_json::{json, Value};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::connection::connections_accepted;
+use crate::processing::hand_over_op;
+use crate::session::Session;
+
+/// How long a request has to arrive, and the main loop has to answer it.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The most a request (headers and body together) can be.
+const MAX_REQUEST: usize = 64 * 1024;
+
+const END_OF_HEADERS: &[u8] = b"\r\n\r\n";
+
+/// Something the admin API has been asked to do, for the main loop to carry
+/// out, since it owns the users and rooms.
+#[derive(Debug)]
+pub enum Request {
+    Health,
+    Metrics,
+    /// The users whose names start with the given pattern.
+    Users(String),
+    /// The rooms whose names start with the given pattern.
+    Rooms(String),
+    /// Say `text` in `room`, as `from`.
+    Post {
+        room: String,
+        from: String,
+        text: String,
+    },
+    /// Log a user off the server.
+    Kick {
+        user: String,
+        message: String,
+    },
+    /// Ban a user from a room, sending them to the lobby if they're in it.
+    Ban {
+        room: String,
+        user: String,
+    },
+}
+
+/// An HTTP status and the JSON to send with it.
+#[derive(Debug)]
+pub struct Reply {
+    status: u16,
+    body: Value,
+}
+
+impl Reply {
+    fn ok(body: Value) -> Reply {
+        Reply { status: 200, body }
+    }
+
+    fn error(status: u16, message: &str) -> Reply {
+        Reply {
+            status,
+            body: json!({ "error": message }),
+        }
+    }
+}
+
+/// A `Request`, and where to send the `Reply` to it.
+pub type Job = (Request, mpsc::Sender<Reply>);
+
+/// The server's state, as far as the admin API needs it.
+pub struct State<'a> {
+    pub started: Instant,
+    pub users_by_id: &'a mut HashMap<u64, User>,
+    pub user_ids_by_str: &'a mut HashMap<String, u64>,
+    pub rooms_by_id: &'a mut HashMap<u64, Room>,
+    pub room_ids_by_str: &'a HashMap<String, u64>,
+    pub sessions: &'a HashMap<String, Session>,
+}
+
+/// Carry out `request` on the server's state. Called from the main loop.
+pub fn handle(request: Request, state: &mut State, cfg: &ServerConfig) -> Reply {
+    match request {
+        Request::Health => Reply::ok(json!({
+            "status": "ok",
+            "uptime_secs": state.started.elapsed().as_secs(),
+        })),
+        Request::Metrics => metrics(state),
+        Request::Users(pattern) => users(state, &pattern),
+        Request::Rooms(pattern) => rooms(state, &pattern),
+        Request::Post { room, from, text } => post(state, cfg, &room, &from, &text),
+        Request::Kick { user, message } => kick(state, &user, &message),
+        Request::Ban { room, user } => ban(state, &room, &user),
+    }
+}
+
+fn metrics(state: &State) -> Reply {
+    let away = state
+        .users_by_id
+        .values()
+        .filter(|u| u.get_away().is_some())
+        .count();
+    let largest_room = state
+        .rooms_by_id
+        .values()
+        .map(|r| r.get_users().len())
+        .max()
+        .unwrap_or(0);
+    Reply::ok(json!({
+        "uptime_secs": state.started.elapsed().as_secs(),
+        "users": state.users_by_id.len(),
+        "away_users": away,
+        "rooms": state.rooms_by_id.len(),
+        "largest_room": largest_room,
+        "resumable_sessions": state.sessions.len(),
+        "connections_accepted": connections_accepted(),
+    }))
+}
+
+/// The room `user_id` is in.
+fn room_of(rooms_by_id: &HashMap<u64, Room>, user_id: u64) -> Option<&Room> {
+    rooms_by_id
+        .values()
+        .find(|r| r.get_users().contains(&user_id))
+}
+
+fn users(state: &State, pattern: &str) -> Reply {
+    let pattern = collapse(pattern);
+    let mut users: Vec<&User> = state
+        .users_by_id
+        .values()
+        .filter(|u| u.get_idstr().starts_with(&pattern))
+        .collect();
+    users.sort_by(|a, b| a.get_idstr().cmp(b.get_idstr()));
+
+    let list: Vec<Value> = users
+        .iter()
+        .map(|u| {
+            json!({
+                "name": u.get_name(),
+                "room": room_of(state.rooms_by_id, u.get_id()).map(Room::get_name),
+                "away": u.get_away(),
+                "idle_secs": u.get_idle().as_secs(),
+            })
+        })
+        .collect();
+    Reply::ok(Value::Array(list))
+}
+
+fn rooms(state: &State, pattern: &str) -> Reply {
+    let pattern = collapse(pattern);
+    let mut rooms: Vec<&Room> = state
+        .rooms_by_id
+        .values()
+        .filter(|r| r.get_idstr().starts_with(&pattern))
+        .collect();
+    rooms.sort_by_key(|r| r.get_id());
+
+    let list: Vec<Value> = rooms
+        .iter()
+        .map(|r| {
+            json!({
+                "name": r.get_name(),
+                "users": r.get_users().len(),
+                "op": state.users_by_id.get(&r.get_op()).map(User::get_name),
+                "closed": r.closed,
+            })
+        })
+        .collect();
+    Reply::ok(Value::Array(list))
+}
+
+fn post(state: &mut State, cfg: &ServerConfig, room: &str, from: &str, text: &str) -> Reply {
+    if collapse(from).is_empty() {
+        return Reply::error(400, "\"from\" must have some non-whitespace characters.");
+    } else if from.len() > cfg.max_user_name_length {
+        return Reply::error(
+            400,
+            &format!(
+                "\"from\" can't be longer than {} characters.",
+                cfg.max_user_name_length
+            ),
+        );
+    } else if state.user_ids_by_str.contains_key(&collapse(from)) {
+        return Reply::error(409, "\"from\" can't be the name of a connected user.");
+    }
+    let room = match state
+        .room_ids_by_str
+        .get(&collapse(room))
+        .and_then(|id| state.rooms_by_id.get_mut(id))
+    {
+        None => return Reply::error(404, "No such room."),
+        Some(room) => room,
+    };
+
+    let lines: Vec<Cow<str>> = text.lines().map(Cow::Borrowed).collect();
+    let lines = if cfg.allow_markup {
+        lines
+    } else {
+        markup::strip(&lines).into_iter().map(Cow::Owned).collect()
+    };
+    let env = Env::new(
+        End::Server,
+        End::Room(room.get_id()),
+        &Msg::Text {
+            who: from.into(),
+            lines,
+        },
+    );
+    room.enqueue(env);
+    Reply::ok(json!({ "room": room.get_name(), "from": from }))
+}
+
+fn kick(state: &mut State, user: &str, message: &str) -> Reply {
+    let mut user = match state
+        .user_ids_by_str
+        .remove(&collapse(user))
+        .and_then(|id| state.users_by_id.remove(&id))
+    {
+        None => return Reply::error(404, "No such user."),
+        Some(user) => user,
+    };
+    let message = if message.is_empty() {
+        "You have been disconnected by the server's administrator."
+    } else {
+        message
+    };
+    user.logout(message);
+
+    if let Some(room) = state
+        .rooms_by_id
+        .values_mut()
+        .find(|r| r.get_users().contains(&user.get_id()))
+    {
+        room.leave(user.get_id());
+        room.enqueue(Env::new(
+            End::Server,
+            End::Room(room.get_id()),
+            &Msg::Left {
+                user: user.get_name().into(),
+                reason: "[ disconnected by server ]".into(),
+            },
+        ));
+        if let Some(env) = hand_over_op(room, state.users_by_id) {
+            room.enqueue(env);
+        }
+    }
+    Reply::ok(json!({ "kicked": user.get_name() }))
+}
+
+fn ban(state: &mut State, room: &str, user: &str) -> Reply {
+    let user_id = match state.user_ids_by_str.get(&collapse(user)) {
+        None => return Reply::error(404, "No such user."),
+        Some(id) => *id,
+    };
+    let room_id = match state.room_ids_by_str.get(&collapse(room)) {
+        None => return Reply::error(404, "No such room."),
+        Some(0) => return Reply::error(400, "Nobody can be banned from the lobby."),
+        Some(id) => *id,
+    };
+    let (user, room) = match (
+        state.users_by_id.get_mut(&user_id),
+        state.rooms_by_id.get_mut(&room_id),
+    ) {
+        (Some(user), Some(room)) => (user, room),
+        _ => return Reply::error(500, "The server's records are inconsistent."),
+    };
+    let reply = Reply::ok(json!({ "banned": user.get_name(), "room": room.get_name() }));
+
+    room.ban(user_id);
+    if !room.get_users().contains(&user_id) {
+        return reply;
+    }
+    user.deliver_msg(&Msg::KickedOut {
+        room: room.get_name().into(),
+    });
+    room.leave(user_id);
+    room.enqueue(Env::new(
+        End::Server,
+        End::Room(room_id),
+        &Msg::Kicked {
+            user: user.get_name().into(),
+            room: room.get_name().into(),
+        },
+    ));
+    let lobby = state.rooms_by_id.get_mut(&0).unwrap();
+    lobby.join(user_id);
+    lobby.enqueue(Env::new(
+        End::Server,
+        End::Room(0),
+        &Msg::Joined {
+            user: user.get_name().into(),
+            room: lobby.get_name().into(),
+        },
+    ));
+
+    let room = state.rooms_by_id.get_mut(&room_id).unwrap();
+    if let Some(env) = hand_over_op(room, state.users_by_id) {
+        room.enqueue(env);
+    }
+    reply
+}
+
+/// Whether `a` and `b` are the same, taking as long to find out either way.
+fn same_token(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0u8, |diff, (x, y)| diff | (x ^ y))
+            == 0
+}
+
+/// Undo the %-encoding of part of a URL.
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(b)) => {
+                decoded.push(b);
+                i += 3;
+            }
+            (b'+', _) => {
+                decoded.push(b' ');
+                i += 1;
+            }
+            (b, _) => {
+                decoded.push(b);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// The value of `key` in a query string, if it's there.
+fn query_param(query: &str, key: &str) -> Option<String> {
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(k, _)| *k == key)
+        .map(|(_, v)| percent_decode(v))
+}
+
+/// A string field of a JSON body.
+fn field(body: &Value, key: &str) -> Option<String> {
+    body.get(key).and_then(Value::as_str).map(String::from)
+}
+
+/// Work out which `Request` an HTTP request is, or the `Reply` saying why it
+/// isn't one.
+fn route(method: &str, target: &str, body: &[u8]) -> Result<Request, Reply> {
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let segments: Vec<String> = path
+        .split('/')
+        .filter(|s| !s.is_empty())
+        .map(percent_decode)
+        .collect();
+    let segments: Vec<&str> = segments.iter().map(String::as_str).collect();
+    let pattern = query_param(query, "match").unwrap_or_default();
+
+    let json_body = || -> Result<Value, Reply> {
+        if body.is_empty() {
+            return Ok(json!({}));
+        }
+        serde_json::from_slice(body).map_err(|e| Reply::error(400, &format!("Bad JSON: {}", e)))
+    };
+    let bad_method = || Reply::error(405, "Method not allowed.");
+
+    match (method, segments.as_slice()) {
+        ("GET", ["health"]) => Ok(Request::Health),
+        ("GET", ["metrics"]) => Ok(Request::Metrics),
+        ("GET", ["users"]) => Ok(Request::Users(pattern)),
+        ("GET", ["rooms"]) => Ok(Request::Rooms(pattern)),
+        ("POST", ["rooms", room, "messages"]) => {
+            let body = json_body()?;
+            match (field(&body, "from"), field(&body, "text")) {
+                (Some(from), Some(text)) => Ok(Request::Post {
+                    room: room.to_string(),
+                    from,
+                    text,
+                }),
+                _ => Err(Reply::error(400, "Expected \"from\" and \"text\" strings.")),
+            }
+        }
+        ("POST", ["users", user, "kick"]) => {
+            let body = json_body()?;
+            Ok(Request::Kick {
+                user: user.to_string(),
+                message: field(&body, "message").unwrap_or_default(),
+            })
+        }
+        ("POST", ["rooms", room, "bans", user]) => Ok(Request::Ban {
+            room: room.to_string(),
+            user: user.to_string(),
+        }),
+        (_, ["health"] | ["metrics"] | ["users"] | ["rooms"]) => Err(bad_method()),
+        (_, ["rooms", _, "messages"] | ["users", _, "kick"] | ["rooms", _, "bans", _]) => {
+            Err(bad_method())
+        }
+        _ => Err(Reply::error(404, "No such endpoint.")),
+    }
+}
+
+/// Read one HTTP request from `stream`, returning its method, target, bearer
+/// token (if any) and body.
+fn read_request(
+    stream: &mut TcpStream,
+) -> Result<(String, String, Option<String>, Vec<u8>), Reply> {
+    let start_t = Instant::now();
+    let mut data: Vec<u8> = Vec::new();
+    let mut buff = [0u8; 1024];
+    let mut parsed: Option<(String, String, Option<String>, usize, usize)> = None;
+
+    loop {
+        if parsed.is_none() {
+            if let Some(n) = data
+                .windows(END_OF_HEADERS.len())
+                .position(|w| w == END_OF_HEADERS)
+            {
+                let mut headers = [httparse::EMPTY_HEADER; 32];
+                let mut request = httparse::Request::new(&mut headers);
+                request
+                    .parse(&data[..n + END_OF_HEADERS.len()])
+                    .map_err(|e| Reply::error(400, &format!("Bad request: {}", e)))?;
+                let header = |name: &str| {
+                    request
+                        .headers
+                        .iter()
+                        .find(|h| h.name.eq_ignore_ascii_case(name))
+                        .and_then(|h| std::str::from_utf8(h.value).ok())
+                };
+                let length: usize = match header("Content-Length") {
+                    None => 0,
+                    Some(l) => l
+                        .trim()
+                        .parse()
+                        .map_err(|_| Reply::error(400, "Bad Content-Length."))?,
+                };
+                let token = header("Authorization")
+                    .and_then(|a| a.strip_prefix("Bearer "))
+                    .map(|t| t.trim().to_string());
+                parsed = Some((
+                    request.method.unwrap_or_default().to_string(),
+                    request.path.unwrap_or_default().to_string(),
+                    token,
+                    n + END_OF_HEADERS.len(),
+                    length,
+                ));
+            }
+        }
+        if let Some((method, target, token, body_start, length)) = &parsed {
+            if body_start + length > MAX_REQUEST {
+                return Err(Reply::error(413, "Request is too large."));
+            }
+            if data.len() >= body_start + length {
+                let body = data[*body_start..body_start + length].to_vec();
+                return Ok((method.clone(), target.clone(), token.clone(), body));
+            }
+        }
+        if data.len() > MAX_REQUEST {
+            return Err(Reply::error(413, "Request is too large."));
+        }
+        if start_t.elapsed() > REQUEST_TIMEOUT {
+            return Err(Reply::error(408, "Timed out reading the request."));
+        }
+        match stream.read(&mut buff) {
+            Ok(0) => return Err(Reply::error(400, "Request ended early.")),
+            Ok(n) => data.extend_from_slice(&buff[..n]),
+            Err(e) => return Err(Reply::error(408, &format!("Error reading request: {}", e))),
+        }
+    }
+}
+
+fn write_reply(stream: &mut TcpStream, reply: &Reply) {
+    let reason = match reply.status {
+        200 => "OK",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        409 => "Conflict",
+        413 => "Payload Too Large",
+        503 => "Service Unavailable",
+        _ => "Internal Server Error",
+    };
+    let body = reply.body.to_string();
+    let mut head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n",
+        reply.status,
+        reason,
+        body.len()
+    );
+    if reply.status == 401 {
+        head.push_str("WWW-Authenticate: Bearer\r\n");
+    }
+    head.push_str("\r\n");
+    if let Err(e) = stream
+        .write_all(head.as_bytes())
+        .and_then(|_| stream.write_all(body.as_bytes()))
+    {
+        debug!("admin: Error writing reply: {}", &e);
+    }
+}
+
+/// Work out what a POST to an incoming webhook, `/hooks/<token>`, is asking
+/// for. The token in the URL is all the authorization it needs.
+fn route_hook(hooks: &[IncomingWebhook], token: &str, body: &[u8]) -> Result<Request, Reply> {
+    let hook = hooks
+        .iter()
+        .find(|h| same_token(token, &h.token))
+        .ok_or_else(|| Reply::error(401, "No such webhook."))?;
+    let body: Value =
+        serde_json::from_slice(body).map_err(|e| Reply::error(400, &format!("Bad JSON: {}", e)))?;
+    match field(&body, "text") {
+        None => Err(Reply::error(400, "Expected a \"text\" string.")),
+        Some(text) => Ok(Request::Post {
+            room: hook.room.clone(),
+            from: hook.name.clone(),
+            text,
+        }),
+    }
+}
+
+/// Answer one connection to the admin API.
+fn serve(
+    stream: &mut TcpStream,
+    token: Option<&str>,
+    hooks: &[IncomingWebhook],
+    tx: &mpsc::Sender<Job>,
+) -> Reply {
+    if let Err(e) = stream.set_read_timeout(Some(REQUEST_TIMEOUT)) {
+        return Reply::error(500, &e.to_string());
+    }
+    let (method, target, presented, body) = match read_request(stream) {
+        Err(reply) => return reply,
+        Ok(request) => request,
+    };
+    let routed = match (method.as_str(), target.strip_prefix("/hooks/")) {
+        ("POST", Some(hook_token)) => route_hook(hooks, hook_token, &body),
+        _ => match (presented, token) {
+            (Some(p), Some(t)) if same_token(&p, t) => route(&method, &target, &body),
+            _ => Err(Reply::error(401, "A valid bearer token is required.")),
+        },
+    };
+    let request = match routed {
+        Err(reply) => return reply,
+        Ok(request) => request,
+    };
+
+    debug!("admin: {} => {:?}", &method, &request);
+    let (reply_tx, reply_rx) = mpsc::channel();
+    if tx.send((request, reply_tx)).is_err() {
+        return Reply::error(503, "The server is shutting down.");
+    }
+    reply_rx
+        .recv_timeout(REQUEST_TIMEOUT)
+        .unwrap_or_else(|_| Reply::error(503, "The server didn't answer in time."))
+}
+
+/// Answer one connection to the admin API, and log it if it went wrong.
+fn answer(
+    mut stream: TcpStream,
+    token: Option<&str>,
+    hooks: &[IncomingWebhook],
+    tx: &mpsc::Sender<Job>,
+) {
+    let reply = serve(&mut stream, token, hooks, tx);
+    if reply.status != 200 {
+        warn!(
+            "admin: {:?} answered with {}: {}",
+            stream.peer_addr(),
+            reply.status,
+            &reply.body
+        );
+    }
+    write_reply(&mut stream, &reply);
+}
+
+/// Serve the admin API on `address`, each connection on its own thread (so
+/// that one slow client can't hold up the rest), handing what it's asked to
+/// do to the main loop through `tx`. Every request has to carry `token` as
+/// its bearer token (so with no token, only the incoming `hooks` work).
+pub fn listen(
+    address: String,
+    token: Option<String>,
+    hooks: Vec<IncomingWebhook>,
+    tx: mpsc::Sender<Job>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let listener = TcpListener::bind(&address)?;
+    println!("Admin API listening on {}", &address);
+    let (token, hooks) = (Arc::new(token), Arc::new(hooks));
+
+    for stream_result in listener.incoming() {
+        let stream = match stream_result {
+            Err(e) => {
+                debug!("admin: Error accepting connection: {}", &e);
+                continue;
+            }
+            Ok(stream) => stream,
+        };
+        let (token, hooks, tx) = (token.clone(), hooks.clone(), tx.clone());
+        thread::spawn(move || answer(stream, token.as_deref(), &hooks, &tx));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The server's end of a fresh TCP connection, and the client's.
+    fn pair() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        (listener.accept().unwrap().0, client)
+    }
+
+    /// What `read_request` makes of `raw`.
+    fn read(raw: &[u8]) -> Result<(String, String, Option<String>, Vec<u8>), Reply> {
+        let (mut stream, mut client) = pair();
+        client.write_all(raw).unwrap();
+        client.shutdown(std::net::Shutdown::Write).unwrap();
+        read_request(&mut stream)
+    }
+
+    fn status<T: std::fmt::Debug>(result: Result<T, Reply>) -> u16 {
+        result.unwrap_err().status
+    }
+
+    #[test]
+    fn percent_decoding() {
+        assert_eq!(percent_decode("a%20b+c%2Fd"), "a b c/d");
+        assert_eq!(percent_decode("%E2%9C%93"), "\u{2713}");
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(percent_decode("%zz%4"), "%zz%4");
+        assert_eq!(percent_decode(""), "");
+    }
+
+    #[test]
+    fn query_params() {
+        assert_eq!(
+            query_param("a=1&match=dev%20team", "match"),
+            Some("dev team".to_string())
+        );
+        assert_eq!(query_param("a=1&matches=x", "match"), None);
+        assert_eq!(query_param("", "match"), None);
+    }
+
+    #[test]
+    fn tokens_are_compared_whole() {
+        assert!(same_token("secret", "secret"));
+        assert!(!same_token("secret", "secreT"));
+        assert!(!same_token("secret", "secret2"));
+        assert!(!same_token("", "secret"));
+    }
+
+    #[test]
+    fn routes_to_each_endpoint() {
+        assert!(matches!(route("GET", "/health", b""), Ok(Request::Health)));
+        assert!(matches!(
+            route("GET", "/metrics", b""),
+            Ok(Request::Metrics)
+        ));
+        assert!(matches!(
+            route("GET", "/users?match=al", b""),
+            Ok(Request::Users(p)) if p == "al"
+        ));
+        assert!(matches!(
+            route("GET", "/rooms/", b""),
+            Ok(Request::Rooms(p)) if p.is_empty()
+        ));
+        assert!(matches!(
+            route("POST", "/rooms/dev%20team/messages", br#"{"from":"ci","text":"hi"}"#),
+            Ok(Request::Post { room, from, text })
+                if room == "dev team" && from == "ci" && text == "hi"
+        ));
+        assert!(matches!(
+            route("POST", "/users/bob/kick", b""),
+            Ok(Request::Kick { user, message }) if user == "bob" && message.is_empty()
+        ));
+        assert!(matches!(
+            route("POST", "/users/bob/kick", br#"{"message":"bye"}"#),
+            Ok(Request::Kick { message, .. }) if message == "bye"
+        ));
+        assert!(matches!(
+            route("POST", "/rooms/dev/bans/bob", b""),
+            Ok(Request::Ban { room, user }) if room == "dev" && user == "bob"
+        ));
+    }
+
+    #[test]
+    fn routing_failures() {
+        assert_eq!(status(route("GET", "/nowhere", b"")), 404);
+        assert_eq!(status(route("GET", "/rooms/dev", b"")), 404);
+        assert_eq!(status(route("POST", "/health", b"")), 405);
+        assert_eq!(status(route("GET", "/users/bob/kick", b"")), 405);
+        assert_eq!(status(route("DELETE", "/rooms/dev/bans/bob", b"")), 405);
+        assert_eq!(status(route("POST", "/rooms/dev/messages", b"{")), 400);
+        assert_eq!(
+            status(route("POST", "/rooms/dev/messages", br#"{"from":"ci"}"#)),
+            400
+        );
+    }
+
+    #[test]
+    fn reads_requests() {
+        let (method, target, token, body) = read(
+            b"POST /users/bob/kick HTTP/1.1\r\nHost: x\r\nauthorization: Bearer  s3cret \r\nContent-Length: 5\r\n\r\nhello",
+        )
+        .unwrap();
+        assert_eq!(
+            (
+                method.as_str(),
+                target.as_str(),
+                token.as_deref(),
+                &body[..]
+            ),
+            ("POST", "/users/bob/kick", Some("s3cret"), &b"hello"[..])
+        );
+
+        let (method, _, token, body) = read(b"GET /health HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!((method.as_str(), token, body.len()), ("GET", None, 0));
+
+        let (_, _, token, _) =
+            read(b"GET /health HTTP/1.1\r\nAuthorization: Basic abc\r\n\r\n").unwrap();
+        assert_eq!(token, None);
+    }
+
+    #[test]
+    fn bad_requests_are_refused() {
+        assert_eq!(status(read(b"GET /health HTTP/1.1\r\n")), 400);
+        assert_eq!(status(read(b"\x01\x02 nonsense\r\n\r\n")), 400);
+        assert_eq!(
+            status(read(b"POST / HTTP/1.1\r\nContent-Length: x\r\n\r\n")),
+            400
+        );
+        assert_eq!(
+            status(read(b"POST / HTTP/1.1\r\nContent-Length: 10\r\n\r\nshort")),
+            400
+        );
+        let huge = format!("POST / HTTP/1.1\r\nContent-Length: {}\r\n\r\n", MAX_REQUEST);
+        assert_eq!(status(read(huge.as_bytes())), 413);
+        let mut endless = b"GET / HTTP/1.1\r\nX: ".to_vec();
+        endless.resize(MAX_REQUEST + 2048, b'x');
+        assert_eq!(status(read(&endless)), 413);
+    }
+
+    /// What `serve` answers to `raw`, with the main loop answering every
+    /// `Request` with its `Debug` form.
+    fn serve_raw(raw: &str, token: Option<&str>) -> Reply {
+        let hooks = vec![IncomingWebhook {
+            room: "dev".into(),
+            name: "ci".into(),
+            token: "hooktoken".into(),
+        }];
+        let (tx, rx) = mpsc::channel::<Job>();
+        thread::spawn(move || {
+            for (request, reply_tx) in rx.iter() {
+                let _ = reply_tx.send(Reply::ok(json!(format!("{:?}", request))));
+            }
+        });
+        let (mut stream, mut client) = pair();
+        client.write_all(raw.as_bytes()).unwrap();
+        serve(&mut stream, token, &hooks, &tx)
+    }
+
+    #[test]
+    fn requests_need_the_bearer_token() {
+        let get = |auth: &str| format!("GET /health HTTP/1.1\r\n{}\r\n", auth);
+        let reply = serve_raw(&get("Authorization: Bearer right\r\n"), Some("right"));
+        assert_eq!((reply.status, reply.body), (200, json!("Health")));
+
+        for auth in [
+            "",
+            "Authorization: Bearer wrong\r\n",
+            "Authorization: right\r\n",
+        ] {
+            assert_eq!(serve_raw(&get(auth), Some("right")).status, 401, "{}", auth);
+        }
+        // With no token set, nothing but the hooks can get in.
+        assert_eq!(
+            serve_raw(&get("Authorization: Bearer \r\n"), None).status,
+            401
+        );
+        // Nor can an unauthorized request find out which endpoints exist.
+        let nowhere = "GET /nowhere HTTP/1.1\r\n\r\n";
+        assert_eq!(serve_raw(nowhere, Some("right")).status, 401);
+    }
+
+    #[test]
+    fn hooks_need_only_their_own_token() {
+        let body = r#"{"text":"build passed"}"#;
+        let post = |path: &str| {
+            format!(
+                "POST {} HTTP/1.1\r\nContent-Length: {}\r\n\r\n{}",
+                path,
+                body.len(),
+                body
+            )
+        };
+        let reply = serve_raw(&post("/hooks/hooktoken"), None);
+        assert_eq!(reply.status, 200);
+        assert_eq!(
+            reply.body,
+            json!(r#"Post { room: "dev", from: "ci", text: "build passed" }"#)
+        );
+        assert_eq!(
+            serve_raw(&post("/hooks/wrong"), Some("hooktoken")).status,
+            401
+        );
+    }
+}
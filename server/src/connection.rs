@@ -1,31 +1,128 @@
-use common::{proto::Rcvr, socket::Socket, user::User};
+use crate::irc::Irc;
+use common::{
+    codec::{self, Json},
+    compress,
+    proto::Msg,
+    socket::Socket,
+    user::User,
+    websocket,
+};
 use log::debug;
-use std::{net::TcpListener, sync::mpsc, time::Duration};
+use std::{
+    net::{TcpListener, TcpStream},
+    sync::atomic::{AtomicU64, Ordering},
+    sync::{mpsc, Arc},
+    thread,
+    time::Duration,
+};
 
-pub fn initial_negotiation(user: &mut User) -> Result<(), String> {
-    match user.blocking_get(Duration::from_secs(5)).map_err(|e| {
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Shared by every listener, so users from each transport get distinct ids.
+static NEXT_USER_ID: AtomicU64 = AtomicU64::new(100);
+
+/// How many connections have been accepted (on any listener) since the server
+/// started.
+pub fn connections_accepted() -> u64 {
+    NEXT_USER_ID.load(Ordering::Relaxed) - 100
+}
+
+fn initial_get(user: &mut User) -> Result<Msg<'static>, String> {
+    user.blocking_get(HANDSHAKE_TIMEOUT).map_err(|e| {
         let err_str = format!("Error reading initial \"Name\" message: {}", e);
         user.logout(&err_str);
         err_str
-    })? {
-        Rcvr::Name(new_name) => {
+    })
+}
+
+/// Reads the client's opening message, returning the resume token it
+/// presented, if any. If the client asks for a codec or compression first,
+/// agrees on them (falling back to JSON, or no compression, if it's asked for
+/// something we don't know or allow) before reading its `Name`. Each can be
+/// asked for only once, so a client can't keep its handshake going by asking
+/// over and over.
+pub fn initial_negotiation(
+    user: &mut User,
+    allow_compression: bool,
+) -> Result<Option<String>, String> {
+    let mut msg = initial_get(user)?;
+    let (mut codec_agreed, mut compression_agreed) = (false, false);
+    loop {
+        match &msg {
+            Msg::Codec(name) if !codec_agreed => {
+                let codec = codec::by_name(name).unwrap_or(&Json);
+                user.blocking_send(&Msg::Codec(codec.name().into()), HANDSHAKE_TIMEOUT)
+                    .map_err(|e| format!("Error agreeing on a codec: {}", e))?;
+                user.set_codec(codec);
+                codec_agreed = true;
+            }
+            Msg::Compress(name) if !compression_agreed => {
+                let compression = if allow_compression {
+                    compress::by_name(name)
+                } else {
+                    None
+                };
+                let answer = compression.as_ref().map_or(compress::NONE, |c| c.name());
+                user.blocking_send(&Msg::Compress(answer.into()), HANDSHAKE_TIMEOUT)
+                    .map_err(|e| format!("Error agreeing on compression: {}", e))?;
+                user.set_compression(compression);
+                compression_agreed = true;
+            }
+            _ => break,
+        }
+        msg = initial_get(user)?;
+    }
+
+    match msg {
+        Msg::Name(new_name) => {
             user.set_name(&new_name);
-            Ok(())
+            Ok(None)
+        }
+        Msg::Resume { name, token } => {
+            user.set_name(&name);
+            Ok(Some(token.into_owned()))
         }
         x => {
-            let err_str = "Protocol error: Initial message should be of type \"Name\".";
+            let err_str =
+                "Protocol error: Initial message should be of type \"Name\" or \"Resume\".";
             user.logout(err_str);
             Err(format!("Bad initial message: {:?}", &x))
         }
     }
 }
 
-pub fn listen(address: String, tx: mpsc::Sender<User>) -> Result<(), Box<dyn std::error::Error>> {
-    let mut new_user_id: u64 = 100;
-    let listener = TcpListener::bind(&address)?;
+/// Negotiates with a freshly-accepted client and sends it down `tx` to join
+/// the server proper.
+fn admit(socket: Socket, allow_compression: bool, tx: &mpsc::Sender<(User, Option<String>)>) {
+    let mut user = User::new(socket, NEXT_USER_ID.fetch_add(1, Ordering::Relaxed));
+    match initial_negotiation(&mut user, allow_compression) {
+        Err(e) => {
+            debug!("admit(): Error negotiating initial protocol: {}", &e);
+        }
+        Ok(resume_token) => {
+            debug!(
+                "admit(): Sending new client \"{}\" through channel.",
+                user.get_name()
+            );
 
-    println!("Listening on {}", &address);
+            if let Err(e) = tx.send((user, resume_token)) {
+                debug!("admit(): Error sending client through channel: {}", &e);
+            }
+        }
+    }
+}
 
+/// Accepts connections on `listener`, handing each one to `setup` to turn
+/// into a `Socket`. Each connection's handshake happens on a thread of its
+/// own, so a client that's slow to say who it is can't keep anyone else
+/// waiting to connect.
+fn accept_loop(
+    listener: TcpListener,
+    allow_compression: bool,
+    tx: mpsc::Sender<(User, Option<String>)>,
+    setup: impl Fn(TcpStream) -> Result<Socket, String> + Send + Sync + 'static,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let setup = Arc::new(setup);
     for stream_result in listener.incoming() {
         let stream = match stream_result {
             Err(e) => {
@@ -40,33 +137,60 @@ pub fn listen(address: String, tx: mpsc::Sender<User>) -> Result<(), Box<dyn std
             stream.peer_addr()?
         );
 
-        let new_socket = match Socket::new(stream) {
+        let (setup, tx) = (Arc::clone(&setup), tx.clone());
+        thread::spawn(move || match setup(stream) {
             Err(e) => {
                 debug!("listen(): Error setting up new Sock: {}", &e);
-                continue;
-            }
-            Ok(socket) => socket,
-        };
-
-        let mut user = User::new(new_socket, new_user_id);
-        match initial_negotiation(&mut user) {
-            Err(e) => {
-                debug!("listen(): Error negotiating initial protocol: {}", &e);
-            }
-            Ok(()) => {
-                debug!(
-                    "listen(): Sending new client \"{}\" through channel.",
-                    user.get_name()
-                );
-
-                if let Err(e) = tx.send(user) {
-                    debug!("listen(): Error sending client through channel: {}", &e);
-                } else {
-                    new_user_id += 1;
-                }
             }
-        }
+            Ok(socket) => admit(socket, allow_compression, &tx),
+        });
     }
 
     Ok(())
 }
+
+pub fn listen(
+    listener: TcpListener,
+    allow_compression: bool,
+    tx: mpsc::Sender<(User, Option<String>)>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    println!("Listening on {}", listener.local_addr()?);
+    accept_loop(listener, allow_compression, tx, |stream| {
+        Socket::new(stream).map_err(|e| e.to_string())
+    })
+}
+
+/// Like `listen`, but for clients that connect over WebSockets (as browsers
+/// do). They speak the same protocol, one message per WebSocket message, and
+/// join the same rooms as everyone else.
+pub fn listen_websocket(
+    address: String,
+    allow_compression: bool,
+    tx: mpsc::Sender<(User, Option<String>)>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    println!("Listening for WebSockets on {}", &address);
+    accept_loop(
+        TcpListener::bind(&address)?,
+        allow_compression,
+        tx,
+        |mut stream| {
+            let already_read = websocket::accept(&mut stream, HANDSHAKE_TIMEOUT)?;
+            Socket::new_websocket(stream, already_read).map_err(|e| e.to_string())
+        },
+    )
+}
+
+/// Like `listen`, but for IRC clients, whose commands are translated into the
+/// protocol's messages (and the server's messages into IRC lines), so that
+/// they can share rooms with everyone else. Parting a channel goes back to
+/// `lobby`.
+pub fn listen_irc(
+    address: String,
+    lobby: String,
+    tx: mpsc::Sender<(User, Option<String>)>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    println!("Listening for IRC clients on {}", &address);
+    accept_loop(TcpListener::bind(&address)?, false, tx, move |stream| {
+        Socket::new_translated(stream, Box::new(Irc::new(&lobby))).map_err(|e| e.to_string())
+    })
+}
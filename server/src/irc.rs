@@ -0,0 +1,849 @@
+use common::{
+    codec::{Codec, Json},
+    proto::{Msg, Op, RosterEntry},
+    socket::Translation,
+    util::collapse,
+};
+use std::borrow::Cow;
+use std::fmt::Write;
+use std::io;
+
+/// What the server calls itself in the prefixes of the lines it sends.
+const SERVER: &str = "fresh";
+
+/// What a channel name can start with.
+const CHANNEL_PREFIXES: [char; 2] = ['#', '&'];
+
+/// The longest line an IRC client can send. (The protocol says 512 bytes,
+/// but some clients go over.)
+const MAX_LINE: usize = 4096;
+
+/// How a fresh user or room name looks on IRC, where names can't have spaces.
+/// The server collapses names before looking them up, so this refers to the
+/// same user or room as the original.
+pub fn irc_name(name: &str) -> String {
+    name.split_whitespace().collect()
+}
+
+/// The channel a fresh room appears as.
+fn channel(room: &str) -> String {
+    format!("#{}", irc_name(room))
+}
+
+/// A nick's full prefix, as the source of a line.
+fn source(name: &str) -> String {
+    let name = irc_name(name);
+    format!("{}!{}@{}", &name, &name, SERVER)
+}
+
+/// Split an IRC line into its prefix (if it has one), command and parameters.
+fn parse(line: &str) -> (Option<&str>, &str, Vec<&str>) {
+    let mut rest = line.trim_start();
+    let mut prefix = None;
+    if let Some(r) = rest.strip_prefix(':') {
+        let (p, r) = r.split_once(' ').unwrap_or((r, ""));
+        prefix = Some(p);
+        rest = r.trim_start();
+    }
+    let (command, mut rest) = rest.split_once(' ').unwrap_or((rest, ""));
+    let mut params = Vec::new();
+    loop {
+        rest = rest.trim_start_matches(' ');
+        if rest.is_empty() {
+            break;
+        } else if let Some(trailing) = rest.strip_prefix(':') {
+            params.push(trailing);
+            break;
+        }
+        let (param, r) = rest.split_once(' ').unwrap_or((rest, ""));
+        params.push(param);
+        rest = r;
+    }
+    (prefix, command, params)
+}
+
+/// The server's end of a connection from an IRC client, as a layer beneath a
+/// `Socket`'s buffers: the lines the client sends are turned into JSON `Msg`s
+/// in the receive buffer, and the JSON `Msg`s the server sends are turned into
+/// IRC lines.
+///
+/// A fresh user is only ever in one room, so an IRC client is only ever on one
+/// channel: joining another leaves the first, and parting goes back to the
+/// lobby. Room and user names have their spaces taken out.
+pub struct Irc {
+    partial: Vec<u8>,
+    nick: String,
+    has_user: bool,
+    registered: bool,
+    room: Option<String>,
+    lobby: String,
+}
+
+impl Irc {
+    /// `lobby` is the name of the room users start out in, which parting a
+    /// channel goes back to.
+    pub fn new(lobby: &str) -> Irc {
+        Irc {
+            partial: Vec::new(),
+            nick: String::from("*"),
+            has_user: false,
+            registered: false,
+            room: None,
+            lobby: lobby.to_string(),
+        }
+    }
+
+    fn is_me(&self, name: &str) -> bool {
+        collapse(name) == collapse(&self.nick)
+    }
+
+    fn is_current(&self, chan: &str) -> bool {
+        let name = chan.trim_start_matches(CHANNEL_PREFIXES);
+        matches!(&self.room, Some(room) if collapse(room) == collapse(name))
+    }
+
+    fn current_channel(&self) -> Option<String> {
+        self.room.as_deref().map(channel)
+    }
+
+    fn numeric(&self, out: &mut Vec<u8>, code: &str, params: &str) {
+        line(
+            out,
+            format_args!(":{} {} {} {}", SERVER, code, &self.nick, params),
+        );
+    }
+
+    fn notice(&self, out: &mut Vec<u8>, text: &str) {
+        for l in text.lines() {
+            line(
+                out,
+                format_args!(":{} NOTICE {} :{}", SERVER, &self.nick, l),
+            );
+        }
+    }
+
+    fn register(&mut self, out: &mut Vec<u8>, replies: &mut Vec<u8>) {
+        if self.registered || !self.has_user || self.nick == "*" {
+            return;
+        }
+        self.registered = true;
+        send(out, &Msg::Name(self.nick.as_str().into()));
+        let nick = self.nick.clone();
+        self.numeric(replies, "001", &format!(":Welcome to fresh, {}", &nick));
+        self.numeric(
+            replies,
+            "005",
+            "CHANTYPES=#& PREFIX=(ov)@+ CHANMODES=b,,,i MAXCHANNELS=1 :are supported by this server",
+        );
+        self.numeric(replies, "422", ":MOTD File is missing");
+    }
+
+    /// Turn one line from the client into `Msg`s for the server, or answer it.
+    /// A line whose prefix names someone other than the client is ignored, as
+    /// the protocol says it should be.
+    fn command(&mut self, text: &str, out: &mut Vec<u8>, replies: &mut Vec<u8>) {
+        let (prefix, command, params) = parse(text);
+        if command.is_empty() {
+            return;
+        }
+        if let Some(prefix) = prefix {
+            let nick = prefix.split(&['!', '@'][..]).next().unwrap_or(prefix);
+            if !self.is_me(nick) {
+                return;
+            }
+        }
+        let command = command.to_ascii_uppercase();
+        match command.as_str() {
+            "NICK" => match params.first() {
+                None => self.numeric(replies, "431", ":No nickname given"),
+                Some(nick) if self.registered => send(out, &Msg::Name((*nick).into())),
+                Some(nick) => {
+                    self.nick = nick.to_string();
+                    self.register(out, replies);
+                }
+            },
+            "USER" => {
+                self.has_user = true;
+                self.register(out, replies);
+            }
+            "PASS" => {}
+            // Capability negotiation isn't supported, which clients take this
+            // to mean.
+            "CAP" => self.numeric(replies, "421", "CAP :Unknown command"),
+            "PING" => {
+                let token = params.first().copied().unwrap_or(SERVER);
+                line(
+                    replies,
+                    format_args!(":{} PONG {} :{}", SERVER, SERVER, token),
+                );
+            }
+            "PONG" if self.registered => send(out, &Msg::Ping),
+            _ if !self.registered => self.numeric(replies, "451", ":You have not registered"),
+
+            "JOIN" => match params.first().and_then(|p| p.split(',').next()) {
+                None => self.numeric(replies, "461", "JOIN :Not enough parameters"),
+                Some("0") => self.part(out, replies, None),
+                Some(chan) => {
+                    let name = chan.trim_start_matches(CHANNEL_PREFIXES);
+                    send(out, &Msg::Join(name.into()));
+                }
+            },
+            "PART" => self.part(out, replies, params.first().copied()),
+            "PRIVMSG" | "NOTICE" => match (params.first(), params.get(1)) {
+                (None, _) => self.numeric(
+                    replies,
+                    "411",
+                    &format!(":No recipient given ({})", &command),
+                ),
+                (_, None) => self.numeric(replies, "412", ":No text to send"),
+                (Some(target), Some(text)) if target.starts_with(CHANNEL_PREFIXES) => {
+                    if self.is_current(target) {
+                        send(
+                            out,
+                            &Msg::Text {
+                                who: "".into(),
+                                lines: vec![Cow::Borrowed(*text)],
+                            },
+                        );
+                    } else {
+                        self.numeric(
+                            replies,
+                            "404",
+                            &format!("{} :Cannot send to channel", target),
+                        );
+                    }
+                }
+                (Some(target), Some(text)) => send(
+                    out,
+                    &Msg::Priv {
+                        who: (*target).into(),
+                        text: (*text).into(),
+                    },
+                ),
+            },
+            "NAMES" => send(out, &query("roster", "")),
+            "LIST" => send(out, &query("rooms", "")),
+            "KICK" => match params.get(1) {
+                None => self.numeric(replies, "461", "KICK :Not enough parameters"),
+                Some(nick) => send(out, &Msg::Op(Op::Kick((*nick).into()))),
+            },
+            "MODE" => self.mode(&params, out, replies),
+            "TOPIC" => match params.first() {
+                None => self.numeric(replies, "461", "TOPIC :Not enough parameters"),
+                Some(chan) if params.len() == 1 => {
+                    self.numeric(replies, "331", &format!("{} :No topic is set", chan))
+                }
+                Some(_) => self.notice(replies, "Rooms here don't have topics."),
+            },
+            "AWAY" => send(
+                out,
+                &Msg::Away(params.first().copied().unwrap_or("").into()),
+            ),
+            "QUIT" => send(
+                out,
+                &Msg::Logout(params.first().copied().unwrap_or("").into()),
+            ),
+            _ => self.numeric(replies, "421", &format!("{} :Unknown command", &command)),
+        }
+    }
+
+    /// Leave `chan` (or the current channel) for the lobby.
+    fn part(&mut self, out: &mut Vec<u8>, replies: &mut Vec<u8>, chan: Option<&str>) {
+        if let Some(chan) = chan.and_then(|c| c.split(',').next()) {
+            if !self.is_current(chan) {
+                self.numeric(
+                    replies,
+                    "442",
+                    &format!("{} :You're not on that channel", chan),
+                );
+                return;
+            }
+        }
+        if self.is_current(&self.lobby) {
+            self.notice(replies, "You can't leave the lobby.");
+        } else {
+            send(out, &Msg::Join(self.lobby.as_str().into()));
+        }
+    }
+
+    /// Channel modes: `+i`/`-i` close and open the room, `+b` kicks (and bans)
+    /// someone, `+o` hands the room over to them, and `+v`/`-v` give or take
+    /// away their voice (both toggle it, as `Op::Voice` does).
+    fn mode(&mut self, params: &[&str], out: &mut Vec<u8>, replies: &mut Vec<u8>) {
+        let (chan, modes) = match (params.first(), params.get(1)) {
+            (Some(chan), Some(modes)) if chan.starts_with(CHANNEL_PREFIXES) => (*chan, *modes),
+            // Mode queries, and user modes, go unanswered.
+            _ => return,
+        };
+        let mut args = params[2..].iter();
+        let mut adding = true;
+        for c in modes.chars() {
+            match c {
+                '+' => adding = true,
+                '-' => adding = false,
+                'i' if adding => send(out, &Msg::Op(Op::Close)),
+                'i' => send(out, &Msg::Op(Op::Open)),
+                'b' => match args.next() {
+                    None => self.numeric(
+                        replies,
+                        "368",
+                        &format!("{} :End of channel ban list", chan),
+                    ),
+                    Some(nick) if adding => send(out, &Msg::Op(Op::Kick((*nick).into()))),
+                    Some(_) => self.notice(replies, "Bans can't be lifted here."),
+                },
+                'o' => match args.next() {
+                    None => self.numeric(replies, "461", "MODE :Not enough parameters"),
+                    Some(nick) if adding => send(out, &Msg::Op(Op::Give((*nick).into()))),
+                    Some(_) => {
+                        self.notice(replies, "Give the room to someone else with +o instead.")
+                    }
+                },
+                'v' => match args.next() {
+                    None => self.numeric(replies, "461", "MODE :Not enough parameters"),
+                    Some(nick) => send(out, &Msg::Op(Op::Voice((*nick).into()))),
+                },
+                c => self.numeric(
+                    replies,
+                    "472",
+                    &format!("{} :is unknown mode char to me", c),
+                ),
+            }
+        }
+    }
+
+    /// Turn one `Msg` from the server into lines for the client.
+    fn render(&mut self, msg: &Msg, out: &mut Vec<u8>, fed: &mut Vec<u8>) {
+        match msg {
+            Msg::Text { who, lines } => {
+                // The client has already shown what its user said.
+                if self.is_me(who) {
+                    return;
+                }
+                if let Some(chan) = self.current_channel() {
+                    for l in lines.iter().flat_map(|l| l.lines()) {
+                        line(
+                            out,
+                            format_args!(":{} PRIVMSG {} :{}", source(who), &chan, l),
+                        );
+                    }
+                }
+            }
+            Msg::Priv { who, text } => {
+                for l in text.lines() {
+                    line(
+                        out,
+                        format_args!(":{} PRIVMSG {} :{}", source(who), &self.nick, l),
+                    );
+                }
+            }
+            Msg::Ping => line(out, format_args!("PING :{}", SERVER)),
+            Msg::Logout(message) => line(out, format_args!("ERROR :Closing link: {}", message)),
+            Msg::Info(text) | Msg::Err(text) | Msg::OverQuota(text) => self.notice(out, text),
+            Msg::Joined { user, room } if self.is_me(user) => {
+                if let Some(old) = self.current_channel() {
+                    line(out, format_args!(":{} PART {}", source(user), old));
+                }
+                self.room = Some(room.to_string());
+                line(
+                    out,
+                    format_args!(":{} JOIN {}", source(user), channel(room)),
+                );
+                send(fed, &query("roster", ""));
+            }
+            Msg::Joined { user, room } => {
+                line(
+                    out,
+                    format_args!(":{} JOIN {}", source(user), channel(room)),
+                );
+            }
+            // Leaving is shown when joining the next room.
+            Msg::Left { user, .. } if self.is_me(user) => {}
+            Msg::Left { user, reason } => {
+                if let Some(chan) = self.current_channel() {
+                    line(
+                        out,
+                        format_args!(":{} PART {} :{}", source(user), chan, reason),
+                    );
+                }
+            }
+            Msg::Renamed { old, new } => {
+                line(
+                    out,
+                    format_args!(":{} NICK :{}", source(old), irc_name(new)),
+                );
+                if self.is_me(old) {
+                    self.nick = irc_name(new);
+                }
+            }
+            Msg::NewOp { user, room } => {
+                let (chan, nick) = (channel(room), irc_name(user));
+                line(out, format_args!(":{} MODE {} +o {}", SERVER, chan, nick));
+            }
+            Msg::IsAway { user, message } => {
+                self.notice(out, &format!("{} is away: {}", user, message))
+            }
+            Msg::IsBack { user } => self.notice(out, &format!("{} is back.", user)),
+            Msg::Voiced { user, by, room } => {
+                let (chan, nick) = (channel(room), irc_name(user));
+                line(
+                    out,
+                    format_args!(":{} MODE {} +v {}", source(by), chan, nick),
+                );
+            }
+            Msg::Devoiced { user, by, room } => {
+                let (chan, nick) = (channel(room), irc_name(user));
+                line(
+                    out,
+                    format_args!(":{} MODE {} -v {}", source(by), chan, nick),
+                );
+            }
+            Msg::Kicked { user, room } => {
+                let (chan, nick) = (channel(room), irc_name(user));
+                line(out, format_args!(":{} KICK {} {}", SERVER, chan, nick));
+            }
+            Msg::KickedOut { room } => {
+                line(
+                    out,
+                    format_args!(":{} KICK {} {}", SERVER, channel(room), &self.nick),
+                );
+                self.room = None;
+            }
+            Msg::Roster(entries) => self.names(entries, out),
+            Msg::Rooms(rooms) => {
+                self.numeric(out, "321", "Channel :Users  Name");
+                for room in rooms.iter() {
+                    self.numeric(out, "322", &format!("{} 0 :", channel(room)));
+                }
+                self.numeric(out, "323", ":End of /LIST");
+            }
+            Msg::Who(users) => self.notice(out, &format!("Users: {}", users.join(", "))),
+            Msg::Addr(addr) => self.notice(out, &format!("Your address is {}.", addr)),
+            Msg::Misc { alt, .. } => self.notice(out, alt),
+            _ => {}
+        }
+    }
+
+    fn names(&self, entries: &[RosterEntry], out: &mut Vec<u8>) {
+        let chan = match self.current_channel() {
+            Some(chan) => chan,
+            None => return,
+        };
+        let mut names = String::new();
+        for e in entries.iter() {
+            let mark = if e.op {
+                "@"
+            } else if e.voiced {
+                "+"
+            } else {
+                ""
+            };
+            write!(&mut names, "{}{} ", mark, irc_name(&e.name)).unwrap();
+        }
+        self.numeric(out, "353", &format!("= {} :{}", &chan, names.trim_end()));
+        self.numeric(out, "366", &format!("{} :End of /NAMES list.", &chan));
+    }
+}
+
+impl Translation for Irc {
+    /// Take the lines in `data`, appending the `Msg`s they make (in JSON) to
+    /// `out`, and anything that's answered without the server's help to
+    /// `replies`. A partial line is kept until the rest arrives.
+    fn read(&mut self, data: &[u8], out: &mut Vec<u8>, replies: &mut Vec<u8>) -> io::Result<()> {
+        self.partial.extend_from_slice(data);
+        while let Some(n) = self.partial.iter().position(|b| *b == b'\n') {
+            let raw: Vec<u8> = self.partial.drain(..=n).collect();
+            let text = String::from_utf8_lossy(&raw);
+            let text = text.trim_end_matches(&['\r', '\n'][..]);
+            if !text.is_empty() {
+                self.command(text, out, replies);
+            }
+        }
+        if self.partial.len() > MAX_LINE {
+            self.partial.clear();
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Line is longer than the limit of {} bytes.", MAX_LINE),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Take the JSON `Msg`s in `data`, appending the IRC lines they make to
+    /// `out`. Some need a `Msg` sent back (like the roster query that follows
+    /// a join), which is appended to `fed`.
+    fn write(&mut self, data: &[u8], out: &mut Vec<u8>, fed: &mut Vec<u8>) {
+        let mut data = data;
+        while let Ok(Some((msg, length))) = Json.decode(data) {
+            self.render(&msg, out, fed);
+            data = &data[length..];
+        }
+    }
+}
+
+fn query<'a>(what: &'a str, arg: &'a str) -> Msg<'a> {
+    Msg::Query {
+        what: what.into(),
+        arg: arg.into(),
+    }
+}
+
+/// Append `msg` to `out` in JSON.
+fn send(out: &mut Vec<u8>, msg: &Msg) {
+    out.extend_from_slice(&Json.encode(msg));
+}
+
+/// Append one IRC line to `out`.
+fn line(out: &mut Vec<u8>, args: std::fmt::Arguments) {
+    let mut text = args.to_string();
+    text.retain(|c| c != '\r' && c != '\n');
+    out.extend_from_slice(text.as_bytes());
+    out.extend_from_slice(b"\r\n");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A client that's registered as "bob".
+    fn client() -> Irc {
+        let mut irc = Irc::new("Lobby");
+        feed(&mut irc, "NICK bob\r\nUSER bob 0 * :Bob\r\n");
+        irc
+    }
+
+    /// What the client sending `text` makes: the `Msg`s for the server, and
+    /// the lines answered without it.
+    fn feed(irc: &mut Irc, text: &str) -> (Vec<Msg<'static>>, String) {
+        let (mut out, mut replies) = (Vec::new(), Vec::new());
+        irc.read(text.as_bytes(), &mut out, &mut replies).unwrap();
+        (decode(&out), String::from_utf8(replies).unwrap())
+    }
+
+    /// What the server sending `msg` makes: the lines for the client, and the
+    /// `Msg`s fed back to the server.
+    fn show(irc: &mut Irc, msg: &Msg) -> (String, Vec<Msg<'static>>) {
+        let (mut out, mut fed) = (Vec::new(), Vec::new());
+        irc.write(&Json.encode(msg), &mut out, &mut fed);
+        (String::from_utf8(out).unwrap(), decode(&fed))
+    }
+
+    fn decode(mut data: &[u8]) -> Vec<Msg<'static>> {
+        let mut msgs = Vec::new();
+        while let Some((msg, length)) = Json.decode(data).unwrap() {
+            msgs.push(msg);
+            data = &data[length..];
+        }
+        msgs
+    }
+
+    /// `client()`, on `#dev`.
+    fn in_dev() -> Irc {
+        let mut irc = client();
+        show(
+            &mut irc,
+            &Msg::Joined {
+                user: "bob".into(),
+                room: "dev".into(),
+            },
+        );
+        irc
+    }
+
+    #[test]
+    fn parses_commands_and_parameters() {
+        assert_eq!(parse("NICK bob"), (None, "NICK", vec!["bob"]));
+        assert_eq!(
+            parse("PRIVMSG #dev :hello  there"),
+            (None, "PRIVMSG", vec!["#dev", "hello  there"])
+        );
+        assert_eq!(
+            parse("  MODE  #dev +o   alice "),
+            (None, "MODE", vec!["#dev", "+o", "alice"])
+        );
+        assert_eq!(parse("PRIVMSG #dev :"), (None, "PRIVMSG", vec!["#dev", ""]));
+        assert_eq!(parse("QUIT"), (None, "QUIT", vec![]));
+        assert_eq!(parse(""), (None, "", vec![]));
+    }
+
+    #[test]
+    fn parses_prefixes() {
+        assert_eq!(
+            parse(":bob!bob@host PRIVMSG alice :hi"),
+            (Some("bob!bob@host"), "PRIVMSG", vec!["alice", "hi"])
+        );
+        assert_eq!(parse(":bob"), (Some("bob"), "", vec![]));
+    }
+
+    #[test]
+    fn lines_with_someone_elses_prefix_are_ignored() {
+        let mut irc = client();
+        assert_eq!(
+            feed(&mut irc, ":alice PRIVMSG carol :hi\r\n"),
+            (vec![], "".into())
+        );
+        assert_eq!(feed(&mut irc, ":bob\r\n"), (vec![], "".into()));
+        let (msgs, _) = feed(&mut irc, ":Bob!bob@host PRIVMSG carol :hi\r\n");
+        assert_eq!(
+            msgs,
+            vec![Msg::Priv {
+                who: "carol".into(),
+                text: "hi".into(),
+            }]
+        );
+    }
+
+    #[test]
+    fn registers_once_it_has_a_nick_and_a_user() {
+        let mut irc = Irc::new("Lobby");
+        let (msgs, replies) = feed(&mut irc, "NICK bob\r\n");
+        assert!(msgs.is_empty() && replies.is_empty());
+        let (msgs, replies) = feed(&mut irc, "PRIVMSG alice :hi\r\n");
+        assert!(msgs.is_empty());
+        assert!(replies.contains(" 451 bob "));
+        let (msgs, replies) = feed(&mut irc, "USER bob 0 * :Bob\r\n");
+        assert_eq!(msgs, vec![Msg::Name("bob".into())]);
+        assert!(replies.starts_with(":fresh 001 bob :Welcome"));
+        assert!(replies.contains(" 422 bob "));
+
+        // After that, a new nick is a rename.
+        let (msgs, replies) = feed(&mut irc, "NICK robert\r\nUSER x\r\n");
+        assert_eq!(msgs, vec![Msg::Name("robert".into())]);
+        assert!(replies.is_empty());
+    }
+
+    #[test]
+    fn waits_for_the_rest_of_a_line() {
+        let mut irc = client();
+        assert!(feed(&mut irc, "AWAY :lun").0.is_empty());
+        assert_eq!(feed(&mut irc, "ch\r\n").0, vec![Msg::Away("lunch".into())]);
+        let (mut out, mut replies) = (Vec::new(), Vec::new());
+        let long = vec![b'x'; MAX_LINE + 1];
+        assert!(irc.read(&long, &mut out, &mut replies).is_err());
+    }
+
+    #[test]
+    fn answers_pings_itself() {
+        let mut irc = client();
+        assert_eq!(
+            feed(&mut irc, "PING :abc\r\n"),
+            (vec![], ":fresh PONG fresh :abc\r\n".into())
+        );
+        assert_eq!(feed(&mut irc, "PONG :fresh\r\n").0, vec![Msg::Ping]);
+    }
+
+    #[test]
+    fn translates_messages() {
+        let mut irc = in_dev();
+        assert_eq!(
+            feed(&mut irc, "PRIVMSG #Dev :hello\r\n").0,
+            vec![Msg::Text {
+                who: "".into(),
+                lines: vec!["hello".into()],
+            }]
+        );
+        let (msgs, replies) = feed(&mut irc, "PRIVMSG #elsewhere :hello\r\n");
+        assert!(msgs.is_empty());
+        assert!(replies.contains(" 404 bob #elsewhere "));
+        assert_eq!(
+            feed(&mut irc, "NOTICE alice :psst\r\n").0,
+            vec![Msg::Priv {
+                who: "alice".into(),
+                text: "psst".into(),
+            }]
+        );
+        assert!(feed(&mut irc, "PRIVMSG\r\n").1.contains(" 411 "));
+        assert!(feed(&mut irc, "PRIVMSG alice\r\n").1.contains(" 412 "));
+    }
+
+    #[test]
+    fn translates_joins_and_parts() {
+        let mut irc = client();
+        assert_eq!(
+            feed(&mut irc, "JOIN #dev,#other key\r\n").0,
+            vec![Msg::Join("dev".into())]
+        );
+        // Until the server says so, the client isn't on it.
+        assert!(feed(&mut irc, "PART #dev\r\n").1.contains(" 442 bob #dev "));
+
+        let mut irc = in_dev();
+        assert_eq!(
+            feed(&mut irc, "PART #dev :bye\r\n").0,
+            vec![Msg::Join("Lobby".into())]
+        );
+        assert_eq!(
+            feed(&mut irc, "PART\r\n").0,
+            vec![Msg::Join("Lobby".into())]
+        );
+        assert_eq!(
+            feed(&mut irc, "JOIN 0\r\n").0,
+            vec![Msg::Join("Lobby".into())]
+        );
+        assert!(feed(&mut irc, "JOIN\r\n").1.contains(" 461 "));
+
+        show(
+            &mut irc,
+            &Msg::Joined {
+                user: "bob".into(),
+                room: "Lobby".into(),
+            },
+        );
+        let (msgs, replies) = feed(&mut irc, "PART #Lobby\r\n");
+        assert!(msgs.is_empty());
+        assert!(replies.contains("You can't leave the lobby."));
+    }
+
+    #[test]
+    fn translates_names_and_list() {
+        let mut irc = in_dev();
+        assert_eq!(
+            feed(&mut irc, "NAMES #dev\r\nLIST\r\n").0,
+            vec![query("roster", ""), query("rooms", "")]
+        );
+    }
+
+    #[test]
+    fn translates_modes() {
+        let mut irc = in_dev();
+        let (msgs, replies) = feed(&mut irc, "MODE #dev +io alice\r\n");
+        assert_eq!(
+            msgs,
+            vec![Msg::Op(Op::Close), Msg::Op(Op::Give("alice".into()))]
+        );
+        assert!(replies.is_empty());
+        assert_eq!(
+            feed(&mut irc, "MODE #dev -i+vb carol dave\r\n").0,
+            vec![
+                Msg::Op(Op::Open),
+                Msg::Op(Op::Voice("carol".into())),
+                Msg::Op(Op::Kick("dave".into())),
+            ]
+        );
+        assert_eq!(
+            feed(&mut irc, "KICK #dev dave :go\r\n").0,
+            vec![Msg::Op(Op::Kick("dave".into()))]
+        );
+
+        // There's no taking op away, only giving it to someone else.
+        let (msgs, replies) = feed(&mut irc, "MODE #dev -o alice\r\n");
+        assert!(msgs.is_empty());
+        assert!(replies.contains(" NOTICE bob :Give the room to someone else"));
+        let (msgs, replies) = feed(&mut irc, "MODE #dev -b dave\r\n");
+        assert!(msgs.is_empty());
+        assert!(replies.contains("Bans can't be lifted here."));
+
+        assert!(feed(&mut irc, "MODE #dev +o\r\n").1.contains(" 461 "));
+        assert!(feed(&mut irc, "MODE #dev +b\r\n")
+            .1
+            .contains(" 368 bob #dev "));
+        assert!(feed(&mut irc, "MODE #dev +k\r\n").1.contains(" 472 bob k "));
+        // Queries and user modes go unanswered.
+        assert_eq!(feed(&mut irc, "MODE #dev\r\n"), (vec![], "".into()));
+        assert_eq!(feed(&mut irc, "MODE bob +i\r\n"), (vec![], "".into()));
+    }
+
+    #[test]
+    fn translates_the_rest() {
+        let mut irc = in_dev();
+        assert_eq!(
+            feed(&mut irc, "AWAY\r\nQUIT :bye\r\n").0,
+            vec![Msg::Away("".into()), Msg::Logout("bye".into())]
+        );
+        assert!(feed(&mut irc, "TOPIC #dev\r\n")
+            .1
+            .contains(" 331 bob #dev "));
+        assert!(feed(&mut irc, "TOPIC #dev :x\r\n")
+            .1
+            .contains("don't have topics"));
+        assert!(feed(&mut irc, "FROB\r\n").1.contains(" 421 bob FROB "));
+    }
+
+    #[test]
+    fn shows_joining_a_room() {
+        let mut irc = in_dev();
+        let (lines, fed) = show(
+            &mut irc,
+            &Msg::Joined {
+                user: "bob".into(),
+                room: "game night".into(),
+            },
+        );
+        assert_eq!(
+            lines,
+            ":bob!bob@fresh PART #dev\r\n:bob!bob@fresh JOIN #gamenight\r\n"
+        );
+        assert_eq!(fed, vec![query("roster", "")]);
+
+        let (lines, fed) = show(
+            &mut irc,
+            &Msg::Joined {
+                user: "Ann Lee".into(),
+                room: "game night".into(),
+            },
+        );
+        assert_eq!(lines, ":AnnLee!AnnLee@fresh JOIN #gamenight\r\n");
+        assert!(fed.is_empty());
+    }
+
+    #[test]
+    fn shows_names() {
+        let mut irc = in_dev();
+        let entry = |name: &str, op, voiced| RosterEntry {
+            name: name.into(),
+            op,
+            voiced,
+            ..RosterEntry::default()
+        };
+        let (lines, _) = show(
+            &mut irc,
+            &Msg::Roster(
+                vec![
+                    entry("alice", true, false),
+                    entry("bob", false, true),
+                    entry("Ann Lee", false, false),
+                ]
+                .into(),
+            ),
+        );
+        assert_eq!(
+            lines,
+            ":fresh 353 bob = #dev :@alice +bob AnnLee\r\n:fresh 366 bob #dev :End of /NAMES list.\r\n"
+        );
+    }
+
+    #[test]
+    fn shows_text_but_not_the_clients_own() {
+        let mut irc = in_dev();
+        let text = |who: &'static str| Msg::Text {
+            who: who.into(),
+            lines: vec!["one\ntwo".into()],
+        };
+        assert_eq!(
+            show(&mut irc, &text("alice")).0,
+            ":alice!alice@fresh PRIVMSG #dev :one\r\n:alice!alice@fresh PRIVMSG #dev :two\r\n"
+        );
+        assert_eq!(show(&mut irc, &text("Bob")).0, "");
+    }
+
+    #[test]
+    fn shows_mode_changes_and_renames() {
+        let mut irc = in_dev();
+        let (lines, _) = show(
+            &mut irc,
+            &Msg::NewOp {
+                user: "alice".into(),
+                room: "dev".into(),
+            },
+        );
+        assert_eq!(lines, ":fresh MODE #dev +o alice\r\n");
+        let (lines, _) = show(
+            &mut irc,
+            &Msg::Renamed {
+                old: "bob".into(),
+                new: "Robert T".into(),
+            },
+        );
+        assert_eq!(lines, ":bob!bob@fresh NICK :RobertT\r\n");
+        assert_eq!(feed(&mut irc, ":RobertT PRIVMSG alice :hi\r\n").0.len(), 1);
+    }
+}
@@ -0,0 +1,511 @@
+mod admin;
+mod connection;
+mod irc;
+mod message;
+mod processing;
+mod session;
+mod webhook;
+
+use crate::connection::{listen, listen_irc, listen_websocket};
+use crate::processing::process_room;
+use crate::session::{evict_stale, gen_token, Session};
+use common::config::ServerConfig;
+use common::proto::*;
+use common::room::Room;
+use common::user::*;
+use common::util::collapse;
+use log::{debug, error, info, warn};
+use std::collections::HashMap;
+use std::error::Error;
+use std::net::TcpListener;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Instant;
+
+/// Unique user name generator.
+fn gen_name(init_count: u64, map: &HashMap<String, u64>) -> String {
+    let mut new_id = init_count;
+    loop {
+        let new_name = format!("user{}", new_id);
+        if map.get(&new_name).is_none() {
+            return new_name;
+        }
+        new_id += 1;
+    }
+}
+
+/// Run the server, taking clients from `listener` (and from whatever other
+/// listeners `cfg` asks for), until something goes badly wrong.
+pub fn serve(cfg: ServerConfig, listener: TcpListener) -> Result<(), Box<dyn Error>> {
+    let allow_compression = cfg.allow_compression;
+
+    let mut users_by_id = HashMap::new();
+    let mut user_ids_by_str = HashMap::new();
+    let mut rooms_by_id = HashMap::new();
+    let mut room_ids_by_str = HashMap::new();
+    let mut sessions: HashMap<String, Session> = HashMap::new();
+
+    let mut lobby: Room = Room::new(0, cfg.lobby_name.clone(), 0);
+    lobby.leave(0);
+    rooms_by_id.insert(0, lobby);
+    room_ids_by_str.insert(collapse(&cfg.lobby_name), 0);
+
+    let (usender, urecvr) = mpsc::channel::<(User, Option<String>)>();
+    let (asender, arecvr) = mpsc::channel::<admin::Job>();
+    let started = Instant::now();
+
+    match cfg.admin_address.clone() {
+        Some(admin_addr) if cfg.admin_token.is_some() || !cfg.incoming_webhooks.is_empty() => {
+            info!("Serving the admin API on {}", admin_addr);
+            let (token, hooks) = (cfg.admin_token.clone(), cfg.incoming_webhooks.clone());
+            thread::spawn(move || {
+                admin::listen(admin_addr, token, hooks, asender).unwrap_or_else(|e| {
+                    error!("admin::listen() encountered an error: {}", e);
+                })
+            });
+        }
+        Some(_) => {
+            error!("Not serving the admin API: \"admin_token\" isn't set.");
+        }
+        None => {}
+    }
+    if cfg.admin_address.is_none() && !cfg.incoming_webhooks.is_empty() {
+        error!("Incoming webhooks need an \"admin_address\" to be served on.");
+    }
+    let hooks = webhook::Outgoing::start(cfg.outgoing_webhooks.clone());
+
+    if let Some(ws_addr) = cfg.websocket_address.clone() {
+        info!("Accepting WebSocket clients on {}", ws_addr);
+        let ws_sender = usender.clone();
+        thread::spawn(move || {
+            listen_websocket(ws_addr, allow_compression, ws_sender).unwrap_or_else(|e| {
+                error!("listen_websocket() encountered an error: {}", e);
+            })
+        });
+    }
+
+    if let Some(irc_addr) = cfg.irc_address.clone() {
+        info!("Accepting IRC clients on {}", irc_addr);
+        let (lobby, irc_sender) = (cfg.lobby_name.clone(), usender.clone());
+        thread::spawn(move || {
+            listen_irc(irc_addr, lobby, irc_sender).unwrap_or_else(|e| {
+                error!("listen_irc() encountered an error: {}", e);
+            })
+        });
+    }
+
+    thread::spawn(move || {
+        listen(listener, allow_compression, usender).unwrap_or_else(|e| {
+            error!("listen() encountered an error: {}", e);
+        })
+    });
+
+    let mut now: Instant;
+
+    loop {
+        now = Instant::now();
+        let mut rooms: Vec<u64> = rooms_by_id.keys().copied().collect();
+        for room_id in rooms.drain(..) {
+            let room_count = rooms_by_id.len();
+            match process_room(
+                room_id,
+                now,
+                &mut users_by_id,
+                &mut user_ids_by_str,
+                &mut rooms_by_id,
+                &mut room_ids_by_str,
+                &cfg,
+                &hooks,
+            ) {
+                Ok(dropped) => sessions.extend(dropped),
+                Err(e) => {
+                    warn!("process_room({}, ...) returned error: {}", room_id, &e);
+                }
+            }
+            if room_count != rooms_by_id.len() {
+                for (k, v) in room_ids_by_str.iter() {
+                    debug!("{} => {}", k, v);
+                }
+                for (k, v) in rooms_by_id.iter() {
+                    debug!("{} => {}", k, v.get_idstr());
+                }
+            }
+
+            if room_id != 0 {
+                let mut remove: bool = false;
+                if let Some(r) = rooms_by_id.get(&room_id) {
+                    if r.get_users().is_empty() {
+                        remove = true;
+                        let _ = room_ids_by_str.remove(r.get_idstr());
+                    }
+                }
+                if remove {
+                    let _ = rooms_by_id.remove(&room_id);
+                }
+            }
+        }
+
+        sessions.retain(|_, s| s.expires > now);
+
+        while let Ok((request, reply_tx)) = arecvr.try_recv() {
+            let mut state = admin::State {
+                started,
+                users_by_id: &mut users_by_id,
+                user_ids_by_str: &mut user_ids_by_str,
+                rooms_by_id: &mut rooms_by_id,
+                room_ids_by_str: &room_ids_by_str,
+                sessions: &sessions,
+            };
+            let _ = reply_tx.send(admin::handle(request, &mut state, &cfg));
+        }
+
+        if let Ok((mut user, resume_token)) = urecvr.try_recv() {
+            debug!("Accepting user {}: {}", user.get_id(), user.get_name());
+            user.deliver_msg(&Msg::Info(cfg.welcome_message.as_str().into()));
+
+            // A user without a token has an empty one, which nobody can claim.
+            let resume_token = resume_token.filter(|t| !t.is_empty());
+            let session = resume_token.and_then(|token| {
+                sessions.remove(&token).or_else(|| {
+                    evict_stale(
+                        &token,
+                        now + cfg.resume_timeout,
+                        &mut users_by_id,
+                        &mut user_ids_by_str,
+                        &mut rooms_by_id,
+                    )
+                })
+            });
+            if let Some(s) = &session {
+                debug!("User {} resumes session of {}", user.get_id(), &s.name);
+                user.set_name(&s.name);
+            }
+
+            let mut required_name_change: Option<String> = None;
+            if user.get_idstr().is_empty() {
+                required_name_change = Some(String::from(
+                    "Your name does not have enough whitespace characters.",
+                ));
+            } else if user.get_name().len() > cfg.max_user_name_length {
+                required_name_change = Some(format!(
+                    "Your name cannot be longer than {} characters.",
+                    cfg.max_user_name_length
+                ));
+            } else {
+                let potential_name_conflict = user_ids_by_str.get(user.get_idstr());
+                if let Some(user_n) = potential_name_conflict {
+                    required_name_change = Some(format!(
+                        "Name \"{}\" exists.",
+                        users_by_id.get(user_n).unwrap().get_name()
+                    ));
+                }
+            }
+
+            if let Some(err_msg) = required_name_change {
+                let suggested_new_name = gen_name(user.get_id(), &user_ids_by_str);
+                let msg = Msg::Err(err_msg.as_str().into());
+                user.deliver_msg(&msg);
+                let original_name = user.get_name().to_string();
+                let msg = Msg::Renamed {
+                    old: original_name.as_str().into(),
+                    new: suggested_new_name.as_str().into(),
+                };
+                user.set_name(&suggested_new_name);
+                user.deliver_msg(&msg);
+            }
+
+            if let Some(s) = &session {
+                user.set_away(s.away.clone());
+            }
+
+            let room_id = session
+                .as_ref()
+                .and_then(|s| room_ids_by_str.get(&s.room_idstr).copied())
+                .unwrap_or(0);
+            let room = rooms_by_id.get_mut(&room_id).unwrap();
+            let env = Env::new(
+                End::Server,
+                End::Room(room_id),
+                &Msg::Joined {
+                    user: user.get_name().into(),
+                    room: room.get_name().into(),
+                },
+            );
+            room.join(user.get_id());
+            room.enqueue(env);
+
+            // Whoever was handed the room while the user was gone keeps it.
+            let op_present = room.get_users().contains(&room.get_op());
+            if room_id != 0 && !op_present && matches!(session, Some(Session { was_op: true, .. }))
+            {
+                room.set_op(user.get_id());
+                let env = Env::new(
+                    End::Server,
+                    End::Room(room_id),
+                    &Msg::NewOp {
+                        user: user.get_name().into(),
+                        room: room.get_name().into(),
+                    },
+                );
+                room.enqueue(env);
+            }
+
+            match gen_token() {
+                Some(token) => {
+                    user.deliver_msg(&Msg::ResumeToken(token.as_str().into()));
+                    user.set_resume_token(token);
+                }
+                None => warn!("Couldn't generate a resume token for {}", user.get_id()),
+            }
+
+            user_ids_by_str.insert(user.get_idstr().to_string(), user.get_id());
+            users_by_id.insert(user.get_id(), user);
+        }
+
+        let loop_time = Instant::now().duration_since(now);
+        if loop_time < cfg.min_tick {
+            thread::sleep(cfg.min_tick - loop_time);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::client::{Connection, Event};
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::time::Duration;
+
+    const LIMIT: Duration = Duration::from_secs(5);
+
+    const ADMIN_TOKEN: &str = "s3cret";
+
+    /// A config for a server that notices dropped connections quickly.
+    fn quick() -> ServerConfig {
+        ServerConfig {
+            min_tick: Duration::from_millis(20),
+            time_to_ping: Duration::from_millis(100),
+            time_to_kick: Duration::from_secs(2),
+            ..ServerConfig::default()
+        }
+    }
+
+    fn server_with(cfg: ServerConfig) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || serve(cfg, listener).unwrap());
+        address
+    }
+
+    fn server() -> String {
+        server_with(quick())
+    }
+
+    /// Start a server with the admin API on, returning the server's address
+    /// and the API's.
+    fn admin_server() -> (String, String) {
+        // A port that was free a moment ago.
+        let admin = TcpListener::bind("127.0.0.1:0")
+            .and_then(|l| l.local_addr())
+            .unwrap()
+            .to_string();
+        let address = server_with(ServerConfig {
+            admin_address: Some(admin.clone()),
+            admin_token: Some(ADMIN_TOKEN.into()),
+            ..quick()
+        });
+        (address, admin)
+    }
+
+    /// Connect to the admin API, once it's listening.
+    fn admin_stream(admin: &str) -> TcpStream {
+        let deadline = Instant::now() + LIMIT;
+        loop {
+            match TcpStream::connect(admin) {
+                Ok(stream) => return stream,
+                Err(e) => assert!(Instant::now() < deadline, "{}", e),
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    /// Make an admin API request, returning the status and the JSON answer.
+    fn http(admin: &str, method: &str, path: &str, body: &str) -> (u16, serde_json::Value) {
+        let mut stream = admin_stream(admin);
+        write!(
+            stream,
+            "{} {} HTTP/1.1\r\nAuthorization: Bearer {}\r\nContent-Length: {}\r\n\r\n{}",
+            method,
+            path,
+            ADMIN_TOKEN,
+            body.len(),
+            body
+        )
+        .unwrap();
+        let mut reply = String::new();
+        stream.read_to_string(&mut reply).unwrap();
+        let (head, body) = reply.split_once("\r\n\r\n").unwrap();
+        (
+            head[9..12].parse().unwrap(),
+            serde_json::from_str(body).unwrap(),
+        )
+    }
+
+    /// Wait for the first `Event` that `f` picks something out of, skipping
+    /// the rest.
+    fn expect<T>(conn: &mut Connection, mut f: impl FnMut(Event) -> Option<T>) -> T {
+        let deadline = Instant::now() + LIMIT;
+        loop {
+            let left = deadline.saturating_duration_since(Instant::now());
+            assert!(left > Duration::ZERO, "{} waited too long.", conn.name());
+            if let Some(x) = conn.wait(left).unwrap().and_then(&mut f) {
+                return x;
+            }
+        }
+    }
+
+    fn joined(conn: &mut Connection, name: &str, room: &str) {
+        expect(conn, |e| match e {
+            Event::Joined { user, room: r } if user == name && r == room => Some(()),
+            _ => None,
+        });
+    }
+
+    /// Connect as `name`, go to `room`, and return the resume token.
+    fn person(address: &str, name: &str, room: &str) -> (Connection, String) {
+        let mut conn = Connection::connect(address, name).unwrap();
+        conn.join(room);
+        joined(&mut conn, name, room);
+        let token = conn.resume_token().unwrap().to_string();
+        (conn, token)
+    }
+
+    /// Who `conn`'s room's roster says is op.
+    fn op_of(conn: &mut Connection) -> Vec<String> {
+        conn.query("roster", "");
+        expect(conn, |e| match e {
+            Event::Roster { entries } => Some(
+                entries
+                    .into_iter()
+                    .filter(|e| e.op)
+                    .map(|e| e.name)
+                    .collect(),
+            ),
+            _ => None,
+        })
+    }
+
+    #[test]
+    fn resuming_op_takes_the_room_back_if_nobody_has_it() {
+        let address = server();
+        let (_op, token) = person(&address, "op", "dev");
+        let (mut alice, _) = person(&address, "alice", "dev");
+
+        // The old connection hasn't been noticed to be gone yet.
+        let mut op = Connection::resume(&address, "op", &token).unwrap();
+        joined(&mut op, "op", "dev");
+        let new_op = expect(&mut alice, |e| match e {
+            Event::NewOp { user, .. } => Some(user),
+            _ => None,
+        });
+        assert_eq!(new_op, "op");
+        assert_eq!(op_of(&mut alice), vec!["op"]);
+    }
+
+    #[test]
+    fn resuming_op_leaves_the_room_with_whoever_was_handed_it() {
+        let address = server();
+        let (op, token) = person(&address, "op", "dev");
+        let (mut alice, _) = person(&address, "alice", "dev");
+
+        drop(op);
+        let new_op = expect(&mut alice, |e| match e {
+            Event::NewOp { user, .. } => Some(user),
+            _ => None,
+        });
+        assert_eq!(new_op, "alice");
+
+        let mut op = Connection::resume(&address, "op", &token).unwrap();
+        joined(&mut op, "op", "dev");
+        joined(&mut alice, "op", "dev");
+        assert_eq!(op_of(&mut alice), vec!["alice"]);
+        assert_eq!(op_of(&mut op), vec!["alice"]);
+    }
+
+    #[test]
+    fn a_silent_client_doesnt_hold_up_anyone_else() {
+        let address = server();
+        let _silent = TcpStream::connect(&address).unwrap();
+
+        // The silent one's handshake takes five seconds to give up on.
+        let start = Instant::now();
+        person(&address, "alice", "dev");
+        assert!(start.elapsed() < Duration::from_secs(2));
+    }
+
+    /// Wait for `conn` to hear who the new op is.
+    fn new_op(conn: &mut Connection) -> String {
+        expect(conn, |e| match e {
+            Event::NewOp { user, .. } => Some(user),
+            _ => None,
+        })
+    }
+
+    #[test]
+    fn kicking_the_op_from_the_admin_api_hands_the_room_over() {
+        let (address, admin) = admin_server();
+        let (_op, _) = person(&address, "op", "dev");
+        let (mut alice, _) = person(&address, "alice", "dev");
+
+        assert_eq!(http(&admin, "POST", "/users/op/kick", "").0, 200);
+        assert_eq!(new_op(&mut alice), "alice");
+        assert_eq!(op_of(&mut alice), vec!["alice"]);
+    }
+
+    #[test]
+    fn banning_the_op_from_the_admin_api_hands_the_room_over() {
+        let (address, admin) = admin_server();
+        let (mut op, _) = person(&address, "op", "dev");
+        let (mut alice, _) = person(&address, "alice", "dev");
+
+        assert_eq!(http(&admin, "POST", "/rooms/dev/bans/op", "").0, 200);
+        joined(&mut op, "op", "Lobby");
+        assert_eq!(new_op(&mut alice), "alice");
+        assert_eq!(op_of(&mut alice), vec!["alice"]);
+    }
+
+    #[test]
+    fn admin_posts_keep_to_the_name_limit() {
+        let (address, admin) = admin_server();
+        let (mut alice, _) = person(&address, "alice", "dev");
+
+        let too_long = format!(r#"{{"from":"{}","text":"hi"}}"#, "x".repeat(25));
+        let (status, body) = http(&admin, "POST", "/rooms/dev/messages", &too_long);
+        assert_eq!(status, 400, "{}", body);
+
+        let (status, _) = http(
+            &admin,
+            "POST",
+            "/rooms/dev/messages",
+            r#"{"from":"ci","text":"hi"}"#,
+        );
+        assert_eq!(status, 200);
+        let who = expect(&mut alice, |e| match e {
+            Event::Text { who, .. } => Some(who),
+            _ => None,
+        });
+        assert_eq!(who, "ci");
+    }
+
+    #[test]
+    fn a_slow_admin_client_holds_nobody_up() {
+        let (_, admin) = admin_server();
+        let mut slow = admin_stream(&admin);
+        slow.write_all(b"GET /health HTTP/1.1\r\n").unwrap();
+
+        let start = Instant::now();
+        assert_eq!(http(&admin, "GET", "/health", "").0, 200);
+        assert!(start.elapsed() < Duration::from_secs(2));
+    }
+}
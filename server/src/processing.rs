@@ -1,18 +1,20 @@
 use common::{
     config::ServerConfig,
-    proto::{End, Env, RcvOp, Rcvr, Sndr},
+    markup,
+    proto::{cows, End, Env, Msg, Op, RosterEntry},
     room::Room,
     user::User,
     util::collapse,
 };
 use log::{debug, trace, warn};
 use smallvec::SmallVec;
-use std::{collections::HashMap, time::Instant};
+use std::{borrow::Cow, collections::HashMap, time::Instant};
 
 use crate::message::Envs;
+use crate::session::{session_for, Session};
+use crate::webhook::Outgoing;
 
 const LOGOUTS_SIZE: usize = 8;
-const TEXT_SIZE: usize = 2;
 const ROOM_SIZE: usize = 64;
 
 struct Context<'a> {
@@ -84,24 +86,30 @@ fn match_string<T>(s: &str, hash: &HashMap<String, T>) -> Vec<String> {
     hash.keys().filter(|k| k.starts_with(s)).cloned().collect()
 }
 
-fn append_comma_delimited_list<T: AsRef<str>>(base: &mut String, v: &[T]) {
-    if !v.is_empty() {
-        base.push_str(&v.iter().map(AsRef::as_ref).collect::<Vec<_>>().join(", "));
-    }
-}
-
 fn first_free_id<T: Sized>(map: &HashMap<u64, T>) -> u64 {
     (0..).find(|n| !map.contains_key(n)).unwrap()
 }
 
-fn do_text(context: &mut Context, lines: Vec<String>) -> Result<Envs, String> {
+fn do_text(
+    context: &mut Context,
+    cfg: &ServerConfig,
+    hooks: &Outgoing,
+    lines: Vec<Cow<str>>,
+) -> Result<Envs, String> {
     let user = context.get_user_by_id(context.current_user_id)?;
 
-    let lines_ref: SmallVec<[&str; TEXT_SIZE]> = lines.iter().map(AsRef::as_ref).collect();
+    let lines = if cfg.allow_markup {
+        lines
+    } else {
+        markup::strip(&lines).into_iter().map(Cow::Owned).collect()
+    };
+
+    let room = context.get_room_by_id(context.current_room_id)?;
+    hooks.notify(room.get_name(), user.get_name(), &lines);
 
-    let msg = Sndr::Text {
-        who: user.get_name(),
-        lines: &lines_ref,
+    let msg = Msg::Text {
+        who: user.get_name().into(),
+        lines,
     };
 
     let env = Env::new(
@@ -122,7 +130,7 @@ fn do_priv(context: &mut Context, who: String, text: String) -> Result<Envs, Str
         let env = Env::new(
             End::Server,
             End::User(context.current_user_id),
-            &Sndr::Err("The recipient name must have at least one non-whitespace character."),
+            &Msg::Err("The recipient name must have at least one non-whitespace character.".into()),
         );
         return Ok(Envs::new1(env));
     }
@@ -132,10 +140,9 @@ fn do_priv(context: &mut Context, who: String, text: String) -> Result<Envs, Str
             let env = Env::new(
                 End::Server,
                 End::User(context.current_user_id),
-                &Sndr::Err(&format!(
-                    "The recipient name \"{}\" is not recognized.",
-                    recipient
-                )),
+                &Msg::Err(
+                    format!("The recipient name \"{}\" is not recognized.", recipient).into(),
+                ),
             );
             return Ok(Envs::new1(env));
         }
@@ -144,31 +151,36 @@ fn do_priv(context: &mut Context, who: String, text: String) -> Result<Envs, Str
 
     let target_user = context.get_user_by_id(target_user_id)?;
 
-    let data: [&str; 2] = [target_user.get_name(), &text];
     let echo_env = Env::new(
         End::Server,
         End::User(context.current_user_id),
-        &Sndr::Misc {
-            what: "priv_echo",
-            data: &data,
-            alt: &format!("$ You @ {}: {}", target_user.get_name(), &text),
+        &Msg::PrivEcho {
+            to: target_user.get_name().into(),
+            text: text.as_str().into(),
         },
     );
 
     let to_env = Env::new(
         End::User(context.current_user_id),
         End::User(target_user_id),
-        &Sndr::Priv {
-            who: user.get_name(),
-            text: &text,
+        &Msg::Priv {
+            who: user.get_name().into(),
+            text: text.as_str().into(),
         },
     );
 
-    Ok(Envs::new2(echo_env, to_env))
+    let mut envs = Envs::new2(echo_env, to_env);
+    if let Some(away) = target_user.get_away() {
+        envs.as_mut().push(Env::new(
+            End::Server,
+            End::User(context.current_user_id),
+            &Msg::Info(format!("{} is away: {}", target_user.get_name(), away).into()),
+        ));
+    }
+    Ok(envs)
 }
 
 /// In response to Msg::Name(new_candidate)
-
 fn do_name(
     context: &mut Context,
     cfg: &ServerConfig,
@@ -180,17 +192,20 @@ fn do_name(
         let env = Env::new(
             End::Server,
             End::User(context.current_user_id),
-            &Sndr::Err("Your name must have more whitespace characters."),
+            &Msg::Err("Your name must have more whitespace characters.".into()),
         );
         return Ok(Envs::new1(env));
     } else if new_candidate.len() > cfg.max_user_name_length {
         let env = Env::new(
             End::Server,
             End::User(context.current_user_id),
-            &Sndr::Err(&format!(
-                "Your name cannot be longer than {} characters.",
-                cfg.max_user_name_length
-            )),
+            &Msg::Err(
+                format!(
+                    "Your name cannot be longer than {} characters.",
+                    cfg.max_user_name_length
+                )
+                .into(),
+            ),
         );
         return Ok(Envs::new1(env));
     }
@@ -201,10 +216,13 @@ fn do_name(
             let env = Env::new(
                 End::Server,
                 End::User(context.current_user_id),
-                &Sndr::Err(&format!(
-                    "There is already a user named \"{}\".",
-                    other_user.get_name()
-                )),
+                &Msg::Err(
+                    format!(
+                        "There is already a user named \"{}\".",
+                        other_user.get_name()
+                    )
+                    .into(),
+                ),
             );
             return Ok(Envs::new1(env));
         }
@@ -217,15 +235,12 @@ fn do_name(
 
         mu.set_name(&new_candidate);
         let new_idstr = mu.get_idstr().to_string();
-        let data: [&str; 2] = [old_name.as_str(), new_candidate.as_str()];
-
         let env = Env::new(
             End::Server,
             End::Room(context.current_room_id),
-            &Sndr::Misc {
-                what: "name",
-                data: &data,
-                alt: &format!("{} is now known as {}.", &old_name, &new_candidate),
+            &Msg::Renamed {
+                old: old_name.as_str().into(),
+                new: new_candidate.as_str().into(),
             },
         );
         (old_idstr, new_idstr, env)
@@ -240,24 +255,26 @@ fn do_name(
 }
 
 /// In response to Msg::Join(room_name)
-
 fn do_join(context: &mut Context, cfg: &ServerConfig, room_name: String) -> Result<Envs, String> {
     let normalized_room_name = collapse(&room_name);
     if normalized_room_name.is_empty() {
         let env = Env::new(
             End::Server,
             End::User(context.current_user_id),
-            &Sndr::Err("A room name must have more non-whitespace characters."),
+            &Msg::Err("A room name must have more non-whitespace characters.".into()),
         );
         return Ok(Envs::new1(env));
     } else if room_name.len() > cfg.max_room_name_length {
         let env = Env::new(
             End::Server,
             End::User(context.current_user_id),
-            &Sndr::Err(&format!(
-                "Room names cannot be longer than {} characters.",
-                cfg.max_room_name_length
-            )),
+            &Msg::Err(
+                format!(
+                    "Room names cannot be longer than {} characters.",
+                    cfg.max_room_name_length
+                )
+                .into(),
+            ),
         );
         return Ok(Envs::new1(env));
     }
@@ -270,7 +287,9 @@ fn do_join(context: &mut Context, cfg: &ServerConfig, room_name: String) -> Resu
             context.room_ids_by_str.insert(normalized_room_name, new_id);
             context.rooms_by_id.insert(new_id, new_room);
             let mu = context.get_user_by_id_mut(context.current_user_id).unwrap();
-            mu.deliver_msg(&Sndr::Info(&format!("You create room \"{}\".", room_name)));
+            mu.deliver_msg(&Msg::Info(
+                format!("You create room \"{}\".", room_name).into(),
+            ));
             new_id
         });
 
@@ -285,53 +304,43 @@ fn do_join(context: &mut Context, cfg: &ServerConfig, room_name: String) -> Resu
         let env = Env::new(
             End::Server,
             End::User(user_id),
-            &Sndr::Info(&format!(
-                "You are already in \"{}\".",
-                target_room.get_name()
-            )),
+            &Msg::Info(format!("You are already in \"{}\".", target_room.get_name()).into()),
         );
         return Ok(Envs::new1(env));
     } else if target_room.is_banned(&user_id) {
         let env = Env::new(
             End::Server,
             End::User(user_id),
-            &Sndr::Info(&format!(
-                "You are banned from \"{}\".",
-                target_room.get_name()
-            )),
+            &Msg::Info(format!("You are banned from \"{}\".", target_room.get_name()).into()),
         );
         return Ok(Envs::new1(env));
     } else if target_room.closed && !target_room.is_invited(&user_id) {
         let env = Env::new(
             End::Server,
             End::User(user_id),
-            &Sndr::Info(&format!("\"{}\" is closed.", target_room.get_name())),
+            &Msg::Info(format!("\"{}\" is closed.", target_room.get_name()).into()),
         );
         return Ok(Envs::new1(env));
     }
 
     target_room.join(user_id);
-    let data: [&str; 2] = [&username, target_room.get_name()];
     let join_env = Env::new(
         End::Server,
         End::Room(target_room_id),
-        &Sndr::Misc {
-            what: "join",
-            data: &data,
-            alt: &format!("{} joins {}.", username, target_room.get_name()),
+        &Msg::Joined {
+            user: username.as_str().into(),
+            room: target_room.get_name().into(),
         },
     );
     target_room.enqueue(join_env);
 
     let current_room = context.get_room_by_id_mut(room_id)?;
-    let leave_data: [&str; 2] = [&username, "[ moved to another room ]"];
     let leave_env = Env::new(
         End::Server,
         End::Room(target_room_id),
-        &Sndr::Misc {
-            what: "leave",
-            data: &leave_data,
-            alt: &format!("{} moved to another room.", username),
+        &Msg::Left {
+            user: username.as_str().into(),
+            reason: "[ moved to another room ]".into(),
         },
     );
     current_room.leave(user_id);
@@ -339,14 +348,13 @@ fn do_join(context: &mut Context, cfg: &ServerConfig, room_name: String) -> Resu
 }
 
 /// In response to Msg::Block(username)
-
 fn do_block(context: &mut Context, username: String) -> Result<Envs, String> {
     let normalized_username = collapse(&username);
     if normalized_username.is_empty() {
         let env = Env::new(
             End::Server,
             End::User(context.current_user_id),
-            &Sndr::Err("A user name must have more non-whitespace characters."),
+            &Msg::Err("A user name must have more non-whitespace characters.".into()),
         );
         return Ok(Envs::new1(env));
     }
@@ -356,10 +364,9 @@ fn do_block(context: &mut Context, username: String) -> Result<Envs, String> {
             let env = Env::new(
                 End::Server,
                 End::User(context.current_user_id),
-                &Sndr::Info(&format!(
-                    "No users matching the pattern \"{}\".",
-                    normalized_username
-                )),
+                &Msg::Info(
+                    format!("No users matching the pattern \"{}\".", normalized_username).into(),
+                ),
             );
             return Ok(Envs::new1(env));
         }
@@ -370,7 +377,7 @@ fn do_block(context: &mut Context, username: String) -> Result<Envs, String> {
         let env = Env::new(
             End::Server,
             End::User(context.current_user_id),
-            &Sndr::Err("You shouldn't block yourself."),
+            &Msg::Err("You shouldn't block yourself.".into()),
         );
         return Ok(Envs::new1(env));
     }
@@ -391,29 +398,26 @@ fn do_block(context: &mut Context, username: String) -> Result<Envs, String> {
     let could_block: bool = user.block_id(othe_user_id);
 
     if could_block {
-        user.deliver_msg(&Sndr::Info(&format!(
-            "You are now blocking {}.",
-            &blocked_name
-        )));
+        user.deliver_msg(&Msg::Info(
+            format!("You are now blocking {}.", &blocked_name).into(),
+        ));
     } else {
-        user.deliver_msg(&Sndr::Err(&format!(
-            "You are already blocking {}.",
-            &blocked_name
-        )));
+        user.deliver_msg(&Msg::Err(
+            format!("You are already blocking {}.", &blocked_name).into(),
+        ));
     };
 
     Ok(Envs::new0())
 }
 
 /// In response to Msg::Unblock(username)
-
 fn do_unblock(context: &mut Context, username: String) -> Result<Envs, String> {
     let normalized_username = collapse(&username);
     if normalized_username.is_empty() {
         let env = Env::new(
             End::Server,
             End::User(context.current_user_id),
-            &Sndr::Err("That cannot be anyone's user name."),
+            &Msg::Err("That cannot be anyone's user name.".into()),
         );
         return Ok(Envs::new1(env));
     }
@@ -423,10 +427,9 @@ fn do_unblock(context: &mut Context, username: String) -> Result<Envs, String> {
             let env = Env::new(
                 End::Server,
                 End::User(context.current_user_id),
-                &Sndr::Info(&format!(
-                    "No users matching the pattern \"{}\".",
-                    normalized_username
-                )),
+                &Msg::Info(
+                    format!("No users matching the pattern \"{}\".", normalized_username).into(),
+                ),
             );
             return Ok(Envs::new1(env));
         }
@@ -437,7 +440,7 @@ fn do_unblock(context: &mut Context, username: String) -> Result<Envs, String> {
         let env = Env::new(
             End::Server,
             End::User(context.current_user_id),
-            &Sndr::Err("You couldn't block yourself; you can't unblock yourself."),
+            &Msg::Err("You couldn't block yourself; you can't unblock yourself.".into()),
         );
         return Ok(Envs::new1(env));
     }
@@ -457,22 +460,57 @@ fn do_unblock(context: &mut Context, username: String) -> Result<Envs, String> {
     let user = context.get_user_by_id_mut(context.current_user_id)?;
     let could_unblock: bool = user.unblock_id(other_user_id);
     if could_unblock {
-        user.deliver_msg(&Sndr::Info(&format!(
-            "You are no longer blocking {}.",
-            &blocked_name
-        )));
+        user.deliver_msg(&Msg::Info(
+            format!("You are no longer blocking {}.", &blocked_name).into(),
+        ));
     } else {
-        user.deliver_msg(&Sndr::Err(&format!(
-            "You are not blocking {}.",
-            &blocked_name
-        )));
+        user.deliver_msg(&Msg::Err(
+            format!("You are not blocking {}.", &blocked_name).into(),
+        ));
     }
 
     Ok(Envs::new0())
 }
 
-/// In response to Msg::Logout(salutation)
+/// In response to Msg::Away(message)
+fn do_away(context: &mut Context, message: String) -> Result<Envs, String> {
+    let room_id = context.current_room_id;
+    let user = context.get_user_by_id_mut(context.current_user_id)?;
+    let message = message.trim();
+
+    if message.is_empty() {
+        if user.get_away().is_none() {
+            let env = Env::new(
+                End::Server,
+                End::User(context.current_user_id),
+                &Msg::Info("You are not marked as away.".into()),
+            );
+            return Ok(Envs::new1(env));
+        }
+        user.set_away(None);
+        let env = Env::new(
+            End::Server,
+            End::Room(room_id),
+            &Msg::IsBack {
+                user: user.get_name().into(),
+            },
+        );
+        Ok(Envs::new1(env))
+    } else {
+        user.set_away(Some(message.to_string()));
+        let env = Env::new(
+            End::Server,
+            End::Room(room_id),
+            &Msg::IsAway {
+                user: user.get_name().into(),
+                message: message.into(),
+            },
+        );
+        Ok(Envs::new1(env))
+    }
+}
 
+/// In response to Msg::Logout(salutation)
 fn do_logout(context: &mut Context, salutation: String) -> Result<Envs, String> {
     let current_room = context
         .rooms_by_id
@@ -499,14 +537,12 @@ fn do_logout(context: &mut Context, salutation: String) -> Result<Envs, String>
     let _ = context.user_ids_by_str.remove(user.get_idstr());
     user.logout("You have logged out.");
 
-    let data: [&str; 2] = [user.get_name(), &salutation];
     let env = Env::new(
         End::Server,
         End::Room(context.current_room_id),
-        &Sndr::Misc {
-            what: "leave",
-            alt: &format!("{} left: {}", user.get_name(), salutation),
-            data: &data,
+        &Msg::Left {
+            user: user.get_name().into(),
+            reason: salutation.as_str().into(),
         },
     );
     current_room.enqueue(env);
@@ -515,86 +551,46 @@ fn do_logout(context: &mut Context, salutation: String) -> Result<Envs, String>
 }
 
 /// In response to Msg::Query { what, arg }
-
 fn do_query(context: &mut Context, what: String, arg: String) -> Result<Envs, String> {
     match what.as_str() {
         "addr" => {
             let current_user = context.get_user_by_id_mut(context.current_user_id)?;
-            let (addr_str, alt_str): (String, String) = match current_user.get_addr() {
-                None => (
-                    "???".to_string(),
-                    "Your public address cannot be determined.".to_string(),
-                ),
-                Some(address) => {
-                    let addr_string = format!("Your public address is {}.", &address);
-                    (address, addr_string)
-                }
-            };
-            let data: [&str; 1] = [&addr_str];
-            let msg = Sndr::Misc {
-                what: "addr",
-                data: &data,
-                alt: &alt_str,
-            };
-            current_user.deliver_msg(&msg);
+            let addr_str = current_user.get_addr().unwrap_or_else(|| "???".to_string());
+            current_user.deliver_msg(&Msg::Addr(addr_str.as_str().into()));
             Ok(Envs::new0())
         }
 
         "roster" => {
             let current_room = context.get_room_by_id(context.current_room_id)?;
             let op_id = current_room.get_op();
-            let mut names_list: SmallVec<[&str; ROOM_SIZE]> =
+            let mut entries: SmallVec<[RosterEntry; ROOM_SIZE]> =
                 SmallVec::with_capacity(current_room.get_users().len());
 
-            for user_id in current_room.get_users().iter().rev() {
-                if *user_id != op_id {
-                    match context.users_by_id.get(user_id) {
-                        None => {
-                            warn!(
-                                "do_query(r {}, u{} {:?}): no User {}",
-                                context.current_room_id, context.current_user_id, &what, user_id
-                            );
-                        }
-                        Some(u) => {
-                            names_list.push(u.get_name());
-                        }
+            for user_id in current_room.get_users().iter() {
+                match context.users_by_id.get(user_id) {
+                    None => {
+                        warn!(
+                            "do_query(r {}, u{} {:?}): no User {}",
+                            context.current_room_id, context.current_user_id, &what, user_id
+                        );
+                    }
+                    Some(u) => {
+                        entries.push(RosterEntry {
+                            name: u.get_name().to_string(),
+                            op: *user_id == op_id,
+                            voiced: current_room.is_voiced(user_id),
+                            away: u.get_away().is_some(),
+                            idle_secs: u.get_idle().as_secs(),
+                        });
                     }
                 }
             }
-
-            let mut alternative_string: String;
-            if op_id == 0 {
-                alternative_string = format!("{} roster: ", current_room.get_name());
-                append_comma_delimited_list(&mut alternative_string, &names_list);
-            } else {
-                let op_name = match context.users_by_id.get(&op_id) {
-                    None => "[ ??? ]",
-                    Some(u) => u.get_name(),
-                };
-                alternative_string = format!(
-                    "{} roster: {} (operator) ",
-                    current_room.get_name(),
-                    op_name
-                );
-                append_comma_delimited_list(&mut alternative_string, &names_list);
-
-                names_list.push(op_name);
-            }
-
-            let mut names_ref: SmallVec<[&str; ROOM_SIZE]> =
-                SmallVec::with_capacity(names_list.len());
-            for name in names_list.iter().rev() {
-                names_ref.push(name);
-            }
+            entries.sort_by_key(|e| !e.op);
 
             let env = Env::new(
                 End::Server,
                 End::User(context.current_user_id),
-                &Sndr::Misc {
-                    what: "roster",
-                    data: &names_ref,
-                    alt: &alternative_string,
-                },
+                &Msg::Roster(entries[..].into()),
             );
             Ok(Envs::new1(env))
         }
@@ -607,23 +603,15 @@ fn do_query(context: &mut Context, what: String, arg: String) -> Result<Envs, St
                 Env::new(
                     End::Server,
                     End::User(context.current_user_id),
-                    &Sndr::Info(&format!(
-                        "No users matching the pattern \"{}\".",
-                        &normalized_arg
-                    )),
+                    &Msg::Info(
+                        format!("No users matching the pattern \"{}\".", &normalized_arg).into(),
+                    ),
                 )
             } else {
-                let mut alternative_string = String::from("Matching names: ");
-                append_comma_delimited_list(&mut alternative_string, &matches);
-                let listref: Vec<&str> = matches.iter().map(|x| x.as_str()).collect();
                 Env::new(
                     End::Server,
                     End::User(context.current_user_id),
-                    &Sndr::Misc {
-                        what: "who",
-                        data: &listref,
-                        alt: &alternative_string,
-                    },
+                    &Msg::Who(cows(&matches)),
                 )
             };
             Ok(Envs::new1(env))
@@ -636,23 +624,15 @@ fn do_query(context: &mut Context, what: String, arg: String) -> Result<Envs, St
                 Env::new(
                     End::Server,
                     End::User(context.current_user_id),
-                    &Sndr::Info(&format!(
-                        "No Rooms matching the pattern \"{}\".",
-                        &normalized_arg
-                    )),
+                    &Msg::Info(
+                        format!("No Rooms matching the pattern \"{}\".", &normalized_arg).into(),
+                    ),
                 )
             } else {
-                let mut alternative_string = String::from("Matching Rooms: ");
-                append_comma_delimited_list(&mut alternative_string, &matches);
-                let listref: Vec<&str> = matches.iter().map(|x| x.as_str()).collect();
                 Env::new(
                     End::Server,
                     End::User(context.current_user_id),
-                    &Sndr::Misc {
-                        what: "rooms",
-                        data: &listref,
-                        alt: &alternative_string,
-                    },
+                    &Msg::Rooms(cows(&matches)),
                 )
             };
             Ok(Envs::new1(env))
@@ -662,24 +642,21 @@ fn do_query(context: &mut Context, what: String, arg: String) -> Result<Envs, St
             let env = Env::new(
                 End::Server,
                 End::User(context.current_user_id),
-                &Sndr::Err(&format!(
-                    "Unknown \"Query\" type: \"{}\".",
-                    unknown_query_type
-                )),
+                &Msg::Err(format!("Unknown \"Query\" type: \"{}\".", unknown_query_type).into()),
             );
             Ok(Envs::new1(env))
         }
     }
 }
 
-fn do_op(context: &mut Context, op: RcvOp) -> Result<Envs, String> {
+fn do_op(context: &mut Context, op: Op<'static>) -> Result<Envs, String> {
     {
         let current_room = context.get_room_by_id(context.current_room_id)?;
         if current_room.get_op() != context.current_user_id {
             let env = Env::new(
                 End::Server,
                 End::User(context.current_user_id),
-                &Sndr::Err("You are not the operator of this Room."),
+                &Msg::Err("You are not the operator of this Room.".into()),
             );
             return Ok(Envs::new1(env));
         }
@@ -692,37 +669,35 @@ fn do_op(context: &mut Context, op: RcvOp) -> Result<Envs, String> {
     };
 
     match op {
-        RcvOp::Open => {
+        Op::Open => {
             let current_room = context.get_room_by_id_mut(room_id)?;
             if current_room.closed {
                 current_room.closed = false;
                 let env = Env::new(
                     End::Server,
                     End::Room(room_id),
-                    &Sndr::Info(&format!(
-                        "{} has opened {}.",
-                        &op_name,
-                        current_room.get_name()
-                    )),
+                    &Msg::Info(
+                        format!("{} has opened {}.", &op_name, current_room.get_name()).into(),
+                    ),
                 );
                 Ok(Envs::new1(env))
             } else {
                 let env = Env::new(
                     End::Server,
                     End::User(user_id),
-                    &Sndr::Info(&format!("{} is already open.", current_room.get_name())),
+                    &Msg::Info(format!("{} is already open.", current_room.get_name()).into()),
                 );
                 Ok(Envs::new1(env))
             }
         }
 
-        RcvOp::Close => {
+        Op::Close => {
             let current_room = context.get_room_by_id_mut(room_id)?;
             if current_room.closed {
                 let env = Env::new(
                     End::Server,
                     End::User(user_id),
-                    &Sndr::Info(&format!("{} is already closed.", current_room.get_name())),
+                    &Msg::Info(format!("{} is already closed.", current_room.get_name()).into()),
                 );
                 Ok(Envs::new1(env))
             } else {
@@ -730,23 +705,21 @@ fn do_op(context: &mut Context, op: RcvOp) -> Result<Envs, String> {
                 let env = Env::new(
                     End::Server,
                     End::Room(room_id),
-                    &Sndr::Info(&format!(
-                        "{} has closed {}.",
-                        &op_name,
-                        current_room.get_name()
-                    )),
+                    &Msg::Info(
+                        format!("{} has closed {}.", &op_name, current_room.get_name()).into(),
+                    ),
                 );
                 Ok(Envs::new1(env))
             }
         }
 
-        RcvOp::Give(ref new_name) => {
+        Op::Give(ref new_name) => {
             let normalized_username = collapse(new_name);
             if normalized_username.is_empty() {
                 let env = Env::new(
                     End::Server,
                     End::User(context.current_user_id),
-                    &Sndr::Err("That cannot be anyone's user name."),
+                    &Msg::Err("That cannot be anyone's user name.".into()),
                 );
                 return Ok(Envs::new1(env));
             }
@@ -756,10 +729,13 @@ fn do_op(context: &mut Context, op: RcvOp) -> Result<Envs, String> {
                     let env = Env::new(
                         End::Server,
                         End::User(context.current_user_id),
-                        &Sndr::Info(&format!(
-                            "No users matching the pattern \"{}\".",
-                            &normalized_username
-                        )),
+                        &Msg::Info(
+                            format!(
+                                "No users matching the pattern \"{}\".",
+                                &normalized_username
+                            )
+                            .into(),
+                        ),
                     );
                     return Ok(Envs::new1(env));
                 }
@@ -770,7 +746,7 @@ fn do_op(context: &mut Context, op: RcvOp) -> Result<Envs, String> {
                 let env = Env::new(
                     End::Server,
                     End::User(context.current_user_id),
-                    &Sndr::Info("You are already the operator of this room."),
+                    &Msg::Info("You are already the operator of this room.".into()),
                 );
                 return Ok(Envs::new1(env));
             }
@@ -785,38 +761,35 @@ fn do_op(context: &mut Context, op: RcvOp) -> Result<Envs, String> {
                 let env = Env::new(
                     End::Server,
                     End::User(context.current_user_id),
-                    &Sndr::Info(&format!(
-                        "{} must be in the room to transfer ownership.",
-                        &other_username
-                    )),
+                    &Msg::Info(
+                        format!(
+                            "{} must be in the room to transfer ownership.",
+                            &other_username
+                        )
+                        .into(),
+                    ),
                 );
                 return Ok(Envs::new1(env));
             }
             current_room.set_op(other_user_id);
-            let data: [&str; 2] = [&other_username, current_room.get_name()];
             let env = Env::new(
                 End::Server,
                 End::Room(room_id),
-                &Sndr::Misc {
-                    what: "new_op",
-                    alt: &format!(
-                        "{} is now the operator of {}.",
-                        &other_username,
-                        current_room.get_name()
-                    ),
-                    data: &data,
+                &Msg::NewOp {
+                    user: other_username.as_str().into(),
+                    room: current_room.get_name().into(),
                 },
             );
             Ok(Envs::new1(env))
         }
 
-        RcvOp::Invite(ref username) => {
+        Op::Invite(ref username) => {
             let normalized_username = collapse(username);
             if normalized_username.is_empty() {
                 let env = Env::new(
                     End::Server,
                     End::User(context.current_user_id),
-                    &Sndr::Info("That cannot be anyone's user name."),
+                    &Msg::Info("That cannot be anyone's user name.".into()),
                 );
                 return Ok(Envs::new1(env));
             }
@@ -826,10 +799,13 @@ fn do_op(context: &mut Context, op: RcvOp) -> Result<Envs, String> {
                     let env = Env::new(
                         End::Server,
                         End::User(context.current_user_id),
-                        &Sndr::Info(&format!(
-                            "No users matching the pattern \"{}\".",
-                            &normalized_username
-                        )),
+                        &Msg::Info(
+                            format!(
+                                "No users matching the pattern \"{}\".",
+                                &normalized_username
+                            )
+                            .into(),
+                        ),
                     );
                     return Ok(Envs::new1(env));
                 }
@@ -853,10 +829,9 @@ fn do_op(context: &mut Context, op: RcvOp) -> Result<Envs, String> {
                 let env = Env::new(
                     End::Server,
                     End::User(context.current_user_id),
-                    &Sndr::Info(&format!(
-                        "You are already allowed in {}.",
-                        current_room.get_name()
-                    )),
+                    &Msg::Info(
+                        format!("You are already allowed in {}.", current_room.get_name()).into(),
+                    ),
                 );
                 return Ok(Envs::new1(env));
             };
@@ -875,11 +850,14 @@ fn do_op(context: &mut Context, op: RcvOp) -> Result<Envs, String> {
                 let env = Env::new(
                     End::Server,
                     End::User(context.current_user_id),
-                    &Sndr::Info(&format!(
-                        "{} has already been invited to {}.",
-                        other_user.get_name(),
-                        current_room.get_name()
-                    )),
+                    &Msg::Info(
+                        format!(
+                            "{} has already been invited to {}.",
+                            other_user.get_name(),
+                            current_room.get_name()
+                        )
+                        .into(),
+                    ),
                 );
                 return Ok(Envs::new1(env));
             };
@@ -890,41 +868,107 @@ fn do_op(context: &mut Context, op: RcvOp) -> Result<Envs, String> {
                 inviter_env = Env::new(
                     End::Server,
                     End::User(context.current_user_id),
-                    &Sndr::Info(&format!(
-                        "{} may now return to {} even when closed.",
-                        other_user.get_name(),
-                        current_room.get_name()
-                    )),
+                    &Msg::Info(
+                        format!(
+                            "{} may now return to {} even when closed.",
+                            other_user.get_name(),
+                            current_room.get_name()
+                        )
+                        .into(),
+                    ),
                 );
-                other_user.deliver_msg(&Sndr::Info(&format!(
-                    "You have been invited to return to {} even if it closes.",
-                    current_room.get_name()
-                )));
+                other_user.deliver_msg(&Msg::Info(
+                    format!(
+                        "You have been invited to return to {} even if it closes.",
+                        current_room.get_name()
+                    )
+                    .into(),
+                ));
             } else {
                 inviter_env = Env::new(
                     End::Server,
                     End::User(context.current_user_id),
-                    &Sndr::Info(&format!(
-                        "You invite {} to join {}.",
-                        other_user.get_name(),
-                        current_room.get_name()
-                    )),
+                    &Msg::Info(
+                        format!(
+                            "You invite {} to join {}.",
+                            other_user.get_name(),
+                            current_room.get_name()
+                        )
+                        .into(),
+                    ),
                 );
-                other_user.deliver_msg(&Sndr::Info(&format!(
-                    "You have been invited to join {}.",
-                    current_room.get_name()
-                )));
+                other_user.deliver_msg(&Msg::Info(
+                    format!("You have been invited to join {}.", current_room.get_name()).into(),
+                ));
             }
             Ok(Envs::new1(inviter_env))
         }
 
-        RcvOp::Kick(ref username) => {
+        Op::Voice(ref username) => {
+            let normalized_username = collapse(username);
+            let other_user_id = match context.user_ids_by_str.get(&normalized_username) {
+                Some(id) if !normalized_username.is_empty() => *id,
+                _ => {
+                    let env = Env::new(
+                        End::Server,
+                        End::User(context.current_user_id),
+                        &Msg::Info(
+                            format!(
+                                "No users matching the pattern \"{}\".",
+                                &normalized_username
+                            )
+                            .into(),
+                        ),
+                    );
+                    return Ok(Envs::new1(env));
+                }
+            };
+
+            let other_username = {
+                let user = context.get_user_by_id(other_user_id)?;
+                user.get_name().to_string()
+            };
+
+            let current_room = context.get_room_by_id_mut(room_id)?;
+            if !current_room.get_users().contains(&other_user_id) {
+                let env = Env::new(
+                    End::Server,
+                    End::User(context.current_user_id),
+                    &Msg::Info(
+                        format!(
+                            "{} must be in the room to be given a voice.",
+                            &other_username
+                        )
+                        .into(),
+                    ),
+                );
+                return Ok(Envs::new1(env));
+            }
+
+            let msg = if current_room.toggle_voice(other_user_id) {
+                Msg::Voiced {
+                    user: other_username.as_str().into(),
+                    by: op_name.as_str().into(),
+                    room: current_room.get_name().into(),
+                }
+            } else {
+                Msg::Devoiced {
+                    user: other_username.as_str().into(),
+                    by: op_name.as_str().into(),
+                    room: current_room.get_name().into(),
+                }
+            };
+            let env = Env::new(End::Server, End::Room(room_id), &msg);
+            Ok(Envs::new1(env))
+        }
+
+        Op::Kick(ref username) => {
             let normalized_username = collapse(username);
             if normalized_username.is_empty() {
                 let env = Env::new(
                     End::Server,
                     End::User(context.current_user_id),
-                    &Sndr::Info("That cannot be anyone's user name."),
+                    &Msg::Info("That cannot be anyone's user name.".into()),
                 );
                 return Ok(Envs::new1(env));
             }
@@ -934,10 +978,13 @@ fn do_op(context: &mut Context, op: RcvOp) -> Result<Envs, String> {
                     let env = Env::new(
                         End::Server,
                         End::User(context.current_user_id),
-                        &Sndr::Info(&format!(
-                            "No users matching the pattern \"{}\".",
-                            &normalized_username
-                        )),
+                        &Msg::Info(
+                            format!(
+                                "No users matching the pattern \"{}\".",
+                                &normalized_username
+                            )
+                            .into(),
+                        ),
                     );
                     return Ok(Envs::new1(env));
                 }
@@ -948,7 +995,7 @@ fn do_op(context: &mut Context, op: RcvOp) -> Result<Envs, String> {
                 let env = Env::new(
                     End::Server,
                     End::User(context.current_user_id),
-                    &Sndr::Info("You cannot kick yourself."),
+                    &Msg::Info("You cannot kick yourself.".into()),
                 );
                 return Ok(Envs::new1(env));
             }
@@ -984,11 +1031,14 @@ fn do_op(context: &mut Context, op: RcvOp) -> Result<Envs, String> {
                     let env = Env::new(
                         End::Server,
                         End::User(context.current_user_id),
-                        &Sndr::Info(&format!(
-                            "{} is already banned from {}.",
-                            target_user.get_name(),
-                            room.get_name()
-                        )),
+                        &Msg::Info(
+                            format!(
+                                "{} is already banned from {}.",
+                                target_user.get_name(),
+                                room.get_name()
+                            )
+                            .into(),
+                        ),
                     );
                     return Ok(Envs::new1(env));
                 };
@@ -1001,22 +1051,20 @@ fn do_op(context: &mut Context, op: RcvOp) -> Result<Envs, String> {
                         let env = Env::new(
                             End::Server,
                             End::User(context.current_user_id),
-                            &Sndr::Info(&format!(
-                                "You have banned {} from {}.",
-                                target_user.get_name(),
-                                room.get_name()
-                            )),
+                            &Msg::Info(
+                                format!(
+                                    "You have banned {} from {}.",
+                                    target_user.get_name(),
+                                    room.get_name()
+                                )
+                                .into(),
+                            ),
                         );
                         return Ok(Envs::new1(env));
                     }
                 } else {
-                    let alternative_string =
-                        format!("You have been kicked from {}.", room.get_name());
-                    let data: [&str; 1] = [room.get_name()];
-                    let to_kicked = Sndr::Misc {
-                        what: "kick_you",
-                        alt: &alternative_string,
-                        data: &data,
+                    let to_kicked = Msg::KickedOut {
+                        room: room.get_name().into(),
                     };
                     target_user.deliver_msg(&to_kicked);
                     room.leave(other_user_id);
@@ -1027,30 +1075,22 @@ fn do_op(context: &mut Context, op: RcvOp) -> Result<Envs, String> {
 
             let lobby = context.rooms_by_id.get_mut(&0).unwrap();
             lobby.join(other_user_id);
-            let data: [&str; 2] = [target_user.get_name(), lobby.get_name()];
             let to_lobby = Env::new(
                 End::Server,
                 End::Room(context.current_room_id),
-                &Sndr::Misc {
-                    what: "join",
-                    data: &data,
-                    alt: &format!("{} joined {}.", target_user.get_name(), lobby.get_name()),
+                &Msg::Joined {
+                    user: target_user.get_name().into(),
+                    room: lobby.get_name().into(),
                 },
             );
             lobby.enqueue(to_lobby);
 
-            let data: [&str; 2] = [target_user.get_name(), &current_room_name];
             let env = Env::new(
                 End::Server,
                 End::Room(context.current_room_id),
-                &Sndr::Misc {
-                    what: "kick_other",
-                    data: &data,
-                    alt: &format!(
-                        "{} has been kicked from {}.",
-                        target_user.get_name(),
-                        &current_room_name
-                    ),
+                &Msg::Kicked {
+                    user: target_user.get_name().into(),
+                    room: current_room_name.as_str().into(),
                 },
             );
 
@@ -1059,6 +1099,26 @@ fn do_op(context: &mut Context, op: RcvOp) -> Result<Envs, String> {
     }
 }
 
+/// If `room`'s operator has left it, make whoever's been there longest the
+/// operator, returning the `NewOp` announcing it. The lobby has no operator.
+pub fn hand_over_op(room: &mut Room, users_by_id: &HashMap<u64, User>) -> Option<Env> {
+    if room.get_id() == 0 || room.get_users().contains(&room.get_op()) {
+        return None;
+    }
+    let new_op_id = *room.get_users().first()?;
+    let new_op = users_by_id.get(&new_op_id)?;
+    room.set_op(new_op_id);
+    Some(Env::new(
+        End::Server,
+        End::Room(room.get_id()),
+        &Msg::NewOp {
+            user: new_op.get_name().into(),
+            room: room.get_name().into(),
+        },
+    ))
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn process_room(
     room_id: u64,
     current_time: Instant,
@@ -1067,7 +1127,8 @@ pub fn process_room(
     rooms_by_id: &mut HashMap<u64, Room>,
     room_ids_by_str: &mut HashMap<String, u64>,
     cfg: &ServerConfig,
-) -> Result<(), String> {
+    hooks: &Outgoing,
+) -> Result<Vec<(String, Session)>, String> {
     let mut user_id_list: SmallVec<[u64; ROOM_SIZE]>;
     {
         match rooms_by_id.get(&room_id) {
@@ -1091,9 +1152,10 @@ pub fn process_room(
 
     let mut envs: Envs = Envs::new0();
     let mut logout_users: SmallVec<[(u64, &str); LOGOUTS_SIZE]> = SmallVec::new();
+    let mut dropped_sessions: Vec<(String, Session)> = Vec::new();
 
     for user_id in &user_id_list {
-        let received_message: Rcvr;
+        let received_message: Msg<'static>;
         {
             let user = match context.users_by_id.get_mut(user_id) {
                 None => {
@@ -1106,11 +1168,20 @@ pub fn process_room(
             let over_quota = user.get_byte_quota() > cfg.byte_limit;
             user.drain_byte_quota(cfg.byte_tick);
             if over_quota && user.get_byte_quota() <= cfg.byte_limit {
-                let msg = Sndr::Err("You may send messages again.");
+                let msg = Msg::Err("You may send messages again.".into());
                 user.deliver_msg(&msg);
             }
 
             match user.try_get() {
+                None if user.has_errors() => {
+                    warn!(
+                        "User {} being logged out for error(s): {}",
+                        user_id,
+                        &user.get_errors()
+                    );
+                    logout_users.push((*user_id, "Communication error."));
+                    continue;
+                }
                 None => {
                     let last = user.get_last_data_time();
                     match current_time.checked_duration_since(last) {
@@ -1121,7 +1192,7 @@ pub fn process_room(
                             ));
                         }
                         Some(x) if x > cfg.time_to_ping => {
-                            user.deliver_msg(&Sndr::Ping);
+                            user.deliver_msg(&Msg::Ping);
                         }
                         _ => {}
                     }
@@ -1129,9 +1200,12 @@ pub fn process_room(
                 }
                 Some(msg) => {
                     if !over_quota {
+                        if matches!(msg, Msg::Text { .. } | Msg::Priv { .. }) {
+                            user.mark_active();
+                        }
                         received_message = msg;
                         if user.get_byte_quota() > cfg.byte_limit {
-                            let msg = Sndr::Err("You have exceeded your data quota and your messages will be ignored for a short time.");
+                            let msg = Msg::OverQuota("You have exceeded your data quota and your messages will be ignored for a short time.".into());
                             user.deliver_msg(&msg);
                         }
                     } else {
@@ -1153,15 +1227,16 @@ pub fn process_room(
         context.current_user_id = *user_id;
 
         let processed_result = match received_message {
-            Rcvr::Text { lines, .. } => do_text(&mut context, lines),
-            Rcvr::Priv { who, text } => do_priv(&mut context, who, text),
-            Rcvr::Name(new_candidate) => do_name(&mut context, cfg, new_candidate),
-            Rcvr::Join(room_name) => do_join(&mut context, cfg, room_name),
-            Rcvr::Block(username) => do_block(&mut context, username),
-            Rcvr::Unblock(username) => do_unblock(&mut context, username),
-            Rcvr::Logout(salutation) => do_logout(&mut context, salutation),
-            Rcvr::Query { what, arg } => do_query(&mut context, what, arg),
-            Rcvr::Op(op) => do_op(&mut context, op),
+            Msg::Text { lines, .. } => do_text(&mut context, cfg, hooks, lines),
+            Msg::Priv { who, text } => do_priv(&mut context, who.into_owned(), text.into_owned()),
+            Msg::Name(new_candidate) => do_name(&mut context, cfg, new_candidate.into_owned()),
+            Msg::Join(room_name) => do_join(&mut context, cfg, room_name.into_owned()),
+            Msg::Block(username) => do_block(&mut context, username.into_owned()),
+            Msg::Unblock(username) => do_unblock(&mut context, username.into_owned()),
+            Msg::Logout(salutation) => do_logout(&mut context, salutation.into_owned()),
+            Msg::Query { what, arg } => do_query(&mut context, what.into_owned(), arg.into_owned()),
+            Msg::Op(op) => do_op(&mut context, op),
+            Msg::Away(message) => do_away(&mut context, message.into_owned()),
             _ => Ok(Envs::new0()),
         };
 
@@ -1181,16 +1256,21 @@ pub fn process_room(
     for (user_id, errmsg) in logout_users.iter() {
         if let Some(mut user) = context.users_by_id.remove(user_id) {
             context.user_ids_by_str.remove(user.get_idstr());
-            let msg = Sndr::Logout(errmsg);
-            user.deliver_msg(&msg);
-            let data: [&str; 2] = [user.get_name(), "[ disconnected by server ]"];
+            let room = context.rooms_by_id.get(&room_id);
+            if let (Some(room), false) = (room, user.get_resume_token().is_empty()) {
+                let expires = current_time + cfg.resume_timeout;
+                dropped_sessions.push((
+                    user.get_resume_token().to_string(),
+                    session_for(&user, room, expires),
+                ));
+            }
+            user.logout(errmsg);
             let env = Env::new(
                 End::Server,
                 End::Room(context.current_room_id),
-                &Sndr::Misc {
-                    what: "leave",
-                    data: &data,
-                    alt: &format!("{} has been disconnected from the server.", user.get_name()),
+                &Msg::Left {
+                    user: user.get_name().into(),
+                    reason: "[ disconnected by server ]".into(),
                 },
             );
             envs.as_mut().push(env);
@@ -1203,24 +1283,9 @@ pub fn process_room(
     }
 
     // Change the room OP if necessary.
-    if room_id != 0 {
-        let room = context.rooms_by_id.get_mut(&room_id).unwrap();
-        let op_id = room.get_op();
-        let op_still_here = room.get_users().contains(&op_id);
-        if !op_still_here {
-            if let Some(potential_new_operator_id) = room.get_users().first() {
-                if let Some(u) = context.users_by_id.get(potential_new_operator_id) {
-                    let nid = *potential_new_operator_id;
-                    room.set_op(nid);
-                    let env = Env::new(
-                        End::Server,
-                        End::Room(room_id),
-                        &Sndr::Info(&format!("{} is now the Room operator.", u.get_name())),
-                    );
-                    envs.as_mut().push(env);
-                }
-            }
-        }
+    let room = context.rooms_by_id.get_mut(&room_id).unwrap();
+    if let Some(env) = hand_over_op(room, context.users_by_id) {
+        envs.as_mut().push(env);
     }
 
     {
@@ -1241,5 +1306,5 @@ pub fn process_room(
         }
     }
 
-    Ok(())
+    Ok(dropped_sessions)
 }
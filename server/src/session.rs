@@ -0,0 +1,90 @@
+use common::{
+    proto::{End, Env, Msg},
+    room::Room,
+    user::User,
+};
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::time::Instant;
+
+/// What the server remembers about a `User` whose connection dropped, so a
+/// reconnecting client presenting the right token can pick up where it left off.
+#[derive(Debug)]
+pub struct Session {
+    pub name: String,
+    pub room_idstr: String,
+    pub was_op: bool,
+    pub away: Option<String>,
+    pub expires: Instant,
+}
+
+/// Generates an unguessable resume token from the operating system's source
+/// of randomness, or returns `None` if it can't be had.
+pub fn gen_token() -> Option<String> {
+    let mut bytes = [0u8; 16];
+    getrandom::getrandom(&mut bytes).ok()?;
+    let mut token = String::with_capacity(2 * bytes.len());
+    for b in bytes.iter() {
+        write!(&mut token, "{:02x}", b).unwrap();
+    }
+    Some(token)
+}
+
+/// Builds the `Session` a `User` leaves behind when it drops out of `room`.
+pub fn session_for(user: &User, room: &Room, expires: Instant) -> Session {
+    Session {
+        name: user.get_name().to_string(),
+        room_idstr: room.get_idstr().to_string(),
+        was_op: room.get_op() == user.get_id(),
+        away: user.get_away().map(String::from),
+        expires,
+    }
+}
+
+/// Removes a still-connected `User` holding `token` (its connection has usually
+/// died without the server noticing yet) and returns the `Session` it leaves.
+pub fn evict_stale(
+    token: &str,
+    expires: Instant,
+    users_by_id: &mut HashMap<u64, User>,
+    user_ids_by_str: &mut HashMap<String, u64>,
+    rooms_by_id: &mut HashMap<u64, Room>,
+) -> Option<Session> {
+    let stale_id = users_by_id
+        .values()
+        .find(|u| u.get_resume_token() == token)
+        .map(User::get_id)?;
+    let mut stale_user = users_by_id.remove(&stale_id)?;
+    user_ids_by_str.remove(stale_user.get_idstr());
+    stale_user.logout("This session has been resumed from another connection.");
+
+    let room = rooms_by_id
+        .values_mut()
+        .find(|r| r.get_users().contains(&stale_id))?;
+    let session = session_for(&stale_user, room, expires);
+    room.leave(stale_id);
+
+    room.enqueue(Env::new(
+        End::Server,
+        End::Room(room.get_id()),
+        &Msg::Left {
+            user: stale_user.get_name().into(),
+            reason: "[ reconnecting ]".into(),
+        },
+    ));
+
+    Some(session)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens_are_long_random_hex() {
+        let (a, b) = (gen_token().unwrap(), gen_token().unwrap());
+        assert_eq!(a.len(), 32);
+        assert!(a.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_ne!(a, b);
+    }
+}
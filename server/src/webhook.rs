@@ -0,0 +1,410 @@
+use common::{config::OutgoingWebhook, util::collapse};
+use log::{debug, error, warn};
+use serde_json::json;
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long to wait to connect to a webhook's server, and for its answer.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How many times to try delivering each message before giving up.
+const MAX_ATTEMPTS: u32 = 5;
+
+/// How long to wait before the first retry; each one after waits twice as long.
+const FIRST_BACKOFF: Duration = Duration::from_secs(1);
+
+/// The most that can be waiting for delivery to any one hook; past this, new
+/// messages for it are dropped rather than piling up behind a server that's
+/// down.
+const MAX_QUEUED: usize = 1000;
+
+/// One message on its way to a webhook.
+struct Delivery {
+    body: String,
+    attempts: u32,
+    due: Instant,
+}
+
+/// An outgoing webhook, and the way to its delivery thread.
+struct Hook {
+    config: OutgoingWebhook,
+    tx: mpsc::Sender<Delivery>,
+}
+
+/// The outgoing webhooks. Each has a thread of its own to deliver to it, so
+/// one whose server is slow or down doesn't hold up the others.
+pub struct Outgoing {
+    hooks: Vec<Hook>,
+}
+
+impl Outgoing {
+    /// Start delivering to `hooks`. Hooks whose URLs aren't `http://` ones are
+    /// left out.
+    pub fn start(hooks: Vec<OutgoingWebhook>) -> Outgoing {
+        let hooks = hooks
+            .into_iter()
+            .filter(|h| {
+                let ok = split_url(&h.url).is_some();
+                if !ok {
+                    error!(
+                        "Ignoring webhook for \"{}\" with bad URL: {}",
+                        &h.room, &h.url
+                    );
+                }
+                ok
+            })
+            .map(|config| {
+                let (tx, rx) = mpsc::channel();
+                let url = config.url.clone();
+                thread::spawn(move || deliver_loop(url, rx));
+                Hook { config, tx }
+            })
+            .collect();
+        Outgoing { hooks }
+    }
+
+    /// Pass what `user` has just said in `room` on to each of the room's
+    /// webhooks that it matches.
+    pub fn notify(&self, room: &str, user: &str, lines: &[Cow<str>]) {
+        if self.hooks.is_empty() {
+            return;
+        }
+        let room_idstr = collapse(room);
+        for hook in self
+            .hooks
+            .iter()
+            .filter(|h| collapse(&h.config.room) == room_idstr && matches(&h.config, lines))
+        {
+            let body = json!({
+                "room": room,
+                "user": user,
+                "text": lines.join("\n"),
+            });
+            let delivery = Delivery {
+                body: body.to_string(),
+                attempts: 0,
+                due: Instant::now(),
+            };
+            if hook.tx.send(delivery).is_err() {
+                warn!(
+                    "Webhook delivery thread for {} has stopped.",
+                    &hook.config.url
+                );
+            }
+        }
+    }
+}
+
+/// Whether a message is one `hook` wants: one whose first line starts with its
+/// `prefix` (as a bot command would; the prefix further down doesn't count), or
+/// that contains its `contains` anywhere, or any message at all if it has
+/// neither.
+fn matches(hook: &OutgoingWebhook, lines: &[Cow<str>]) -> bool {
+    let by_prefix = matches!(
+        (&hook.prefix, lines.first()),
+        (Some(p), Some(l)) if l.starts_with(p.as_str())
+    );
+    let by_contents = match &hook.contains {
+        Some(c) => lines.iter().any(|l| l.contains(c.as_str())),
+        None => false,
+    };
+    (hook.prefix.is_none() && hook.contains.is_none()) || by_prefix || by_contents
+}
+
+/// Deliver what comes through `rx` to `url`, retrying failures with
+/// exponential backoff. Runs until the server drops its `Outgoing`.
+fn deliver_loop(url: String, rx: mpsc::Receiver<Delivery>) {
+    let mut queue: VecDeque<Delivery> = VecDeque::new();
+    loop {
+        let wait = queue
+            .iter()
+            .map(|d| d.due.saturating_duration_since(Instant::now()))
+            .min()
+            .unwrap_or(Duration::from_secs(3600));
+        match rx.recv_timeout(wait) {
+            Ok(delivery) if queue.len() < MAX_QUEUED => queue.push_back(delivery),
+            Ok(_) => warn!("Webhook queue is full; dropping message for {}", &url),
+            Err(mpsc::RecvTimeoutError::Timeout) => {}
+            Err(mpsc::RecvTimeoutError::Disconnected) => return,
+        }
+
+        let now = Instant::now();
+        let (due, waiting): (Vec<Delivery>, Vec<Delivery>) =
+            queue.drain(..).partition(|d| d.due <= now);
+        queue.extend(waiting);
+
+        for mut delivery in due {
+            delivery.attempts += 1;
+            match post(&url, &delivery.body) {
+                Ok(()) => debug!("Webhook delivered to {}", &url),
+                Err(e) if delivery.attempts < MAX_ATTEMPTS => {
+                    let backoff = FIRST_BACKOFF * 2u32.pow(delivery.attempts - 1);
+                    debug!(
+                        "Webhook to {} failed ({}); retrying in {:?}",
+                        &url, &e, backoff
+                    );
+                    delivery.due = Instant::now() + backoff;
+                    queue.push_back(delivery);
+                }
+                Err(e) => warn!(
+                    "Webhook to {} failed {} times; giving up: {}",
+                    &url, delivery.attempts, &e
+                ),
+            }
+        }
+    }
+}
+
+/// Split an `http://host[:port]/path` URL into its host (with port) and
+/// path.
+fn split_url(url: &str) -> Option<(&str, &str)> {
+    let rest = url.strip_prefix("http://")?;
+    let (host, path) = match rest.find('/') {
+        Some(n) => rest.split_at(n),
+        None => (rest, "/"),
+    };
+    if host.is_empty() {
+        None
+    } else {
+        Some((host, path))
+    }
+}
+
+/// POST `body` to `url`, succeeding if the answer has a 2xx status.
+fn post(url: &str, body: &str) -> Result<(), String> {
+    let (host, path) = split_url(url).ok_or_else(|| format!("Bad URL: {}", url))?;
+    let address = if host.contains(':') {
+        host.to_string()
+    } else {
+        format!("{}:80", host)
+    };
+    let socket_addr = address
+        .to_socket_addrs()
+        .map_err(|e| e.to_string())?
+        .next()
+        .ok_or_else(|| format!("No address for {}", host))?;
+
+    let mut stream =
+        TcpStream::connect_timeout(&socket_addr, TIMEOUT).map_err(|e| e.to_string())?;
+    stream
+        .set_read_timeout(Some(TIMEOUT))
+        .and_then(|_| stream.set_write_timeout(Some(TIMEOUT)))
+        .map_err(|e| e.to_string())?;
+
+    let request = format!(
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\nUser-Agent: fresh-server\r\n\r\n{}",
+        path,
+        host,
+        body.len(),
+        body
+    );
+    stream
+        .write_all(request.as_bytes())
+        .map_err(|e| e.to_string())?;
+
+    // Only the status line matters.
+    let mut answer = Vec::new();
+    let mut buff = [0u8; 256];
+    while !answer.contains(&b'\n') {
+        match stream.read(&mut buff) {
+            Ok(0) => break,
+            Ok(n) => answer.extend_from_slice(&buff[..n]),
+            Err(e) => return Err(e.to_string()),
+        }
+    }
+    let status_line = String::from_utf8_lossy(&answer);
+    match status_line.split_whitespace().nth(1) {
+        Some(status) if status.starts_with('2') => Ok(()),
+        Some(status) => Err(format!("Answered with status {}", status)),
+        None => Err(String::from("No answer.")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    fn hook(prefix: Option<&str>, contains: Option<&str>) -> OutgoingWebhook {
+        OutgoingWebhook {
+            room: "dev".into(),
+            url: "http://127.0.0.1/".into(),
+            prefix: prefix.map(String::from),
+            contains: contains.map(String::from),
+        }
+    }
+
+    fn lines(text: &str) -> Vec<Cow<'_, str>> {
+        text.lines().map(Cow::from).collect()
+    }
+
+    #[test]
+    fn matching() {
+        let all = hook(None, None);
+        assert!(all.prefix.is_none() && matches(&all, &lines("anything")));
+        assert!(matches(&all, &[]));
+
+        let prefix = hook(Some("!deploy"), None);
+        assert!(matches(&prefix, &lines("!deploy now")));
+        assert!(!matches(&prefix, &lines("please !deploy")));
+        assert!(!matches(&prefix, &[]));
+
+        let contains = hook(None, Some("broken"));
+        assert!(matches(&contains, &lines("it's broken")));
+        assert!(matches(&contains, &lines("first\nsecond is broken")));
+        assert!(!matches(&contains, &lines("all fine")));
+
+        let both = hook(Some("!deploy"), Some("broken"));
+        assert!(matches(&both, &lines("!deploy")));
+        assert!(matches(&both, &lines("so\nbroken")));
+        assert!(!matches(&both, &lines("neither")));
+    }
+
+    #[test]
+    fn prefixes_only_count_at_the_start_of_the_first_line() {
+        let prefix = hook(Some("!deploy"), None);
+        assert!(matches(&prefix, &lines("!deploy now\nplease")));
+        assert!(!matches(&prefix, &lines("first\n!deploy second")));
+        assert!(!matches(&prefix, &lines("\n!deploy")));
+        assert!(!matches(&prefix, &lines(" !deploy")));
+    }
+
+    #[test]
+    fn splitting_urls() {
+        assert_eq!(
+            split_url("http://example.com:8080/chat/in"),
+            Some(("example.com:8080", "/chat/in"))
+        );
+        assert_eq!(split_url("http://example.com"), Some(("example.com", "/")));
+        assert_eq!(split_url("http://10.0.0.1/"), Some(("10.0.0.1", "/")));
+        assert_eq!(split_url("https://example.com/"), None);
+        assert_eq!(split_url("ftp://example.com/"), None);
+        assert_eq!(split_url("example.com/chat"), None);
+        assert_eq!(split_url("http:///chat"), None);
+        assert_eq!(split_url("http://"), None);
+    }
+
+    #[test]
+    fn bad_urls_are_left_out() {
+        let mut https = hook(None, None);
+        https.url = "https://example.com/".into();
+        let outgoing = Outgoing::start(vec![https]);
+        assert!(outgoing.hooks.is_empty());
+    }
+
+    /// Read one request off `stream`: its head, and as much body as its
+    /// `Content-Length` says.
+    fn read_request(stream: &mut TcpStream) -> (String, String) {
+        let mut data = Vec::new();
+        let mut buff = [0u8; 1024];
+        loop {
+            let n = stream.read(&mut buff).unwrap();
+            assert!(n > 0, "Connection closed mid-request.");
+            data.extend_from_slice(&buff[..n]);
+            let text = String::from_utf8_lossy(&data).into_owned();
+            if let Some(end) = text.find("\r\n\r\n") {
+                let head = text[..end].to_string();
+                let length: usize = head
+                    .lines()
+                    .find_map(|l| l.strip_prefix("Content-Length: "))
+                    .unwrap()
+                    .parse()
+                    .unwrap();
+                let body = &text[end + 4..];
+                if body.len() >= length {
+                    return (head, body.to_string());
+                }
+            }
+        }
+    }
+
+    /// Listen on a free port, answering each of `statuses` to one request in
+    /// turn, and hand back the URL and the thread that returns what was asked.
+    fn mock(
+        statuses: &'static [&'static str],
+    ) -> (String, thread::JoinHandle<Vec<(String, String)>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/chat", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            let mut requests = Vec::new();
+            for status in statuses {
+                let (mut stream, _) = listener.accept().unwrap();
+                requests.push(read_request(&mut stream));
+                let answer = format!("HTTP/1.1 {}\r\nContent-Length: 0\r\n\r\n", status);
+                stream.write_all(answer.as_bytes()).unwrap();
+            }
+            requests
+        });
+        (url, server)
+    }
+
+    #[test]
+    fn post_checks_the_status() {
+        let (url, server) = mock(&["204 No Content", "404 Not Found"]);
+        assert_eq!(post(&url, "{}"), Ok(()));
+        assert_eq!(
+            post(&url, "{}"),
+            Err(String::from("Answered with status 404"))
+        );
+        let requests = server.join().unwrap();
+        let (head, body) = &requests[0];
+        assert!(head.starts_with("POST /chat HTTP/1.1\r\n"));
+        assert!(head.contains("Content-Type: application/json"));
+        assert_eq!(body, "{}");
+    }
+
+    #[test]
+    fn post_fails_with_nobody_listening() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        drop(listener);
+        assert!(post(&url, "{}").is_err());
+    }
+
+    #[test]
+    fn delivery_is_retried_until_it_succeeds() {
+        let (url, server) = mock(&["500 Internal Server Error", "200 OK"]);
+        let mut hook = hook(Some("!deploy"), None);
+        hook.url = url;
+        let outgoing = Outgoing::start(vec![hook]);
+
+        let started = Instant::now();
+        outgoing.notify("DEV", "alice", &lines("not for the hook"));
+        outgoing.notify("other", "alice", &lines("!deploy elsewhere"));
+        outgoing.notify("DEV", "alice", &lines("!deploy now\nplease"));
+        let requests = server.join().unwrap();
+        assert!(started.elapsed() >= FIRST_BACKOFF);
+
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0], requests[1]);
+        let body: serde_json::Value = serde_json::from_str(&requests[0].1).unwrap();
+        assert_eq!(
+            body,
+            json!({"room": "DEV", "user": "alice", "text": "!deploy now\nplease"})
+        );
+    }
+
+    #[test]
+    fn a_hook_thats_down_doesnt_hold_up_the_others() {
+        // Takes connections, but never answers.
+        let silent = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut stuck = hook(None, None);
+        stuck.url = format!("http://{}/", silent.local_addr().unwrap());
+
+        let (url, server) = mock(&["200 OK"]);
+        let mut working = hook(None, None);
+        working.url = url;
+        let outgoing = Outgoing::start(vec![stuck, working]);
+
+        let started = Instant::now();
+        outgoing.notify("dev", "alice", &lines("hello"));
+        let requests = server.join().unwrap();
+        assert!(started.elapsed() < TIMEOUT);
+        assert_eq!(requests.len(), 1);
+    }
+}